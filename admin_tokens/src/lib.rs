@@ -0,0 +1,150 @@
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Distinguishes admin API tokens from gateway API keys, which are UUIDs,
+/// and from JWTs, which contain dots.
+pub const ADMIN_API_TOKEN_PREFIX: &str = "gwadm_";
+
+/// Returns true if `token` looks like an admin API token. It is not checked
+/// against the database.
+pub fn is_admin_api_token(token: &str) -> bool {
+    token.starts_with(ADMIN_API_TOKEN_PREFIX)
+}
+
+fn generate_admin_api_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    format!("{ADMIN_API_TOKEN_PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn hash_admin_api_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub struct NewAdminApiToken {
+    pub admin_api_token_id: Uuid,
+    /// Shown once; only its hash is stored.
+    pub token: String,
+}
+
+/// Creates a token for the admin JSON API. `created_by` is the admin
+/// creating it.
+pub async fn create_admin_api_token(
+    pool: &PgPool,
+    name: &str,
+    created_by: &str,
+) -> Result<NewAdminApiToken> {
+    let token = generate_admin_api_token();
+
+    let admin_api_token_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO admin_api_tokens (name, created_by, token_hash)
+        VALUES ($1, $2, $3)
+        RETURNING admin_api_token_id
+        "#,
+        name,
+        created_by.to_lowercase(),
+        hash_admin_api_token(&token),
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(NewAdminApiToken {
+        admin_api_token_id,
+        token,
+    })
+}
+
+/// Returns the name of the token if it exists and has not been revoked, and
+/// records its use.
+pub async fn verify_admin_api_token(pool: &PgPool, token: &str) -> Result<Option<String>> {
+    let name = sqlx::query_scalar!(
+        r#"
+        UPDATE admin_api_tokens
+        SET last_used_at = now()
+        WHERE token_hash = $1 AND revoked_at IS NULL
+        RETURNING name
+        "#,
+        hash_admin_api_token(token),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(name)
+}
+
+pub struct AdminApiTokenSummary {
+    pub admin_api_token_id: Uuid,
+    pub created_at: OffsetDateTime,
+    pub created_by: String,
+    pub last_used_at: Option<OffsetDateTime>,
+    pub name: String,
+    pub revoked_at: Option<OffsetDateTime>,
+}
+
+/// Lists all tokens, newest first.
+pub async fn list_admin_api_tokens(pool: &PgPool) -> Result<Vec<AdminApiTokenSummary>> {
+    let tokens = sqlx::query_as!(
+        AdminApiTokenSummary,
+        r#"
+        SELECT admin_api_token_id, created_at, created_by, last_used_at, name, revoked_at
+        FROM admin_api_tokens
+        ORDER BY created_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tokens)
+}
+
+/// Revokes the token. Returns its name, or `None` if it does not exist or was
+/// already revoked.
+pub async fn revoke_admin_api_token(
+    pool: &PgPool,
+    admin_api_token_id: Uuid,
+) -> Result<Option<String>> {
+    let name = sqlx::query_scalar!(
+        r#"
+        UPDATE admin_api_tokens
+        SET revoked_at = now()
+        WHERE admin_api_token_id = $1 AND revoked_at IS NULL
+        RETURNING name
+        "#,
+        admin_api_token_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_tokens_are_recognized_and_unique() {
+        let token = generate_admin_api_token();
+        assert!(is_admin_api_token(&token));
+        assert!(!token.contains('.'));
+        assert_ne!(token, generate_admin_api_token());
+    }
+
+    #[test]
+    fn api_keys_are_not_admin_api_tokens() {
+        assert!(!is_admin_api_token("0a1b2c3d-4e5f-6789-abcd-ef0123456789"));
+    }
+
+    #[test]
+    fn hashes_are_hex_sha256() {
+        let hash = hash_admin_api_token("gwadm_example");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_admin_api_token("gwadm_example"));
+    }
+}
@@ -1,31 +1,79 @@
 use anyhow::Result;
 use axum::http::HeaderMap;
 use sqlx::PgPool;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
-pub async fn create_api_key(pool: &PgPool, user_email: &str) -> Result<Uuid> {
+/// A newly created API key. The key itself is only shown to its owner once.
+pub struct CreatedApiKey {
+    pub api_key: Uuid,
+    pub api_key_id: Uuid,
+}
+
+/// Creates a personal API key for `user_email`. Fails if there is no such
+/// user.
+pub async fn create_api_key(pool: &PgPool, user_email: &str) -> Result<CreatedApiKey> {
     let api_key = Uuid::new_v4();
 
-    sqlx::query!(
+    let api_key_id = sqlx::query_scalar!(
         r#"
         INSERT INTO api_keys (api_key, user_id)
         SELECT $1, user_id FROM users WHERE user_email = $2
+        RETURNING api_key_id
         "#,
         api_key.to_string(),
         user_email.to_lowercase()
     )
-    .execute(pool)
-    .await?;
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("User not found: {user_email}"))?;
 
-    Ok(api_key)
+    Ok(CreatedApiKey {
+        api_key,
+        api_key_id,
+    })
 }
 
+/// Creates an API key for `user_email` that bills `team_id`. Team keys belong
+/// to the team: `user_email` only records who created the key, and the key
+/// stays active when that member leaves the team or is deactivated. Team keys
+/// are disabled one at a time. Callers must check team membership first.
+pub async fn create_team_api_key(
+    pool: &PgPool,
+    user_email: &str,
+    team_id: Uuid,
+) -> Result<CreatedApiKey> {
+    let api_key = Uuid::new_v4();
+
+    let api_key_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO api_keys (api_key, user_id, team_id)
+        SELECT $1, user_id, $3 FROM users WHERE user_email = $2
+        RETURNING api_key_id
+        "#,
+        api_key.to_string(),
+        user_email.to_lowercase(),
+        team_id,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("User not found: {user_email}"))?;
+
+    Ok(CreatedApiKey {
+        api_key,
+        api_key_id,
+    })
+}
+
+/// Disables all of the user's personal keys. Team keys the user created
+/// belong to the team and are left active.
 pub async fn disable_all_api_keys(pool: &PgPool, user_email: &str) -> Result<u64> {
     let result = sqlx::query!(
         r#"
         UPDATE api_keys
         SET is_disabled = TRUE, updated_at = now()
         WHERE user_id = (SELECT user_id FROM users WHERE user_email = $1)
+          AND team_id IS NULL
         "#,
         user_email.to_lowercase()
     )
@@ -35,6 +83,27 @@ pub async fn disable_all_api_keys(pool: &PgPool, user_email: &str) -> Result<u64
     Ok(result.rows_affected())
 }
 
+/// Disables a single key. Returns the key's user, or `None` if the key does
+/// not exist. Disabling a disabled key changes nothing, so callers can retry
+/// the cleanup that follows.
+pub async fn disable_api_key(pool: &PgPool, api_key_id: Uuid) -> Result<Option<Uuid>> {
+    let user_id = sqlx::query_scalar!(
+        r#"
+        WITH disabled AS (
+            UPDATE api_keys
+            SET is_disabled = TRUE, updated_at = now()
+            WHERE api_key_id = $1 AND is_disabled = FALSE
+        )
+        SELECT user_id as "user_id!" FROM api_keys WHERE api_key_id = $1
+        "#,
+        api_key_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user_id)
+}
+
 pub async fn get_api_keys_count_and_api_keys_count_active(
     pool: &PgPool,
     user_email: &str,
@@ -73,17 +142,183 @@ pub async fn get_active_api_key(pool: &PgPool, user_email: &str) -> Result<Optio
     Ok(result)
 }
 
-pub async fn get_api_key(headers: &HeaderMap) -> Option<String> {
+pub async fn get_user_email_by_api_key(pool: &PgPool, api_key: &str) -> Result<Option<String>> {
+    let result = sqlx::query_scalar!(
+        r#"
+        SELECT u.user_email
+        FROM api_keys ak
+        JOIN users u ON u.user_id = ak.user_id
+        WHERE ak.api_key = $1 AND ak.is_disabled = FALSE
+        "#,
+        api_key.to_lowercase()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result)
+}
+
+pub struct ApiKeySummary {
+    pub api_key_id: Uuid,
+    pub api_key_prefix: String,
+    pub capture_enabled: Option<bool>,
+    pub created_at: OffsetDateTime,
+    pub is_disabled: bool,
+    pub team_name: Option<String>,
+}
+
+/// Lists a user's API keys, newest first, identified only by their prefix.
+pub async fn list_api_keys(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiKeySummary>> {
+    let api_keys = sqlx::query_as!(
+        ApiKeySummary,
+        r#"
+        SELECT
+            ak.api_key_id,
+            left(ak.api_key, 8) AS "api_key_prefix!",
+            ak.capture_enabled,
+            ak.created_at,
+            ak.is_disabled,
+            t.team_name AS "team_name?"
+        FROM api_keys ak
+        LEFT JOIN teams t ON t.team_id = ak.team_id
+        WHERE ak.user_id = $1
+        ORDER BY ak.created_at DESC
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(api_keys)
+}
+
+/// Lists the API keys billed to a team, newest first.
+pub async fn list_team_api_keys(pool: &PgPool, team_id: Uuid) -> Result<Vec<ApiKeySummary>> {
+    let api_keys = sqlx::query_as!(
+        ApiKeySummary,
+        r#"
+        SELECT
+            ak.api_key_id,
+            left(ak.api_key, 8) AS "api_key_prefix!",
+            ak.capture_enabled,
+            ak.created_at,
+            ak.is_disabled,
+            t.team_name AS "team_name?"
+        FROM api_keys ak
+        LEFT JOIN teams t ON t.team_id = ak.team_id
+        WHERE ak.team_id = $1
+        ORDER BY ak.created_at DESC
+        "#,
+        team_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(api_keys)
+}
+
+/// A credential presented by an API client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Credential {
+    /// A gateway API key, from `Authorization: Bearer` or `x-api-key`.
+    ApiKey(String),
+    /// A JWT from `Authorization: Bearer`, to be validated against the
+    /// identity provider.
+    BearerToken(String),
+}
+
+/// Returns true if `token` has the three dot-separated segments of a compact
+/// JWT. Gateway API keys are UUIDs, which never contain dots.
+pub fn is_jwt(token: &str) -> bool {
+    let segments = token.split('.').collect::<Vec<_>>();
+    segments.len() == 3 && segments.iter().all(|segment| !segment.is_empty())
+}
+
+pub fn get_credential(headers: &HeaderMap) -> Option<Credential> {
     if let Some(token) = headers
         .get("Authorization")
         .and_then(|v| v.to_str().ok())
         .and_then(|v| v.strip_prefix("Bearer "))
     {
-        return Some(token.trim().to_string());
+        let token = token.trim().to_string();
+        return Some(if is_jwt(&token) {
+            Credential::BearerToken(token)
+        } else {
+            Credential::ApiKey(token)
+        });
     }
 
     headers
         .get("x-api-key")
         .and_then(|v| v.to_str().ok())
-        .map(|v| v.trim().to_string())
+        .map(|v| Credential::ApiKey(v.trim().to_string()))
+}
+
+/// Returns the request's gateway API key. Identity provider tokens are not
+/// API keys and yield `None`; see [`get_credential`].
+pub async fn get_api_key(headers: &HeaderMap) -> Option<String> {
+    match get_credential(headers)? {
+        Credential::ApiKey(api_key) => Some(api_key),
+        Credential::BearerToken(_) => None,
+    }
+}
+
+/// Returns the leading characters of `api_key` used to identify it in logs
+/// and reports without revealing the key itself.
+pub fn api_key_prefix(api_key: &str) -> String {
+    api_key.chars().take(8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_key_prefix_keeps_first_eight_characters() {
+        assert_eq!(
+            api_key_prefix("0a1b2c3d-4e5f-6789-abcd-ef0123456789"),
+            "0a1b2c3d"
+        );
+        assert_eq!(api_key_prefix("short"), "short");
+    }
+
+    #[test]
+    fn get_credential_distinguishes_jwts_from_api_keys() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(get_credential(&headers), None);
+
+        headers.insert(
+            "Authorization",
+            "Bearer 0a1b2c3d-4e5f-6789-abcd-ef0123456789"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            get_credential(&headers),
+            Some(Credential::ApiKey(
+                "0a1b2c3d-4e5f-6789-abcd-ef0123456789".to_string()
+            ))
+        );
+
+        headers.insert("Authorization", "Bearer eyJh.eyJz.c2ln".parse().unwrap());
+        assert_eq!(
+            get_credential(&headers),
+            Some(Credential::BearerToken("eyJh.eyJz.c2ln".to_string()))
+        );
+
+        headers.remove("Authorization");
+        headers.insert("x-api-key", "eyJh.eyJz.c2ln".parse().unwrap());
+        assert_eq!(
+            get_credential(&headers),
+            Some(Credential::ApiKey("eyJh.eyJz.c2ln".to_string()))
+        );
+    }
+
+    #[test]
+    fn is_jwt_requires_three_non_empty_segments() {
+        assert!(is_jwt("a.b.c"));
+        assert!(!is_jwt("a.b"));
+        assert!(!is_jwt("a..c"));
+        assert!(!is_jwt("0a1b2c3d-4e5f-6789-abcd-ef0123456789"));
+    }
 }
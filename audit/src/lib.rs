@@ -0,0 +1,312 @@
+use anyhow::Result;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{
+        HeaderMap,
+        header::{HeaderName, USER_AGENT},
+        request::Parts,
+    },
+};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::PgPool;
+use std::{convert::Infallible, net::SocketAddr};
+use time::OffsetDateTime;
+use tracing::error;
+use uuid::Uuid;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuditAction {
+    AdminApiTokenCreate,
+    AdminApiTokenRevoke,
+    ApiKeyCreate,
+    ApiKeyDisable,
+    ApiKeyProvision,
+    ApiKeyProvisionDenied,
+    ApiKeysDisable,
+    CapturePolicyUpdate,
+    DeviceApprove,
+    DeviceDeny,
+    InferenceProfilesDelete,
+    Login,
+    Logout,
+    ModelAdd,
+    ModelDelete,
+    ModelDisable,
+    ModelEnable,
+    ModelUpdate,
+    ServiceAccountCreate,
+    ServiceAccountKeyCreate,
+    ServiceAccountUpdate,
+    SessionTokenIssue,
+    SessionTokenRevoke,
+    TeamCreate,
+    TeamMemberAdd,
+    TeamMemberRemove,
+    TeamUpdate,
+    UserCreate,
+    UserDeactivate,
+    UserReactivate,
+    UserUpdate,
+}
+
+pub const AUDIT_ACTIONS: [AuditAction; 31] = [
+    AuditAction::AdminApiTokenCreate,
+    AuditAction::AdminApiTokenRevoke,
+    AuditAction::ApiKeyCreate,
+    AuditAction::ApiKeyDisable,
+    AuditAction::ApiKeyProvision,
+    AuditAction::ApiKeyProvisionDenied,
+    AuditAction::ApiKeysDisable,
+    AuditAction::CapturePolicyUpdate,
+    AuditAction::DeviceApprove,
+    AuditAction::DeviceDeny,
+    AuditAction::InferenceProfilesDelete,
+    AuditAction::Login,
+    AuditAction::Logout,
+    AuditAction::ModelAdd,
+    AuditAction::ModelDelete,
+    AuditAction::ModelDisable,
+    AuditAction::ModelEnable,
+    AuditAction::ModelUpdate,
+    AuditAction::ServiceAccountCreate,
+    AuditAction::ServiceAccountKeyCreate,
+    AuditAction::ServiceAccountUpdate,
+    AuditAction::SessionTokenIssue,
+    AuditAction::SessionTokenRevoke,
+    AuditAction::TeamCreate,
+    AuditAction::TeamMemberAdd,
+    AuditAction::TeamMemberRemove,
+    AuditAction::TeamUpdate,
+    AuditAction::UserCreate,
+    AuditAction::UserDeactivate,
+    AuditAction::UserReactivate,
+    AuditAction::UserUpdate,
+];
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::AdminApiTokenCreate => "admin_api_token.create",
+            AuditAction::AdminApiTokenRevoke => "admin_api_token.revoke",
+            AuditAction::ApiKeyCreate => "api_key.create",
+            AuditAction::ApiKeyDisable => "api_key.disable",
+            AuditAction::ApiKeyProvision => "api_key.provision",
+            AuditAction::ApiKeyProvisionDenied => "api_key.provision_denied",
+            AuditAction::ApiKeysDisable => "api_key.disable_all",
+            AuditAction::CapturePolicyUpdate => "capture_policy.update",
+            AuditAction::DeviceApprove => "device.approve",
+            AuditAction::DeviceDeny => "device.deny",
+            AuditAction::InferenceProfilesDelete => "inference_profile.delete_all",
+            AuditAction::Login => "login",
+            AuditAction::Logout => "logout",
+            AuditAction::ModelAdd => "model.add",
+            AuditAction::ModelDelete => "model.delete",
+            AuditAction::ModelDisable => "model.disable",
+            AuditAction::ModelEnable => "model.enable",
+            AuditAction::ModelUpdate => "model.update",
+            AuditAction::ServiceAccountCreate => "service_account.create",
+            AuditAction::ServiceAccountKeyCreate => "service_account.key_create",
+            AuditAction::ServiceAccountUpdate => "service_account.update",
+            AuditAction::SessionTokenIssue => "session_token.issue",
+            AuditAction::SessionTokenRevoke => "session_token.revoke",
+            AuditAction::TeamCreate => "team.create",
+            AuditAction::TeamMemberAdd => "team.member_add",
+            AuditAction::TeamMemberRemove => "team.member_remove",
+            AuditAction::TeamUpdate => "team.update",
+            AuditAction::UserCreate => "user.create",
+            AuditAction::UserDeactivate => "user.deactivate",
+            AuditAction::UserReactivate => "user.reactivate",
+            AuditAction::UserUpdate => "user.update",
+        }
+    }
+}
+
+/// Client details recorded with every audit event. `ip_address` is the peer
+/// address of the connection; `forwarded_for` is the untrusted
+/// `X-Forwarded-For` header, kept for requests arriving through a proxy.
+#[derive(Clone, Debug, Default)]
+pub struct RequestInfo {
+    pub forwarded_for: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl RequestInfo {
+    pub fn from_parts(headers: &HeaderMap, peer: Option<SocketAddr>) -> Self {
+        let header = |name: &HeaderName| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+
+        Self {
+            forwarded_for: header(&X_FORWARDED_FOR),
+            ip_address: peer.map(|peer| peer.ip().to_string()),
+            user_agent: header(&USER_AGENT),
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| *peer);
+        Ok(Self::from_parts(&parts.headers, peer))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditEvent {
+    pub action: AuditAction,
+    pub actor: Option<String>,
+    pub details: Option<Value>,
+    pub target: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            actor: None,
+            details: None,
+            target: None,
+        }
+    }
+
+    pub fn actor(mut self, actor: &str) -> Self {
+        self.actor = Some(actor.to_string());
+        self
+    }
+
+    pub fn details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn target(mut self, target: &str) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+}
+
+pub async fn insert_audit_event(
+    pool: &PgPool,
+    request: &RequestInfo,
+    event: &AuditEvent,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_events (action, actor, details, forwarded_for, ip_address, target, user_agent)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        event.action.as_str(),
+        event.actor,
+        event.details,
+        request.forwarded_for,
+        request.ip_address,
+        event.target,
+        request.user_agent,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Records `event`, logging rather than propagating failures so that the
+/// audited action itself is not rolled back.
+pub async fn record_audit_event(pool: &PgPool, request: &RequestInfo, event: AuditEvent) {
+    if let Err(e) = insert_audit_event(pool, request, &event).await {
+        error!(
+            "Failed to record audit event {}: {:?}",
+            event.action.as_str(),
+            e
+        );
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    pub action: Option<String>,
+    pub actor: Option<String>,
+    pub since: Option<OffsetDateTime>,
+    pub until: Option<OffsetDateTime>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEventRow {
+    pub audit_event_id: Uuid,
+    pub action: String,
+    pub actor: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    pub details: Option<Value>,
+    pub forwarded_for: Option<String>,
+    pub ip_address: Option<String>,
+    pub target: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Returns audit events matching `filter`, newest first.
+pub async fn get_audit_events(
+    pool: &PgPool,
+    filter: &AuditFilter,
+    limit: i64,
+) -> Result<Vec<AuditEventRow>> {
+    let events = sqlx::query_as!(
+        AuditEventRow,
+        r#"
+        SELECT audit_event_id, action, actor, created_at, details, forwarded_for, ip_address, target, user_agent
+        FROM audit_events
+        WHERE ($1::text IS NULL OR action = $1)
+          AND ($2::text IS NULL OR actor = $2)
+          AND ($3::timestamptz IS NULL OR created_at >= $3)
+          AND ($4::timestamptz IS NULL OR created_at < $4)
+        ORDER BY created_at DESC
+        LIMIT $5
+        "#,
+        filter.action,
+        filter.actor.as_deref().map(str::to_lowercase),
+        filter.since,
+        filter.until,
+        limit,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_info_reads_peer_and_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, "curl/8.0".parse().unwrap());
+        headers.insert(X_FORWARDED_FOR, "203.0.113.7".parse().unwrap());
+
+        let request = RequestInfo::from_parts(&headers, Some("10.0.0.1:443".parse().unwrap()));
+
+        assert_eq!(request.ip_address.as_deref(), Some("10.0.0.1"));
+        assert_eq!(request.forwarded_for.as_deref(), Some("203.0.113.7"));
+        assert_eq!(request.user_agent.as_deref(), Some("curl/8.0"));
+    }
+
+    #[test]
+    fn audit_actions_are_unique() {
+        let mut names = AUDIT_ACTIONS.map(|action| action.as_str()).to_vec();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), AUDIT_ACTIONS.len());
+    }
+}
@@ -0,0 +1,496 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Upper bound on the streamed response bytes kept for one capture.
+const MAX_RESPONSE_BYTES: usize = 10 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureSinkKind {
+    Jsonl,
+    #[default]
+    Postgres,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct NewCapture {
+    pub api_key_id: Option<Uuid>,
+    pub model_name: String,
+    pub request_body: String,
+    pub response_text: String,
+    pub stop_reason: Option<String>,
+    pub user_id: Uuid,
+}
+
+#[derive(Serialize)]
+struct JsonlCapture<'a> {
+    capture_id: Uuid,
+    created_at: String,
+    #[serde(flatten)]
+    capture: &'a NewCapture,
+}
+
+/// Appends captures to JSONL files in `dir`, starting a new file once the
+/// current one reaches `max_file_bytes`.
+pub struct JsonlSink {
+    current: tokio::sync::Mutex<Option<(PathBuf, u64)>>,
+    dir: PathBuf,
+    max_file_bytes: u64,
+}
+
+impl JsonlSink {
+    pub fn new(dir: impl Into<PathBuf>, max_file_bytes: u64) -> Self {
+        Self {
+            current: tokio::sync::Mutex::new(None),
+            dir: dir.into(),
+            max_file_bytes,
+        }
+    }
+
+    async fn write(&self, capture: &NewCapture) -> Result<()> {
+        let now = OffsetDateTime::now_utc();
+        let mut line = serde_json::to_vec(&JsonlCapture {
+            capture_id: Uuid::new_v4(),
+            created_at: now.format(&Rfc3339)?,
+            capture,
+        })?;
+        line.push(b'\n');
+
+        let mut current = self.current.lock().await;
+        let rotate = current
+            .as_ref()
+            .is_none_or(|(_, size)| *size + line.len() as u64 > self.max_file_bytes);
+        if rotate {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            let file_name = format!("captures-{}.jsonl", now.unix_timestamp_nanos());
+            *current = Some((self.dir.join(file_name), 0));
+        }
+
+        let (path, size) = current.as_mut().expect("current file is set above");
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&*path)
+            .await?;
+        file.write_all(&line).await?;
+        *size += line.len() as u64;
+
+        Ok(())
+    }
+
+    async fn purge_older_than(&self, cutoff: SystemTime) -> Result<u64> {
+        let current = self.current.lock().await;
+        let current_path = current.as_ref().map(|(path, _)| path.clone());
+
+        let mut removed = 0;
+        let Ok(mut entries) = tokio::fs::read_dir(&self.dir).await else {
+            return Ok(0);
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let is_capture_file = path.extension().is_some_and(|ext| ext == "jsonl");
+            if !is_capture_file || Some(&path) == current_path.as_ref() {
+                continue;
+            }
+            if entry.metadata().await?.modified()? < cutoff {
+                tokio::fs::remove_file(&path).await?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+pub enum CaptureSink {
+    Jsonl(JsonlSink),
+    Postgres(Arc<PgPool>),
+}
+
+impl CaptureSink {
+    pub async fn write(&self, capture: &NewCapture) -> Result<()> {
+        match self {
+            CaptureSink::Jsonl(sink) => sink.write(capture).await,
+            CaptureSink::Postgres(pool) => insert_capture(pool, capture).await,
+        }
+    }
+
+    /// Deletes captures created before `cutoff`; for the JSONL sink, whole
+    /// files last written before `cutoff` are removed. Returns the number of
+    /// rows or files deleted.
+    pub async fn purge_older_than(&self, cutoff: OffsetDateTime) -> Result<u64> {
+        match self {
+            CaptureSink::Jsonl(sink) => sink.purge_older_than(cutoff.into()).await,
+            CaptureSink::Postgres(pool) => delete_captures_before(pool, cutoff).await,
+        }
+    }
+
+    pub fn is_searchable(&self) -> bool {
+        matches!(self, CaptureSink::Postgres(_))
+    }
+
+    /// Purges captures older than `retention` every `period` until aborted.
+    pub async fn continuously_purge_expired(
+        self: Arc<Self>,
+        retention: time::Duration,
+        period: std::time::Duration,
+    ) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match self
+                .purge_older_than(OffsetDateTime::now_utc() - retention)
+                .await
+            {
+                Ok(0) => {}
+                Ok(purged) => info!("Purged {} expired captures", purged),
+                Err(e) => error!("Failed to purge expired captures: {:?}", e),
+            }
+        }
+    }
+}
+
+/// Rebuilds the assistant text and stop reason from Anthropic Messages SSE
+/// output (`content_block_delta` text deltas and the `message_delta`).
+pub fn reassemble_sse_response(sse: &str) -> (String, Option<String>) {
+    let mut text = String::new();
+    let mut stop_reason = None;
+
+    for data in sse.lines().filter_map(|line| line.strip_prefix("data:")) {
+        let Ok(event) = serde_json::from_str::<Value>(data.trim()) else {
+            continue;
+        };
+        match event["type"].as_str() {
+            Some("content_block_delta") if event["delta"]["type"] == "text_delta" => {
+                if let Some(delta) = event["delta"]["text"].as_str() {
+                    text.push_str(delta);
+                }
+            }
+            Some("message_delta") => {
+                if let Some(reason) = event["delta"]["stop_reason"].as_str() {
+                    stop_reason = Some(reason.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    (text, stop_reason)
+}
+
+/// Accumulates a streamed response and writes the capture to `sink` once
+/// dropped, i.e. when the response stream ends.
+pub struct CaptureRecorder {
+    capture: NewCapture,
+    response: Mutex<Vec<u8>>,
+    sink: Arc<CaptureSink>,
+}
+
+impl CaptureRecorder {
+    pub fn new(sink: Arc<CaptureSink>, capture: NewCapture) -> Self {
+        Self {
+            capture,
+            response: Mutex::default(),
+            sink,
+        }
+    }
+
+    pub fn push(&self, chunk: &[u8]) {
+        let mut response = self.response.lock().unwrap();
+        let remaining = MAX_RESPONSE_BYTES.saturating_sub(response.len());
+        response.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+    }
+}
+
+impl Drop for CaptureRecorder {
+    fn drop(&mut self) {
+        let response = std::mem::take(self.response.get_mut().unwrap());
+        let (response_text, stop_reason) =
+            reassemble_sse_response(&String::from_utf8_lossy(&response));
+
+        let mut capture = self.capture.clone();
+        capture.response_text = response_text;
+        capture.stop_reason = stop_reason;
+        let sink = self.sink.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = sink.write(&capture).await {
+                error!(
+                    "Failed to write capture for model {}: {:?}",
+                    capture.model_name, e
+                );
+            }
+        });
+    }
+}
+
+pub async fn insert_capture(pool: &PgPool, capture: &NewCapture) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO captures (api_key_id, model_name, request_body, response_text, stop_reason, user_id)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        capture.api_key_id,
+        capture.model_name,
+        capture.request_body,
+        capture.response_text,
+        capture.stop_reason,
+        capture.user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_captures_before(pool: &PgPool, cutoff: OffsetDateTime) -> Result<u64> {
+    let result = sqlx::query!("DELETE FROM captures WHERE created_at < $1", cutoff)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CaptureFilter {
+    pub model_name: Option<String>,
+    pub text: Option<String>,
+    pub user_email: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CaptureSummary {
+    pub capture_id: Uuid,
+    pub created_at: OffsetDateTime,
+    pub model_name: String,
+    pub preview: String,
+    pub user_email: String,
+}
+
+/// Returns the most recent captures matching `filter`, newest first. `text`
+/// matches the request body or response case-insensitively.
+pub async fn search_captures(
+    pool: &PgPool,
+    filter: &CaptureFilter,
+    limit: i64,
+) -> Result<Vec<CaptureSummary>> {
+    let captures = sqlx::query_as!(
+        CaptureSummary,
+        r#"
+        SELECT
+            c.capture_id,
+            c.created_at,
+            c.model_name,
+            left(c.response_text, 200) AS "preview!",
+            u.user_email
+        FROM captures c
+        JOIN users u ON u.user_id = c.user_id
+        WHERE ($1::text IS NULL OR u.user_email = $1)
+          AND ($2::text IS NULL OR c.model_name = $2)
+          AND (
+            $3::text IS NULL
+            OR c.request_body ILIKE '%' || $3 || '%'
+            OR c.response_text ILIKE '%' || $3 || '%'
+          )
+        ORDER BY c.created_at DESC
+        LIMIT $4
+        "#,
+        filter.user_email.as_deref().map(str::to_lowercase),
+        filter.model_name.as_deref().map(str::to_lowercase),
+        filter.text,
+        limit,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(captures)
+}
+
+#[derive(Clone, Debug)]
+pub struct Capture {
+    pub api_key_prefix: Option<String>,
+    pub capture_id: Uuid,
+    pub created_at: OffsetDateTime,
+    pub model_name: String,
+    pub request_body: String,
+    pub response_text: String,
+    pub stop_reason: Option<String>,
+    pub user_email: String,
+}
+
+pub async fn get_capture(pool: &PgPool, capture_id: Uuid) -> Result<Option<Capture>> {
+    let capture = sqlx::query_as!(
+        Capture,
+        r#"
+        SELECT
+            left(ak.api_key, 8) AS api_key_prefix,
+            c.capture_id,
+            c.created_at,
+            c.model_name,
+            c.request_body,
+            c.response_text,
+            c.stop_reason,
+            u.user_email
+        FROM captures c
+        JOIN users u ON u.user_id = c.user_id
+        LEFT JOIN api_keys ak ON ak.api_key_id = c.api_key_id
+        WHERE c.capture_id = $1
+        "#,
+        capture_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(capture)
+}
+
+/// Enables or disables capture for the user with `user_email`. API keys without
+/// an override follow this setting. Returns the number of users updated.
+pub async fn set_user_capture_enabled(
+    pool: &PgPool,
+    user_email: &str,
+    capture_enabled: bool,
+) -> Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE users
+        SET capture_enabled = $2, updated_at = now()
+        WHERE user_email = $1
+        "#,
+        user_email.to_lowercase(),
+        capture_enabled,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Sets the capture override for the API key; `None` makes the key follow its
+/// user's setting again. Returns the number of keys updated.
+pub async fn set_api_key_capture_enabled(
+    pool: &PgPool,
+    api_key_id: Uuid,
+    capture_enabled: Option<bool>,
+) -> Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE api_keys
+        SET capture_enabled = $2, updated_at = now()
+        WHERE api_key_id = $1
+        "#,
+        api_key_id,
+        capture_enabled,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[derive(Clone, Debug)]
+pub struct CapturePolicy {
+    pub api_key_id: Option<Uuid>,
+    pub api_key_prefix: Option<String>,
+    pub capture_enabled: bool,
+    pub user_email: String,
+}
+
+/// Lists users with capture enabled and API keys that override their user's
+/// setting.
+pub async fn get_capture_policies(pool: &PgPool) -> Result<Vec<CapturePolicy>> {
+    let policies = sqlx::query_as!(
+        CapturePolicy,
+        r#"
+        SELECT NULL::uuid AS api_key_id, NULL::text AS api_key_prefix, capture_enabled AS "capture_enabled!", user_email AS "user_email!"
+        FROM users
+        WHERE capture_enabled
+        UNION ALL
+        SELECT ak.api_key_id, left(ak.api_key, 8), ak.capture_enabled, u.user_email
+        FROM api_keys ak
+        JOIN users u ON u.user_id = ak.user_id
+        WHERE ak.capture_enabled IS NOT NULL
+        ORDER BY 4, 2 NULLS FIRST
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(policies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassemble_sse_response_joins_text_deltas() {
+        let sse = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"hmm\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\", world\"}}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"}}\n\n",
+        );
+
+        assert_eq!(
+            reassemble_sse_response(sse),
+            ("Hello, world".to_string(), Some("end_turn".to_string()))
+        );
+    }
+
+    #[test]
+    fn reassemble_sse_response_ignores_malformed_events() {
+        assert_eq!(
+            reassemble_sse_response("data: not json\n\n: keep-alive\n\n"),
+            (String::new(), None)
+        );
+    }
+
+    #[tokio::test]
+    async fn jsonl_sink_rotates_files() {
+        let dir = std::env::temp_dir().join(format!("captures-test-{}", Uuid::new_v4()));
+        let sink = JsonlSink::new(&dir, 1);
+        let capture = NewCapture {
+            api_key_id: None,
+            model_name: "model-a".to_string(),
+            request_body: "{}".to_string(),
+            response_text: "Hello".to_string(),
+            stop_reason: None,
+            user_id: Uuid::new_v4(),
+        };
+
+        sink.write(&capture).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        sink.write(&capture).await.unwrap();
+
+        let mut files = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 2);
+
+        let removed = sink
+            .purge_older_than(SystemTime::now() + std::time::Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(removed, 1);
+        files = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -32,6 +32,7 @@ in
         entrypointScript
         migrations
         pkgs.dockerTools.caCertificates
+        rustPackage
       ];
     };
   }
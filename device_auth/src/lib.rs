@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::{Rng, RngCore};
+use service_accounts::Scope;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// How long a device code stays valid.
+pub const DEVICE_CODE_TTL: Duration = Duration::minutes(10);
+
+/// Minimum seconds between polls; faster clients are told to `slow_down`.
+pub const POLL_INTERVAL_SECS: i64 = 5;
+
+/// The window `/api/v1/device/code` requests are counted in per client.
+const RATE_LIMIT_WINDOW: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Clients tracked by [`DeviceCodeLimiter`] at most.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Consonants only, so user codes are easy to read out and never spell words
+/// (RFC 8628 section 6.1).
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LEN: usize = 8;
+
+/// Returns a fresh user code such as `BDFG-HJKL`.
+pub fn generate_user_code() -> String {
+    let mut rng = rand::rng();
+    let code = (0..USER_CODE_LEN)
+        .map(|_| USER_CODE_ALPHABET[rng.random_range(0..USER_CODE_ALPHABET.len())] as char)
+        .collect::<String>();
+    format_user_code(&code)
+}
+
+fn format_user_code(code: &str) -> String {
+    let (first, second) = code.split_at(USER_CODE_LEN / 2);
+    format!("{first}-{second}")
+}
+
+/// Normalizes a user code as typed: case, spaces and dashes are ignored.
+/// Returns `None` if it cannot be a valid code.
+pub fn normalize_user_code(input: &str) -> Option<String> {
+    let code = input
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<String>();
+
+    (code.len() == USER_CODE_LEN && code.bytes().all(|b| USER_CODE_ALPHABET.contains(&b)))
+        .then(|| format_user_code(&code))
+}
+
+fn generate_device_code() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn hash_device_code(device_code: &str) -> String {
+    format!("{:x}", Sha256::digest(device_code.as_bytes()))
+}
+
+/// Parses a space-separated OAuth `scope` parameter. An empty or missing
+/// parameter requests an unrestricted key.
+pub fn parse_scopes(scope: Option<&str>) -> Result<Option<Vec<Scope>>, String> {
+    let Some(scope) = scope.map(str::trim).filter(|scope| !scope.is_empty()) else {
+        return Ok(None);
+    };
+
+    scope
+        .split_whitespace()
+        .map(|s| Scope::parse(s).ok_or_else(|| format!("Unknown scope: {s}")))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+pub struct NewDeviceAuthorization {
+    pub device_code: String,
+    pub expires_at: OffsetDateTime,
+    pub user_code: String,
+}
+
+/// Limits how many device authorization requests unauthenticated clients
+/// can start: per client IP address within a minute, and pending in total.
+pub struct DeviceCodeLimiter {
+    max_pending: i64,
+    requests_per_minute: u32,
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl DeviceCodeLimiter {
+    /// A `requests_per_minute` of zero disables the per-client limit.
+    pub fn new(requests_per_minute: u32, max_pending: i64) -> Self {
+        Self {
+            max_pending,
+            requests_per_minute,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The most requests that may be pending at once.
+    pub fn max_pending(&self) -> i64 {
+        self.max_pending
+    }
+
+    /// Counts a request from `ip` and returns whether it is within the limit.
+    /// IPv6 clients are counted per /64, which a single client usually has.
+    /// When too many clients are tracked, new ones are refused until their
+    /// windows pass.
+    pub fn check(&self, ip: IpAddr) -> bool {
+        if self.requests_per_minute == 0 {
+            return true;
+        }
+        let Ok(mut windows) = self.windows.lock() else {
+            return true;
+        };
+
+        let client = match ip {
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & !(u128::MAX >> 64))),
+            ip => ip,
+        };
+        let now = Instant::now();
+        if windows.len() >= MAX_TRACKED_CLIENTS && !windows.contains_key(&client) {
+            windows
+                .retain(|_, (started_at, _)| now.duration_since(*started_at) < RATE_LIMIT_WINDOW);
+            if windows.len() >= MAX_TRACKED_CLIENTS {
+                return false;
+            }
+        }
+
+        let (started_at, count) = windows.entry(client).or_insert((now, 0));
+        if now.duration_since(*started_at) >= RATE_LIMIT_WINDOW {
+            *started_at = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.requests_per_minute
+    }
+}
+
+/// Starts a device authorization request, unless `max_pending` requests are
+/// already pending. Returns `None` in that case.
+pub async fn create_device_authorization(
+    pool: &PgPool,
+    client_name: Option<&str>,
+    scopes: Option<&[Scope]>,
+    max_pending: i64,
+) -> Result<Option<NewDeviceAuthorization>> {
+    let device_code = generate_device_code();
+    let user_code = generate_user_code();
+    let expires_at = OffsetDateTime::now_utc() + DEVICE_CODE_TTL;
+    let scopes = scopes.map(|scopes| {
+        scopes
+            .iter()
+            .map(|scope| scope.as_str().to_string())
+            .collect::<Vec<_>>()
+    });
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO device_authorizations (device_code_hash, user_code, client_name, scopes, expires_at)
+        SELECT $1, $2, $3, $4, $5
+        WHERE (
+            SELECT COUNT(*) FROM device_authorizations
+            WHERE status = 'pending' AND expires_at > now()
+        ) < $6
+        "#,
+        hash_device_code(&device_code),
+        user_code,
+        client_name,
+        scopes.as_deref(),
+        expires_at,
+        max_pending,
+    )
+    .execute(pool)
+    .await?;
+
+    if inserted.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(NewDeviceAuthorization {
+        device_code,
+        expires_at,
+        user_code,
+    }))
+}
+
+/// A request awaiting the user's decision, as shown on the approval page.
+pub struct PendingDeviceAuthorization {
+    pub client_name: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub scopes: Option<Vec<String>>,
+    pub user_code: String,
+}
+
+pub async fn get_pending_device_authorization(
+    pool: &PgPool,
+    user_code: &str,
+) -> Result<Option<PendingDeviceAuthorization>> {
+    let authorization = sqlx::query_as!(
+        PendingDeviceAuthorization,
+        r#"
+        SELECT client_name, created_at, scopes, user_code
+        FROM device_authorizations
+        WHERE user_code = $1 AND status = 'pending' AND expires_at > now()
+        "#,
+        user_code,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(authorization)
+}
+
+/// Records the user's decision on a pending request. Returns false if the
+/// code is unknown, expired or already decided.
+pub async fn decide_device_authorization(
+    pool: &PgPool,
+    user_code: &str,
+    user_id: Uuid,
+    approve: bool,
+) -> Result<bool> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE device_authorizations
+        SET status = CASE WHEN $3 THEN 'approved' ELSE 'denied' END, user_id = $2
+        WHERE user_code = $1 AND status = 'pending' AND expires_at > now()
+        "#,
+        user_code,
+        user_id,
+        approve,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(updated.rows_affected() > 0)
+}
+
+/// The outcome of a CLI polling with its device code, named after the RFC
+/// 8628 token endpoint responses.
+#[derive(Debug, PartialEq)]
+pub enum DeviceTokenResult {
+    AuthorizationPending,
+    SlowDown,
+    AccessDenied,
+    ExpiredToken,
+    /// The device code is unknown or was already redeemed.
+    InvalidGrant,
+    Issued {
+        api_key: Uuid,
+        scopes: Option<Vec<String>>,
+        user_email: String,
+    },
+}
+
+/// Redeems a device code. Once the user has approved, issues a personal API
+/// key carrying the requested scopes; each code yields at most one key.
+pub async fn redeem_device_code(pool: &PgPool, device_code: &str) -> Result<DeviceTokenResult> {
+    let mut tx = pool.begin().await?;
+
+    let Some(authorization) = sqlx::query!(
+        r#"
+        SELECT
+            d.expires_at,
+            d.last_polled_at,
+            d.scopes,
+            d.status,
+            d.user_id,
+            u.user_email AS "user_email?",
+            u.is_disabled AS "is_disabled?"
+        FROM device_authorizations d
+        LEFT JOIN users u ON u.user_id = d.user_id
+        WHERE d.device_code_hash = $1
+        FOR UPDATE OF d
+        "#,
+        hash_device_code(device_code),
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(DeviceTokenResult::InvalidGrant);
+    };
+
+    let now = OffsetDateTime::now_utc();
+
+    sqlx::query!(
+        "UPDATE device_authorizations SET last_polled_at = $2 WHERE device_code_hash = $1",
+        hash_device_code(device_code),
+        now,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let result = match authorization.status.as_str() {
+        "consumed" => DeviceTokenResult::InvalidGrant,
+        _ if authorization.expires_at <= now => DeviceTokenResult::ExpiredToken,
+        "denied" => DeviceTokenResult::AccessDenied,
+        "approved" => match (authorization.user_id, authorization.user_email) {
+            (Some(user_id), Some(user_email)) if authorization.is_disabled != Some(true) => {
+                let api_key = Uuid::new_v4();
+                sqlx::query!(
+                    "INSERT INTO api_keys (api_key, user_id, scopes) VALUES ($1, $2, $3)",
+                    api_key.to_string(),
+                    user_id,
+                    authorization.scopes.as_deref(),
+                )
+                .execute(&mut *tx)
+                .await?;
+
+                sqlx::query!(
+                    "UPDATE device_authorizations SET status = 'consumed' WHERE device_code_hash = $1",
+                    hash_device_code(device_code),
+                )
+                .execute(&mut *tx)
+                .await?;
+
+                DeviceTokenResult::Issued {
+                    api_key,
+                    scopes: authorization.scopes,
+                    user_email,
+                }
+            }
+            _ => DeviceTokenResult::AccessDenied,
+        },
+        _ if authorization
+            .last_polled_at
+            .is_some_and(|last| now - last < Duration::seconds(POLL_INTERVAL_SECS)) =>
+        {
+            DeviceTokenResult::SlowDown
+        }
+        _ => DeviceTokenResult::AuthorizationPending,
+    };
+
+    tx.commit().await?;
+
+    Ok(result)
+}
+
+/// Deletes requests that expired more than a day ago. Returns the number of
+/// rows deleted.
+pub async fn purge_expired_device_authorizations(pool: &PgPool) -> Result<u64> {
+    let deleted = sqlx::query!(
+        "DELETE FROM device_authorizations WHERE expires_at < now() - interval '1 day'"
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(deleted.rows_affected())
+}
+
+/// Purges expired requests every `period` until aborted.
+pub async fn continuously_purge_expired_device_authorizations(
+    pool: Arc<PgPool>,
+    period: std::time::Duration,
+) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        match purge_expired_device_authorizations(&pool).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {} expired device authorizations", purged),
+            Err(e) => error!("Failed to purge expired device authorizations: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_user_codes_normalize_to_themselves() {
+        for _ in 0..100 {
+            let code = generate_user_code();
+            assert_eq!(code.len(), USER_CODE_LEN + 1);
+            assert_eq!(normalize_user_code(&code), Some(code));
+        }
+    }
+
+    #[test]
+    fn normalize_user_code_ignores_case_and_separators() {
+        assert_eq!(
+            normalize_user_code(" bdfg hjkl "),
+            Some("BDFG-HJKL".to_string())
+        );
+        assert_eq!(
+            normalize_user_code("BDFGHJKL"),
+            Some("BDFG-HJKL".to_string())
+        );
+        assert_eq!(normalize_user_code("BDFG-HJK"), None);
+        assert_eq!(normalize_user_code("ABCD-EFGH"), None);
+    }
+
+    #[test]
+    fn parse_scopes_accepts_space_separated_scopes() {
+        assert_eq!(parse_scopes(None), Ok(None));
+        assert_eq!(parse_scopes(Some("  ")), Ok(None));
+        assert_eq!(
+            parse_scopes(Some("messages count_tokens")),
+            Ok(Some(vec![Scope::Messages, Scope::CountTokens]))
+        );
+        assert!(parse_scopes(Some("messages admin")).is_err());
+    }
+
+    #[test]
+    fn device_codes_are_hashed_for_storage() {
+        let device_code = generate_device_code();
+        let hash = hash_device_code(&device_code);
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, device_code);
+        assert_eq!(hash, hash_device_code(&device_code));
+    }
+
+    #[test]
+    fn device_code_requests_are_limited_per_client() {
+        let limiter = DeviceCodeLimiter::new(2, 100);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(limiter.check(client));
+        assert!(limiter.check(client));
+        assert!(!limiter.check(client));
+        assert!(limiter.check("192.0.2.2".parse().unwrap()));
+
+        let ipv6_client: IpAddr = "2001:db8::1".parse().unwrap();
+        assert!(limiter.check(ipv6_client));
+        assert!(limiter.check(ipv6_client));
+        assert!(!limiter.check("2001:db8::2".parse().unwrap()));
+
+        let unlimited = DeviceCodeLimiter::new(0, 100);
+        assert!((0..10).all(|_| unlimited.check(client)));
+    }
+}
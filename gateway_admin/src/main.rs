@@ -0,0 +1,640 @@
+mod config;
+
+use anyhow::{Context, bail};
+use apikeys::{
+    api_key_prefix, create_api_key, create_team_api_key, disable_all_api_keys, disable_api_key,
+    list_api_keys,
+};
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
+use clap::{Parser, Subcommand};
+use inference_profiles::{
+    BedrockControlPlane, ReconcileOptions, delete_api_key_inference_profiles,
+    delete_user_inference_profiles, list_all_inference_profiles, reconcile_inference_profiles,
+    retag_inference_profiles,
+};
+use models::{create_model, disable_model, enable_model, get_models, set_model_protected};
+use serde_json::json;
+use sqlx::{PgPool, postgres::PgPoolOptions};
+use std::time::Duration;
+use teams::is_team_member;
+use time::{
+    Date, OffsetDateTime, format_description::BorrowedFormatItem, macros::format_description,
+};
+use usage::{GROUP_BYS, GroupBy, UsageFilter, format_usd, get_usage_summary};
+use users::{UserSummary, deactivate_user, find_user, insert_user, list_users, reactivate_user};
+use uuid::Uuid;
+
+use crate::config::{CliConfig, load_config};
+
+const DATE_FORMAT: &[BorrowedFormatItem<'static>] = format_description!("[year]-[month]-[day]");
+
+const DEFAULT_RANGE_DAYS: i64 = 30;
+
+/// Manages the gateway's users, API keys, models and inference profiles
+/// directly in its database.
+#[derive(Parser)]
+#[command(name = "gateway-admin")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create, list, disable and enable users
+    #[command(subcommand)]
+    Users(UsersCommand),
+    /// Create, list and disable API keys
+    #[command(subcommand)]
+    Keys(KeysCommand),
+    /// Add, list, enable, disable and protect models
+    #[command(subcommand)]
+    Models(ModelsCommand),
+    /// List inference profiles
+    #[command(subcommand)]
+    Profiles(ProfilesCommand),
+    /// Bring the inference_profiles table in line with AWS
+    Reconcile {
+        /// Only report drift; change nothing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Apply the configured tags to existing inference profiles
+    Retag {
+        /// Only report outdated profiles; change nothing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print usage aggregated over whole UTC days
+    Usage {
+        /// First day, YYYY-MM-DD; defaults to 29 days before --to
+        #[arg(long)]
+        from: Option<String>,
+        /// Last day, YYYY-MM-DD; defaults to today
+        #[arg(long)]
+        to: Option<String>,
+        /// Only this user's usage
+        #[arg(long)]
+        user: Option<String>,
+        /// Only this model's usage
+        #[arg(long)]
+        model: Option<String>,
+        /// One of day, user, team, cost_center, model or api_key
+        #[arg(long, default_value = "day", value_parser = parse_group_by)]
+        group_by: GroupBy,
+    },
+}
+
+#[derive(Subcommand)]
+enum UsersCommand {
+    /// List users ordered by email
+    List {
+        /// Only users whose email contains this text
+        #[arg(long)]
+        search: Option<String>,
+        #[arg(long, default_value_t = 100)]
+        limit: i64,
+    },
+    Create {
+        email: String,
+        #[arg(long)]
+        external_id: Option<String>,
+        #[arg(long)]
+        cost_center: Option<String>,
+    },
+    /// Disable the user and their API keys and delete their inference
+    /// profiles
+    Disable { email: String },
+    /// Let a disabled user sign in again. Their old keys stay disabled.
+    Enable { email: String },
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// List a user's API keys
+    List { email: String },
+    /// Create an API key and print it
+    Create {
+        email: String,
+        /// Bill the key to this team; the user must be a member
+        #[arg(long)]
+        team_id: Option<Uuid>,
+    },
+    /// Disable one API key and delete its own inference profiles
+    Disable { api_key_id: Uuid },
+    /// Disable all of a user's personal API keys and delete their inference
+    /// profiles. Team keys the user created stay active
+    DisableAll { email: String },
+}
+
+#[derive(Subcommand)]
+enum ModelsCommand {
+    List,
+    /// Add a model. Its inference profiles are created on first use.
+    Add {
+        model_name: String,
+    },
+    Enable {
+        model_name: String,
+    },
+    Disable {
+        model_name: String,
+    },
+    /// Prevent the model from being enabled, disabled or deleted
+    Protect {
+        model_name: String,
+    },
+    Unprotect {
+        model_name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfilesCommand {
+    /// List inference profiles ordered by user and model
+    List {
+        /// Only profiles of users whose email contains this text
+        #[arg(long)]
+        user: Option<String>,
+        /// Only profiles of this model
+        #[arg(long)]
+        model: Option<String>,
+        #[arg(long, default_value_t = 500)]
+        limit: i64,
+    },
+}
+
+fn parse_group_by(value: &str) -> Result<GroupBy, String> {
+    GROUP_BYS
+        .into_iter()
+        .find(|group_by| group_by.as_str() == value)
+        .ok_or_else(|| format!("Invalid group by \"{value}\""))
+}
+
+fn parse_date(name: &str, value: &str) -> anyhow::Result<Date> {
+    Date::parse(value, DATE_FORMAT)
+        .with_context(|| format!("Invalid {name} date \"{value}\", expected YYYY-MM-DD"))
+}
+
+/// Resolves optional inclusive bounds into a `(from, to)` pair, defaulting to
+/// the last 30 days ending `today`.
+fn date_range(from: Option<&str>, to: Option<&str>, today: Date) -> anyhow::Result<(Date, Date)> {
+    let to = match to {
+        Some(to) => parse_date("to", to)?,
+        None => today,
+    };
+    let from = match from {
+        Some(from) => parse_date("from", from)?,
+        None => to - time::Duration::days(DEFAULT_RANGE_DAYS - 1),
+    };
+
+    if from > to {
+        bail!("from must not be after to");
+    }
+
+    Ok((from, to))
+}
+
+fn status(is_disabled: bool) -> &'static str {
+    if is_disabled { "disabled" } else { "active" }
+}
+
+/// What the CLI needs to run a command, and who to record in the audit log.
+struct Admin {
+    actor: String,
+    config: CliConfig,
+    pool: PgPool,
+}
+
+impl Admin {
+    async fn audit(&self, event: AuditEvent) {
+        record_audit_event(
+            &self.pool,
+            &RequestInfo::default(),
+            event.actor(&self.actor),
+        )
+        .await;
+    }
+
+    async fn control_plane(&self) -> BedrockControlPlane {
+        let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        BedrockControlPlane::from_config(&aws_config, &self.config.aws_region)
+    }
+
+    async fn user(&self, email: &str) -> anyhow::Result<UserSummary> {
+        find_user(&self.pool, Some(email), None)
+            .await?
+            .with_context(|| format!("User {email} not found"))
+    }
+
+    async fn users(&self, command: UsersCommand) -> anyhow::Result<()> {
+        match command {
+            UsersCommand::List { search, limit } => {
+                println!("email\tstatus\tactive keys\tcost center\tuser id");
+                for user in list_users(&self.pool, search.as_deref(), 0, limit).await? {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        user.user_email,
+                        status(user.is_disabled),
+                        user.api_keys_count_active,
+                        user.cost_center.as_deref().unwrap_or("-"),
+                        user.user_id
+                    );
+                }
+            }
+            UsersCommand::Create {
+                email,
+                external_id,
+                cost_center,
+            } => {
+                let user_id = insert_user(
+                    &self.pool,
+                    &email,
+                    external_id.as_deref(),
+                    cost_center.as_deref(),
+                )
+                .await?;
+                self.audit(AuditEvent::new(AuditAction::UserCreate).target(&email))
+                    .await;
+                println!("{user_id}");
+            }
+            UsersCommand::Disable { email } => {
+                let user = self.user(&email).await?;
+                let disabled_api_keys_count = deactivate_user(&self.pool, user.user_id)
+                    .await?
+                    .with_context(|| format!("User {email} not found"))?;
+                let report = delete_user_inference_profiles(
+                    &self.control_plane().await,
+                    &self.pool,
+                    user.user_id,
+                    self.config.inference_profile_cleanup_dry_run,
+                )
+                .await?;
+                self.audit(
+                    AuditEvent::new(AuditAction::UserDeactivate)
+                        .target(&user.user_email)
+                        .details(json!({
+                            "disabled_api_keys_count": disabled_api_keys_count,
+                            "deleted_inference_profiles_count": report.deleted,
+                        })),
+                )
+                .await;
+                println!(
+                    "Disabled {} and {disabled_api_keys_count} API key(s); deleted {} of {} inference profile(s)",
+                    user.user_email, report.deleted, report.matched
+                );
+            }
+            UsersCommand::Enable { email } => {
+                let user = self.user(&email).await?;
+                reactivate_user(&self.pool, user.user_id).await?;
+                self.audit(AuditEvent::new(AuditAction::UserReactivate).target(&user.user_email))
+                    .await;
+                println!("Enabled {}", user.user_email);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn keys(&self, command: KeysCommand) -> anyhow::Result<()> {
+        match command {
+            KeysCommand::List { email } => {
+                let user = self.user(&email).await?;
+                println!("prefix\tstatus\tteam\tcreated\tapi key id");
+                for api_key in list_api_keys(&self.pool, user.user_id).await? {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        api_key.api_key_prefix,
+                        status(api_key.is_disabled),
+                        api_key.team_name.as_deref().unwrap_or("-"),
+                        api_key.created_at.date(),
+                        api_key.api_key_id
+                    );
+                }
+            }
+            KeysCommand::Create { email, team_id } => {
+                let user = self.user(&email).await?;
+                if user.is_disabled {
+                    bail!("User {} is disabled", user.user_email);
+                }
+                let api_key = match team_id {
+                    Some(team_id) => {
+                        if !is_team_member(&self.pool, team_id, &user.user_email).await? {
+                            bail!("User {} is not a member of this team", user.user_email);
+                        }
+                        create_team_api_key(&self.pool, &user.user_email, team_id)
+                            .await?
+                            .api_key
+                    }
+                    None => create_api_key(&self.pool, &user.user_email).await?.api_key,
+                };
+                self.audit(
+                    AuditEvent::new(AuditAction::ApiKeyCreate)
+                        .target(&api_key_prefix(&api_key.to_string()))
+                        .details(json!({ "team_id": team_id, "user_email": user.user_email })),
+                )
+                .await;
+                println!("{api_key}");
+            }
+            KeysCommand::Disable { api_key_id } => {
+                let user_id = disable_api_key(&self.pool, api_key_id)
+                    .await?
+                    .context("API key not found")?;
+                let report = delete_api_key_inference_profiles(
+                    &self.control_plane().await,
+                    &self.pool,
+                    api_key_id,
+                    self.config.inference_profile_cleanup_dry_run,
+                )
+                .await?;
+                self.audit(
+                    AuditEvent::new(AuditAction::ApiKeyDisable)
+                        .target(&api_key_id.to_string())
+                        .details(json!({
+                            "deleted_inference_profiles_count": report.deleted,
+                            "user_id": user_id,
+                        })),
+                )
+                .await;
+                println!("Disabled API key {api_key_id}");
+            }
+            KeysCommand::DisableAll { email } => {
+                let user = self.user(&email).await?;
+                let disabled_api_keys_count =
+                    disable_all_api_keys(&self.pool, &user.user_email).await?;
+                let report = delete_user_inference_profiles(
+                    &self.control_plane().await,
+                    &self.pool,
+                    user.user_id,
+                    self.config.inference_profile_cleanup_dry_run,
+                )
+                .await?;
+                self.audit(
+                    AuditEvent::new(AuditAction::ApiKeysDisable)
+                        .target(&user.user_email)
+                        .details(json!({
+                            "disabled_api_keys_count": disabled_api_keys_count,
+                            "deleted_inference_profiles_count": report.deleted,
+                        })),
+                )
+                .await;
+                println!(
+                    "Disabled {disabled_api_keys_count} API key(s); deleted {} of {} inference profile(s)",
+                    report.deleted, report.matched
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn models(&self, command: ModelsCommand) -> anyhow::Result<()> {
+        let (model_name, action) = match command {
+            ModelsCommand::List => {
+                println!("model\tstatus\tprotected");
+                for model in get_models(&self.pool).await? {
+                    println!(
+                        "{}\t{}\t{}",
+                        model.model_name,
+                        status(model.is_disabled),
+                        model.protected
+                    );
+                }
+                return Ok(());
+            }
+            ModelsCommand::Add { model_name } => {
+                create_model(&self.pool, &model_name).await?;
+                (model_name, AuditAction::ModelAdd)
+            }
+            ModelsCommand::Enable { model_name } => {
+                if !enable_model(&self.pool, &model_name).await? {
+                    bail!("Model {model_name} not found or protected");
+                }
+                (model_name, AuditAction::ModelEnable)
+            }
+            ModelsCommand::Disable { model_name } => {
+                if !disable_model(&self.pool, &model_name).await? {
+                    bail!("Model {model_name} not found or protected");
+                }
+                (model_name, AuditAction::ModelDisable)
+            }
+            ModelsCommand::Protect { model_name } => {
+                return self.set_model_protected(&model_name, true).await;
+            }
+            ModelsCommand::Unprotect { model_name } => {
+                return self.set_model_protected(&model_name, false).await;
+            }
+        };
+
+        self.audit(AuditEvent::new(action).target(&model_name))
+            .await;
+        println!("{model_name}: {}", action.as_str());
+
+        Ok(())
+    }
+
+    async fn set_model_protected(&self, model_name: &str, protected: bool) -> anyhow::Result<()> {
+        if !set_model_protected(&self.pool, model_name, protected).await? {
+            bail!("Model {model_name} not found");
+        }
+        self.audit(
+            AuditEvent::new(AuditAction::ModelUpdate)
+                .target(model_name)
+                .details(json!({ "protected": protected })),
+        )
+        .await;
+        println!("{model_name}: protected = {protected}");
+
+        Ok(())
+    }
+
+    async fn profiles(&self, command: ProfilesCommand) -> anyhow::Result<()> {
+        match command {
+            ProfilesCommand::List { user, model, limit } => {
+                println!("user\tmodel\towner\tteam\tarn");
+                for profile in list_all_inference_profiles(
+                    &self.pool,
+                    user.as_deref(),
+                    model.as_deref(),
+                    limit,
+                )
+                .await?
+                {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        profile.user_email,
+                        profile.model_name,
+                        profile.owner_type,
+                        profile.team_name.as_deref().unwrap_or("-"),
+                        profile.inference_profile_arn
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reconcile(&self, dry_run: bool) -> anyhow::Result<()> {
+        let report = reconcile_inference_profiles(
+            &self.pool,
+            &self.control_plane().await,
+            &ReconcileOptions {
+                delete_orphans: self.config.inference_profile_reconcile_delete_orphans,
+                dry_run,
+                max_removal_fraction: self.config.inference_profile_reconcile_max_removal_fraction,
+                orphan_grace_period: time::Duration::seconds(
+                    self.config.inference_profile_orphan_grace_period_secs as i64,
+                ),
+                settings: self.config.inference_profile_settings()?,
+            },
+        )
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("A gateway instance is already reconciling"))?;
+
+        println!("missing\t{}", report.missing);
+        println!("rows removed\t{}", report.rows_removed);
+        println!("recreated\t{}", report.recreated);
+        println!("orphaned\t{}", report.orphaned);
+        println!("orphans deleted\t{}", report.orphans_deleted);
+        println!("failures\t{}", report.failures);
+
+        Ok(())
+    }
+
+    async fn retag(&self, dry_run: bool) -> anyhow::Result<()> {
+        let report = retag_inference_profiles(
+            &self.pool,
+            &self.control_plane().await,
+            &self.config.inference_profile_settings()?,
+            dry_run,
+        )
+        .await?;
+
+        println!("checked\t{}", report.checked);
+        println!("outdated\t{}", report.outdated);
+        println!("retagged\t{}", report.retagged);
+        println!("failures\t{}", report.failures);
+
+        Ok(())
+    }
+
+    async fn usage(
+        &self,
+        from: Option<&str>,
+        to: Option<&str>,
+        user: Option<&str>,
+        model: Option<&str>,
+        group_by: GroupBy,
+    ) -> anyhow::Result<()> {
+        let (from, to) = date_range(from, to, OffsetDateTime::now_utc().date())?;
+        let mut filter = UsageFilter::new(
+            from.midnight().assume_utc(),
+            to.midnight().assume_utc() + time::Duration::days(1),
+        );
+        if let Some(user) = user {
+            filter = filter.user_email(user);
+        }
+        if let Some(model) = model {
+            filter = filter.model_name(model);
+        }
+
+        println!(
+            "{}\trequests\tinput tokens\toutput tokens\tcache read tokens\tcache write tokens\tcost",
+            group_by.as_str()
+        );
+        for summary in get_usage_summary(&self.pool, &filter, group_by).await? {
+            println!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                summary.group,
+                summary.requests,
+                summary.input_tokens,
+                summary.output_tokens,
+                summary.cache_read_input_tokens,
+                summary.cache_write_input_tokens,
+                format_usd(summary.cost_usd_micros)
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let config = load_config()?;
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .acquire_timeout(Duration::from_secs(3))
+        .connect(&config.database_url)
+        .await
+        .context("Failed to connect to the database")?;
+    let admin = Admin {
+        actor: format!(
+            "gateway-admin:{}",
+            std::env::var("USER").unwrap_or_else(|_| "unknown".to_string())
+        ),
+        config,
+        pool,
+    };
+
+    match cli.command {
+        Command::Users(command) => admin.users(command).await,
+        Command::Keys(command) => admin.keys(command).await,
+        Command::Models(command) => admin.models(command).await,
+        Command::Profiles(command) => admin.profiles(command).await,
+        Command::Reconcile { dry_run } => admin.reconcile(dry_run).await,
+        Command::Retag { dry_run } => admin.retag(dry_run).await,
+        Command::Usage {
+            from,
+            to,
+            user,
+            model,
+            group_by,
+        } => {
+            admin
+                .usage(
+                    from.as_deref(),
+                    to.as_deref(),
+                    user.as_deref(),
+                    model.as_deref(),
+                    group_by,
+                )
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use time::macros::date;
+
+    #[test]
+    fn cli_is_well_formed() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn group_by_accepts_report_names() {
+        assert_eq!(parse_group_by("cost_center"), Ok(GroupBy::CostCenter));
+        assert!(parse_group_by("week").is_err());
+    }
+
+    #[test]
+    fn date_range_defaults_to_last_30_days() {
+        let today = date!(2026 - 03 - 31);
+        assert_eq!(
+            date_range(None, None, today).unwrap(),
+            (date!(2026 - 03 - 02), today)
+        );
+        assert!(date_range(Some("2026-04-01"), None, today).is_err());
+    }
+}
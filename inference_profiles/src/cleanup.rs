@@ -0,0 +1,222 @@
+use anyhow::Result;
+use sqlx::PgPool;
+use tracing::{error, info, instrument};
+use uuid::Uuid;
+
+use crate::InferenceProfileControlPlane;
+
+/// Counts of the inference profiles a cleanup found and removed.
+#[derive(Debug, Default, PartialEq)]
+pub struct CleanupReport {
+    /// Profiles deleted in AWS whose rows were removed.
+    pub deleted: usize,
+    /// Profiles that failed to delete. Their rows are kept so a later
+    /// cleanup or the reconciler can retry.
+    pub failures: usize,
+    /// Profiles found. Nothing is deleted when running dry.
+    pub matched: usize,
+}
+
+impl CleanupReport {
+    /// Whether any matched profile, and so its row, remains.
+    pub fn is_incomplete(&self) -> bool {
+        self.deleted < self.matched
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ProfileToDelete {
+    pub inference_profile_arn: String,
+    pub inference_profile_id: Uuid,
+}
+
+/// Deletes the profiles in AWS and returns the ids of those deleted.
+pub(crate) async fn delete_in_aws(
+    control_plane: &impl InferenceProfileControlPlane,
+    profiles: &[ProfileToDelete],
+    dry_run: bool,
+    report: &mut CleanupReport,
+) -> Vec<Uuid> {
+    report.matched += profiles.len();
+
+    let mut deleted = Vec::new();
+    for profile in profiles {
+        if dry_run {
+            info!(
+                "Would delete inference profile {}",
+                profile.inference_profile_arn
+            );
+            continue;
+        }
+
+        match control_plane.delete(&profile.inference_profile_arn).await {
+            Ok(()) => {
+                info!(
+                    "Deleted inference profile {}",
+                    profile.inference_profile_arn
+                );
+                deleted.push(profile.inference_profile_id);
+            }
+            Err(e) => {
+                error!(
+                    "Failed to delete inference profile {}: {:?}",
+                    profile.inference_profile_arn, e
+                );
+                report.failures += 1;
+            }
+        }
+    }
+
+    deleted
+}
+
+async fn delete_profiles(
+    control_plane: &impl InferenceProfileControlPlane,
+    pool: &PgPool,
+    profiles: &[ProfileToDelete],
+    dry_run: bool,
+) -> Result<CleanupReport> {
+    let mut report = CleanupReport::default();
+    let deleted = delete_in_aws(control_plane, profiles, dry_run, &mut report).await;
+
+    if !deleted.is_empty() {
+        sqlx::query!(
+            "DELETE FROM inference_profiles WHERE inference_profile_id = ANY($1)",
+            &deleted,
+        )
+        .execute(pool)
+        .await?;
+        report.deleted = deleted.len();
+    }
+
+    Ok(report)
+}
+
+/// Deletes the user's personal and key inference profiles in Bedrock and
+/// removes their rows. Team profiles, and the profiles of team keys the user
+/// created, are left alone, as the rest of the team still uses them.
+#[instrument(skip_all, fields(user_id = %user_id))]
+pub async fn delete_user_inference_profiles(
+    control_plane: &impl InferenceProfileControlPlane,
+    pool: &PgPool,
+    user_id: Uuid,
+    dry_run: bool,
+) -> Result<CleanupReport> {
+    let profiles = sqlx::query_as!(
+        ProfileToDelete,
+        r#"
+        SELECT inference_profile_arn, inference_profile_id
+        FROM inference_profiles
+        WHERE user_id = $1
+          AND owner_type <> 'team'
+          AND NOT EXISTS (
+              SELECT 1 FROM api_keys k
+              WHERE k.api_key_id = inference_profiles.api_key_id AND k.team_id IS NOT NULL
+          )
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    delete_profiles(control_plane, pool, &profiles, dry_run).await
+}
+
+/// Deletes the key's own inference profiles, used under the `key`
+/// granularity, in Bedrock and removes their rows.
+#[instrument(skip_all, fields(api_key_id = %api_key_id))]
+pub async fn delete_api_key_inference_profiles(
+    control_plane: &impl InferenceProfileControlPlane,
+    pool: &PgPool,
+    api_key_id: Uuid,
+    dry_run: bool,
+) -> Result<CleanupReport> {
+    let profiles = sqlx::query_as!(
+        ProfileToDelete,
+        r#"
+        SELECT inference_profile_arn, inference_profile_id
+        FROM inference_profiles
+        WHERE api_key_id = $1 AND owner_type = 'key'
+        "#,
+        api_key_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    delete_profiles(control_plane, pool, &profiles, dry_run).await
+}
+
+/// Deletes every inference profile of the model, personal and team, in
+/// Bedrock and removes their rows, so the model itself can be deleted.
+/// Protected models cannot be deleted and are left alone.
+#[instrument(skip_all, fields(model = %model_name))]
+pub async fn delete_model_inference_profiles(
+    control_plane: &impl InferenceProfileControlPlane,
+    pool: &PgPool,
+    model_name: &str,
+    dry_run: bool,
+) -> Result<CleanupReport> {
+    let profiles = sqlx::query_as!(
+        ProfileToDelete,
+        r#"
+        SELECT ip.inference_profile_arn, ip.inference_profile_id
+        FROM inference_profiles ip
+        JOIN models m ON m.model_id = ip.model_id
+        WHERE m.model_name = $1 AND m.protected = FALSE
+        "#,
+        model_name.to_lowercase(),
+    )
+    .fetch_all(pool)
+    .await?;
+
+    delete_profiles(control_plane, pool, &profiles, dry_run).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockControlPlane;
+
+    const ARN_A: &str = "arn:aws:bedrock:us-east-1:123456789012:application-inference-profile/a1";
+    const ARN_B: &str = "arn:aws:bedrock:us-east-1:123456789012:application-inference-profile/b2";
+
+    fn profiles() -> Vec<ProfileToDelete> {
+        [ARN_A, ARN_B]
+            .into_iter()
+            .map(|arn| ProfileToDelete {
+                inference_profile_arn: arn.to_string(),
+                inference_profile_id: Uuid::new_v4(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn failed_deletions_keep_their_rows() {
+        let control_plane = MockControlPlane {
+            failing_arns: vec![ARN_B.to_string()],
+            ..Default::default()
+        };
+        let profiles = profiles();
+
+        let mut report = CleanupReport::default();
+        let deleted = delete_in_aws(&control_plane, &profiles, false, &mut report).await;
+
+        assert_eq!(deleted, vec![profiles[0].inference_profile_id]);
+        assert_eq!(report.matched, 2);
+        assert_eq!(report.failures, 1);
+        assert_eq!(*control_plane.deleted.lock().unwrap(), vec![ARN_A]);
+    }
+
+    #[tokio::test]
+    async fn dry_run_deletes_nothing() {
+        let control_plane = MockControlPlane::default();
+
+        let mut report = CleanupReport::default();
+        let deleted = delete_in_aws(&control_plane, &profiles(), true, &mut report).await;
+
+        assert!(deleted.is_empty());
+        assert_eq!(report.matched, 2);
+        assert!(report.is_incomplete());
+        assert!(control_plane.deleted.lock().unwrap().is_empty());
+    }
+}
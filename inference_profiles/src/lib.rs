@@ -1,98 +1,1091 @@
+mod cleanup;
+mod provision;
+mod reconcile;
+mod retag;
+mod tags;
+#[cfg(test)]
+mod testing;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+
 use anyhow::Result;
-use aws_sdk_bedrock::types::{InferenceProfileModelSource, Tag};
-use sqlx::PgPool;
-use tracing::{error, info};
+use aws_sdk_bedrock::types::{InferenceProfileModelSource, InferenceProfileType, Tag};
+use futures::{StreamExt, stream};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::{PgPool, Postgres, Transaction};
+use time::OffsetDateTime;
+use tracing::{error, info, instrument};
 use uuid::Uuid;
 
-pub async fn create_inference_profile(
+pub use cleanup::{
+    CleanupReport, delete_api_key_inference_profiles, delete_model_inference_profiles,
+    delete_user_inference_profiles,
+};
+pub use provision::{InferenceProfileProvisioner, ProvisionJob};
+pub use reconcile::{
+    ReconcileOptions, ReconcileReport, continuously_reconcile_inference_profiles,
+    reconcile_inference_profiles,
+};
+pub use retag::{RetagReport, retag_inference_profiles};
+pub use tags::{TagContext, TagTemplates, sanitize_tag_value};
+
+/// Tag identifying the user a gateway inference profile was created for.
+/// Profiles without it were not created by the gateway.
+pub const GATEWAY_USER_ID_TAG: &str = "GatewayUserId";
+/// Tag identifying the gateway deployment that created a profile, so that
+/// gateways sharing an AWS account only reconcile their own profiles.
+pub const GATEWAY_DEPLOYMENT_ID_TAG: &str = "GatewayDeploymentId";
+pub const GATEWAY_API_KEY_ID_TAG: &str = "GatewayApiKeyId";
+pub const GATEWAY_MODEL_ID_TAG: &str = "GatewayModelId";
+pub const GATEWAY_TEAM_ID_TAG: &str = "GatewayTeamId";
+pub const GATEWAY_TEAM_TAG: &str = "GatewayTeam";
+
+/// How the gateway creates inference profiles.
+#[derive(Clone, Debug, Default)]
+pub struct InferenceProfileSettings {
+    pub aws_account_id: String,
+    pub aws_region: String,
+    /// Tagged on every profile. Empty leaves the tag off, and the reconciler
+    /// then never treats a profile as orphaned.
+    pub deployment_id: String,
+    pub granularity: InferenceProfileGranularity,
+    pub inference_profile_prefixes: Vec<String>,
+    /// Cost allocation tags added to the gateway's own.
+    pub tags: TagTemplates,
+}
+
+/// What an inference request does when its inference profile cannot be
+/// created.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InferenceProfileFailurePolicy {
+    /// Reject the request, so no spend goes unattributed.
+    Fail,
+    /// Invoke the model directly, without per-user cost attribution.
+    #[default]
+    Fallback,
+}
+
+/// The unit an inference profile attributes spend to, stored as
+/// `inference_profiles.owner_type`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProfileOwnerType {
+    Key,
+    Team,
+    User,
+}
+
+impl ProfileOwnerType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProfileOwnerType::Key => "key",
+            ProfileOwnerType::Team => "team",
+            ProfileOwnerType::User => "user",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [
+            ProfileOwnerType::Key,
+            ProfileOwnerType::Team,
+            ProfileOwnerType::User,
+        ]
+        .into_iter()
+        .find(|owner_type| owner_type.as_str() == value)
+    }
+}
+
+/// Which inference profile a request with a gateway API key, or a session
+/// token exchanged for one, uses. Requests authenticated with an identity
+/// provider token have no key and always use the user's profile.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InferenceProfileGranularity {
+    /// One profile per key and model, isolating each key's spend.
+    Key,
+    /// One profile per team and model for team keys, shared by the whole
+    /// team; one per user and model otherwise.
+    #[default]
+    Team,
+    /// One profile per user and model, team keys included.
+    User,
+}
+
+impl InferenceProfileGranularity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InferenceProfileGranularity::Key => "key",
+            InferenceProfileGranularity::Team => "team",
+            InferenceProfileGranularity::User => "user",
+        }
+    }
+
+    /// Returns the owner of the profile used with a key, given its id, its
+    /// team and its user. The lookups in the server's validation queries
+    /// make the same choice.
+    pub fn owner<'a>(
+        &self,
+        api_key_id: Option<Uuid>,
+        team_id: Option<Uuid>,
+        user_id: Uuid,
+    ) -> ProfileOwner<'a> {
+        match (self, api_key_id, team_id) {
+            (InferenceProfileGranularity::Key, Some(api_key_id), _) => ProfileOwner::Key {
+                api_key_id,
+                user_id,
+            },
+            (
+                InferenceProfileGranularity::Key | InferenceProfileGranularity::Team,
+                _,
+                Some(team_id),
+            ) => ProfileOwner::Team { team_id, user_id },
+            _ => ProfileOwner::User(user_id),
+        }
+    }
+}
+
+/// An application inference profile as listed by Bedrock.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AwsInferenceProfile {
+    pub arn: String,
+    pub created_at: Option<OffsetDateTime>,
+    pub name: String,
+}
+
+/// How many tag lookups run at once. Bedrock lists tags one profile at a
+/// time, so lookups are limited to the profiles that need them and spread
+/// over a few concurrent requests.
+const TAG_LOOKUP_CONCURRENCY: usize = 8;
+
+/// Looks up the tags of each profile in `arns`, a few at a time.
+pub(crate) async fn list_tags(
+    control_plane: &impl InferenceProfileControlPlane,
+    arns: Vec<String>,
+) -> Vec<(String, Result<HashMap<String, String>>)> {
+    stream::iter(arns)
+        .map(|arn| async move {
+            let tags = control_plane.tags(&arn).await;
+            (arn, tags)
+        })
+        .buffer_unordered(TAG_LOOKUP_CONCURRENCY)
+        .collect()
+        .await
+}
+
+/// An application inference profile to create in Bedrock.
+pub struct NewAwsInferenceProfile {
+    pub copy_from: String,
+    pub name: String,
+    pub tags: Vec<(String, String)>,
+}
+
+/// The Bedrock control-plane operations the gateway performs on inference
+/// profiles, so they can be replaced in tests.
+pub trait InferenceProfileControlPlane: Send + Sync {
+    /// Creates a profile and returns its ARN.
+    fn create(
+        &self,
+        profile: NewAwsInferenceProfile,
+    ) -> impl Future<Output = Result<String>> + Send;
+
+    fn delete(&self, arn: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Adds tags to a profile, overwriting the values of existing keys.
+    fn tag(
+        &self,
+        arn: &str,
+        tags: Vec<(String, String)>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Lists all application inference profiles in the account, without
+    /// their tags.
+    fn list(&self) -> impl Future<Output = Result<Vec<AwsInferenceProfile>>> + Send;
+
+    fn tags(&self, arn: &str) -> impl Future<Output = Result<HashMap<String, String>>> + Send;
+}
+
+/// [`InferenceProfileControlPlane`] backed by the Bedrock API.
+#[derive(Clone)]
+pub struct BedrockControlPlane {
+    client: aws_sdk_bedrock::Client,
+}
+
+impl BedrockControlPlane {
+    pub fn new(client: aws_sdk_bedrock::Client) -> Self {
+        Self { client }
+    }
+
+    /// Builds the client from an already loaded AWS configuration, so that
+    /// credentials are resolved once at startup rather than per request. It
+    /// calls `region`, where the gateway's profiles are created, whatever
+    /// region the environment names.
+    pub fn from_config(config: &aws_config::SdkConfig, region: &str) -> Self {
+        let config = config
+            .to_builder()
+            .region(aws_config::Region::new(region.to_string()))
+            .build();
+        Self::new(aws_sdk_bedrock::Client::new(&config))
+    }
+}
+
+impl InferenceProfileControlPlane for BedrockControlPlane {
+    async fn create(&self, profile: NewAwsInferenceProfile) -> Result<String> {
+        let mut request = self
+            .client
+            .create_inference_profile()
+            .inference_profile_name(&profile.name)
+            .model_source(InferenceProfileModelSource::CopyFrom(profile.copy_from));
+        for (key, value) in profile.tags {
+            request = request.tags(Tag::builder().key(key).value(value).build()?);
+        }
+
+        let response = request.send().await.map_err(|e| {
+            error!(
+                "Failed to create inference profile '{}': {:?}",
+                profile.name, e
+            );
+            e
+        })?;
+
+        Ok(response.inference_profile_arn().to_string())
+    }
+
+    async fn delete(&self, arn: &str) -> Result<()> {
+        self.client
+            .delete_inference_profile()
+            .inference_profile_identifier(arn)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn tag(&self, arn: &str, tags: Vec<(String, String)>) -> Result<()> {
+        let tags = tags
+            .into_iter()
+            .map(|(key, value)| Tag::builder().key(key).value(value).build())
+            .collect::<Result<Vec<_>, _>>()?;
+        self.client
+            .tag_resource()
+            .resource_arn(arn)
+            .set_tags(Some(tags))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<AwsInferenceProfile>> {
+        let mut profiles = Vec::new();
+        let mut next_token = None;
+
+        loop {
+            let response = self
+                .client
+                .list_inference_profiles()
+                .type_equals(InferenceProfileType::Application)
+                .set_next_token(next_token)
+                .send()
+                .await?;
+
+            for summary in response.inference_profile_summaries() {
+                profiles.push(AwsInferenceProfile {
+                    arn: summary.inference_profile_arn().to_string(),
+                    created_at: summary.created_at().and_then(|created_at| {
+                        OffsetDateTime::from_unix_timestamp(created_at.secs()).ok()
+                    }),
+                    name: summary.inference_profile_name().to_string(),
+                });
+            }
+
+            next_token = response.next_token().map(str::to_string);
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(profiles)
+    }
+
+    async fn tags(&self, arn: &str) -> Result<HashMap<String, String>> {
+        Ok(self
+            .client
+            .list_tags_for_resource()
+            .resource_arn(arn)
+            .send()
+            .await?
+            .tags()
+            .iter()
+            .map(|tag| (tag.key().to_string(), tag.value().to_string()))
+            .collect())
+    }
+}
+
+pub struct InferenceProfileSummary {
+    pub created_at: OffsetDateTime,
+    pub inference_profile_arn: String,
+    pub model_name: String,
+}
+
+pub async fn list_inference_profiles(
     pool: &PgPool,
-    api_key: &str,
-    model_name: &str,
-    aws_region: &str,
-    aws_account_id: &str,
-    inference_profile_prefixes: &[String],
-) -> Result<String> {
-    let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-    let client = aws_sdk_bedrock::Client::new(&config);
+    user_id: Uuid,
+) -> Result<Vec<InferenceProfileSummary>> {
+    let inference_profiles = sqlx::query_as!(
+        InferenceProfileSummary,
+        r#"
+        SELECT ip.created_at, ip.inference_profile_arn, m.model_name
+        FROM inference_profiles ip
+        JOIN models m ON m.model_id = ip.model_id
+        WHERE ip.user_id = $1
+        ORDER BY m.model_name
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await?;
 
-    let copy_from = if inference_profile_prefixes
-        .iter()
-        .any(|inference_profile_prefix| model_name.starts_with(inference_profile_prefix.as_str()))
-    {
-        format!("arn:aws:bedrock:{aws_region}:{aws_account_id}:inference-profile/{model_name}")
-    } else {
-        model_name.to_string()
-    };
+    Ok(inference_profiles)
+}
 
-    let inference_profile_name = Uuid::new_v4().to_string();
+/// An inference profile with its owner and model, for the admin pages.
+pub struct InferenceProfileListing {
+    pub created_at: OffsetDateTime,
+    pub inference_profile_arn: String,
+    pub model_is_disabled: bool,
+    pub model_name: String,
+    pub owner_type: String,
+    pub team_id: Option<Uuid>,
+    pub team_name: Option<String>,
+    pub user_email: String,
+    pub user_id: Uuid,
+    pub user_is_disabled: bool,
+}
 
-    let ids = sqlx::query!(
+/// Lists inference profiles ordered by user and model, optionally only those
+/// whose user email contains `user_search` or whose model is `model_name`.
+pub async fn list_all_inference_profiles(
+    pool: &PgPool,
+    user_search: Option<&str>,
+    model_name: Option<&str>,
+    limit: i64,
+) -> Result<Vec<InferenceProfileListing>> {
+    let inference_profiles = sqlx::query_as!(
+        InferenceProfileListing,
         r#"
         SELECT
-            (SELECT user_id FROM api_keys WHERE api_key = $1) AS user_id,
-            (SELECT model_id FROM models WHERE model_name = $2 AND is_disabled = FALSE) AS model_id
+            ip.created_at,
+            ip.inference_profile_arn,
+            m.is_disabled AS model_is_disabled,
+            m.model_name,
+            ip.owner_type,
+            ip.team_id,
+            t.team_name AS "team_name?",
+            u.user_email,
+            u.user_id,
+            u.is_disabled AS user_is_disabled
+        FROM inference_profiles ip
+        JOIN models m ON m.model_id = ip.model_id
+        JOIN users u ON u.user_id = ip.user_id
+        LEFT JOIN teams t ON t.team_id = ip.team_id
+        WHERE ($1::text IS NULL OR strpos(u.user_email, $1) > 0)
+          AND ($2::text IS NULL OR m.model_name = $2)
+        ORDER BY u.user_email, m.model_name
+        LIMIT $3
         "#,
-        api_key.to_lowercase(),
-        model_name.to_lowercase(),
+        user_search.map(str::to_lowercase),
+        model_name.map(str::to_lowercase),
+        limit,
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await?;
 
+    Ok(inference_profiles)
+}
+
+/// Who an inference profile is created for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProfileOwner<'a> {
+    /// The owner of a gateway API key under the configured
+    /// [`InferenceProfileGranularity`].
+    ApiKey(&'a str),
+    /// A single API key. `user_id` is the key's user.
+    Key { api_key_id: Uuid, user_id: Uuid },
+    /// A team. `user_id` is the user whose key or token uses the profile.
+    Team { team_id: Uuid, user_id: Uuid },
+    /// A user.
+    User(Uuid),
+}
+
+impl ProfileOwner<'_> {
+    /// Returns the owner recorded on an `inference_profiles` row, or `None`
+    /// if the row is inconsistent.
+    pub(crate) fn stored(
+        owner_type: &str,
+        api_key_id: Option<Uuid>,
+        team_id: Option<Uuid>,
+        user_id: Uuid,
+    ) -> Option<Self> {
+        match (ProfileOwnerType::parse(owner_type)?, api_key_id, team_id) {
+            (ProfileOwnerType::Key, Some(api_key_id), _) => Some(ProfileOwner::Key {
+                api_key_id,
+                user_id,
+            }),
+            (ProfileOwnerType::Team, _, Some(team_id)) => {
+                Some(ProfileOwner::Team { team_id, user_id })
+            }
+            (ProfileOwnerType::User, _, _) => Some(ProfileOwner::User(user_id)),
+            _ => None,
+        }
+    }
+}
+
+struct ProfileOwnerIds {
+    api_key_id: Option<Uuid>,
+    model_id: Option<Uuid>,
+    team_id: Option<Uuid>,
+    team_name: Option<String>,
+    user_id: Option<Uuid>,
+}
+
+/// A profile's owner as stored on its row. `owner_id` is the key, team or
+/// user id, according to `owner_type`.
+struct ResolvedOwner {
+    api_key_id: Option<Uuid>,
+    model_id: Uuid,
+    owner_id: Uuid,
+    owner_type: ProfileOwnerType,
+    team_id: Option<Uuid>,
+    team_name: Option<String>,
+    user_id: Uuid,
+}
+
+async fn resolve_owner(
+    pool: &PgPool,
+    owner: ProfileOwner<'_>,
+    granularity: InferenceProfileGranularity,
+    model_name: &str,
+) -> Result<ResolvedOwner> {
+    let owner = match owner {
+        ProfileOwner::ApiKey(api_key) => {
+            let key = sqlx::query!(
+                "SELECT api_key_id, team_id, user_id FROM api_keys WHERE api_key = $1",
+                api_key.to_lowercase(),
+            )
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Inference profile owner not found"))?;
+            granularity.owner(Some(key.api_key_id), key.team_id, key.user_id)
+        }
+        owner => owner,
+    };
+
+    let (owner_type, ids) = match owner {
+        ProfileOwner::ApiKey(_) => unreachable!("API keys are resolved to their owner above"),
+        ProfileOwner::Key {
+            api_key_id,
+            user_id,
+        } => {
+            let ids = sqlx::query_as!(
+                ProfileOwnerIds,
+                r#"
+                SELECT
+                    (SELECT user_id FROM users WHERE user_id = $1) AS user_id,
+                    (SELECT model_id FROM models WHERE model_name = $3 AND is_disabled = FALSE) AS model_id,
+                    (SELECT api_key_id FROM api_keys WHERE api_key_id = $2) AS api_key_id,
+                    (SELECT team_id FROM api_keys WHERE api_key_id = $2) AS team_id,
+                    (
+                        SELECT t.team_name
+                        FROM teams t
+                        JOIN api_keys ak ON ak.team_id = t.team_id
+                        WHERE ak.api_key_id = $2
+                    ) AS team_name
+                "#,
+                user_id,
+                api_key_id,
+                model_name.to_lowercase(),
+            )
+            .fetch_one(pool)
+            .await?;
+            (ProfileOwnerType::Key, ids)
+        }
+        ProfileOwner::Team { team_id, user_id } => {
+            let ids = sqlx::query_as!(
+                ProfileOwnerIds,
+                r#"
+                SELECT
+                    (SELECT user_id FROM users WHERE user_id = $1) AS user_id,
+                    (SELECT model_id FROM models WHERE model_name = $3 AND is_disabled = FALSE) AS model_id,
+                    NULL::uuid AS api_key_id,
+                    (SELECT team_id FROM teams WHERE team_id = $2) AS team_id,
+                    (SELECT team_name FROM teams WHERE team_id = $2) AS team_name
+                "#,
+                user_id,
+                team_id,
+                model_name.to_lowercase(),
+            )
+            .fetch_one(pool)
+            .await?;
+            (ProfileOwnerType::Team, ids)
+        }
+        ProfileOwner::User(user_id) => {
+            let ids = sqlx::query_as!(
+                ProfileOwnerIds,
+                r#"
+                SELECT
+                    (SELECT user_id FROM users WHERE user_id = $1) AS user_id,
+                    (SELECT model_id FROM models WHERE model_name = $2 AND is_disabled = FALSE) AS model_id,
+                    NULL::uuid AS api_key_id,
+                    NULL::uuid AS team_id,
+                    NULL::text AS team_name
+                "#,
+                user_id,
+                model_name.to_lowercase(),
+            )
+            .fetch_one(pool)
+            .await?;
+            (ProfileOwnerType::User, ids)
+        }
+    };
+
     let user_id = ids
         .user_id
-        .ok_or_else(|| anyhow::anyhow!("API key not found: {}", api_key))?;
+        .ok_or_else(|| anyhow::anyhow!("Inference profile owner not found"))?;
     let model_id = ids
         .model_id
         .ok_or_else(|| anyhow::anyhow!("Model not found: {}", model_name))?;
+    let owner_id = match owner_type {
+        ProfileOwnerType::Key => ids.api_key_id,
+        ProfileOwnerType::Team => ids.team_id,
+        ProfileOwnerType::User => Some(user_id),
+    }
+    .ok_or_else(|| anyhow::anyhow!("Inference profile owner not found"))?;
+
+    Ok(ResolvedOwner {
+        api_key_id: ids.api_key_id,
+        model_id,
+        owner_id,
+        owner_type,
+        team_id: ids.team_id,
+        team_name: ids.team_name,
+        user_id,
+    })
+}
 
-    let response = client
-        .create_inference_profile()
-        .inference_profile_name(&inference_profile_name)
-        .model_source(InferenceProfileModelSource::CopyFrom(copy_from))
-        .tags(
-            Tag::builder()
-                .key("GatewayUserId")
-                .value(user_id.to_string())
-                .build()?,
+/// Creates the owner's inference profile for the model, unless it exists.
+/// If another request is already creating it, waits for that creation when
+/// `wait_if_busy` is set and fails at once otherwise.
+#[instrument(skip_all, fields(model = %model_name))]
+pub async fn create_inference_profile(
+    control_plane: &(impl InferenceProfileControlPlane + Clone + 'static),
+    pool: &PgPool,
+    owner: ProfileOwner<'_>,
+    model_name: &str,
+    settings: &InferenceProfileSettings,
+    wait_if_busy: bool,
+) -> Result<String> {
+    let copy_from = if settings
+        .inference_profile_prefixes
+        .iter()
+        .any(|inference_profile_prefix| model_name.starts_with(inference_profile_prefix.as_str()))
+    {
+        format!(
+            "arn:aws:bedrock:{}:{}:inference-profile/{model_name}",
+            settings.aws_region, settings.aws_account_id
         )
-        .tags(
-            Tag::builder()
-                .key("GatewayModelId")
-                .value(model_id.to_string())
-                .build()?,
+    } else {
+        model_name.to_string()
+    };
+
+    let inference_profile_name = Uuid::new_v4().to_string();
+
+    let owner = resolve_owner(pool, owner, settings.granularity, model_name).await?;
+
+    let mut tags = gateway_tags(
+        &settings.deployment_id,
+        owner.user_id,
+        owner.model_id,
+        owner.api_key_id,
+        owner.team_id,
+        owner.team_name.as_deref(),
+    );
+    if !settings.tags.is_empty() {
+        let context = load_tag_context(
+            pool,
+            owner.user_id,
+            owner.team_id,
+            owner.team_name.clone(),
+            model_name,
         )
-        .send()
-        .await
-        .map_err(|e| {
-            error!(
-                "Failed to create inference profile '{}': {:?}",
-                inference_profile_name, e
-            );
-            e
-        })?;
+        .await?;
+        tags.extend(settings.tags.render(&context));
+    }
+
+    // Runs in its own task, so that a request dropped mid-creation still
+    // stores the profile or releases the reservation.
+    tokio::spawn(create_reserved_inference_profile(
+        control_plane.clone(),
+        pool.clone(),
+        owner,
+        NewAwsInferenceProfile {
+            copy_from,
+            name: inference_profile_name,
+            tags,
+        },
+        wait_if_busy,
+    ))
+    .await?
+}
+
+/// Reserves the profile's creation, creates it in Bedrock and stores it.
+async fn create_reserved_inference_profile(
+    control_plane: impl InferenceProfileControlPlane,
+    pool: PgPool,
+    owner: ResolvedOwner,
+    profile: NewAwsInferenceProfile,
+    wait_if_busy: bool,
+) -> Result<String> {
+    // Concurrent first requests in this process share one creation; other
+    // instances are held off by its reservation.
+    let lock_key = creation_lock_key(owner.owner_type, owner.owner_id, owner.model_id);
+    let local_lock = creation_lock(&lock_key);
+    let _creating = if wait_if_busy {
+        local_lock.lock().await
+    } else {
+        local_lock
+            .try_lock()
+            .map_err(|_| anyhow::anyhow!("Inference profile is being created by another request"))?
+    };
+
+    loop {
+        match reserve_creation(&pool, &owner, &lock_key).await? {
+            Reservation::Existing(existing_arn) => return Ok(existing_arn),
+            Reservation::Reserved => break,
+            Reservation::Busy if wait_if_busy => {
+                tokio::time::sleep(RESERVATION_POLL_INTERVAL).await
+            }
+            Reservation::Busy => {
+                anyhow::bail!("Inference profile is being created by another instance")
+            }
+        }
+    }
+
+    let inference_profile_name = profile.name.clone();
+    let inference_profile_arn = match control_plane.create(profile).await {
+        Ok(inference_profile_arn) => inference_profile_arn,
+        Err(e) => {
+            release_reservation(&pool, &lock_key).await;
+            return Err(e);
+        }
+    };
+
+    match store_inference_profile(
+        &pool,
+        &owner,
+        &lock_key,
+        &inference_profile_arn,
+        &inference_profile_name,
+    )
+    .await
+    {
+        Ok(None) => {}
+        Ok(Some(existing_arn)) => {
+            delete_losing_inference_profile(&control_plane, &inference_profile_arn).await;
+            return Ok(existing_arn);
+        }
+        Err(e) => {
+            delete_losing_inference_profile(&control_plane, &inference_profile_arn).await;
+            release_reservation(&pool, &lock_key).await;
+            return Err(e);
+        }
+    }
+
+    info!(
+        "Created and stored inference profile: {} (ARN: {})",
+        inference_profile_name, inference_profile_arn
+    );
+
+    Ok(inference_profile_arn)
+}
+
+/// The tags identifying a gateway profile. Team and team key profiles are
+/// tagged so that cost explorer can attribute their spend to the team, and
+/// key profiles so that it can attribute it to the key.
+pub(crate) fn gateway_tags(
+    deployment_id: &str,
+    user_id: Uuid,
+    model_id: Uuid,
+    api_key_id: Option<Uuid>,
+    team_id: Option<Uuid>,
+    team_name: Option<&str>,
+) -> Vec<(String, String)> {
+    let mut tags = vec![
+        (GATEWAY_USER_ID_TAG.to_string(), user_id.to_string()),
+        (GATEWAY_MODEL_ID_TAG.to_string(), model_id.to_string()),
+    ];
+    if !deployment_id.is_empty() {
+        tags.push((
+            GATEWAY_DEPLOYMENT_ID_TAG.to_string(),
+            deployment_id.to_string(),
+        ));
+    }
+    if let Some(api_key_id) = api_key_id {
+        tags.push((GATEWAY_API_KEY_ID_TAG.to_string(), api_key_id.to_string()));
+    }
+    if let (Some(team_id), Some(team_name)) = (team_id, team_name) {
+        tags.push((GATEWAY_TEAM_ID_TAG.to_string(), team_id.to_string()));
+        tags.push((GATEWAY_TEAM_TAG.to_string(), sanitize_tag_value(team_name)));
+    }
+    tags
+}
+
+/// Converts the `users.attributes` JSON object to strings, dropping nulls,
+/// arrays and objects.
+pub(crate) fn attributes_from_json(attributes: Value) -> BTreeMap<String, String> {
+    let Value::Object(attributes) = attributes else {
+        return BTreeMap::new();
+    };
+    attributes
+        .into_iter()
+        .filter_map(|(name, value)| match value {
+            Value::String(value) => Some((name, value)),
+            Value::Bool(_) | Value::Number(_) => Some((name, value.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+async fn load_tag_context(
+    pool: &PgPool,
+    user_id: Uuid,
+    team_id: Option<Uuid>,
+    team_name: Option<String>,
+    model_name: &str,
+) -> Result<TagContext> {
+    let user = sqlx::query!(
+        "SELECT attributes, cost_center, user_email FROM users WHERE user_id = $1",
+        user_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(TagContext {
+        attributes: attributes_from_json(user.attributes),
+        cost_center: user.cost_center,
+        model_name: model_name.to_lowercase(),
+        team_id,
+        team_name,
+        user_email: user.user_email,
+        user_id,
+    })
+}
+
+/// Profiles are locked by their owner rather than by the user creating
+/// them, as team profiles are shared by the whole team.
+fn creation_lock_key(owner_type: ProfileOwnerType, owner_id: Uuid, model_id: Uuid) -> String {
+    format!(
+        "inference_profile:{}:{owner_id}:{model_id}",
+        owner_type.as_str()
+    )
+}
+
+/// In-process creation locks by lock key, so that concurrent requests for
+/// the same profile make a single Bedrock call. Entries are dropped once no
+/// request holds them.
+static CREATION_LOCKS: LazyLock<Mutex<HashMap<String, Weak<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn creation_lock(lock_key: &str) -> Arc<tokio::sync::Mutex<()>> {
+    let mut locks = CREATION_LOCKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    locks.retain(|_, lock| lock.strong_count() > 0);
+    if let Some(lock) = locks.get(lock_key).and_then(Weak::upgrade) {
+        return lock;
+    }
+    let lock = Arc::new(tokio::sync::Mutex::new(()));
+    locks.insert(lock_key.to_string(), Arc::downgrade(&lock));
+    lock
+}
+
+/// How long a reservation keeps other instances waiting. Older ones are
+/// taken over, as the instance holding them has likely died.
+const RESERVATION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+const RESERVATION_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+enum Reservation {
+    /// The profile already exists.
+    Existing(String),
+    /// This request may create the profile.
+    Reserved,
+    /// Another instance is creating the profile.
+    Busy,
+}
+
+/// Returns the owner's profile if it exists, and otherwise reserves its
+/// creation. The advisory lock is only held while checking, never during
+/// the Bedrock call.
+async fn reserve_creation(
+    pool: &PgPool,
+    owner: &ResolvedOwner,
+    lock_key: &str,
+) -> Result<Reservation> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "SELECT pg_advisory_xact_lock(hashtextextended($1, 0))",
+        lock_key,
+    )
+    .execute(&mut *tx)
+    .await?;
 
-    let inference_profile_arn = response.inference_profile_arn().to_string();
+    if let Some(existing_arn) = find_inference_profile_arn(&mut tx, owner).await? {
+        tx.commit().await?;
+        return Ok(Reservation::Existing(existing_arn));
+    }
 
+    let reserved = sqlx::query!(
+        r#"
+        INSERT INTO inference_profile_reservations (lock_key) VALUES ($1)
+        ON CONFLICT (lock_key) DO UPDATE SET reserved_at = now()
+        WHERE inference_profile_reservations.reserved_at < now() - make_interval(secs => $2)
+        "#,
+        lock_key,
+        RESERVATION_TIMEOUT.as_secs_f64(),
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(if reserved.rows_affected() > 0 {
+        Reservation::Reserved
+    } else {
+        Reservation::Busy
+    })
+}
+
+/// Stores a created profile and releases its reservation. Returns the
+/// existing profile instead if one was stored first, which happens when the
+/// reservation timed out and another instance took it over.
+async fn store_inference_profile(
+    pool: &PgPool,
+    owner: &ResolvedOwner,
+    lock_key: &str,
+    inference_profile_arn: &str,
+    inference_profile_name: &str,
+) -> Result<Option<String>> {
+    let mut tx = pool.begin().await?;
     sqlx::query!(
+        "SELECT pg_advisory_xact_lock(hashtextextended($1, 0))",
+        lock_key,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let inserted = sqlx::query!(
         r#"
-        INSERT INTO inference_profiles (user_id, model_id, inference_profile_arn, inference_profile_name)
-        SELECT ak.user_id, m.model_id, $3, $4
-        FROM api_keys ak, models m
-        WHERE ak.api_key = $1 AND m.model_name = $2 AND m.is_disabled = FALSE
-        ON CONFLICT (user_id, model_id) DO NOTHING
+        INSERT INTO inference_profiles (user_id, model_id, team_id, api_key_id, owner_type, inference_profile_arn, inference_profile_name)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT DO NOTHING
         "#,
-        api_key.to_lowercase(),
-        model_name.to_lowercase(),
+        owner.user_id,
+        owner.model_id,
+        owner.team_id,
+        owner.api_key_id,
+        owner.owner_type.as_str(),
         &inference_profile_arn.to_lowercase(),
         &inference_profile_name.to_lowercase(),
     )
-    .execute(pool)
+    .execute(&mut *tx)
     .await?;
 
-    info!(
-        "Created and stored inference profile: {} (ARN: {})",
-        inference_profile_name, inference_profile_arn
-    );
+    let existing_arn = if inserted.rows_affected() == 0 {
+        find_inference_profile_arn(&mut tx, owner).await?
+    } else {
+        None
+    };
+
+    sqlx::query!(
+        "DELETE FROM inference_profile_reservations WHERE lock_key = $1",
+        lock_key,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(existing_arn)
+}
+
+/// Releases a reservation after a failed creation, so that the next request
+/// retries at once rather than after the timeout.
+async fn release_reservation(pool: &PgPool, lock_key: &str) {
+    if let Err(e) = sqlx::query!(
+        "DELETE FROM inference_profile_reservations WHERE lock_key = $1",
+        lock_key,
+    )
+    .execute(pool)
+    .await
+    {
+        error!(
+            "Failed to release inference profile reservation {}: {:?}",
+            lock_key, e
+        );
+    }
+}
+
+async fn find_inference_profile_arn(
+    tx: &mut Transaction<'_, Postgres>,
+    owner: &ResolvedOwner,
+) -> Result<Option<String>> {
+    let inference_profile_arn = sqlx::query_scalar!(
+        r#"
+        SELECT inference_profile_arn
+        FROM inference_profiles
+        WHERE model_id = $1
+          AND owner_type = $2
+          AND CASE $2
+            WHEN 'key' THEN api_key_id = $3
+            WHEN 'team' THEN team_id = $3
+            ELSE user_id = $3
+          END
+        LIMIT 1
+        "#,
+        owner.model_id,
+        owner.owner_type.as_str(),
+        owner.owner_id,
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
 
     Ok(inference_profile_arn)
 }
+
+/// Deletes a profile created by a request that lost the race to store it.
+async fn delete_losing_inference_profile(
+    control_plane: &impl InferenceProfileControlPlane,
+    inference_profile_arn: &str,
+) {
+    match control_plane.delete(inference_profile_arn).await {
+        Ok(()) => info!(
+            "Deleted duplicate inference profile {}",
+            inference_profile_arn
+        ),
+        Err(e) => error!(
+            "Failed to delete duplicate inference profile {}: {:?}",
+            inference_profile_arn, e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creation_locks_are_shared_while_held() {
+        let lock = creation_lock("inference_profile:user:a:b");
+        assert!(Arc::ptr_eq(
+            &lock,
+            &creation_lock("inference_profile:user:a:b")
+        ));
+        assert!(!Arc::ptr_eq(
+            &lock,
+            &creation_lock("inference_profile:user:a:c")
+        ));
+
+        let weak = Arc::downgrade(&lock);
+        drop(lock);
+        creation_lock("inference_profile:user:a:d");
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn lock_keys_are_unchanged_for_team_and_personal_profiles() {
+        // Gateways running an older version lock with the same keys.
+        let owner_id = Uuid::nil();
+        let model_id = Uuid::max();
+        assert_eq!(
+            creation_lock_key(ProfileOwnerType::Team, owner_id, model_id),
+            format!("inference_profile:team:{owner_id}:{model_id}")
+        );
+        assert_eq!(
+            creation_lock_key(ProfileOwnerType::User, owner_id, model_id),
+            format!("inference_profile:user:{owner_id}:{model_id}")
+        );
+    }
+
+    #[test]
+    fn owners_of_the_same_id_are_locked_separately() {
+        let owner_id = Uuid::new_v4();
+        let model_id = Uuid::new_v4();
+        assert_ne!(
+            creation_lock_key(ProfileOwnerType::Key, owner_id, model_id),
+            creation_lock_key(ProfileOwnerType::User, owner_id, model_id)
+        );
+        assert_ne!(
+            creation_lock_key(ProfileOwnerType::User, owner_id, model_id),
+            creation_lock_key(ProfileOwnerType::User, owner_id, Uuid::new_v4())
+        );
+    }
+
+    #[test]
+    fn granularity_picks_the_profile_owner() {
+        let api_key_id = Uuid::new_v4();
+        let team_id = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+
+        let key = InferenceProfileGranularity::Key;
+        assert_eq!(
+            key.owner(Some(api_key_id), Some(team_id), user_id),
+            ProfileOwner::Key {
+                api_key_id,
+                user_id
+            }
+        );
+        // Session tokens not exchanged for a key fall back to the team.
+        assert_eq!(
+            key.owner(None, Some(team_id), user_id),
+            ProfileOwner::Team { team_id, user_id }
+        );
+
+        let team = InferenceProfileGranularity::Team;
+        assert_eq!(
+            team.owner(Some(api_key_id), Some(team_id), user_id),
+            ProfileOwner::Team { team_id, user_id }
+        );
+        assert_eq!(
+            team.owner(Some(api_key_id), None, user_id),
+            ProfileOwner::User(user_id)
+        );
+
+        let user = InferenceProfileGranularity::User;
+        assert_eq!(
+            user.owner(Some(api_key_id), Some(team_id), user_id),
+            ProfileOwner::User(user_id)
+        );
+    }
+
+    #[test]
+    fn stored_owners_follow_the_owner_type() {
+        let api_key_id = Uuid::new_v4();
+        let team_id = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+
+        assert_eq!(
+            ProfileOwner::stored("key", Some(api_key_id), Some(team_id), user_id),
+            Some(ProfileOwner::Key {
+                api_key_id,
+                user_id
+            })
+        );
+        assert_eq!(
+            ProfileOwner::stored("team", None, Some(team_id), user_id),
+            Some(ProfileOwner::Team { team_id, user_id })
+        );
+        assert_eq!(
+            ProfileOwner::stored("user", None, None, user_id),
+            Some(ProfileOwner::User(user_id))
+        );
+        assert_eq!(ProfileOwner::stored("team", None, None, user_id), None);
+        assert_eq!(ProfileOwner::stored("group", None, None, user_id), None);
+    }
+}
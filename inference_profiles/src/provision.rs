@@ -0,0 +1,193 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    InferenceProfileControlPlane, InferenceProfileSettings, ProfileOwner, create_inference_profile,
+};
+
+/// Jobs queued beyond this are dropped; their profiles are then created on
+/// first use as usual.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Profiles created per job at most, so enabling a model for thousands of
+/// users does not exhaust the Bedrock control plane quota. The rest are
+/// created on first use.
+const MAX_PROFILES_PER_JOB: usize = 200;
+
+/// Pause between profile creations.
+const CREATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longest pause after repeated failures, such as throttling.
+const MAX_CREATE_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Personal inference profiles to create ahead of first use.
+#[derive(Debug, PartialEq)]
+pub enum ProvisionJob {
+    /// One for every active user, for a newly enabled model.
+    Model(String),
+    /// One for every enabled model, for a new user.
+    User(Uuid),
+}
+
+/// Queues [`ProvisionJob`]s for a background worker, so that users' first
+/// requests do not wait for their inference profile to be created.
+#[derive(Clone)]
+pub struct InferenceProfileProvisioner {
+    sender: mpsc::Sender<ProvisionJob>,
+}
+
+impl InferenceProfileProvisioner {
+    /// Returns the provisioner and the worker processing its jobs, which the
+    /// caller spawns.
+    pub fn new(
+        pool: Arc<PgPool>,
+        control_plane: impl InferenceProfileControlPlane + Clone + 'static,
+        settings: InferenceProfileSettings,
+    ) -> (Self, impl Future<Output = ()> + Send) {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let worker = provision_inference_profiles(receiver, pool, control_plane, settings);
+        (Self { sender }, worker)
+    }
+
+    /// Returns false if the job was dropped because the queue is full.
+    pub fn enqueue(&self, job: ProvisionJob) -> bool {
+        match self.sender.try_send(job) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Dropped inference profile provisioning job: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Lists the (user, model) pairs a job covers that have no personal profile
+/// yet.
+async fn list_missing_profiles(pool: &PgPool, job: &ProvisionJob) -> Result<Vec<(Uuid, String)>> {
+    let (user_id, model_name) = match job {
+        ProvisionJob::Model(model_name) => (None, Some(model_name.to_lowercase())),
+        ProvisionJob::User(user_id) => (Some(*user_id), None),
+    };
+
+    let missing = sqlx::query!(
+        r#"
+        SELECT u.user_id, m.model_name
+        FROM users u
+        CROSS JOIN models m
+        WHERE NOT u.is_disabled
+          AND NOT u.is_service_account
+          AND NOT m.is_disabled
+          AND ($1::uuid IS NULL OR u.user_id = $1)
+          AND ($2::text IS NULL OR m.model_name = $2)
+          AND NOT EXISTS (
+            SELECT 1 FROM inference_profiles ip
+            WHERE ip.user_id = u.user_id AND ip.model_id = m.model_id AND ip.owner_type = 'user'
+          )
+        ORDER BY u.user_id, m.model_name
+        "#,
+        user_id,
+        model_name,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(missing
+        .into_iter()
+        .map(|row| (row.user_id, row.model_name))
+        .collect())
+}
+
+/// The pause before the next creation: back to [`CREATE_INTERVAL`] after a
+/// success, doubled up to [`MAX_CREATE_BACKOFF`] after a failure.
+fn next_create_delay(delay: Duration, succeeded: bool) -> Duration {
+    if succeeded {
+        CREATE_INTERVAL
+    } else {
+        (delay * 2).min(MAX_CREATE_BACKOFF)
+    }
+}
+
+async fn provision_inference_profiles(
+    mut receiver: mpsc::Receiver<ProvisionJob>,
+    pool: Arc<PgPool>,
+    control_plane: impl InferenceProfileControlPlane + Clone + 'static,
+    settings: InferenceProfileSettings,
+) {
+    while let Some(job) = receiver.recv().await {
+        let missing = match list_missing_profiles(&pool, &job).await {
+            Ok(missing) => missing,
+            Err(e) => {
+                error!("Failed to list inference profiles to provision: {:?}", e);
+                continue;
+            }
+        };
+
+        let attempted = missing.len().min(MAX_PROFILES_PER_JOB);
+        let mut created = 0;
+        let mut delay = CREATE_INTERVAL;
+        for (index, (user_id, model_name)) in missing.iter().take(attempted).enumerate() {
+            if index > 0 {
+                tokio::time::sleep(delay).await;
+            }
+            let result = create_inference_profile(
+                &control_plane,
+                &pool,
+                ProfileOwner::User(*user_id),
+                model_name,
+                &settings,
+                true,
+            )
+            .await;
+            delay = next_create_delay(delay, result.is_ok());
+            match result {
+                Ok(_) => created += 1,
+                Err(e) => error!(
+                    "Failed to provision inference profile for {}: {:?}",
+                    model_name, e
+                ),
+            }
+        }
+
+        info!(
+            ?job,
+            created,
+            failed = attempted - created,
+            skipped = missing.len() - attempted,
+            "Provisioned inference profiles"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enqueue_drops_jobs_when_the_queue_is_full() {
+        let (sender, _receiver) = mpsc::channel(1);
+        let provisioner = InferenceProfileProvisioner { sender };
+
+        assert!(provisioner.enqueue(ProvisionJob::User(Uuid::new_v4())));
+        assert!(!provisioner.enqueue(ProvisionJob::Model("claude-opus-4-6".to_string())));
+    }
+
+    #[test]
+    fn failures_back_off_until_a_creation_succeeds() {
+        let mut delay = CREATE_INTERVAL;
+        for _ in 0..20 {
+            delay = next_create_delay(delay, false);
+        }
+        assert_eq!(delay, MAX_CREATE_BACKOFF);
+        assert_eq!(next_create_delay(delay, true), CREATE_INTERVAL);
+        assert_eq!(
+            next_create_delay(CREATE_INTERVAL, false),
+            CREATE_INTERVAL * 2
+        );
+    }
+}
@@ -0,0 +1,529 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    AwsInferenceProfile, GATEWAY_DEPLOYMENT_ID_TAG, InferenceProfileControlPlane,
+    InferenceProfileSettings, ProfileOwner, create_inference_profile, list_tags,
+};
+
+/// Advisory lock held by the instance reconciling, so that only one of
+/// several gateway instances does so at a time.
+const RECONCILE_LOCK_KEY: &str = "inference_profile_reconcile";
+
+/// Settings for [`reconcile_inference_profiles`].
+#[derive(Clone)]
+pub struct ReconcileOptions {
+    /// Delete orphaned profiles. Otherwise they are only reported.
+    pub delete_orphans: bool,
+    /// Only report drift; change nothing.
+    pub dry_run: bool,
+    /// The largest share of rows a run may remove as missing in AWS. Runs
+    /// that would remove more are refused, as the listing is more likely
+    /// incomplete than that many profiles deleted.
+    pub max_removal_fraction: f64,
+    /// Profiles younger than this are never treated as orphans, as their
+    /// row may not have been written yet.
+    pub orphan_grace_period: Duration,
+    /// Used to recreate missing profiles.
+    pub settings: InferenceProfileSettings,
+}
+
+/// Counts of drift found, and repaired unless running dry.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReconcileReport {
+    pub failures: usize,
+    /// Rows whose profile no longer exists in AWS.
+    pub missing: usize,
+    /// Profiles in AWS tagged with this deployment's id with no row.
+    pub orphaned: usize,
+    pub orphans_deleted: usize,
+    pub recreated: usize,
+    pub rows_removed: usize,
+}
+
+impl ReconcileReport {
+    pub fn has_drift(&self) -> bool {
+        self.missing > 0 || self.orphaned > 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct StoredInferenceProfile {
+    pub api_key_id: Option<Uuid>,
+    pub inference_profile_arn: String,
+    pub inference_profile_id: Uuid,
+    /// False once the user, model or key is disabled; such profiles are not
+    /// recreated.
+    pub is_active: bool,
+    pub model_name: String,
+    pub owner_type: String,
+    pub team_id: Option<Uuid>,
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ReconcilePlan {
+    pub missing: Vec<StoredInferenceProfile>,
+    /// Profiles past the grace period with no row. Only those tagged with
+    /// this deployment's id are orphans.
+    pub untracked: Vec<AwsInferenceProfile>,
+}
+
+/// Compares the table with the profiles in AWS. ARNs are stored lowercase,
+/// so they are compared case-insensitively.
+pub(crate) fn plan_reconciliation(
+    stored: &[StoredInferenceProfile],
+    aws: &[AwsInferenceProfile],
+    orphan_grace_period: Duration,
+    now: OffsetDateTime,
+) -> ReconcilePlan {
+    let aws_arns = aws
+        .iter()
+        .map(|profile| profile.arn.to_lowercase())
+        .collect::<HashSet<_>>();
+    let stored_arns = stored
+        .iter()
+        .map(|profile| profile.inference_profile_arn.to_lowercase())
+        .collect::<HashSet<_>>();
+
+    ReconcilePlan {
+        missing: stored
+            .iter()
+            .filter(|profile| !aws_arns.contains(&profile.inference_profile_arn.to_lowercase()))
+            .cloned()
+            .collect(),
+        untracked: aws
+            .iter()
+            .filter(|profile| !stored_arns.contains(&profile.arn.to_lowercase()))
+            .filter(|profile| {
+                profile
+                    .created_at
+                    .is_none_or(|created_at| now - created_at >= orphan_grace_period)
+            })
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Refuses to remove rows on the word of an AWS listing that is likely
+/// incomplete: an empty one while rows exist, or one missing more than
+/// `max_removal_fraction` of them.
+pub(crate) fn check_removal_limit(
+    stored: usize,
+    aws: usize,
+    missing: usize,
+    max_removal_fraction: f64,
+) -> Result<()> {
+    if stored > 0 && aws == 0 {
+        bail!("AWS listed no inference profiles while {stored} rows exist; removing none");
+    }
+    if missing as f64 > stored as f64 * max_removal_fraction {
+        bail!(
+            "{missing} of {stored} inference profiles are missing in AWS, more than the \
+             allowed fraction of {max_removal_fraction}; removing none"
+        );
+    }
+    Ok(())
+}
+
+/// Whether a profile's tags mark it as created by the deployment
+/// `deployment_id`. Without a deployment id no profile is.
+pub(crate) fn belongs_to_deployment(tags: &HashMap<String, String>, deployment_id: &str) -> bool {
+    !deployment_id.is_empty()
+        && tags
+            .get(GATEWAY_DEPLOYMENT_ID_TAG)
+            .is_some_and(|tag| tag == deployment_id)
+}
+
+/// Returns the untracked profiles tagged with this deployment's id. Tags
+/// are only looked up for untracked profiles, a few at a time.
+async fn find_orphans(
+    control_plane: &impl InferenceProfileControlPlane,
+    untracked: Vec<AwsInferenceProfile>,
+    deployment_id: &str,
+    report: &mut ReconcileReport,
+) -> Vec<AwsInferenceProfile> {
+    if deployment_id.is_empty() {
+        if !untracked.is_empty() {
+            info!(
+                "{} inference profiles have no row; set inference_profile_deployment_id to find orphans among them",
+                untracked.len()
+            );
+        }
+        return Vec::new();
+    }
+
+    let arns = untracked
+        .iter()
+        .map(|profile| profile.arn.clone())
+        .collect();
+    let mut orphan_arns = HashSet::new();
+    for (arn, tags) in list_tags(control_plane, arns).await {
+        match tags {
+            Ok(tags) if belongs_to_deployment(&tags, deployment_id) => {
+                orphan_arns.insert(arn);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!("Failed to list tags of inference profile {}: {:?}", arn, e);
+                report.failures += 1;
+            }
+        }
+    }
+
+    untracked
+        .into_iter()
+        .filter(|profile| orphan_arns.contains(&profile.arn))
+        .collect()
+}
+
+async fn list_stored_inference_profiles(pool: &PgPool) -> Result<Vec<StoredInferenceProfile>> {
+    let inference_profiles = sqlx::query_as!(
+        StoredInferenceProfile,
+        r#"
+        SELECT
+            ip.api_key_id,
+            ip.inference_profile_arn,
+            ip.inference_profile_id,
+            (NOT m.is_disabled AND NOT u.is_disabled AND NOT COALESCE(ak.is_disabled, FALSE)) AS "is_active!",
+            m.model_name,
+            ip.owner_type,
+            ip.team_id,
+            ip.user_id
+        FROM inference_profiles ip
+        JOIN models m ON m.model_id = ip.model_id
+        JOIN users u ON u.user_id = ip.user_id
+        LEFT JOIN api_keys ak ON ak.api_key_id = ip.api_key_id
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(inference_profiles)
+}
+
+async fn delete_orphans(
+    control_plane: &impl InferenceProfileControlPlane,
+    orphans: &[AwsInferenceProfile],
+    report: &mut ReconcileReport,
+) {
+    for orphan in orphans {
+        match control_plane.delete(&orphan.arn).await {
+            Ok(()) => {
+                info!("Deleted orphaned inference profile {}", orphan.arn);
+                report.orphans_deleted += 1;
+            }
+            Err(e) => {
+                error!(
+                    "Failed to delete orphaned inference profile {}: {:?}",
+                    orphan.arn, e
+                );
+                report.failures += 1;
+            }
+        }
+    }
+}
+
+/// Brings `inference_profiles` in line with the profiles in AWS: rows whose
+/// profile was deleted are removed and, for active users and models,
+/// recreated. Profiles tagged with this deployment's id that have no row are
+/// reported, and deleted if `delete_orphans` is set. Returns `None` without
+/// doing anything if another instance is already reconciling.
+pub async fn reconcile_inference_profiles(
+    pool: &PgPool,
+    control_plane: &(impl InferenceProfileControlPlane + Clone + 'static),
+    options: &ReconcileOptions,
+) -> Result<Option<ReconcileReport>> {
+    // A transaction lock, held by a transaction left open for the whole run.
+    // It is released when the transaction ends, including when its
+    // connection is lost or the run fails, so it never outlives the run on a
+    // connection returned to the pool.
+    let mut tx = pool.begin().await?;
+    let locked = sqlx::query_scalar!(
+        r#"SELECT pg_try_advisory_xact_lock(hashtextextended($1, 0)) AS "locked!""#,
+        RECONCILE_LOCK_KEY,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !locked {
+        return Ok(None);
+    }
+
+    let report = reconcile(pool, control_plane, options).await;
+    tx.commit().await?;
+
+    report.map(Some)
+}
+
+async fn reconcile(
+    pool: &PgPool,
+    control_plane: &(impl InferenceProfileControlPlane + Clone + 'static),
+    options: &ReconcileOptions,
+) -> Result<ReconcileReport> {
+    let stored = list_stored_inference_profiles(pool).await?;
+    let aws = control_plane.list().await?;
+    let plan = plan_reconciliation(
+        &stored,
+        &aws,
+        options.orphan_grace_period,
+        OffsetDateTime::now_utc(),
+    );
+
+    let mut report = ReconcileReport {
+        missing: plan.missing.len(),
+        ..Default::default()
+    };
+    let orphaned = find_orphans(
+        control_plane,
+        plan.untracked,
+        &options.settings.deployment_id,
+        &mut report,
+    )
+    .await;
+    report.orphaned = orphaned.len();
+
+    for profile in &plan.missing {
+        warn!(
+            "Inference profile {} is missing in AWS",
+            profile.inference_profile_arn
+        );
+    }
+    for profile in &orphaned {
+        warn!("Inference profile {} has no row", profile.arn);
+    }
+
+    if options.dry_run {
+        return Ok(report);
+    }
+    check_removal_limit(
+        stored.len(),
+        aws.len(),
+        plan.missing.len(),
+        options.max_removal_fraction,
+    )?;
+
+    for profile in &plan.missing {
+        sqlx::query!(
+            "DELETE FROM inference_profiles WHERE inference_profile_id = $1",
+            profile.inference_profile_id,
+        )
+        .execute(pool)
+        .await?;
+        report.rows_removed += 1;
+
+        if !profile.is_active {
+            continue;
+        }
+
+        let Some(owner) = ProfileOwner::stored(
+            &profile.owner_type,
+            profile.api_key_id,
+            profile.team_id,
+            profile.user_id,
+        ) else {
+            error!(
+                "Cannot recreate inference profile {} with owner type {}",
+                profile.inference_profile_arn, profile.owner_type
+            );
+            report.failures += 1;
+            continue;
+        };
+        match create_inference_profile(
+            control_plane,
+            pool,
+            owner,
+            &profile.model_name,
+            &options.settings,
+            true,
+        )
+        .await
+        {
+            Ok(_) => report.recreated += 1,
+            Err(e) => {
+                error!(
+                    "Failed to recreate inference profile for {}: {:?}",
+                    profile.model_name, e
+                );
+                report.failures += 1;
+            }
+        }
+    }
+
+    if options.delete_orphans {
+        delete_orphans(control_plane, &orphaned, &mut report).await;
+    }
+
+    Ok(report)
+}
+
+/// Reconciles every `period` until aborted. Each gateway instance runs this,
+/// but only the one holding the reconcile lock reconciles at a time.
+pub async fn continuously_reconcile_inference_profiles(
+    pool: Arc<PgPool>,
+    control_plane: impl InferenceProfileControlPlane + Clone + 'static,
+    options: ReconcileOptions,
+    period: std::time::Duration,
+) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        match reconcile_inference_profiles(&pool, &control_plane, &options).await {
+            Ok(Some(report)) if report.has_drift() => warn!(
+                missing = report.missing,
+                orphaned = report.orphaned,
+                rows_removed = report.rows_removed,
+                recreated = report.recreated,
+                orphans_deleted = report.orphans_deleted,
+                failures = report.failures,
+                dry_run = options.dry_run,
+                "Inference profiles drifted from AWS"
+            ),
+            Ok(Some(_)) => info!("Inference profiles match AWS"),
+            Ok(None) => info!("Another instance is reconciling inference profiles"),
+            Err(e) => error!("Failed to reconcile inference profiles: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockControlPlane;
+
+    fn now() -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(1_800_000_000).unwrap()
+    }
+
+    fn aws_profile(arn: &str, age: Duration) -> AwsInferenceProfile {
+        AwsInferenceProfile {
+            arn: arn.to_string(),
+            created_at: Some(now() - age),
+            name: arn.rsplit('/').next().unwrap_or_default().to_string(),
+        }
+    }
+
+    fn deployment_tags(deployment_id: &str) -> HashMap<String, String> {
+        HashMap::from([(
+            GATEWAY_DEPLOYMENT_ID_TAG.to_string(),
+            deployment_id.to_string(),
+        )])
+    }
+
+    fn stored_profile(arn: &str) -> StoredInferenceProfile {
+        StoredInferenceProfile {
+            api_key_id: None,
+            inference_profile_arn: arn.to_string(),
+            inference_profile_id: Uuid::new_v4(),
+            is_active: true,
+            model_name: "us.anthropic.claude-sonnet-4-6".to_string(),
+            owner_type: "user".to_string(),
+            team_id: None,
+            user_id: Uuid::new_v4(),
+        }
+    }
+
+    const ARN_A: &str = "arn:aws:bedrock:us-east-1:123456789012:application-inference-profile/a1";
+    const ARN_B: &str = "arn:aws:bedrock:us-east-1:123456789012:application-inference-profile/b2";
+    const ARN_C: &str = "arn:aws:bedrock:us-east-1:123456789012:application-inference-profile/c3";
+
+    #[test]
+    fn matching_profiles_have_no_drift() {
+        let plan = plan_reconciliation(
+            &[stored_profile(ARN_A)],
+            &[aws_profile(&ARN_A.to_uppercase(), Duration::days(1))],
+            Duration::hours(1),
+            now(),
+        );
+        assert_eq!(plan, ReconcilePlan::default());
+    }
+
+    #[test]
+    fn rows_without_an_aws_profile_are_missing() {
+        let stored = [stored_profile(ARN_A), stored_profile(ARN_B)];
+        let plan = plan_reconciliation(
+            &stored,
+            &[aws_profile(ARN_A, Duration::days(1))],
+            Duration::hours(1),
+            now(),
+        );
+        assert_eq!(plan.missing, vec![stored[1].clone()]);
+        assert!(plan.untracked.is_empty());
+    }
+
+    #[test]
+    fn removals_beyond_the_limit_are_refused() {
+        assert!(check_removal_limit(10, 8, 2, 0.5).is_ok());
+        assert!(check_removal_limit(10, 4, 6, 0.5).is_err());
+        assert!(check_removal_limit(10, 0, 10, 1.0).is_err());
+        assert!(check_removal_limit(0, 0, 0, 0.5).is_ok());
+    }
+
+    #[test]
+    fn only_old_profiles_without_a_row_are_untracked() {
+        let aws = [
+            aws_profile(ARN_A, Duration::days(1)),
+            aws_profile(ARN_B, Duration::days(1)),
+            aws_profile(ARN_C, Duration::minutes(5)),
+        ];
+        let plan = plan_reconciliation(&[stored_profile(ARN_B)], &aws, Duration::hours(1), now());
+        assert_eq!(plan.untracked, vec![aws[0].clone()]);
+    }
+
+    #[test]
+    fn only_profiles_of_this_deployment_belong_to_it() {
+        assert!(belongs_to_deployment(&deployment_tags("prod"), "prod"));
+        assert!(!belongs_to_deployment(&deployment_tags("staging"), "prod"));
+        assert!(!belongs_to_deployment(&HashMap::new(), "prod"));
+        assert!(!belongs_to_deployment(&deployment_tags(""), ""));
+    }
+
+    #[tokio::test]
+    async fn orphans_are_untracked_profiles_of_this_deployment() {
+        let control_plane = MockControlPlane {
+            failing_arns: vec![ARN_C.to_string()],
+            tags: HashMap::from([
+                (ARN_A.to_string(), deployment_tags("prod")),
+                (ARN_B.to_string(), deployment_tags("staging")),
+            ]),
+            ..Default::default()
+        };
+        let untracked = vec![
+            aws_profile(ARN_A, Duration::days(1)),
+            aws_profile(ARN_B, Duration::days(1)),
+            aws_profile(ARN_C, Duration::days(1)),
+        ];
+
+        let mut report = ReconcileReport::default();
+        let orphans = find_orphans(&control_plane, untracked.clone(), "prod", &mut report).await;
+        assert_eq!(orphans, vec![untracked[0].clone()]);
+        assert_eq!(report.failures, 1);
+
+        let orphans = find_orphans(&control_plane, untracked, "", &mut report).await;
+        assert!(orphans.is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_orphans_counts_failures() {
+        let control_plane = MockControlPlane {
+            failing_arns: vec![ARN_B.to_string()],
+            ..Default::default()
+        };
+        let orphans = [
+            aws_profile(ARN_A, Duration::days(1)),
+            aws_profile(ARN_B, Duration::days(1)),
+        ];
+
+        let mut report = ReconcileReport::default();
+        delete_orphans(&control_plane, &orphans, &mut report).await;
+
+        assert_eq!(report.orphans_deleted, 1);
+        assert_eq!(report.failures, 1);
+        assert_eq!(*control_plane.deleted.lock().unwrap(), vec![ARN_A]);
+    }
+}
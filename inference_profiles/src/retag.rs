@@ -0,0 +1,193 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use serde_json::Value;
+use sqlx::PgPool;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    InferenceProfileControlPlane, InferenceProfileSettings, TagContext, attributes_from_json,
+    gateway_tags, list_tags,
+};
+
+/// Counts from [`retag_inference_profiles`].
+#[derive(Debug, Default, PartialEq)]
+pub struct RetagReport {
+    /// Profiles found both in the table and in AWS.
+    pub checked: usize,
+    pub failures: usize,
+    /// Profiles whose tags differ from what the templates now produce.
+    pub outdated: usize,
+    pub retagged: usize,
+}
+
+struct StoredProfileOwner {
+    api_key_id: Option<Uuid>,
+    attributes: Value,
+    cost_center: Option<String>,
+    inference_profile_arn: String,
+    model_id: Uuid,
+    model_name: String,
+    team_id: Option<Uuid>,
+    team_name: Option<String>,
+    user_email: String,
+    user_id: Uuid,
+}
+
+/// Returns the tags in `desired` that are missing from `current` or have a
+/// different value there.
+pub(crate) fn changed_tags(
+    desired: Vec<(String, String)>,
+    current: &HashMap<String, String>,
+) -> Vec<(String, String)> {
+    desired
+        .into_iter()
+        .filter(|(key, value)| current.get(key) != Some(value))
+        .collect()
+}
+
+/// Applies the gateway's tags and the configured tag templates to existing
+/// profiles, e.g. after the templates changed. Tags no longer produced by
+/// any template are left in place.
+pub async fn retag_inference_profiles(
+    pool: &PgPool,
+    control_plane: &impl InferenceProfileControlPlane,
+    settings: &InferenceProfileSettings,
+    dry_run: bool,
+) -> Result<RetagReport> {
+    let stored = sqlx::query_as!(
+        StoredProfileOwner,
+        r#"
+        SELECT
+            ip.api_key_id,
+            u.attributes,
+            u.cost_center,
+            ip.inference_profile_arn,
+            m.model_id,
+            m.model_name,
+            ip.team_id,
+            t.team_name AS "team_name?",
+            u.user_email,
+            u.user_id
+        FROM inference_profiles ip
+        JOIN models m ON m.model_id = ip.model_id
+        JOIN users u ON u.user_id = ip.user_id
+        LEFT JOIN teams t ON t.team_id = ip.team_id
+        ORDER BY ip.created_at
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    // Profiles missing in AWS are left to the reconciler.
+    let aws_arns = control_plane
+        .list()
+        .await?
+        .into_iter()
+        .map(|profile| profile.arn.to_lowercase())
+        .collect::<HashSet<_>>();
+    let stored = stored
+        .into_iter()
+        .filter(|profile| aws_arns.contains(&profile.inference_profile_arn.to_lowercase()))
+        .collect::<Vec<_>>();
+
+    let mut report = RetagReport::default();
+    let mut aws_tags = HashMap::new();
+    let arns = stored
+        .iter()
+        .map(|profile| profile.inference_profile_arn.clone())
+        .collect();
+    for (arn, tags) in list_tags(control_plane, arns).await {
+        match tags {
+            Ok(tags) => {
+                aws_tags.insert(arn, tags);
+            }
+            Err(e) => {
+                error!("Failed to list tags of inference profile {}: {:?}", arn, e);
+                report.failures += 1;
+            }
+        }
+    }
+
+    for profile in stored {
+        let Some(current) = aws_tags.get(&profile.inference_profile_arn) else {
+            continue;
+        };
+        report.checked += 1;
+
+        let mut desired = gateway_tags(
+            &settings.deployment_id,
+            profile.user_id,
+            profile.model_id,
+            profile.api_key_id,
+            profile.team_id,
+            profile.team_name.as_deref(),
+        );
+        desired.extend(settings.tags.render(&TagContext {
+            attributes: attributes_from_json(profile.attributes),
+            cost_center: profile.cost_center,
+            model_name: profile.model_name,
+            team_id: profile.team_id,
+            team_name: profile.team_name,
+            user_email: profile.user_email,
+            user_id: profile.user_id,
+        }));
+
+        let changed = changed_tags(desired, current);
+        if changed.is_empty() {
+            continue;
+        }
+        report.outdated += 1;
+
+        if dry_run {
+            info!(
+                "Would retag inference profile {}: {:?}",
+                profile.inference_profile_arn, changed
+            );
+            continue;
+        }
+
+        match control_plane
+            .tag(&profile.inference_profile_arn, changed)
+            .await
+        {
+            Ok(()) => report.retagged += 1,
+            Err(e) => {
+                error!(
+                    "Failed to retag inference profile {}: {:?}",
+                    profile.inference_profile_arn, e
+                );
+                report.failures += 1;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_missing_and_changed_tags_are_applied() {
+        let current = HashMap::from([
+            ("CostCenter".to_string(), "CC-1".to_string()),
+            ("Team".to_string(), "platform".to_string()),
+        ]);
+        let desired = vec![
+            ("CostCenter".to_string(), "CC-2".to_string()),
+            ("Environment".to_string(), "production".to_string()),
+            ("Team".to_string(), "platform".to_string()),
+        ];
+
+        assert_eq!(
+            changed_tags(desired, &current),
+            vec![
+                ("CostCenter".to_string(), "CC-2".to_string()),
+                ("Environment".to_string(), "production".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use uuid::Uuid;
+
+use crate::{
+    GATEWAY_API_KEY_ID_TAG, GATEWAY_DEPLOYMENT_ID_TAG, GATEWAY_MODEL_ID_TAG, GATEWAY_TEAM_ID_TAG,
+    GATEWAY_TEAM_TAG, GATEWAY_USER_ID_TAG,
+};
+
+const MAX_TAG_KEY_CHARS: usize = 128;
+const MAX_TAG_VALUE_CHARS: usize = 256;
+
+/// The values a tag template can refer to, for one inference profile.
+#[derive(Clone, Debug, Default)]
+pub struct TagContext {
+    /// User attributes copied from identity provider claims at sign-in.
+    pub attributes: BTreeMap<String, String>,
+    pub cost_center: Option<String>,
+    pub model_name: String,
+    pub team_id: Option<Uuid>,
+    pub team_name: Option<String>,
+    pub user_email: String,
+    pub user_id: Uuid,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Attribute(String),
+    CostCenter,
+    Literal(String),
+    ModelName,
+    TeamId,
+    TeamName,
+    UserEmail,
+    UserId,
+}
+
+impl Segment {
+    fn placeholder(name: &str) -> Result<Self> {
+        if let Some(attribute) = name.strip_prefix("attribute:") {
+            return Ok(Segment::Attribute(attribute.to_string()));
+        }
+        Ok(match name {
+            "cost_center" => Segment::CostCenter,
+            "model_name" => Segment::ModelName,
+            "team_id" => Segment::TeamId,
+            "team_name" => Segment::TeamName,
+            "user_email" => Segment::UserEmail,
+            "user_id" => Segment::UserId,
+            _ => bail!("Unknown tag template placeholder {{{name}}}"),
+        })
+    }
+
+    fn render(&self, context: &TagContext) -> Option<String> {
+        match self {
+            Segment::Attribute(attribute) => context.attributes.get(attribute).cloned(),
+            Segment::CostCenter => context.cost_center.clone(),
+            Segment::Literal(literal) => Some(literal.clone()),
+            Segment::ModelName => Some(context.model_name.clone()),
+            Segment::TeamId => context.team_id.map(|team_id| team_id.to_string()),
+            Segment::TeamName => context.team_name.clone(),
+            Segment::UserEmail => Some(context.user_email.clone()),
+            Segment::UserId => Some(context.user_id.to_string()),
+        }
+    }
+}
+
+fn parse_template(template: &str) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_string()));
+        }
+        let Some(end) = rest[start..].find('}') else {
+            bail!("Unclosed placeholder in tag template {template:?}");
+        };
+        segments.push(Segment::placeholder(&rest[start + 1..start + end])?);
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.to_string()));
+    }
+    Ok(segments)
+}
+
+fn is_allowed_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c.is_whitespace() || "_.:/=+-@".contains(c)
+}
+
+/// Replaces characters AWS does not allow in tag values and truncates to the
+/// maximum length.
+pub fn sanitize_tag_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if is_allowed_tag_char(c) { c } else { '_' })
+        .take(MAX_TAG_VALUE_CHARS)
+        .collect()
+}
+
+/// Cost allocation tags applied to inference profiles in addition to the
+/// gateway's own, keyed by tag key. Values are templates such as
+/// `"{team_name}"` or `"gateway-{attribute:department}"`.
+#[derive(Clone, Debug, Default)]
+pub struct TagTemplates {
+    templates: Vec<(String, Vec<Segment>)>,
+}
+
+impl TagTemplates {
+    /// Fails on unknown placeholders and on keys AWS or the gateway reserve.
+    pub fn parse(templates: &BTreeMap<String, String>) -> Result<Self> {
+        let reserved = [
+            GATEWAY_API_KEY_ID_TAG,
+            GATEWAY_DEPLOYMENT_ID_TAG,
+            GATEWAY_MODEL_ID_TAG,
+            GATEWAY_TEAM_ID_TAG,
+            GATEWAY_TEAM_TAG,
+            GATEWAY_USER_ID_TAG,
+        ];
+
+        let mut parsed = Vec::new();
+        for (key, template) in templates {
+            if key.is_empty()
+                || key.chars().count() > MAX_TAG_KEY_CHARS
+                || !key.chars().all(is_allowed_tag_char)
+            {
+                bail!("Invalid tag key {key:?}");
+            }
+            if key.to_lowercase().starts_with("aws:") || reserved.contains(&key.as_str()) {
+                bail!("Tag key {key:?} is reserved");
+            }
+            parsed.push((key.clone(), parse_template(template)?));
+        }
+
+        Ok(Self { templates: parsed })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    /// Renders the tags for a profile. A tag referring to a value the profile
+    /// does not have, such as the team of a personal profile, is left out.
+    pub fn render(&self, context: &TagContext) -> Vec<(String, String)> {
+        self.templates
+            .iter()
+            .filter_map(|(key, segments)| {
+                let value = segments
+                    .iter()
+                    .map(|segment| segment.render(context))
+                    .collect::<Option<String>>()?;
+                Some((key.clone(), sanitize_tag_value(&value)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates(entries: &[(&str, &str)]) -> Result<TagTemplates> {
+        TagTemplates::parse(
+            &entries
+                .iter()
+                .map(|(key, template)| (key.to_string(), template.to_string()))
+                .collect(),
+        )
+    }
+
+    fn context() -> TagContext {
+        TagContext {
+            attributes: BTreeMap::from([("department".to_string(), "R&D".to_string())]),
+            cost_center: Some("CC-42".to_string()),
+            model_name: "us.anthropic.claude-opus-4-6-v1".to_string(),
+            user_email: "jane@example.com".to_string(),
+            user_id: Uuid::nil(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn renders_placeholders_and_literals() {
+        let templates = templates(&[
+            ("CostCenter", "{cost_center}"),
+            ("Department", "dept-{attribute:department}"),
+            ("Environment", "production"),
+            ("User", "{user_email}"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            templates.render(&context()),
+            vec![
+                ("CostCenter".to_string(), "CC-42".to_string()),
+                ("Department".to_string(), "dept-R_D".to_string()),
+                ("Environment".to_string(), "production".to_string()),
+                ("User".to_string(), "jane@example.com".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tags_with_missing_values_are_left_out() {
+        let templates = templates(&[
+            ("Team", "{team_name}"),
+            ("Title", "{attribute:title}"),
+            ("Model", "{model_name}"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            templates.render(&context()),
+            vec![(
+                "Model".to_string(),
+                "us.anthropic.claude-opus-4-6-v1".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_templates_and_reserved_keys() {
+        assert!(templates(&[("Team", "{team}")]).is_err());
+        assert!(templates(&[("Team", "{team_name")]).is_err());
+        assert!(templates(&[("aws:createdBy", "gateway")]).is_err());
+        assert!(templates(&[(GATEWAY_USER_ID_TAG, "{user_id}")]).is_err());
+        assert!(templates(&[("Bad#Key", "gateway")]).is_err());
+    }
+
+    #[test]
+    fn sanitize_truncates_long_values() {
+        assert_eq!(sanitize_tag_value(&"a".repeat(300)).len(), 256);
+    }
+}
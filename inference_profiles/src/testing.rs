@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Result;
+
+use crate::{AwsInferenceProfile, InferenceProfileControlPlane, NewAwsInferenceProfile};
+
+/// Tags applied to an inference profile, by ARN.
+type TagCall = (String, Vec<(String, String)>);
+
+/// An in-memory [`InferenceProfileControlPlane`] that records deletions and
+/// tagging.
+#[derive(Default)]
+pub(crate) struct MockControlPlane {
+    pub deleted: Mutex<Vec<String>>,
+    pub failing_arns: Vec<String>,
+    pub profiles: Vec<AwsInferenceProfile>,
+    /// Tags of `profiles`, by ARN.
+    pub tags: HashMap<String, HashMap<String, String>>,
+    pub tagged: Mutex<Vec<TagCall>>,
+}
+
+impl InferenceProfileControlPlane for MockControlPlane {
+    async fn create(&self, profile: NewAwsInferenceProfile) -> Result<String> {
+        Ok(format!(
+            "arn:aws:bedrock:us-east-1:123456789012:application-inference-profile/{}",
+            profile.name
+        ))
+    }
+
+    async fn delete(&self, arn: &str) -> Result<()> {
+        if self.failing_arns.iter().any(|failing| failing == arn) {
+            anyhow::bail!("AccessDeniedException");
+        }
+        self.deleted.lock().unwrap().push(arn.to_string());
+        Ok(())
+    }
+
+    async fn tag(&self, arn: &str, tags: Vec<(String, String)>) -> Result<()> {
+        if self.failing_arns.iter().any(|failing| failing == arn) {
+            anyhow::bail!("AccessDeniedException");
+        }
+        self.tagged.lock().unwrap().push((arn.to_string(), tags));
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<AwsInferenceProfile>> {
+        Ok(self.profiles.clone())
+    }
+
+    async fn tags(&self, arn: &str) -> Result<HashMap<String, String>> {
+        if self.failing_arns.iter().any(|failing| failing == arn) {
+            anyhow::bail!("AccessDeniedException");
+        }
+        Ok(self.tags.get(arn).cloned().unwrap_or_default())
+    }
+}
@@ -0,0 +1,69 @@
+-- Prices are USD per million tokens. The long_context_* prices apply when a
+-- request's total input (including cache reads and writes) exceeds 200K tokens;
+-- NULL means the model has no long-context premium.
+create table if not exists model_prices (
+    cache_read_usd_per_mtok double precision not null default 0,
+    cache_write_usd_per_mtok double precision not null default 0,
+    constraint fk_model_id foreign key (model_id) references models(model_id) on delete cascade,
+    created_at timestamptz not null default now(),
+    input_usd_per_mtok double precision not null,
+    long_context_cache_read_usd_per_mtok double precision,
+    long_context_cache_write_usd_per_mtok double precision,
+    long_context_input_usd_per_mtok double precision,
+    long_context_output_usd_per_mtok double precision,
+    model_id uuid primary key,
+    output_usd_per_mtok double precision not null,
+    updated_at timestamptz not null default now()
+);
+
+INSERT INTO model_prices (model_id, input_usd_per_mtok, output_usd_per_mtok, cache_read_usd_per_mtok, cache_write_usd_per_mtok)
+SELECT model_id, 1.00, 5.00, 0.10, 1.25
+FROM models
+WHERE model_name IN (
+    'global.anthropic.claude-haiku-4-5-20251001-v1:0',
+    'us.anthropic.claude-haiku-4-5-20251001-v1:0'
+);
+
+INSERT INTO model_prices (model_id, input_usd_per_mtok, output_usd_per_mtok, cache_read_usd_per_mtok, cache_write_usd_per_mtok)
+SELECT model_id, 5.00, 25.00, 0.50, 6.25
+FROM models
+WHERE model_name = 'us.anthropic.claude-opus-4-5-20251101-v1:0';
+
+INSERT INTO model_prices (
+    model_id,
+    input_usd_per_mtok,
+    output_usd_per_mtok,
+    cache_read_usd_per_mtok,
+    cache_write_usd_per_mtok,
+    long_context_input_usd_per_mtok,
+    long_context_output_usd_per_mtok,
+    long_context_cache_read_usd_per_mtok,
+    long_context_cache_write_usd_per_mtok
+)
+SELECT model_id, 5.00, 25.00, 0.50, 6.25, 10.00, 37.50, 1.00, 12.50
+FROM models
+WHERE model_name IN (
+    'global.anthropic.claude-opus-4-6-v1',
+    'global.anthropic.claude-opus-4-7',
+    'us.anthropic.claude-opus-4-6-v1',
+    'us.anthropic.claude-opus-4-7'
+);
+
+INSERT INTO model_prices (
+    model_id,
+    input_usd_per_mtok,
+    output_usd_per_mtok,
+    cache_read_usd_per_mtok,
+    cache_write_usd_per_mtok,
+    long_context_input_usd_per_mtok,
+    long_context_output_usd_per_mtok,
+    long_context_cache_read_usd_per_mtok,
+    long_context_cache_write_usd_per_mtok
+)
+SELECT model_id, 3.00, 15.00, 0.30, 3.75, 6.00, 22.50, 0.60, 7.50
+FROM models
+WHERE model_name IN (
+    'global.anthropic.claude-sonnet-4-6',
+    'us.anthropic.claude-sonnet-4-5-20250929-v1:0',
+    'us.anthropic.claude-sonnet-4-6'
+);
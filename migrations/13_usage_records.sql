@@ -0,0 +1,19 @@
+create table if not exists usage_records (
+    api_key_id uuid,
+    cache_read_input_tokens bigint not null default 0,
+    cache_write_input_tokens bigint not null default 0,
+    constraint fk_api_key_id foreign key (api_key_id) references api_keys(api_key_id),
+    constraint fk_model_id foreign key (model_id) references models(model_id) on delete set null,
+    constraint fk_user_id foreign key (user_id) references users(user_id),
+    cost_usd_micros bigint not null default 0,
+    created_at timestamptz not null default now(),
+    input_tokens bigint not null default 0,
+    model_id uuid,
+    model_name varchar(255) not null,
+    output_tokens bigint not null default 0,
+    usage_record_id uuid primary key default uuid_generate_v4(),
+    user_id uuid not null
+);
+
+create index if not exists idx_usage_records_created_at on usage_records (created_at);
+create index if not exists idx_usage_records_user_id_created_at on usage_records (user_id, created_at);
@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN cost_center varchar(255);
@@ -0,0 +1,18 @@
+ALTER TABLE users ADD COLUMN capture_enabled boolean NOT NULL DEFAULT false;
+ALTER TABLE api_keys ADD COLUMN capture_enabled boolean;
+
+create table if not exists captures (
+    api_key_id uuid,
+    capture_id uuid primary key default uuid_generate_v4(),
+    constraint fk_api_key_id foreign key (api_key_id) references api_keys(api_key_id),
+    constraint fk_user_id foreign key (user_id) references users(user_id),
+    created_at timestamptz not null default now(),
+    model_name varchar(255) not null,
+    request_body text not null,
+    response_text text not null,
+    stop_reason varchar(255),
+    user_id uuid not null
+);
+
+create index if not exists idx_captures_created_at on captures (created_at);
+create index if not exists idx_captures_user_id_created_at on captures (user_id, created_at);
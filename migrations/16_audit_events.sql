@@ -0,0 +1,24 @@
+create table if not exists audit_events (
+    action varchar(255) not null,
+    actor varchar(255),
+    audit_event_id uuid primary key default uuid_generate_v4(),
+    created_at timestamptz not null default now(),
+    details jsonb,
+    forwarded_for text,
+    ip_address varchar(45),
+    target varchar(255),
+    user_agent text
+);
+
+create index if not exists idx_audit_events_created_at on audit_events (created_at);
+create index if not exists idx_audit_events_actor_created_at on audit_events (actor, created_at);
+
+create or replace function reject_audit_event_modification() returns trigger as $$
+begin
+    raise exception 'audit_events is append-only';
+end;
+$$ language plpgsql;
+
+create or replace trigger audit_events_append_only
+    before update or delete on audit_events
+    for each row execute function reject_audit_event_modification();
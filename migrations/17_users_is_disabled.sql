@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN is_disabled boolean NOT NULL DEFAULT false;
@@ -0,0 +1,3 @@
+ALTER TABLE users ADD COLUMN external_id varchar(255);
+
+create unique index if not exists idx_users_external_id on users (external_id);
@@ -0,0 +1,31 @@
+-- budget_usd_micros is a monthly budget; NULL means unlimited.
+create table if not exists teams (
+    budget_usd_micros bigint,
+    created_at timestamptz not null default now(),
+    team_id uuid primary key default uuid_generate_v4(),
+    team_name varchar(255) not null unique,
+    updated_at timestamptz not null default now()
+);
+
+create table if not exists team_members (
+    constraint fk_team_id foreign key (team_id) references teams(team_id) on delete cascade,
+    constraint fk_user_id foreign key (user_id) references users(user_id),
+    created_at timestamptz not null default now(),
+    primary key (team_id, user_id),
+    team_id uuid not null,
+    user_id uuid not null
+);
+
+create index if not exists idx_team_members_user_id on team_members (user_id);
+
+ALTER TABLE api_keys ADD COLUMN team_id uuid REFERENCES teams(team_id);
+ALTER TABLE usage_records ADD COLUMN team_id uuid REFERENCES teams(team_id);
+ALTER TABLE inference_profiles ADD COLUMN team_id uuid REFERENCES teams(team_id);
+
+-- Team keys share one inference profile per (team, model); personal keys keep
+-- one per (user, model).
+ALTER TABLE inference_profiles DROP CONSTRAINT uq_inference_profiles_user_id_model_id;
+create unique index if not exists uq_inference_profiles_user_id_model_id on inference_profiles (user_id, model_id) where team_id is null;
+create unique index if not exists uq_inference_profiles_team_id_model_id on inference_profiles (team_id, model_id) where team_id is not null;
+
+create index if not exists idx_usage_records_team_id_created_at on usage_records (team_id, created_at);
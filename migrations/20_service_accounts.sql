@@ -0,0 +1,8 @@
+-- Service accounts are users rows that never log in. scopes restricts which
+-- endpoints their keys may call (NULL means unrestricted, as for humans) and
+-- budget_usd_micros is a monthly budget (NULL means unlimited).
+ALTER TABLE users ADD COLUMN is_service_account boolean NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN description text;
+ALTER TABLE users ADD COLUMN owner_team_id uuid REFERENCES teams(team_id);
+ALTER TABLE users ADD COLUMN scopes text[];
+ALTER TABLE users ADD COLUMN budget_usd_micros bigint;
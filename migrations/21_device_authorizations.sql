@@ -0,0 +1,21 @@
+-- Pending OAuth 2.0 device authorization grants (RFC 8628) from CLI tools.
+-- Only a SHA-256 hash of the device code is stored. status moves from
+-- pending to approved or denied, and to consumed once the key is issued.
+create table if not exists device_authorizations (
+    client_name varchar(255),
+    constraint fk_user_id foreign key (user_id) references users(user_id),
+    created_at timestamptz not null default now(),
+    device_code_hash varchar(64) primary key,
+    expires_at timestamptz not null,
+    last_polled_at timestamptz,
+    scopes text[],
+    status varchar(16) not null default 'pending',
+    user_code varchar(16) not null unique,
+    user_id uuid
+);
+
+create index if not exists idx_device_authorizations_expires_at on device_authorizations (expires_at);
+
+-- Per-key scopes for keys issued through the device flow; NULL falls back to
+-- the owning account's scopes.
+ALTER TABLE api_keys ADD COLUMN scopes text[];
@@ -0,0 +1,10 @@
+-- Denylist of short-lived session tokens revoked before they expire. Tokens
+-- are validated without the database, so each instance loads this table into
+-- memory; rows are only needed until the token's own expiry.
+create table if not exists revoked_session_tokens (
+    expires_at timestamptz not null,
+    jti uuid primary key,
+    revoked_at timestamptz not null default now()
+);
+
+create index if not exists idx_revoked_session_tokens_expires_at on revoked_session_tokens (expires_at);
@@ -0,0 +1,63 @@
+-- Tell gateway instances to drop cached validations and budget checks when
+-- the rows they were built from change. Keys are identified by the SHA-256
+-- hash the cache uses, never in plain text, and by id so cached session
+-- token validations of the key are dropped too.
+create or replace function notify_validation_cache() returns trigger as $$
+declare
+    target record;
+begin
+    if TG_LEVEL = 'STATEMENT' then
+        perform pg_notify('validation_cache', 'all');
+        return null;
+    end if;
+
+    if TG_OP = 'DELETE' then
+        target := OLD;
+    else
+        target := NEW;
+    end if;
+
+    if TG_TABLE_NAME = 'api_keys' then
+        perform pg_notify('validation_cache', 'api_key:' || encode(sha256(convert_to(target.api_key, 'UTF8')), 'hex'));
+        perform pg_notify('validation_cache', 'api_key_id:' || target.api_key_id);
+    elsif TG_TABLE_NAME = 'users' then
+        perform pg_notify('validation_cache', 'user:' || target.user_id);
+    elsif TG_TABLE_NAME = 'inference_profiles' then
+        if target.team_id is not null then
+            perform pg_notify('validation_cache', 'team:' || target.team_id);
+        else
+            perform pg_notify('validation_cache', 'user:' || target.user_id);
+        end if;
+    elsif TG_TABLE_NAME in ('teams', 'team_members') then
+        perform pg_notify('validation_cache', 'team:' || target.team_id);
+    else
+        perform pg_notify('validation_cache', 'all');
+    end if;
+
+    return null;
+end;
+$$ language plpgsql;
+
+create or replace trigger api_keys_notify_validation_cache
+    after update or delete on api_keys
+    for each row execute function notify_validation_cache();
+
+create or replace trigger users_notify_validation_cache
+    after update or delete on users
+    for each row execute function notify_validation_cache();
+
+create or replace trigger inference_profiles_notify_validation_cache
+    after insert or update or delete on inference_profiles
+    for each row execute function notify_validation_cache();
+
+create or replace trigger models_notify_validation_cache
+    after insert or update or delete on models
+    for each statement execute function notify_validation_cache();
+
+create or replace trigger teams_notify_validation_cache
+    after update or delete on teams
+    for each row execute function notify_validation_cache();
+
+create or replace trigger team_members_notify_validation_cache
+    after insert or delete on team_members
+    for each row execute function notify_validation_cache();
@@ -0,0 +1,9 @@
+-- An instance creating an inference profile reserves it here first, keyed by
+-- the same key as the creation advisory lock, so that the Bedrock call is
+-- made outside any transaction while other instances wait for it instead of
+-- creating a duplicate. A reservation whose instance died is taken over once
+-- it is old enough.
+create table if not exists inference_profile_reservations (
+    lock_key text primary key,
+    reserved_at timestamptz not null default now()
+);
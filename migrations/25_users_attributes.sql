@@ -0,0 +1,3 @@
+-- Identity provider claims listed in oidc_attribute_claims, copied at sign-in
+-- so that inference profile tag templates can refer to them.
+ALTER TABLE users ADD COLUMN attributes jsonb NOT NULL DEFAULT '{}'::jsonb;
@@ -0,0 +1,20 @@
+-- owner_type is the unit an inference profile attributes spend to: a user,
+-- a team or a single API key (see inference_profile_granularity). Key
+-- profiles keep the key's team_id, if any, for tagging. Existing rows are
+-- user or team profiles and stay in use under the default granularity.
+ALTER TABLE inference_profiles ADD COLUMN owner_type text NOT NULL DEFAULT 'user';
+ALTER TABLE inference_profiles ADD COLUMN api_key_id uuid REFERENCES api_keys(api_key_id);
+
+UPDATE inference_profiles SET owner_type = 'team' WHERE team_id IS NOT NULL;
+
+ALTER TABLE inference_profiles ADD CONSTRAINT ck_inference_profiles_owner_type CHECK (
+    (owner_type = 'user' AND team_id IS NULL AND api_key_id IS NULL)
+    OR (owner_type = 'team' AND team_id IS NOT NULL AND api_key_id IS NULL)
+    OR (owner_type = 'key' AND api_key_id IS NOT NULL)
+);
+
+DROP INDEX IF EXISTS uq_inference_profiles_user_id_model_id;
+DROP INDEX IF EXISTS uq_inference_profiles_team_id_model_id;
+create unique index if not exists uq_inference_profiles_user_id_model_id on inference_profiles (user_id, model_id) where owner_type = 'user';
+create unique index if not exists uq_inference_profiles_team_id_model_id on inference_profiles (team_id, model_id) where owner_type = 'team';
+create unique index if not exists uq_inference_profiles_api_key_id_model_id on inference_profiles (api_key_id, model_id) where owner_type = 'key';
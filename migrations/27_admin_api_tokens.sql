@@ -0,0 +1,12 @@
+-- Bearer tokens for the /api/v1/admin JSON API, for automation such as
+-- Terraform or scripts. Only a SHA-256 hash of each token is stored; the
+-- token itself is shown once, when created.
+create table if not exists admin_api_tokens (
+    admin_api_token_id uuid primary key default uuid_generate_v4(),
+    created_at timestamptz not null default now(),
+    created_by varchar(255) not null,
+    last_used_at timestamptz,
+    name varchar(255) not null,
+    revoked_at timestamptz,
+    token_hash varchar(64) not null unique
+);
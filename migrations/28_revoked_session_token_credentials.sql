@@ -0,0 +1,41 @@
+-- API keys and users whose session tokens must no longer be accepted because
+-- they were disabled or deleted. Tokens issued up to revoked_at are denied, so
+-- a re-enabled credential can exchange new ones. Rows are only needed for the
+-- session token maximum TTL after revocation.
+create table if not exists revoked_session_token_credentials (
+    credential_id uuid primary key,
+    revoked_at timestamptz not null default now()
+);
+
+create index if not exists idx_revoked_session_token_credentials_revoked_at on revoked_session_token_credentials (revoked_at);
+
+create or replace function revoke_session_token_credential() returns trigger as $$
+declare
+    revoked_id uuid;
+begin
+    if TG_OP = 'UPDATE' and (OLD.is_disabled or not NEW.is_disabled) then
+        return null;
+    end if;
+
+    if TG_TABLE_NAME = 'api_keys' then
+        revoked_id := OLD.api_key_id;
+    else
+        revoked_id := OLD.user_id;
+    end if;
+
+    insert into revoked_session_token_credentials (credential_id)
+    values (revoked_id)
+    on conflict (credential_id)
+    do update set revoked_at = now();
+
+    return null;
+end;
+$$ language plpgsql;
+
+create or replace trigger api_keys_revoke_session_tokens
+    after update of is_disabled or delete on api_keys
+    for each row execute function revoke_session_token_credential();
+
+create or replace trigger users_revoke_session_tokens
+    after update of is_disabled or delete on users
+    for each row execute function revoke_session_token_credential();
@@ -70,8 +70,29 @@ pub async fn create_model(pool: &PgPool, model_name: &str) -> anyhow::Result<()>
     Ok(())
 }
 
-pub async fn disable_model(pool: &PgPool, model_name: &str) -> anyhow::Result<()> {
-    sqlx::query!(
+/// Returns the model, including disabled and protected ones.
+pub async fn get_model(pool: &PgPool, model_name: &str) -> anyhow::Result<Option<Model>> {
+    let model = sqlx::query_as!(
+        Model,
+        r#"
+        SELECT
+            model_name,
+            protected,
+            is_disabled
+        FROM models
+        WHERE model_name = $1
+        "#,
+        model_name.to_lowercase()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(model)
+}
+
+/// Returns false if the model does not exist or is protected.
+pub async fn disable_model(pool: &PgPool, model_name: &str) -> anyhow::Result<bool> {
+    let updated = sqlx::query!(
         r#"
         UPDATE models
         SET is_disabled = TRUE, updated_at = now()
@@ -82,11 +103,12 @@ pub async fn disable_model(pool: &PgPool, model_name: &str) -> anyhow::Result<()
     .execute(pool)
     .await?;
 
-    Ok(())
+    Ok(updated.rows_affected() > 0)
 }
 
-pub async fn enable_model(pool: &PgPool, model_name: &str) -> anyhow::Result<()> {
-    sqlx::query!(
+/// Returns false if the model does not exist or is protected.
+pub async fn enable_model(pool: &PgPool, model_name: &str) -> anyhow::Result<bool> {
+    let updated = sqlx::query!(
         r#"
         UPDATE models
         SET is_disabled = FALSE, updated_at = now()
@@ -97,11 +119,34 @@ pub async fn enable_model(pool: &PgPool, model_name: &str) -> anyhow::Result<()>
     .execute(pool)
     .await?;
 
-    Ok(())
+    Ok(updated.rows_affected() > 0)
 }
 
-pub async fn delete_model(pool: &PgPool, model_name: &str) -> anyhow::Result<()> {
-    sqlx::query!(
+/// Protected models cannot be disabled, enabled or deleted until they are
+/// unprotected. Returns false if the model does not exist.
+pub async fn set_model_protected(
+    pool: &PgPool,
+    model_name: &str,
+    protected: bool,
+) -> anyhow::Result<bool> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE models
+        SET protected = $2, updated_at = now()
+        WHERE model_name = $1
+        "#,
+        model_name.to_lowercase(),
+        protected,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(updated.rows_affected() > 0)
+}
+
+/// Returns false if the model does not exist or is protected.
+pub async fn delete_model(pool: &PgPool, model_name: &str) -> anyhow::Result<bool> {
+    let deleted = sqlx::query!(
         r#"
         DELETE FROM models
         WHERE model_name = $1 AND protected = false
@@ -111,7 +156,7 @@ pub async fn delete_model(pool: &PgPool, model_name: &str) -> anyhow::Result<()>
     .execute(pool)
     .await?;
 
-    Ok(())
+    Ok(deleted.rows_affected() > 0)
 }
 
 pub fn to_models_response(model_names: &[String]) -> ModelsResponse {
@@ -1,6 +1,6 @@
 use anyhow::Error as AnyhowError;
 use aws_sdk_bedrockruntime::{
-    error::SdkError,
+    error::{ProvideErrorMetadata, SdkError},
     operation::{converse_stream::ConverseStreamError, count_tokens::CountTokensError},
 };
 use axum::{http::StatusCode, response::IntoResponse};
@@ -54,6 +54,28 @@ where
     }
 }
 
+fn sdk_error_type<E: ProvideErrorMetadata>(err: &SdkError<E>) -> String {
+    match err {
+        SdkError::ServiceError(e) => e.err().code().unwrap_or("Unknown").to_string(),
+        SdkError::TimeoutError(_) => "TimeoutError".to_string(),
+        SdkError::DispatchFailure(_) => "DispatchFailure".to_string(),
+        SdkError::ResponseError(_) => "ResponseError".to_string(),
+        SdkError::ConstructionFailure(_) => "ConstructionFailure".to_string(),
+        _ => "Unknown".to_string(),
+    }
+}
+
+/// Returns the Bedrock exception type (e.g. `ThrottlingException`) if `err`
+/// wraps a Bedrock runtime SDK error.
+pub fn bedrock_exception_type(err: &AnyhowError) -> Option<String> {
+    err.downcast_ref::<SdkError<ConverseStreamError>>()
+        .map(sdk_error_type)
+        .or_else(|| {
+            err.downcast_ref::<SdkError<CountTokensError>>()
+                .map(sdk_error_type)
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,4 +116,22 @@ mod tests {
         let body = response.into_body().collect().await.unwrap().to_bytes();
         assert_eq!(String::from_utf8(body.to_vec()).unwrap(), expected);
     }
+
+    #[test]
+    fn bedrock_exception_type_reads_error_code() {
+        let raw = SmithyResponse::new(SmithyStatusCode::try_from(429).unwrap(), SdkBody::empty());
+        let err = ConverseStreamError::generic(
+            ErrorMetadata::builder()
+                .code("ThrottlingException")
+                .message("Too many requests")
+                .build(),
+        );
+        let sdk_err: SdkError<ConverseStreamError> = SdkError::service_error(err, raw);
+
+        assert_eq!(
+            bedrock_exception_type(&anyhow::Error::from(sdk_err)).as_deref(),
+            Some("ThrottlingException")
+        );
+        assert_eq!(bedrock_exception_type(&anyhow::anyhow!("other")), None);
+    }
 }
@@ -1,15 +1,28 @@
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
 use aws_sdk_bedrockruntime::Client;
 use axum::{
     extract::{Query, State},
+    http::StatusCode,
     response::{IntoResponse, Redirect, Response},
 };
+use captures::CaptureSink;
 use chrono::{DateTime, Utc};
-use handlers::CallbackQuery;
+use device_auth::DeviceCodeLimiter;
+use inference_profiles::{
+    BedrockControlPlane, InferenceProfileFailurePolicy, InferenceProfileProvisioner,
+    InferenceProfileSettings, ProvisionJob,
+};
 use myerrors::AppError;
+use mymetrics::Metrics;
+use oidc::{Identity, OidcClient, Pkce, random_token};
 use serde::{Deserialize, Serialize};
+use session_tokens::SessionTokenIssuer;
 use sqlx::PgPool;
 use std::{collections::HashMap, sync::Arc};
 use tower_sessions::Session;
+use tracing::warn;
+use users::{ensure_user, update_user_attributes};
+use validation_cache::ValidationCache;
 
 // ── Model config ─────────────────────────────────────────────────
 
@@ -55,53 +68,209 @@ pub struct ModelsResponse {
 
 #[derive(Clone)]
 pub struct AppState {
+    pub admin_emails: Vec<String>,
+    pub admin_groups: Vec<String>,
     pub anthropic_beta_whitelist: Vec<String>,
-    pub aws_account_id: String,
-    pub aws_region: String,
+    /// Shared so that AWS credentials are not resolved again per request.
+    pub bedrock_control_plane: BedrockControlPlane,
     pub bedrockruntime_client: Client,
-    pub cognito_client_id: String,
-    pub cognito_client_secret: String,
-    pub cognito_domain: String,
-    pub cognito_redirect_uri: String,
-    pub cognito_region: String,
-    pub cognito_user_pool_id: String,
+    pub capture_sink: Arc<CaptureSink>,
     pub db_pool: Arc<PgPool>,
+    pub device_code_limiter: Arc<DeviceCodeLimiter>,
+    /// Log the inference profiles that disabling keys or deleting models
+    /// would remove instead of removing them.
+    pub inference_profile_cleanup_dry_run: bool,
+    pub inference_profile_failure_policy: InferenceProfileFailurePolicy,
     pub inference_profile_prefixes: Vec<String>,
+    /// `None` unless inference profile pre-provisioning is enabled.
+    pub inference_profile_provisioner: Option<InferenceProfileProvisioner>,
+    pub inference_profile_settings: Arc<InferenceProfileSettings>,
+    pub metrics: Arc<Metrics>,
+    /// `/metrics` is not served when unset.
+    pub metrics_bearer_token: Option<String>,
+    pub oidc: Arc<OidcClient>,
+    pub scim_bearer_token: Option<String>,
+    /// `None` when no session token secret is configured.
+    pub session_tokens: Option<Arc<SessionTokenIssuer>>,
+    pub validation_cache: Arc<ValidationCache>,
     pub anthropic_to_bedrock: HashMap<String, String>,
     pub model_configs: Vec<ModelConfig>,
 }
 
-pub async fn logout(session: Session) -> Result<Response, AppError> {
+impl AppState {
+    pub fn is_admin(&self, email: &str) -> bool {
+        is_admin_email(&self.admin_emails, email)
+    }
+
+    /// Like [`AppState::is_admin`], but also grants admin to members of any
+    /// of `admin_groups` according to the identity provider.
+    pub fn is_admin_with_groups(&self, email: &str, groups: &[String]) -> bool {
+        self.is_admin(email) || has_admin_group(&self.admin_groups, groups)
+    }
+
+    /// Creates the user signing in with `identity` unless they exist, and
+    /// stores their attribute claims for inference profile tags. Called at
+    /// login and token exchange, and for token users validation finds no
+    /// user for; never for every request.
+    pub async fn ensure_identity_user(&self, identity: &Identity) -> anyhow::Result<()> {
+        let created_user_id = ensure_user(&self.db_pool, &identity.email).await?;
+        if !identity.attributes.is_empty() {
+            update_user_attributes(&self.db_pool, &identity.email, &identity.attributes).await?;
+        }
+        if let Some(user_id) = created_user_id {
+            self.provision_inference_profiles(ProvisionJob::User(user_id));
+        }
+        Ok(())
+    }
+
+    /// Queues creation of inference profiles ahead of first use, if
+    /// pre-provisioning is enabled.
+    pub fn provision_inference_profiles(&self, job: ProvisionJob) {
+        if let Some(provisioner) = &self.inference_profile_provisioner {
+            provisioner.enqueue(job);
+        }
+    }
+}
+
+/// Returns true if `email` is listed in `admin_emails` (case-insensitive).
+pub fn is_admin_email(admin_emails: &[String], email: &str) -> bool {
+    admin_emails
+        .iter()
+        .any(|admin_email| admin_email.eq_ignore_ascii_case(email))
+}
+
+/// Returns true if any of the user's `groups` is listed in `admin_groups`.
+pub fn has_admin_group(admin_groups: &[String], groups: &[String]) -> bool {
+    groups.iter().any(|group| admin_groups.contains(group))
+}
+
+/// Compares a presented bearer token against the configured one without
+/// short-circuiting on the first differing byte.
+pub fn token_matches(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+pub async fn logout(
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+) -> Result<Response, AppError> {
+    if let Some(email) = session.get::<String>("email").await? {
+        record_audit_event(
+            &state.db_pool,
+            &request_info,
+            AuditEvent::new(AuditAction::Logout).actor(&email),
+        )
+        .await;
+    }
     session.delete().await?;
     Ok(Redirect::to("/").into_response())
 }
 
+// Session keys holding the in-flight authorization request between
+// /login and /callback.
+const OIDC_STATE: &str = "oidc_state";
+const OIDC_NONCE: &str = "oidc_nonce";
+const OIDC_CODE_VERIFIER: &str = "oidc_code_verifier";
+
+/// Session key for a local path to return to after logging in, set by pages
+/// that send signed-out users to /login.
+pub const LOGIN_REDIRECT: &str = "login_redirect";
+
+/// Returns `path` if it is safe to redirect to after login: a local path,
+/// never another origin.
+pub fn local_redirect_path(path: &str) -> Option<&str> {
+    (path.starts_with('/') && !path.starts_with("//") && !path.contains('\\')).then_some(path)
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    pub code: String,
+    pub state: String,
+}
+
 pub async fn login(session: Session, state: State<AppState>) -> Result<Response, AppError> {
-    let state = State(handlers::AppState {
-        client_id: state.cognito_client_id.clone(),
-        client_secret: state.cognito_client_secret.clone(),
-        domain: state.cognito_domain.clone(),
-        redirect_uri: state.cognito_redirect_uri.clone(),
-        region: state.cognito_region.clone(),
-        user_pool_id: state.cognito_user_pool_id.clone(),
-    });
-    Ok(handlers::login(session, state).await?)
+    let oidc_state = random_token();
+    let nonce = random_token();
+    let pkce = Pkce::generate();
+
+    let authorization_url = state
+        .oidc
+        .authorization_url(&oidc_state, &nonce, &pkce)
+        .await?;
+
+    session.insert(OIDC_STATE, oidc_state).await?;
+    session.insert(OIDC_NONCE, nonce).await?;
+    session.insert(OIDC_CODE_VERIFIER, pkce.verifier).await?;
+
+    Ok(Redirect::to(&authorization_url).into_response())
 }
 
 pub async fn callback(
-    query: Query<CallbackQuery>,
+    Query(query): Query<CallbackQuery>,
     session: Session,
-    state: State<AppState>,
+    State(app_state): State<AppState>,
+    request_info: RequestInfo,
 ) -> Result<Response, AppError> {
-    let state = State(handlers::AppState {
-        client_id: state.cognito_client_id.clone(),
-        client_secret: state.cognito_client_secret.clone(),
-        domain: state.cognito_domain.clone(),
-        redirect_uri: state.cognito_redirect_uri.clone(),
-        region: state.cognito_region.clone(),
-        user_pool_id: state.cognito_user_pool_id.clone(),
-    });
-    Ok(handlers::callback(query, session, state).await?)
+    let oidc_state = session.remove::<String>(OIDC_STATE).await?;
+    let nonce = session.remove::<String>(OIDC_NONCE).await?;
+    let code_verifier = session.remove::<String>(OIDC_CODE_VERIFIER).await?;
+
+    let (Some(nonce), Some(code_verifier)) = (nonce, code_verifier) else {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "No login in progress",
+        ));
+    };
+    if oidc_state.as_deref() != Some(query.state.as_str()) {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Invalid login state",
+        ));
+    }
+
+    let tokens = app_state
+        .oidc
+        .exchange_code(&query.code, &code_verifier)
+        .await?;
+    let id_token = tokens
+        .id_token
+        .ok_or_else(|| AppError::new(StatusCode::BAD_GATEWAY, "Provider returned no ID token"))?;
+    let identity = app_state
+        .oidc
+        .verify_id_token(&id_token, &nonce)
+        .await
+        .map_err(|e| {
+            warn!("ID token validation failed: {e:#}");
+            AppError::new(StatusCode::UNAUTHORIZED, "Invalid ID token")
+        })?;
+
+    app_state.ensure_identity_user(&identity).await?;
+
+    session.cycle_id().await?;
+    session.insert("email", &identity.email).await?;
+    session.insert("groups", &identity.groups).await?;
+
+    record_audit_event(
+        &app_state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::Login).actor(&identity.email),
+    )
+    .await;
+
+    let redirect = session.remove::<String>(LOGIN_REDIRECT).await?;
+    Ok(Redirect::to(
+        redirect
+            .as_deref()
+            .and_then(local_redirect_path)
+            .unwrap_or("/"),
+    )
+    .into_response())
 }
 
 #[cfg(test)]
@@ -145,6 +314,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn is_admin_email_ignores_case() {
+        let admin_emails = vec!["Admin@Example.com".to_string()];
+        assert!(is_admin_email(&admin_emails, "admin@example.com"));
+        assert!(!is_admin_email(&admin_emails, "user@example.com"));
+        assert!(!is_admin_email(&[], "admin@example.com"));
+    }
+
+    #[test]
+    fn has_admin_group_requires_exact_match() {
+        let admin_groups = vec!["gateway-admins".to_string()];
+        assert!(has_admin_group(
+            &admin_groups,
+            &["engineering".to_string(), "gateway-admins".to_string()]
+        ));
+        assert!(!has_admin_group(
+            &admin_groups,
+            &["Gateway-Admins".to_string()]
+        ));
+        assert!(!has_admin_group(&[], &["gateway-admins".to_string()]));
+    }
+
+    #[test]
+    fn local_redirect_path_rejects_other_origins() {
+        assert_eq!(
+            local_redirect_path("/device?user_code=BDFG-HJKL"),
+            Some("/device?user_code=BDFG-HJKL")
+        );
+        assert_eq!(local_redirect_path("//evil.example.com"), None);
+        assert_eq!(local_redirect_path("/\\evil.example.com"), None);
+        assert_eq!(local_redirect_path("https://evil.example.com"), None);
+    }
+
+    #[test]
+    fn token_matches_requires_exact_token() {
+        assert!(token_matches("secret", "secret"));
+        assert!(!token_matches("secreT", "secret"));
+        assert!(!token_matches("secret2", "secret"));
+    }
+
     #[test]
     fn empty_map_passes_through_all_ids() {
         let map = HashMap::new();
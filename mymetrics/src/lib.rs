@@ -0,0 +1,397 @@
+use anyhow::Result;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use usage::TokenCounts;
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+const STREAM_DURATION_BUCKETS: &[f64] = &[
+    0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0,
+];
+
+pub struct Metrics {
+    registry: Registry,
+    user_labels: bool,
+    active_streams: IntGaugeVec,
+    bedrock_errors_total: IntCounterVec,
+    db_pool_connections: IntGaugeVec,
+    inference_profile_creations_total: IntCounterVec,
+    inference_profile_fallbacks_total: IntCounterVec,
+    request_duration_seconds: HistogramVec,
+    requests_total: IntCounterVec,
+    stream_duration_seconds: HistogramVec,
+    time_to_first_token_seconds: HistogramVec,
+    tokens_total: IntCounterVec,
+}
+
+impl Metrics {
+    /// Creates and registers all gateway metrics. When `user_labels` is false
+    /// the `user` label is always empty to keep cardinality bounded.
+    pub fn new(user_labels: bool) -> Result<Self> {
+        let registry = Registry::new();
+
+        let active_streams = IntGaugeVec::new(
+            Opts::new(
+                "gateway_active_streams",
+                "Number of response streams currently open",
+            ),
+            &["model"],
+        )?;
+        let bedrock_errors_total = IntCounterVec::new(
+            Opts::new(
+                "gateway_bedrock_errors_total",
+                "Bedrock errors by exception type",
+            ),
+            &["model", "exception"],
+        )?;
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "gateway_db_pool_connections",
+                "Database pool connections by state",
+            ),
+            &["state"],
+        )?;
+        let inference_profile_creations_total = IntCounterVec::new(
+            Opts::new(
+                "gateway_inference_profile_creations_total",
+                "Inference profile creation attempts by outcome",
+            ),
+            &["outcome"],
+        )?;
+        let inference_profile_fallbacks_total = IntCounterVec::new(
+            Opts::new(
+                "gateway_inference_profile_fallbacks_total",
+                "Requests sent without an inference profile after creating one failed",
+            ),
+            &["model"],
+        )?;
+        let request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_request_duration_seconds",
+                "Time until response headers are sent",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["route", "model", "status"],
+        )?;
+        let requests_total = IntCounterVec::new(
+            Opts::new("gateway_requests_total", "HTTP requests handled"),
+            &["route", "model", "status", "user"],
+        )?;
+        let stream_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_stream_duration_seconds",
+                "Time from request start until the response stream ends",
+            )
+            .buckets(STREAM_DURATION_BUCKETS.to_vec()),
+            &["model"],
+        )?;
+        let time_to_first_token_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "gateway_time_to_first_token_seconds",
+                "Time from request start until the first streamed event",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["model"],
+        )?;
+        let tokens_total = IntCounterVec::new(
+            Opts::new("gateway_tokens_total", "Tokens processed by type"),
+            &["model", "type", "user"],
+        )?;
+
+        registry.register(Box::new(active_streams.clone()))?;
+        registry.register(Box::new(bedrock_errors_total.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(inference_profile_creations_total.clone()))?;
+        registry.register(Box::new(inference_profile_fallbacks_total.clone()))?;
+        registry.register(Box::new(request_duration_seconds.clone()))?;
+        registry.register(Box::new(requests_total.clone()))?;
+        registry.register(Box::new(stream_duration_seconds.clone()))?;
+        registry.register(Box::new(time_to_first_token_seconds.clone()))?;
+        registry.register(Box::new(tokens_total.clone()))?;
+
+        Ok(Self {
+            registry,
+            user_labels,
+            active_streams,
+            bedrock_errors_total,
+            db_pool_connections,
+            inference_profile_creations_total,
+            inference_profile_fallbacks_total,
+            request_duration_seconds,
+            requests_total,
+            stream_duration_seconds,
+            time_to_first_token_seconds,
+            tokens_total,
+        })
+    }
+
+    fn user_label<'a>(&self, user: &'a str) -> &'a str {
+        if self.user_labels { user } else { "" }
+    }
+
+    pub fn observe_request(&self, route: &str, request_metrics: &RequestMetrics, status: u16) {
+        let (model, user) = request_metrics.labels();
+        let status = status.to_string();
+        self.requests_total
+            .with_label_values(&[route, &model, &status, self.user_label(&user)])
+            .inc();
+        self.request_duration_seconds
+            .with_label_values(&[route, &model, &status])
+            .observe(request_metrics.started().elapsed().as_secs_f64());
+    }
+
+    pub fn record_tokens(&self, model: &str, user: &str, tokens: &TokenCounts) {
+        let user = self.user_label(user);
+        for (token_type, count) in [
+            ("input", tokens.input_tokens),
+            ("output", tokens.output_tokens),
+            ("cache_read", tokens.cache_read_input_tokens),
+            ("cache_write", tokens.cache_write_input_tokens),
+        ] {
+            self.tokens_total
+                .with_label_values(&[model, token_type, user])
+                .inc_by(count.max(0) as u64);
+        }
+    }
+
+    pub fn record_bedrock_error(&self, model: &str, exception: &str) {
+        self.bedrock_errors_total
+            .with_label_values(&[model, exception])
+            .inc();
+    }
+
+    pub fn record_inference_profile_creation(&self, success: bool) {
+        let outcome = if success { "success" } else { "failure" };
+        self.inference_profile_creations_total
+            .with_label_values(&[outcome])
+            .inc();
+    }
+
+    pub fn record_inference_profile_fallback(&self, model: &str) {
+        self.inference_profile_fallbacks_total
+            .with_label_values(&[model])
+            .inc();
+    }
+
+    pub fn set_db_pool_connections(&self, size: u32, idle: usize) {
+        let idle = idle as i64;
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(i64::from(size) - idle);
+    }
+
+    /// Starts tracking a response stream for `model`. Timings are measured
+    /// from `started`, normally the moment the request was received.
+    pub fn stream_guard(self: &Arc<Self>, model: &str, started: Instant) -> StreamGuard {
+        self.active_streams.with_label_values(&[model]).inc();
+        StreamGuard {
+            metrics: self.clone(),
+            model: model.to_string(),
+            started,
+            first_event_seen: false,
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+/// Keeps the active stream gauge raised while a response stream is alive and
+/// records the stream duration when it is dropped.
+pub struct StreamGuard {
+    metrics: Arc<Metrics>,
+    model: String,
+    started: Instant,
+    first_event_seen: bool,
+}
+
+impl StreamGuard {
+    /// Records an event; returns the time to first token for the first one.
+    pub fn on_event(&mut self) -> Option<Duration> {
+        if self.first_event_seen {
+            return None;
+        }
+
+        self.first_event_seen = true;
+        let time_to_first_token = self.started.elapsed();
+        self.metrics
+            .time_to_first_token_seconds
+            .with_label_values(&[&self.model])
+            .observe(time_to_first_token.as_secs_f64());
+        Some(time_to_first_token)
+    }
+
+    pub fn on_error(&self, exception: &str) {
+        self.metrics.record_bedrock_error(&self.model, exception);
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.metrics
+            .active_streams
+            .with_label_values(&[&self.model])
+            .dec();
+        self.metrics
+            .stream_duration_seconds
+            .with_label_values(&[&self.model])
+            .observe(self.started.elapsed().as_secs_f64());
+    }
+}
+
+/// Per-request metrics context inserted by [`track_metrics`]. Handlers fill
+/// in the model and user labels once known; they are read back when the
+/// response is recorded.
+#[derive(Clone)]
+pub struct RequestMetrics {
+    labels: Arc<Mutex<(String, String)>>,
+    started: Instant,
+}
+
+impl RequestMetrics {
+    fn new(started: Instant) -> Self {
+        Self {
+            labels: Arc::default(),
+            started,
+        }
+    }
+
+    pub fn set_model(&self, model: &str) {
+        self.labels.lock().unwrap().0 = model.to_string();
+    }
+
+    pub fn set_user(&self, user: &str) {
+        self.labels.lock().unwrap().1 = user.to_string();
+    }
+
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    fn labels(&self) -> (String, String) {
+        self.labels.lock().unwrap().clone()
+    }
+}
+
+/// Middleware counting requests and measuring latency per matched route.
+pub async fn track_metrics(
+    State(metrics): State<Arc<Metrics>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_metrics = RequestMetrics::new(Instant::now());
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| "unmatched".to_string(), |p| p.as_str().to_string());
+    request.extensions_mut().insert(request_metrics.clone());
+
+    let response = next.run(request).await;
+
+    metrics.observe_request(&route, &request_metrics, response.status().as_u16());
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_includes_recorded_metrics() {
+        let metrics = Metrics::new(false).unwrap();
+        metrics.record_inference_profile_creation(false);
+        metrics.record_bedrock_error("model-a", "ThrottlingException");
+        metrics.record_inference_profile_fallback("model-a");
+
+        let rendered = metrics.render().unwrap();
+        assert!(
+            rendered.contains(r#"gateway_inference_profile_creations_total{outcome="failure"} 1"#)
+        );
+        assert!(rendered.contains(
+            r#"gateway_bedrock_errors_total{exception="ThrottlingException",model="model-a"} 1"#
+        ));
+        assert!(
+            rendered.contains(r#"gateway_inference_profile_fallbacks_total{model="model-a"} 1"#)
+        );
+    }
+
+    #[test]
+    fn user_labels_are_blank_unless_enabled() {
+        let tokens = TokenCounts {
+            input_tokens: 10,
+            output_tokens: 20,
+            cache_read_input_tokens: 0,
+            cache_write_input_tokens: 0,
+        };
+
+        let metrics = Metrics::new(false).unwrap();
+        metrics.record_tokens("model-a", "user-1", &tokens);
+        assert!(
+            metrics
+                .render()
+                .unwrap()
+                .contains(r#"gateway_tokens_total{model="model-a",type="output",user=""} 20"#)
+        );
+
+        let metrics = Metrics::new(true).unwrap();
+        metrics.record_tokens("model-a", "user-1", &tokens);
+        assert!(
+            metrics.render().unwrap().contains(
+                r#"gateway_tokens_total{model="model-a",type="output",user="user-1"} 20"#
+            )
+        );
+    }
+
+    #[test]
+    fn stream_guard_tracks_active_streams() {
+        let metrics = Arc::new(Metrics::new(false).unwrap());
+
+        let mut guard = metrics.stream_guard("model-a", Instant::now());
+        assert!(guard.on_event().is_some());
+        assert!(guard.on_event().is_none());
+        assert_eq!(
+            metrics.active_streams.with_label_values(&["model-a"]).get(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .time_to_first_token_seconds
+                .with_label_values(&["model-a"])
+                .get_sample_count(),
+            1
+        );
+
+        drop(guard);
+        assert_eq!(
+            metrics.active_streams.with_label_values(&["model-a"]).get(),
+            0
+        );
+        assert_eq!(
+            metrics
+                .stream_duration_seconds
+                .with_label_values(&["model-a"])
+                .get_sample_count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,415 @@
+use axum::{
+    Json,
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::OffsetDateTime;
+
+pub const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+pub const SCHEMA_ENTERPRISE_USER: &str =
+    "urn:ietf:params:scim:schemas:extension:enterprise:2.0:User";
+pub const SCHEMA_ERROR: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+pub const SCHEMA_LIST_RESPONSE: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const SCHEMA_PATCH_OP: &str = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+pub const SCHEMA_USER: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+
+/// SCIM error response (RFC 7644 section 3.12).
+#[derive(Debug, PartialEq)]
+pub struct ScimError {
+    pub status: StatusCode,
+    pub scim_type: Option<&'static str>,
+    pub detail: String,
+}
+
+impl ScimError {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            scim_type: None,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn bad_request(scim_type: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            scim_type: Some(scim_type),
+            detail: detail.into(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "Resource not found")
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ScimError {
+    fn from(err: E) -> Self {
+        let err: anyhow::Error = err.into();
+        tracing::error!("SCIM internal error: {err:#}");
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+    }
+}
+
+impl IntoResponse for ScimError {
+    fn into_response(self) -> Response {
+        let mut body = serde_json::json!({
+            "schemas": [SCHEMA_ERROR],
+            "status": self.status.as_u16().to_string(),
+            "detail": self.detail,
+        });
+        if let Some(scim_type) = self.scim_type {
+            body["scimType"] = Value::from(scim_type);
+        }
+        scim_response(self.status, body)
+    }
+}
+
+/// Serializes `body` as a SCIM response with the `application/scim+json`
+/// content type.
+pub fn scim_response(status: StatusCode, body: impl Serialize) -> Response {
+    (status, [(CONTENT_TYPE, SCIM_CONTENT_TYPE)], Json(body)).into_response()
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct EnterpriseUser {
+    #[serde(rename = "costCenter", skip_serializing_if = "Option::is_none")]
+    pub cost_center: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Email {
+    pub value: String,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub resource_type: &'static str,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_modified: OffsetDateTime,
+    pub location: String,
+}
+
+/// A user resource as returned to the identity provider.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    pub schemas: Vec<&'static str>,
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub user_name: String,
+    pub active: bool,
+    pub emails: Vec<Email>,
+    #[serde(rename = "urn:ietf:params:scim:schemas:extension:enterprise:2.0:User")]
+    pub enterprise: EnterpriseUser,
+    pub meta: Meta,
+}
+
+/// The fields a create (POST) or replace (PUT) request may set.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUserRequest {
+    pub user_name: String,
+    #[serde(default)]
+    pub external_id: Option<String>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+    #[serde(
+        rename = "urn:ietf:params:scim:schemas:extension:enterprise:2.0:User",
+        default
+    )]
+    pub enterprise: Option<EnterpriseUser>,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl ScimUserRequest {
+    /// Returns the request as a full set of attribute values, as used by a
+    /// replace.
+    pub fn into_attributes(self) -> UserAttributes {
+        UserAttributes {
+            active: self.active,
+            cost_center: self
+                .enterprise
+                .and_then(|enterprise| enterprise.cost_center),
+            external_id: self.external_id,
+            user_name: self.user_name,
+        }
+    }
+}
+
+/// The user attributes the gateway stores.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserAttributes {
+    pub active: bool,
+    pub cost_center: Option<String>,
+    pub external_id: Option<String>,
+    pub user_name: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PatchOperation {
+    pub op: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub value: Value,
+}
+
+/// Identity providers disagree on whether booleans are JSON booleans or
+/// strings such as `"False"`, so accept both.
+fn parse_bool(value: &Value) -> Result<bool, ScimError> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        Value::String(s) if s.eq_ignore_ascii_case("true") => Ok(true),
+        Value::String(s) if s.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(ScimError::bad_request(
+            "invalidValue",
+            "active must be a boolean",
+        )),
+    }
+}
+
+fn parse_string(value: &Value, path: &str) -> Result<Option<String>, ScimError> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s.clone())),
+        _ => Err(ScimError::bad_request(
+            "invalidValue",
+            format!("{path} must be a string"),
+        )),
+    }
+}
+
+fn apply_attribute(
+    attributes: &mut UserAttributes,
+    path: &str,
+    value: &Value,
+) -> Result<(), ScimError> {
+    let cost_center_path = format!("{SCHEMA_ENTERPRISE_USER}:costCenter");
+
+    match path {
+        "active" => attributes.active = parse_bool(value)?,
+        "userName" => {
+            attributes.user_name = parse_string(value, path)?.ok_or_else(|| {
+                ScimError::bad_request("invalidValue", "userName must not be empty")
+            })?
+        }
+        "externalId" => attributes.external_id = parse_string(value, path)?,
+        path if path == cost_center_path || path == "costCenter" => {
+            attributes.cost_center = parse_string(value, path)?
+        }
+        SCHEMA_ENTERPRISE_USER => {
+            if let Some(cost_center) = value.get("costCenter") {
+                attributes.cost_center = parse_string(cost_center, "costCenter")?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Applies a PatchOp request to `attributes`. Only `add` and `replace` are
+/// supported, with or without a `path`; unknown attributes are ignored.
+pub fn apply_patch(attributes: &mut UserAttributes, patch: &PatchRequest) -> Result<(), ScimError> {
+    for operation in &patch.operations {
+        let op = operation.op.to_ascii_lowercase();
+        if op != "add" && op != "replace" {
+            return Err(ScimError::bad_request(
+                "invalidValue",
+                format!("Unsupported patch operation: {}", operation.op),
+            ));
+        }
+
+        match &operation.path {
+            Some(path) => apply_attribute(attributes, path, &operation.value)?,
+            None => {
+                let Value::Object(values) = &operation.value else {
+                    return Err(ScimError::bad_request(
+                        "invalidValue",
+                        "value must be an object when path is omitted",
+                    ));
+                };
+                for (path, value) in values {
+                    apply_attribute(attributes, path, value)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A parsed `filter` query parameter. Only the equality filters identity
+/// providers use to look up an existing user are supported.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserFilter {
+    ExternalId(String),
+    UserName(String),
+}
+
+pub fn parse_filter(filter: &str) -> Result<UserFilter, ScimError> {
+    let invalid =
+        || ScimError::bad_request("invalidFilter", format!("Unsupported filter: {filter}"));
+
+    let mut parts = filter.trim().splitn(3, char::is_whitespace);
+    let (Some(attribute), Some(operator), Some(value)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+
+    if !operator.eq_ignore_ascii_case("eq") {
+        return Err(invalid());
+    }
+
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(invalid)?
+        .to_string();
+
+    match attribute {
+        attribute if attribute.eq_ignore_ascii_case("userName") => Ok(UserFilter::UserName(value)),
+        attribute if attribute.eq_ignore_ascii_case("externalId") => {
+            Ok(UserFilter::ExternalId(value))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    pub schemas: Vec<&'static str>,
+    pub total_results: usize,
+    pub start_index: usize,
+    pub items_per_page: usize,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+impl<T> ListResponse<T> {
+    pub fn new(resources: Vec<T>, total_results: usize, start_index: usize) -> Self {
+        Self {
+            schemas: vec![SCHEMA_LIST_RESPONSE],
+            total_results,
+            start_index,
+            items_per_page: resources.len(),
+            resources,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn attributes() -> UserAttributes {
+        UserAttributes {
+            active: true,
+            cost_center: None,
+            external_id: None,
+            user_name: "alice@example.com".to_string(),
+        }
+    }
+
+    fn patch(operations: Value) -> PatchRequest {
+        serde_json::from_value(json!({
+            "schemas": [SCHEMA_PATCH_OP],
+            "Operations": operations,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn apply_patch_with_path_accepts_string_booleans() {
+        let mut attributes = attributes();
+        apply_patch(
+            &mut attributes,
+            &patch(json!([{ "op": "Replace", "path": "active", "value": "False" }])),
+        )
+        .unwrap();
+        assert!(!attributes.active);
+    }
+
+    #[test]
+    fn apply_patch_without_path_reads_value_object() {
+        let mut attributes = attributes();
+        apply_patch(
+            &mut attributes,
+            &patch(json!([{
+                "op": "replace",
+                "value": {
+                    "active": false,
+                    "userName": "bob@example.com",
+                    "urn:ietf:params:scim:schemas:extension:enterprise:2.0:User:costCenter": "R&D",
+                },
+            }])),
+        )
+        .unwrap();
+        assert_eq!(
+            attributes,
+            UserAttributes {
+                active: false,
+                cost_center: Some("R&D".to_string()),
+                external_id: None,
+                user_name: "bob@example.com".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn apply_patch_rejects_remove() {
+        let mut attributes = attributes();
+        let err = apply_patch(
+            &mut attributes,
+            &patch(json!([{ "op": "remove", "path": "externalId" }])),
+        )
+        .unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn parse_filter_supports_equality_on_user_name_and_external_id() {
+        assert_eq!(
+            parse_filter(r#"userName eq "alice@example.com""#),
+            Ok(UserFilter::UserName("alice@example.com".to_string()))
+        );
+        assert_eq!(
+            parse_filter(r#"externalId EQ "00u1""#),
+            Ok(UserFilter::ExternalId("00u1".to_string()))
+        );
+        assert!(parse_filter(r#"userName sw "a""#).is_err());
+        assert!(parse_filter("userName eq alice").is_err());
+    }
+
+    #[test]
+    fn user_request_defaults_to_active() {
+        let request: ScimUserRequest = serde_json::from_value(json!({
+            "schemas": [SCHEMA_USER],
+            "userName": "alice@example.com",
+        }))
+        .unwrap();
+        assert!(request.into_attributes().active);
+    }
+}
@@ -0,0 +1,226 @@
+use apikeys::api_key_prefix;
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, header::USER_AGENT},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::info;
+use usage::TokenCounts;
+
+use crate::telemetry::REQUEST_ID;
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Json,
+    #[default]
+    Text,
+}
+
+/// Controls which identifying values may appear in access logs. API keys are
+/// always reduced to their prefix and request/response content is never
+/// logged; emails are masked when `redact_emails` is set.
+#[derive(Clone, Copy, Default)]
+pub struct RedactionPolicy {
+    pub redact_emails: bool,
+}
+
+impl RedactionPolicy {
+    pub fn email(&self, email: &str) -> String {
+        if self.redact_emails {
+            redact_email(email)
+        } else {
+            email.to_string()
+        }
+    }
+}
+
+/// Masks the local part of an email address, keeping its first character and
+/// the domain: `alice@example.com` becomes `a***@example.com`.
+pub fn redact_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            format!("{}***@{domain}", local.chars().take(1).collect::<String>())
+        }
+        None => "***".to_string(),
+    }
+}
+
+#[derive(Default)]
+struct AccessLogFields {
+    anthropic_beta: Option<Vec<String>>,
+    api_key_prefix: Option<String>,
+    auth: Option<&'static str>,
+    latency: Option<Duration>,
+    model: Option<String>,
+    status: Option<u16>,
+    time_to_first_token: Option<Duration>,
+    tokens: Option<TokenCounts>,
+    upstream_model: Option<String>,
+    user: Option<String>,
+}
+
+struct AccessLogInner {
+    fields: Mutex<AccessLogFields>,
+    method: String,
+    path: String,
+    policy: RedactionPolicy,
+    request_id: String,
+    started: Instant,
+    user_agent: String,
+}
+
+impl Drop for AccessLogInner {
+    fn drop(&mut self) {
+        let fields = self.fields.get_mut().unwrap();
+        let tokens = fields.tokens.as_ref();
+
+        info!(
+            target: "access_log",
+            request_id = %self.request_id,
+            method = %self.method,
+            path = %self.path,
+            status = fields.status,
+            user = fields.user.as_deref(),
+            api_key_prefix = fields.api_key_prefix.as_deref(),
+            auth = fields.auth,
+            model = fields.model.as_deref(),
+            upstream_model = fields.upstream_model.as_deref(),
+            latency_ms = fields.latency.map(|d| d.as_millis() as u64),
+            duration_ms = self.started.elapsed().as_millis() as u64,
+            time_to_first_token_ms = fields.time_to_first_token.map(|d| d.as_millis() as u64),
+            input_tokens = tokens.map(|t| t.input_tokens),
+            output_tokens = tokens.map(|t| t.output_tokens),
+            cache_read_input_tokens = tokens.map(|t| t.cache_read_input_tokens),
+            cache_write_input_tokens = tokens.map(|t| t.cache_write_input_tokens),
+            anthropic_beta = fields.anthropic_beta.as_ref().map(|b| b.join(",")),
+            user_agent = %self.user_agent,
+            "access"
+        );
+    }
+}
+
+/// Access log record for one request. Handlers fill in what they learn; the
+/// record is emitted once the last clone is dropped, which for streaming
+/// responses is when the stream ends.
+#[derive(Clone)]
+pub struct AccessLog(Arc<AccessLogInner>);
+
+impl AccessLog {
+    fn update(&self, f: impl FnOnce(&mut AccessLogFields)) {
+        f(&mut self.0.fields.lock().unwrap());
+    }
+
+    pub fn set_anthropic_beta(&self, anthropic_beta: &Option<Vec<String>>) {
+        self.update(|fields| fields.anthropic_beta = anthropic_beta.clone());
+    }
+
+    pub fn set_api_key(&self, api_key: &str) {
+        self.update(|fields| {
+            fields.api_key_prefix = Some(api_key_prefix(api_key));
+            fields.auth = Some("api_key");
+        });
+    }
+
+    /// Marks the request as authenticated with an identity provider token
+    /// rather than an API key.
+    pub fn set_oidc_auth(&self) {
+        self.update(|fields| fields.auth = Some("oidc"));
+    }
+
+    /// Marks the request as authenticated with a gateway session token.
+    pub fn set_session_token_auth(&self) {
+        self.update(|fields| fields.auth = Some("session_token"));
+    }
+
+    pub fn set_model(&self, model: &str) {
+        self.update(|fields| fields.model = Some(model.to_string()));
+    }
+
+    pub fn set_time_to_first_token(&self, time_to_first_token: Duration) {
+        self.update(|fields| fields.time_to_first_token = Some(time_to_first_token));
+    }
+
+    pub fn set_tokens(&self, tokens: &TokenCounts) {
+        self.update(|fields| fields.tokens = Some(tokens.clone()));
+    }
+
+    pub fn set_upstream_model(&self, upstream_model: &str) {
+        self.update(|fields| fields.upstream_model = Some(upstream_model.to_string()));
+    }
+
+    pub fn set_user(&self, email: &str) {
+        let user = self.0.policy.email(email);
+        self.update(|fields| fields.user = Some(user));
+    }
+}
+
+fn header_value(request: &Request, name: HeaderName) -> String {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Middleware attaching an [`AccessLog`] to every request.
+pub async fn log_access(
+    State(policy): State<RedactionPolicy>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_id = header_value(&request, REQUEST_ID);
+    let user_agent = header_value(&request, USER_AGENT);
+
+    let access_log = AccessLog(Arc::new(AccessLogInner {
+        fields: Mutex::default(),
+        method: request.method().to_string(),
+        path: request.uri().path().to_string(),
+        policy,
+        request_id,
+        started: Instant::now(),
+        user_agent,
+    }));
+    request.extensions_mut().insert(access_log.clone());
+
+    let response = next.run(request).await;
+
+    let latency = access_log.0.started.elapsed();
+    access_log.update(|fields| {
+        fields.status = Some(response.status().as_u16());
+        fields.latency = Some(latency);
+    });
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_email_masks_local_part() {
+        assert_eq!(redact_email("alice@example.com"), "a***@example.com");
+        assert_eq!(redact_email("not-an-email"), "***");
+    }
+
+    #[test]
+    fn redaction_policy_only_masks_when_enabled() {
+        let email = "alice@example.com";
+        assert_eq!(RedactionPolicy::default().email(email), email);
+        assert_eq!(
+            RedactionPolicy {
+                redact_emails: true
+            }
+            .email(email),
+            "a***@example.com"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use admin_tokens::{is_admin_api_token, verify_admin_api_token};
+use apikeys::{get_api_key, get_user_email_by_api_key};
+use axum::http::{HeaderMap, StatusCode};
+use myerrors::AppError;
+use myhandlers::AppState;
+use tower_sessions::Session;
+use tracing::{error, warn};
+
+/// Trims an admin form or query field, treating a blank one as absent.
+pub fn non_empty(value: &str) -> Option<&str> {
+    Some(value.trim()).filter(|value| !value.is_empty())
+}
+
+/// Returns true if the signed-in `email` is an admin, either by address or
+/// through a group the identity provider reported at login.
+pub async fn is_session_admin(
+    session: &Session,
+    state: &AppState,
+    email: &str,
+) -> Result<bool, AppError> {
+    let groups = session
+        .get::<Vec<String>>("groups")
+        .await?
+        .unwrap_or_default();
+    Ok(state.is_admin_with_groups(email, &groups))
+}
+
+/// Returns the signed-in user's email, `None` if nobody is signed in, or a
+/// 403 error if the user is not an admin.
+pub async fn get_admin_email(
+    session: &Session,
+    state: &AppState,
+) -> Result<Option<String>, AppError> {
+    let Some(email) = session.get::<String>("email").await? else {
+        return Ok(None);
+    };
+
+    if !is_session_admin(session, state, &email).await? {
+        return Err(AppError::new(StatusCode::FORBIDDEN, "Forbidden"));
+    }
+
+    Ok(Some(email))
+}
+
+/// Returns the email of the admin owning the request's API key, or
+/// `admin-api-token:<name>` for an admin API token, to record as the actor.
+/// Fails with a 401 or 403 error. Used by the `/api/v1/admin` JSON endpoints.
+pub async fn require_admin_credential(
+    headers: &HeaderMap,
+    state: &AppState,
+) -> Result<String, AppError> {
+    let api_key = get_api_key(headers)
+        .await
+        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "Invalid or missing API key"))?;
+
+    if is_admin_api_token(&api_key) {
+        let name = verify_admin_api_token(&state.db_pool, &api_key)
+            .await?
+            .ok_or_else(|| {
+                warn!("Admin API token validation failed: unknown or revoked token");
+                AppError::new(
+                    StatusCode::UNAUTHORIZED,
+                    "Invalid or revoked admin API token",
+                )
+            })?;
+        return Ok(format!("admin-api-token:{name}"));
+    }
+
+    let email = get_user_email_by_api_key(&state.db_pool, &api_key)
+        .await?
+        .ok_or_else(|| {
+            error!("API key validation failed: Invalid API key");
+            AppError::new(StatusCode::UNAUTHORIZED, "Invalid or missing API key")
+        })?;
+
+    if !state.is_admin(&email) {
+        return Err(AppError::new(StatusCode::FORBIDDEN, "Forbidden"));
+    }
+
+    Ok(email)
+}
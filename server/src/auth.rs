@@ -0,0 +1,84 @@
+use apikeys::{Credential, get_credential};
+use axum::http::{HeaderMap, StatusCode};
+use inference_profiles::{InferenceProfileGranularity, ProfileOwner};
+use myerrors::AppError;
+use myhandlers::AppState;
+use oidc::Identity;
+use session_tokens::{SessionTokenClaims, is_session_token};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::access_log::AccessLog;
+
+/// The authenticated client of an inference API request.
+pub enum Caller {
+    ApiKey(String),
+    /// A user presenting an access token from the identity provider.
+    Oidc(Identity),
+    /// A short-lived token issued by `/api/v1/token`.
+    SessionToken(SessionTokenClaims),
+}
+
+impl Caller {
+    /// Returns who inference profiles created for this caller belong to.
+    /// `user_id` is the validated caller's user, needed for token users;
+    /// without it there is no owner.
+    pub fn profile_owner(
+        &self,
+        user_id: Option<Uuid>,
+        granularity: InferenceProfileGranularity,
+    ) -> anyhow::Result<ProfileOwner<'_>> {
+        match self {
+            Caller::ApiKey(api_key) => Ok(ProfileOwner::ApiKey(api_key)),
+            Caller::Oidc(identity) => user_id.map(ProfileOwner::User).ok_or_else(|| {
+                anyhow::anyhow!("Identity provider token user not found: {}", identity.email)
+            }),
+            Caller::SessionToken(claims) => {
+                Ok(granularity.owner(claims.api_key_id, claims.team_id, claims.sub))
+            }
+        }
+    }
+}
+
+/// Identifies the caller by gateway API key, gateway session token or
+/// identity provider access token, so services already holding corporate
+/// JWTs need no long-lived key. Token users are created on first use by
+/// validation, not here, so authenticating never writes to the database.
+/// Session tokens are checked without the database.
+pub async fn authenticate(
+    state: &AppState,
+    headers: &HeaderMap,
+    access_log: &AccessLog,
+) -> Result<Caller, AppError> {
+    match get_credential(headers) {
+        Some(Credential::ApiKey(api_key)) => {
+            access_log.set_api_key(&api_key);
+            Ok(Caller::ApiKey(api_key))
+        }
+        Some(Credential::BearerToken(token)) if is_session_token(&token) => {
+            access_log.set_session_token_auth();
+            let claims = state
+                .session_tokens
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("Session tokens are not enabled"))
+                .and_then(|issuer| issuer.verify(&token))
+                .map_err(|e| {
+                    warn!("Session token validation failed: {e:#}");
+                    AppError::new(StatusCode::UNAUTHORIZED, "Invalid or expired token")
+                })?;
+            Ok(Caller::SessionToken(claims))
+        }
+        Some(Credential::BearerToken(token)) => {
+            access_log.set_oidc_auth();
+            let identity = state.oidc.verify_access_token(&token).await.map_err(|e| {
+                warn!("Access token validation failed: {e:#}");
+                AppError::new(StatusCode::UNAUTHORIZED, "Invalid or expired token")
+            })?;
+            Ok(Caller::Oidc(identity))
+        }
+        None => Err(AppError::new(
+            StatusCode::UNAUTHORIZED,
+            "Invalid or missing API key",
+        )),
+    }
+}
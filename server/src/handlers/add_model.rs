@@ -1,8 +1,10 @@
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
 use axum::{
     extract::{Form, State},
     response::{Html, IntoResponse, Redirect, Response},
 };
 use axum_csrf::CsrfToken;
+use inference_profiles::ProvisionJob;
 use myerrors::AppError;
 use myhandlers::AppState;
 use serde::Deserialize;
@@ -61,9 +63,10 @@ pub async fn add_model_post(
     token: CsrfToken,
     session: Session,
     state: State<AppState>,
+    request_info: RequestInfo,
     form: Form<AddModelForm>,
 ) -> Result<Response, AppError> {
-    let _email = match session.get::<String>("email").await? {
+    let email = match session.get::<String>("email").await? {
         Some(email) => email,
         None => return Ok(Redirect::to("/login").into_response()),
     };
@@ -72,6 +75,16 @@ pub async fn add_model_post(
 
     match models::create_model(&state.db_pool, &form.model_name).await {
         Ok(_) => {
+            state.provision_inference_profiles(ProvisionJob::Model(form.model_name.clone()));
+            record_audit_event(
+                &state.db_pool,
+                &request_info,
+                AuditEvent::new(AuditAction::ModelAdd)
+                    .actor(&email)
+                    .target(&form.model_name),
+            )
+            .await;
+
             let html = format!(
                 r#"
                 <!DOCTYPE html>
@@ -0,0 +1,1096 @@
+use std::collections::BTreeMap;
+
+use apikeys::{
+    ApiKeySummary, api_key_prefix, create_api_key, create_team_api_key, disable_api_key,
+    list_api_keys,
+};
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use inference_profiles::{
+    CleanupReport, InferenceProfileListing, ProvisionJob, delete_api_key_inference_profiles,
+    delete_model_inference_profiles, delete_user_inference_profiles, list_all_inference_profiles,
+};
+use models::{
+    Model, create_model, delete_model, disable_model, enable_model, get_model, get_models,
+    set_model_protected,
+};
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use service_accounts::{get_service_account, set_service_account_budget};
+use teams::{get_team, is_team_member, set_team_budget};
+use time::OffsetDateTime;
+use users::{UserSummary, get_user, insert_user, list_users, reactivate_user, update_user};
+use utoipa::{
+    IntoParams, Modify, OpenApi, ToSchema,
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+};
+use uuid::Uuid;
+
+use crate::admin::{non_empty, require_admin_credential};
+use crate::handlers::admin_users::deactivate_and_audit;
+use crate::handlers::scim_users::is_unique_violation;
+
+const DEFAULT_LIMIT: i64 = 100;
+
+const MAX_LIMIT: i64 = 500;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Gateway admin API",
+        description = "Manages users, API keys, models, budgets, inference profiles, service \
+                       accounts and session tokens, and reports usage. \
+                       Authenticate with an admin API token created at /admin/api-tokens."
+    ),
+    paths(
+        admin_api_users_get,
+        admin_api_users_post,
+        admin_api_user_get,
+        admin_api_user_patch,
+        admin_api_user_api_keys_get,
+        admin_api_user_api_keys_post,
+        admin_api_api_key_delete,
+        admin_api_models_get,
+        admin_api_models_post,
+        admin_api_model_patch,
+        admin_api_model_delete,
+        admin_api_team_budget_put,
+        admin_api_service_account_budget_put,
+        admin_api_inference_profiles_get,
+        admin_api_user_inference_profiles_delete,
+        crate::handlers::admin_service_accounts::admin_service_accounts_api_get,
+        crate::handlers::admin_service_accounts::admin_service_accounts_api_post,
+        crate::handlers::admin_service_accounts::admin_service_account_keys_api_post,
+        crate::handlers::admin_usage_report::admin_usage_report,
+        crate::handlers::session_tokens::admin_session_token_revoke_post,
+    ),
+    modifiers(&AdminApiTokenSecurity),
+    security(("admin_api_token" = [])),
+    tags(
+        (name = "users"),
+        (name = "api-keys"),
+        (name = "models"),
+        (name = "budgets"),
+        (name = "inference-profiles"),
+        (name = "service-accounts"),
+        (name = "usage"),
+        (name = "session-tokens"),
+    )
+)]
+pub struct AdminApiDoc;
+
+struct AdminApiTokenSecurity;
+
+impl Modify for AdminApiTokenSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "admin_api_token",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminUser {
+    pub api_keys_count_active: i64,
+    pub cost_center: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    pub external_id: Option<String>,
+    pub is_disabled: bool,
+    pub is_service_account: bool,
+    pub user_email: String,
+    pub user_id: Uuid,
+}
+
+impl From<UserSummary> for AdminUser {
+    fn from(user: UserSummary) -> Self {
+        Self {
+            api_keys_count_active: user.api_keys_count_active,
+            cost_center: user.cost_center,
+            created_at: user.created_at,
+            external_id: user.external_id,
+            is_disabled: user.is_disabled,
+            is_service_account: user.is_service_account,
+            user_email: user.user_email,
+            user_id: user.user_id,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminUsersResponse {
+    pub users: Vec<AdminUser>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AdminUsersQuery {
+    /// Only users whose email contains this text.
+    pub q: Option<String>,
+    pub offset: Option<i64>,
+    /// At most 500; defaults to 100.
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateUserRequest {
+    pub cost_center: Option<String>,
+    pub email: String,
+    pub external_id: Option<String>,
+}
+
+/// Fields left out are unchanged. An empty `cost_center` or `external_id`
+/// clears it.
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateUserRequest {
+    pub cost_center: Option<String>,
+    pub email: Option<String>,
+    pub external_id: Option<String>,
+    pub is_disabled: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminApiKey {
+    pub api_key_id: Uuid,
+    /// The first characters of the key; the full key is only returned when
+    /// it is created.
+    pub api_key_prefix: String,
+    pub capture_enabled: Option<bool>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    pub is_disabled: bool,
+    pub team_name: Option<String>,
+}
+
+impl From<ApiKeySummary> for AdminApiKey {
+    fn from(api_key: ApiKeySummary) -> Self {
+        Self {
+            api_key_id: api_key.api_key_id,
+            api_key_prefix: api_key.api_key_prefix,
+            capture_enabled: api_key.capture_enabled,
+            created_at: api_key.created_at,
+            is_disabled: api_key.is_disabled,
+            team_name: api_key.team_name,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminApiKeysResponse {
+    pub api_keys: Vec<AdminApiKey>,
+}
+
+#[derive(Default, Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    /// Bills the key to this team. The user must be a member.
+    pub team_id: Option<Uuid>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedApiKeyResponse {
+    pub api_key: String,
+    pub api_key_id: Uuid,
+    pub user_id: Uuid,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminModel {
+    pub is_disabled: bool,
+    pub model_name: String,
+    pub protected: bool,
+}
+
+impl From<Model> for AdminModel {
+    fn from(model: Model) -> Self {
+        Self {
+            is_disabled: model.is_disabled,
+            model_name: model.model_name,
+            protected: model.protected,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminModelsResponse {
+    /// Anthropic model ids and the Bedrock models they map to, from the
+    /// gateway's configuration.
+    pub aliases: BTreeMap<String, String>,
+    pub models: Vec<AdminModel>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateModelRequest {
+    pub model_name: String,
+}
+
+/// Fields left out are unchanged. A protected model has to be unprotected
+/// before it can be disabled or enabled; both can be done in one request.
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateModelRequest {
+    pub is_disabled: Option<bool>,
+    pub protected: Option<bool>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BudgetRequest {
+    /// Monthly budget in millionths of a dollar; null removes the limit.
+    pub budget_usd_micros: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminInferenceProfile {
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    pub inference_profile_arn: String,
+    pub model_is_disabled: bool,
+    pub model_name: String,
+    /// `user`, `team` or `key`.
+    pub owner_type: String,
+    pub team_id: Option<Uuid>,
+    pub team_name: Option<String>,
+    pub user_email: String,
+    pub user_id: Uuid,
+    pub user_is_disabled: bool,
+}
+
+impl From<InferenceProfileListing> for AdminInferenceProfile {
+    fn from(profile: InferenceProfileListing) -> Self {
+        Self {
+            created_at: profile.created_at,
+            inference_profile_arn: profile.inference_profile_arn,
+            model_is_disabled: profile.model_is_disabled,
+            model_name: profile.model_name,
+            owner_type: profile.owner_type,
+            team_id: profile.team_id,
+            team_name: profile.team_name,
+            user_email: profile.user_email,
+            user_id: profile.user_id,
+            user_is_disabled: profile.user_is_disabled,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminInferenceProfilesResponse {
+    pub inference_profiles: Vec<AdminInferenceProfile>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AdminInferenceProfilesQuery {
+    /// Only profiles of users whose email contains this text.
+    pub user: Option<String>,
+    /// Only profiles of this model.
+    pub model: Option<String>,
+    /// At most 500; defaults to 100.
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CleanupResponse {
+    pub deleted: usize,
+    /// Profiles that failed to delete in Bedrock and are kept for a retry.
+    pub failures: usize,
+    pub matched: usize,
+}
+
+impl From<CleanupReport> for CleanupResponse {
+    fn from(report: CleanupReport) -> Self {
+        Self {
+            deleted: report.deleted,
+            failures: report.failures,
+            matched: report.matched,
+        }
+    }
+}
+
+fn limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+}
+
+fn validate_budget(budget_usd_micros: Option<i64>) -> Result<(), AppError> {
+    if budget_usd_micros.is_some_and(|budget| budget < 0) {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Budget must not be negative",
+        ));
+    }
+    Ok(())
+}
+
+async fn load_user(state: &AppState, user_id: Uuid) -> Result<UserSummary, AppError> {
+    get_user(&state.db_pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "User not found"))
+}
+
+async fn load_model(state: &AppState, model_name: &str) -> Result<Model, AppError> {
+    get_model(&state.db_pool, model_name)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Model not found"))
+}
+
+fn protected_model_error(model_name: &str) -> AppError {
+    AppError::new(
+        StatusCode::CONFLICT,
+        format!("Model \"{model_name}\" is protected"),
+    )
+}
+
+/// GET /api/v1/admin/openapi.json
+///
+/// Not authenticated, so that tooling can fetch it.
+pub async fn admin_api_openapi_get() -> Json<utoipa::openapi::OpenApi> {
+    Json(AdminApiDoc::openapi())
+}
+
+/// Lists human users ordered by email.
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/users",
+    tag = "users",
+    params(AdminUsersQuery),
+    responses((status = 200, body = AdminUsersResponse))
+)]
+pub async fn admin_api_users_get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<AdminUsersQuery>,
+) -> Result<Json<AdminUsersResponse>, AppError> {
+    require_admin_credential(&headers, &state).await?;
+
+    let users = list_users(
+        &state.db_pool,
+        query.q.as_deref().and_then(non_empty),
+        query.offset.unwrap_or(0).max(0),
+        limit(query.limit),
+    )
+    .await?;
+
+    Ok(Json(AdminUsersResponse {
+        users: users.into_iter().map(AdminUser::from).collect(),
+    }))
+}
+
+/// Creates a user and provisions their inference profiles.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users",
+    tag = "users",
+    request_body = CreateUserRequest,
+    responses(
+        (status = 201, body = AdminUser),
+        (status = 409, description = "A user with this email or external id exists")
+    )
+)]
+pub async fn admin_api_users_post(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Json(request): Json<CreateUserRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    let Some(email) = non_empty(&request.email) else {
+        return Err(AppError::new(StatusCode::BAD_REQUEST, "Email is required"));
+    };
+
+    let user_id = insert_user(
+        &state.db_pool,
+        email,
+        request.external_id.as_deref().and_then(non_empty),
+        request.cost_center.as_deref().and_then(non_empty),
+    )
+    .await
+    .map_err(|e| {
+        if is_unique_violation(&e) {
+            AppError::new(
+                StatusCode::CONFLICT,
+                "A user with this email or external id already exists",
+            )
+        } else {
+            AppError::from(e)
+        }
+    })?;
+    state.provision_inference_profiles(ProvisionJob::User(user_id));
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::UserCreate)
+            .actor(&admin_email)
+            .target(email),
+    )
+    .await;
+
+    let user = load_user(&state, user_id).await?;
+    Ok((StatusCode::CREATED, Json(AdminUser::from(user))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/users/{user_id}",
+    tag = "users",
+    params(("user_id" = Uuid, Path)),
+    responses((status = 200, body = AdminUser), (status = 404))
+)]
+pub async fn admin_api_user_get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<AdminUser>, AppError> {
+    require_admin_credential(&headers, &state).await?;
+
+    Ok(Json(AdminUser::from(load_user(&state, user_id).await?)))
+}
+
+/// Updates a user's attributes. Disabling a user also disables their API
+/// keys and deletes their inference profiles.
+#[utoipa::path(
+    patch,
+    path = "/api/v1/admin/users/{user_id}",
+    tag = "users",
+    params(("user_id" = Uuid, Path)),
+    request_body = UpdateUserRequest,
+    responses(
+        (status = 200, body = AdminUser),
+        (status = 404),
+        (status = 409, description = "A user with this email or external id exists")
+    )
+)]
+pub async fn admin_api_user_patch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Path(user_id): Path<Uuid>,
+    Json(request): Json<UpdateUserRequest>,
+) -> Result<Json<AdminUser>, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    let user = load_user(&state, user_id).await?;
+
+    let email = match request.email.as_deref() {
+        Some(email) => non_empty(email)
+            .ok_or_else(|| AppError::new(StatusCode::BAD_REQUEST, "Email must not be empty"))?,
+        None => &user.user_email,
+    };
+    let external_id = match request.external_id.as_deref() {
+        Some(external_id) => non_empty(external_id),
+        None => user.external_id.as_deref(),
+    };
+    let cost_center = match request.cost_center.as_deref() {
+        Some(cost_center) => non_empty(cost_center),
+        None => user.cost_center.as_deref(),
+    };
+
+    if email != user.user_email
+        || external_id != user.external_id.as_deref()
+        || cost_center != user.cost_center.as_deref()
+    {
+        update_user(&state.db_pool, user_id, email, external_id, cost_center)
+            .await
+            .map_err(|e| {
+                if is_unique_violation(&e) {
+                    AppError::new(
+                        StatusCode::CONFLICT,
+                        "A user with this email or external id already exists",
+                    )
+                } else {
+                    AppError::from(e)
+                }
+            })?;
+
+        record_audit_event(
+            &state.db_pool,
+            &request_info,
+            AuditEvent::new(AuditAction::UserUpdate)
+                .actor(&admin_email)
+                .target(email)
+                .details(json!({ "previous_user_email": user.user_email })),
+        )
+        .await;
+    }
+
+    match request.is_disabled {
+        Some(true) if !user.is_disabled => {
+            deactivate_and_audit(&state, &request_info, &admin_email, &user).await?;
+        }
+        Some(false) if user.is_disabled => {
+            reactivate_user(&state.db_pool, user_id).await?;
+
+            record_audit_event(
+                &state.db_pool,
+                &request_info,
+                AuditEvent::new(AuditAction::UserReactivate)
+                    .actor(&admin_email)
+                    .target(email),
+            )
+            .await;
+        }
+        _ => {}
+    }
+
+    Ok(Json(AdminUser::from(load_user(&state, user_id).await?)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/users/{user_id}/api-keys",
+    tag = "api-keys",
+    params(("user_id" = Uuid, Path)),
+    responses((status = 200, body = AdminApiKeysResponse), (status = 404))
+)]
+pub async fn admin_api_user_api_keys_get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<AdminApiKeysResponse>, AppError> {
+    require_admin_credential(&headers, &state).await?;
+
+    load_user(&state, user_id).await?;
+
+    let api_keys = list_api_keys(&state.db_pool, user_id).await?;
+
+    Ok(Json(AdminApiKeysResponse {
+        api_keys: api_keys.into_iter().map(AdminApiKey::from).collect(),
+    }))
+}
+
+/// Creates an API key for an active user. The key is only returned here.
+/// Service accounts get keys from `/api/v1/admin/service-accounts/{user_id}/keys`.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users/{user_id}/api-keys",
+    tag = "api-keys",
+    params(("user_id" = Uuid, Path)),
+    request_body(content = CreateApiKeyRequest, description = "Optional"),
+    responses(
+        (status = 201, body = CreatedApiKeyResponse),
+        (status = 400, description = "The user is a service account or not in the team"),
+        (status = 404),
+        (status = 409, description = "The user is disabled")
+    )
+)]
+pub async fn admin_api_user_api_keys_post(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Path(user_id): Path<Uuid>,
+    request: Option<Json<CreateApiKeyRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let user = load_user(&state, user_id).await?;
+    if user.is_service_account {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Use /api/v1/admin/service-accounts/{user_id}/keys for service accounts",
+        ));
+    }
+    if user.is_disabled {
+        return Err(AppError::new(StatusCode::CONFLICT, "User is disabled"));
+    }
+
+    let created = match request.team_id {
+        Some(team_id) => {
+            if !is_team_member(&state.db_pool, team_id, &user.user_email).await? {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    "User is not a member of this team",
+                ));
+            }
+            create_team_api_key(&state.db_pool, &user.user_email, team_id).await?
+        }
+        None => create_api_key(&state.db_pool, &user.user_email).await?,
+    };
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ApiKeyCreate)
+            .actor(&admin_email)
+            .target(&api_key_prefix(&created.api_key.to_string()))
+            .details(json!({
+                "api_key_id": created.api_key_id,
+                "team_id": request.team_id,
+                "user_email": user.user_email,
+            })),
+    )
+    .await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKeyResponse {
+            api_key: created.api_key.to_string(),
+            api_key_id: created.api_key_id,
+            user_id,
+        }),
+    ))
+}
+
+/// Disables an API key and deletes its own inference profiles, if inference
+/// profiles are created per key. Deleting a disabled key retries the cleanup.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/api-keys/{api_key_id}",
+    tag = "api-keys",
+    params(("api_key_id" = Uuid, Path)),
+    responses(
+        (status = 204),
+        (status = 404, description = "No such key")
+    )
+)]
+pub async fn admin_api_api_key_delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Path(api_key_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    let user_id = disable_api_key(&state.db_pool, api_key_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "API key not found"))?;
+    let deleted_inference_profiles_count = delete_api_key_inference_profiles(
+        &state.bedrock_control_plane,
+        &state.db_pool,
+        api_key_id,
+        state.inference_profile_cleanup_dry_run,
+    )
+    .await?
+    .deleted;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ApiKeyDisable)
+            .actor(&admin_email)
+            .target(&api_key_id.to_string())
+            .details(json!({
+                "deleted_inference_profiles_count": deleted_inference_profiles_count,
+                "user_id": user_id,
+            })),
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lists models, except retired ones that are both protected and disabled,
+/// with the configured model id aliases.
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/models",
+    tag = "models",
+    responses((status = 200, body = AdminModelsResponse))
+)]
+pub async fn admin_api_models_get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<AdminModelsResponse>, AppError> {
+    require_admin_credential(&headers, &state).await?;
+
+    let models = get_models(&state.db_pool).await?;
+
+    Ok(Json(AdminModelsResponse {
+        aliases: state
+            .anthropic_to_bedrock
+            .iter()
+            .map(|(alias, model_name)| (alias.clone(), model_name.clone()))
+            .collect(),
+        models: models.into_iter().map(AdminModel::from).collect(),
+    }))
+}
+
+/// Adds a model and provisions its inference profiles.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/models",
+    tag = "models",
+    request_body = CreateModelRequest,
+    responses(
+        (status = 201, body = AdminModel),
+        (status = 409, description = "The model exists")
+    )
+)]
+pub async fn admin_api_models_post(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Json(request): Json<CreateModelRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    let Some(model_name) = non_empty(&request.model_name) else {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Model name is required",
+        ));
+    };
+
+    create_model(&state.db_pool, model_name)
+        .await
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                AppError::new(StatusCode::CONFLICT, "Model already exists")
+            } else {
+                AppError::from(e)
+            }
+        })?;
+    state.provision_inference_profiles(ProvisionJob::Model(model_name.to_string()));
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ModelAdd)
+            .actor(&admin_email)
+            .target(model_name),
+    )
+    .await;
+
+    let model = load_model(&state, model_name).await?;
+    Ok((StatusCode::CREATED, Json(AdminModel::from(model))))
+}
+
+/// Disables, enables, protects or unprotects a model. Enabling a model
+/// provisions its inference profiles.
+#[utoipa::path(
+    patch,
+    path = "/api/v1/admin/models/{model_name}",
+    tag = "models",
+    params(("model_name" = String, Path)),
+    request_body = UpdateModelRequest,
+    responses(
+        (status = 200, body = AdminModel),
+        (status = 404),
+        (status = 409, description = "The model is protected")
+    )
+)]
+pub async fn admin_api_model_patch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Path(model_name): Path<String>,
+    Json(request): Json<UpdateModelRequest>,
+) -> Result<Json<AdminModel>, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    let model = load_model(&state, &model_name).await?;
+
+    // Unprotect before and protect after changing the disabled flag, so both
+    // can be done in one request.
+    if request.protected == Some(false) && model.protected {
+        set_model_protected(&state.db_pool, &model_name, false).await?;
+        record_audit_event(
+            &state.db_pool,
+            &request_info,
+            AuditEvent::new(AuditAction::ModelUpdate)
+                .actor(&admin_email)
+                .target(&model.model_name)
+                .details(json!({ "protected": false })),
+        )
+        .await;
+    }
+
+    match request.is_disabled {
+        Some(true) if !model.is_disabled => {
+            if !disable_model(&state.db_pool, &model_name).await? {
+                return Err(protected_model_error(&model.model_name));
+            }
+            record_audit_event(
+                &state.db_pool,
+                &request_info,
+                AuditEvent::new(AuditAction::ModelDisable)
+                    .actor(&admin_email)
+                    .target(&model.model_name),
+            )
+            .await;
+        }
+        Some(false) if model.is_disabled => {
+            if !enable_model(&state.db_pool, &model_name).await? {
+                return Err(protected_model_error(&model.model_name));
+            }
+            state.provision_inference_profiles(ProvisionJob::Model(model.model_name.clone()));
+            record_audit_event(
+                &state.db_pool,
+                &request_info,
+                AuditEvent::new(AuditAction::ModelEnable)
+                    .actor(&admin_email)
+                    .target(&model.model_name),
+            )
+            .await;
+        }
+        _ => {}
+    }
+
+    if request.protected == Some(true) && !model.protected {
+        set_model_protected(&state.db_pool, &model_name, true).await?;
+        record_audit_event(
+            &state.db_pool,
+            &request_info,
+            AuditEvent::new(AuditAction::ModelUpdate)
+                .actor(&admin_email)
+                .target(&model.model_name)
+                .details(json!({ "protected": true })),
+        )
+        .await;
+    }
+
+    Ok(Json(AdminModel::from(
+        load_model(&state, &model_name).await?,
+    )))
+}
+
+/// Deletes a model after deleting its inference profiles in Bedrock.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/models/{model_name}",
+    tag = "models",
+    params(("model_name" = String, Path)),
+    responses(
+        (status = 204),
+        (status = 404),
+        (status = 409, description = "The model is protected or some of its inference profiles could not be deleted")
+    )
+)]
+pub async fn admin_api_model_delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Path(model_name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    let model = load_model(&state, &model_name).await?;
+    if model.protected {
+        return Err(protected_model_error(&model.model_name));
+    }
+
+    // The model's rows in inference_profiles would block its deletion.
+    let report = delete_model_inference_profiles(
+        &state.bedrock_control_plane,
+        &state.db_pool,
+        &model.model_name,
+        state.inference_profile_cleanup_dry_run,
+    )
+    .await?;
+    if report.is_incomplete() {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "Model \"{}\" still has {} inference profile(s)",
+                model.model_name,
+                report.matched - report.deleted
+            ),
+        ));
+    }
+
+    if !delete_model(&state.db_pool, &model.model_name).await? {
+        return Err(protected_model_error(&model.model_name));
+    }
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ModelDelete)
+            .actor(&admin_email)
+            .target(&model.model_name)
+            .details(json!({ "deleted_inference_profiles_count": report.deleted })),
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/teams/{team_id}/budget",
+    tag = "budgets",
+    params(("team_id" = Uuid, Path)),
+    request_body = BudgetRequest,
+    responses((status = 204), (status = 400), (status = 404))
+)]
+pub async fn admin_api_team_budget_put(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Path(team_id): Path<Uuid>,
+    Json(request): Json<BudgetRequest>,
+) -> Result<StatusCode, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    validate_budget(request.budget_usd_micros)?;
+    let team = get_team(&state.db_pool, team_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Team not found"))?;
+
+    set_team_budget(&state.db_pool, team_id, request.budget_usd_micros).await?;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::TeamUpdate)
+            .actor(&admin_email)
+            .target(&team.team_name)
+            .details(json!({
+                "previous_budget_usd_micros": team.budget_usd_micros,
+                "budget_usd_micros": request.budget_usd_micros,
+            })),
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/service-accounts/{user_id}/budget",
+    tag = "budgets",
+    params(("user_id" = Uuid, Path)),
+    request_body = BudgetRequest,
+    responses((status = 204), (status = 400), (status = 404))
+)]
+pub async fn admin_api_service_account_budget_put(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Path(user_id): Path<Uuid>,
+    Json(request): Json<BudgetRequest>,
+) -> Result<StatusCode, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    validate_budget(request.budget_usd_micros)?;
+    let service_account = get_service_account(&state.db_pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Service account not found"))?;
+
+    set_service_account_budget(&state.db_pool, user_id, request.budget_usd_micros).await?;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ServiceAccountUpdate)
+            .actor(&admin_email)
+            .target(&service_account.name)
+            .details(json!({
+                "previous_budget_usd_micros": service_account.budget_usd_micros,
+                "budget_usd_micros": request.budget_usd_micros,
+            })),
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lists inference profiles ordered by user and model.
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/inference-profiles",
+    tag = "inference-profiles",
+    params(AdminInferenceProfilesQuery),
+    responses((status = 200, body = AdminInferenceProfilesResponse))
+)]
+pub async fn admin_api_inference_profiles_get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<AdminInferenceProfilesQuery>,
+) -> Result<Json<AdminInferenceProfilesResponse>, AppError> {
+    require_admin_credential(&headers, &state).await?;
+
+    let inference_profiles = list_all_inference_profiles(
+        &state.db_pool,
+        query.user.as_deref().and_then(non_empty),
+        query.model.as_deref().and_then(non_empty),
+        limit(query.limit),
+    )
+    .await?;
+
+    Ok(Json(AdminInferenceProfilesResponse {
+        inference_profiles: inference_profiles
+            .into_iter()
+            .map(AdminInferenceProfile::from)
+            .collect(),
+    }))
+}
+
+/// Deletes the user's personal and key inference profiles in Bedrock. Active
+/// users get new ones on their next request.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/users/{user_id}/inference-profiles",
+    tag = "inference-profiles",
+    params(("user_id" = Uuid, Path)),
+    responses((status = 200, body = CleanupResponse), (status = 404))
+)]
+pub async fn admin_api_user_inference_profiles_delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<CleanupResponse>, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    let user = load_user(&state, user_id).await?;
+
+    let report = delete_user_inference_profiles(
+        &state.bedrock_control_plane,
+        &state.db_pool,
+        user_id,
+        state.inference_profile_cleanup_dry_run,
+    )
+    .await?;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::InferenceProfilesDelete)
+            .actor(&admin_email)
+            .target(&user.user_email)
+            .details(json!({ "deleted_inference_profiles_count": report.deleted })),
+    )
+    .await;
+
+    Ok(Json(CleanupResponse::from(report)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openapi_document_covers_every_path() {
+        let openapi = AdminApiDoc::openapi();
+        assert_eq!(openapi.paths.paths.len(), 14);
+        assert!(
+            openapi
+                .paths
+                .paths
+                .contains_key("/api/v1/admin/users/{user_id}")
+        );
+        assert!(openapi.paths.paths.contains_key("/api/v1/admin/usage"));
+        assert!(
+            openapi.components.is_some_and(|components| components
+                .security_schemes
+                .contains_key("admin_api_token"))
+        );
+    }
+
+    #[test]
+    fn limits_are_clamped() {
+        assert_eq!(limit(None), DEFAULT_LIMIT);
+        assert_eq!(limit(Some(0)), 1);
+        assert_eq!(limit(Some(10_000)), MAX_LIMIT);
+    }
+}
@@ -0,0 +1,207 @@
+use admin_tokens::{create_admin_api_token, list_admin_api_tokens, revoke_admin_api_token};
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
+use axum::{
+    extract::{Form, Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use axum_csrf::CsrfToken;
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::Deserialize;
+use serde_json::json;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::admin::get_admin_email;
+use crate::csrf::{get_authenticity_token, verify_authenticity_token};
+use crate::templates::common::{admin_nav_menu, common_styles, html_escape};
+
+#[derive(Deserialize)]
+pub struct CreateAdminApiTokenForm {
+    pub authenticity_token: String,
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct RevokeAdminApiTokenForm {
+    pub authenticity_token: String,
+}
+
+/// GET /admin/api-tokens
+pub async fn admin_api_tokens_get(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let authenticity_token = get_authenticity_token(&token, &session).await?;
+
+    let mut rows = String::new();
+    for admin_api_token in list_admin_api_tokens(&state.db_pool).await? {
+        let action = match admin_api_token.revoked_at {
+            Some(revoked_at) => format!("revoked {}", revoked_at.date()),
+            None => format!(
+                r#"<form action="/admin/api-tokens/{}/revoke" method="post">
+                    <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                    <button type="submit">Revoke</button>
+                </form>"#,
+                admin_api_token.admin_api_token_id
+            ),
+        };
+        rows.push_str(&format!(
+            r#"<tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            html_escape(&admin_api_token.name),
+            html_escape(&admin_api_token.created_by),
+            admin_api_token.created_at.date(),
+            admin_api_token.last_used_at.map_or_else(
+                || "never".to_string(),
+                |last_used_at| last_used_at.to_string()
+            ),
+            action
+        ));
+    }
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Admin API Tokens</h1>
+                <p>Tokens authenticate automation against the <code>/api/v1/admin</code> JSON API, described at <a href="/api/v1/admin/openapi.json">/api/v1/admin/openapi.json</a>. Send them as <code>Authorization: Bearer &lt;token&gt;</code>.</p>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>Name</th>
+                            <th>Created by</th>
+                            <th>Created</th>
+                            <th>Last used</th>
+                            <th></th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {rows}
+                    </tbody>
+                </table>
+                <h2>Create token</h2>
+                <form action="/admin/api-tokens" method="post">
+                    <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                    <label for="name">Name:</label><br>
+                    <input type="text" id="name" name="name" placeholder="terraform" required><br><br>
+                    <button type="submit">Create Token</button>
+                </form>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        admin_nav_menu()
+    );
+
+    Ok((token, Html(html)).into_response())
+}
+
+/// POST /admin/api-tokens
+pub async fn admin_api_tokens_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Form(form): Form<CreateAdminApiTokenForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Token name is required",
+        ));
+    }
+
+    let admin_api_token = create_admin_api_token(&state.db_pool, name, &admin_email).await?;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::AdminApiTokenCreate)
+            .actor(&admin_email)
+            .target(name)
+            .details(json!({ "admin_api_token_id": admin_api_token.admin_api_token_id })),
+    )
+    .await;
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Admin API Token</h1>
+                <p>Please save this token securely. It will not be shown again.</p>
+                <pre>{}</pre>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        admin_api_token.token,
+        admin_nav_menu()
+    );
+
+    Ok((token, Html(html)).into_response())
+}
+
+/// POST /admin/api-tokens/{admin_api_token_id}/revoke
+pub async fn admin_api_token_revoke_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Path(admin_api_token_id): Path<Uuid>,
+    Form(form): Form<RevokeAdminApiTokenForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let name = revoke_admin_api_token(&state.db_pool, admin_api_token_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Token not found or revoked"))?;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::AdminApiTokenRevoke)
+            .actor(&admin_email)
+            .target(&name)
+            .details(json!({ "admin_api_token_id": admin_api_token_id })),
+    )
+    .await;
+
+    Ok(Redirect::to("/admin/api-tokens").into_response())
+}
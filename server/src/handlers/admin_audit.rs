@@ -0,0 +1,186 @@
+use audit::{AUDIT_ACTIONS, AuditEventRow, AuditFilter, get_audit_events};
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{StatusCode, header::CONTENT_DISPOSITION},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::Deserialize;
+use time::{Date, Duration, OffsetDateTime};
+use tower_sessions::Session;
+
+use crate::admin::{get_admin_email, non_empty};
+use crate::handlers::admin_usage_report::parse_date_range;
+use crate::templates::common::{admin_nav_menu, common_styles, html_escape};
+
+const PAGE_LIMIT: i64 = 500;
+
+const EXPORT_LIMIT: i64 = 100_000;
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+}
+
+impl AuditQuery {
+    fn date_range(&self) -> Result<(Date, Date), AppError> {
+        parse_date_range(
+            self.from.as_deref().and_then(non_empty),
+            self.to.as_deref().and_then(non_empty),
+            OffsetDateTime::now_utc().date(),
+        )
+        .map_err(|message| AppError::new(StatusCode::BAD_REQUEST, message))
+    }
+
+    /// Builds a filter covering whole UTC days from `from` through `to`.
+    fn audit_filter(&self, from: Date, to: Date) -> AuditFilter {
+        AuditFilter {
+            action: self.action.as_deref().and_then(non_empty).map(String::from),
+            actor: self.actor.as_deref().and_then(non_empty).map(String::from),
+            since: Some(from.midnight().assume_utc()),
+            until: Some(to.midnight().assume_utc() + Duration::days(1)),
+        }
+    }
+}
+
+fn event_row(event: &AuditEventRow) -> String {
+    format!(
+        r#"<tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+        event.created_at,
+        html_escape(&event.action),
+        html_escape(event.actor.as_deref().unwrap_or("")),
+        html_escape(event.target.as_deref().unwrap_or("")),
+        html_escape(event.ip_address.as_deref().unwrap_or("")),
+        html_escape(event.user_agent.as_deref().unwrap_or("")),
+        html_escape(
+            &event
+                .details
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        )
+    )
+}
+
+pub async fn admin_audit_get(
+    session: Session,
+    state: State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let (from, to) = query.date_range()?;
+    let filter = query.audit_filter(from, to);
+    let events = get_audit_events(&state.db_pool, &filter, PAGE_LIMIT).await?;
+
+    let rows = events.iter().map(event_row).collect::<String>();
+
+    let selected_action = query.action.as_deref().and_then(non_empty).unwrap_or("");
+    let action_options = AUDIT_ACTIONS
+        .iter()
+        .map(|action| {
+            format!(
+                r#"<option value="{0}"{1}>{0}</option>"#,
+                action.as_str(),
+                if action.as_str() == selected_action {
+                    " selected"
+                } else {
+                    ""
+                }
+            )
+        })
+        .collect::<String>();
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Audit Log</h1>
+                <form action="/admin/audit" method="get">
+                    <label for="from">From:</label>
+                    <input type="date" id="from" name="from" value="{from}">
+                    <label for="to">To:</label>
+                    <input type="date" id="to" name="to" value="{to}"><br><br>
+                    <label for="actor">Actor email:</label><br>
+                    <input type="text" id="actor" name="actor" value="{}"><br><br>
+                    <label for="action">Action:</label>
+                    <select id="action" name="action">
+                        <option value="">all</option>
+                        {action_options}
+                    </select><br><br>
+                    <button type="submit">Apply</button>
+                    <button type="submit" formaction="/admin/audit.json">Export JSON</button>
+                </form>
+                <p>Showing the most recent {} event(s).</p>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>Time</th>
+                            <th>Action</th>
+                            <th>Actor</th>
+                            <th>Target</th>
+                            <th>IP address</th>
+                            <th>User agent</th>
+                            <th>Details</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {rows}
+                    </tbody>
+                </table>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        html_escape(query.actor.as_deref().and_then(non_empty).unwrap_or("")),
+        events.len(),
+        admin_nav_menu()
+    );
+
+    Ok(Html(html).into_response())
+}
+
+pub async fn admin_audit_json_get(
+    session: Session,
+    state: State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let (from, to) = query.date_range()?;
+    let filter = query.audit_filter(from, to);
+    let events = get_audit_events(&state.db_pool, &filter, EXPORT_LIMIT).await?;
+
+    Ok((
+        [(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"audit-{from}-{to}.json\""),
+        )],
+        Json(events),
+    )
+        .into_response())
+}
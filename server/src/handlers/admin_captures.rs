@@ -0,0 +1,318 @@
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
+use axum::{
+    extract::{Form, Path, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use axum_csrf::CsrfToken;
+use captures::{
+    CaptureFilter, get_capture, get_capture_policies, search_captures, set_api_key_capture_enabled,
+    set_user_capture_enabled,
+};
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::Deserialize;
+use serde_json::json;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::admin::{get_admin_email, non_empty};
+use crate::csrf::{get_authenticity_token, verify_authenticity_token};
+use crate::templates::common::{admin_nav_menu, common_styles, html_escape};
+
+const SEARCH_LIMIT: i64 = 100;
+
+#[derive(Deserialize)]
+pub struct CaptureSearchQuery {
+    pub user: Option<String>,
+    pub model: Option<String>,
+    pub q: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CapturePolicyForm {
+    pub authenticity_token: String,
+    pub user_email: String,
+    pub api_key_id: String,
+    pub capture: String,
+}
+
+pub async fn admin_captures_get(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    Query(query): Query<CaptureSearchQuery>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let authenticity_token = get_authenticity_token(&token, &session).await?;
+
+    let mut policy_rows = String::new();
+    for policy in get_capture_policies(&state.db_pool).await? {
+        policy_rows.push_str(&format!(
+            r#"<tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            html_escape(&policy.user_email),
+            match (policy.api_key_prefix, policy.api_key_id) {
+                (Some(api_key_prefix), Some(api_key_id)) =>
+                    format!("{api_key_prefix} ({api_key_id})"),
+                _ => "all keys".to_string(),
+            },
+            if policy.capture_enabled { "on" } else { "off" }
+        ));
+    }
+
+    let filter = CaptureFilter {
+        model_name: query.model.as_deref().and_then(non_empty).map(String::from),
+        text: query.q.as_deref().and_then(non_empty).map(String::from),
+        user_email: query.user.as_deref().and_then(non_empty).map(String::from),
+    };
+
+    let results = if state.capture_sink.is_searchable() {
+        let mut rows = String::new();
+        for capture in search_captures(&state.db_pool, &filter, SEARCH_LIMIT).await? {
+            rows.push_str(&format!(
+                r#"<tr>
+                    <td><a href="/admin/captures/{}">{}</a></td>
+                    <td>{}</td>
+                    <td>{}</td>
+                    <td>{}</td>
+                </tr>"#,
+                capture.capture_id,
+                capture.created_at,
+                html_escape(&capture.user_email),
+                html_escape(&capture.model_name),
+                html_escape(&capture.preview)
+            ));
+        }
+        format!(
+            r#"
+            <table>
+                <thead>
+                    <tr>
+                        <th>Captured at</th>
+                        <th>User</th>
+                        <th>Model</th>
+                        <th>Response</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    {rows}
+                </tbody>
+            </table>
+            "#
+        )
+    } else {
+        "<p>Captures are written to JSONL files; search requires the postgres capture sink.</p>"
+            .to_string()
+    };
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Captures</h1>
+                <h2>Capture policy</h2>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>User</th>
+                            <th>API key</th>
+                            <th>Capture</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {policy_rows}
+                    </tbody>
+                </table>
+                <form action="/admin/captures/policy" method="post">
+                    <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                    <label for="user_email">User email:</label><br>
+                    <input type="text" id="user_email" name="user_email"><br><br>
+                    <label for="api_key_id">API key id, from the user's page (overrides the user setting for that key):</label><br>
+                    <input type="text" id="api_key_id" name="api_key_id"><br><br>
+                    <select name="capture">
+                        <option value="on">on</option>
+                        <option value="off">off</option>
+                        <option value="inherit">inherit (API key only)</option>
+                    </select>
+                    <button type="submit">Set Policy</button>
+                </form>
+                <h2>Search</h2>
+                <form action="/admin/captures" method="get">
+                    <label for="user">User email:</label><br>
+                    <input type="text" id="user" name="user" value="{}"><br><br>
+                    <label for="model">Model:</label><br>
+                    <input type="text" id="model" name="model" value="{}"><br><br>
+                    <label for="q">Text:</label><br>
+                    <input type="text" id="q" name="q" value="{}"><br><br>
+                    <button type="submit">Search</button>
+                </form>
+                {results}
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        html_escape(filter.user_email.as_deref().unwrap_or_default()),
+        html_escape(filter.model_name.as_deref().unwrap_or_default()),
+        html_escape(filter.text.as_deref().unwrap_or_default()),
+        admin_nav_menu()
+    );
+
+    Ok((token, Html(html)).into_response())
+}
+
+pub async fn admin_capture_policy_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Form(form): Form<CapturePolicyForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let capture_enabled = match form.capture.as_str() {
+        "on" => Some(true),
+        "off" => Some(false),
+        "inherit" => None,
+        _ => {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "capture must be on, off or inherit",
+            ));
+        }
+    };
+
+    let api_key_id = form.api_key_id.trim();
+    let user_email = form.user_email.trim();
+
+    let target = if !api_key_id.is_empty() {
+        api_key_id
+    } else {
+        user_email
+    };
+
+    let updated = if !api_key_id.is_empty() {
+        let api_key_id = Uuid::parse_str(api_key_id)
+            .map_err(|_| AppError::new(StatusCode::BAD_REQUEST, "Invalid API key id"))?;
+        set_api_key_capture_enabled(&state.db_pool, api_key_id, capture_enabled).await?
+    } else if !user_email.is_empty() {
+        let capture_enabled = capture_enabled.ok_or_else(|| {
+            AppError::new(StatusCode::BAD_REQUEST, "inherit only applies to API keys")
+        })?;
+        set_user_capture_enabled(&state.db_pool, user_email, capture_enabled).await?
+    } else {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "A user email or API key id is required",
+        ));
+    };
+
+    if updated == 0 {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            "No matching user or API key",
+        ));
+    }
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::CapturePolicyUpdate)
+            .actor(&admin_email)
+            .target(target)
+            .details(json!({ "capture": form.capture })),
+    )
+    .await;
+
+    Ok(Redirect::to("/admin/captures").into_response())
+}
+
+pub async fn admin_capture_get(
+    session: Session,
+    state: State<AppState>,
+    Path(capture_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let capture = get_capture(&state.db_pool, capture_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Capture not found"))?;
+
+    let request_body = serde_json::from_str::<serde_json::Value>(&capture.request_body)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or(capture.request_body);
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Capture</h1>
+                <table>
+                    <tr>
+                        <th>Captured at</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>User</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>API key</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Model</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Stop reason</th>
+                        <td>{}</td>
+                    </tr>
+                </table>
+                <h2>Request</h2>
+                <pre style="white-space: pre-wrap">{}</pre>
+                <h2>Response</h2>
+                <pre style="white-space: pre-wrap">{}</pre>
+                <p><a href="/admin/captures">Back to captures</a></p>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        capture.created_at,
+        html_escape(&capture.user_email),
+        capture.api_key_prefix.as_deref().unwrap_or("-"),
+        html_escape(&capture.model_name),
+        html_escape(capture.stop_reason.as_deref().unwrap_or("-")),
+        html_escape(&request_body),
+        html_escape(&capture.response_text),
+        admin_nav_menu()
+    );
+
+    Ok(Html(html).into_response())
+}
@@ -0,0 +1,143 @@
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use inference_profiles::{InferenceProfileListing, list_all_inference_profiles};
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::Deserialize;
+use tower_sessions::Session;
+
+use crate::admin::get_admin_email;
+use crate::templates::common::{admin_nav_menu, common_styles, html_escape};
+
+const LIST_LIMIT: i64 = 500;
+
+#[derive(Deserialize)]
+pub struct InferenceProfileSearchQuery {
+    pub model: Option<String>,
+    pub user: Option<String>,
+}
+
+/// Profiles of disabled users or models are no longer used and are left
+/// behind if their cleanup failed or ran dry.
+fn status(inference_profile: &InferenceProfileListing) -> &'static str {
+    if inference_profile.user_is_disabled {
+        "user disabled"
+    } else if inference_profile.model_is_disabled {
+        "model disabled"
+    } else {
+        "active"
+    }
+}
+
+/// GET /admin/inference-profiles
+pub async fn admin_inference_profiles_get(
+    session: Session,
+    state: State<AppState>,
+    Query(query): Query<InferenceProfileSearchQuery>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let user_search = query
+        .user
+        .as_deref()
+        .map(str::trim)
+        .filter(|q| !q.is_empty());
+    let model_name = query
+        .model
+        .as_deref()
+        .map(str::trim)
+        .filter(|q| !q.is_empty());
+
+    let inference_profiles =
+        list_all_inference_profiles(&state.db_pool, user_search, model_name, LIST_LIMIT).await?;
+
+    let mut rows = String::new();
+    for inference_profile in &inference_profiles {
+        let team = match (inference_profile.team_id, &inference_profile.team_name) {
+            (Some(team_id), Some(team_name)) => format!(
+                r#"<a href="/admin/teams/{}">{}</a>"#,
+                team_id,
+                html_escape(team_name)
+            ),
+            _ => "-".to_string(),
+        };
+        rows.push_str(&format!(
+            r#"<tr>
+                <td><a href="/admin/users/{}">{}</a></td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            inference_profile.user_id,
+            html_escape(&inference_profile.user_email),
+            html_escape(&inference_profile.owner_type),
+            team,
+            html_escape(&inference_profile.model_name),
+            html_escape(&inference_profile.inference_profile_arn),
+            status(inference_profile),
+            inference_profile.created_at.date()
+        ));
+    }
+
+    let dry_run_notice = if state.inference_profile_cleanup_dry_run {
+        "<p>Cleanup is in dry-run mode: profiles of disabled users and deleted models are logged but kept.</p>"
+    } else {
+        ""
+    };
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Inference Profiles</h1>
+                {dry_run_notice}
+                <form action="/admin/inference-profiles" method="get">
+                    <label for="user">User email contains:</label><br>
+                    <input type="text" id="user" name="user" value="{}"><br><br>
+                    <label for="model">Model:</label><br>
+                    <input type="text" id="model" name="model" value="{}"><br><br>
+                    <button type="submit">Search</button>
+                </form>
+                <p>{} profile(s)</p>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>User</th>
+                            <th>Owner</th>
+                            <th>Team</th>
+                            <th>Model</th>
+                            <th>ARN</th>
+                            <th>Status</th>
+                            <th>Created</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {rows}
+                    </tbody>
+                </table>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        html_escape(user_search.unwrap_or_default()),
+        html_escape(model_name.unwrap_or_default()),
+        inference_profiles.len(),
+        admin_nav_menu()
+    );
+
+    Ok(Html(html).into_response())
+}
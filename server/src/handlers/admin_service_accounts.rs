@@ -0,0 +1,471 @@
+use apikeys::api_key_prefix;
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
+use axum::{
+    Json,
+    extract::{Form, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use axum_csrf::CsrfToken;
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use service_accounts::{
+    NewServiceAccount, SCOPES, Scope, ServiceAccount, create_service_account,
+    create_service_account_api_key, list_service_accounts, validate_service_account_name,
+};
+use teams::{get_team, list_teams};
+use tower_sessions::Session;
+use usage::{format_usd, parse_usd_micros};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::admin::{get_admin_email, require_admin_credential};
+use crate::csrf::{get_authenticity_token, verify_authenticity_token};
+use crate::templates::common::{admin_nav_menu, common_styles, html_escape};
+
+#[derive(Deserialize)]
+pub struct CreateServiceAccountForm {
+    pub authenticity_token: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub owner_team_id: String,
+    #[serde(default)]
+    pub budget_usd: String,
+    #[serde(default)]
+    pub scope_count_tokens: bool,
+    #[serde(default)]
+    pub scope_messages: bool,
+    #[serde(default)]
+    pub scope_models: bool,
+    #[serde(default)]
+    pub scope_usage: bool,
+}
+
+impl CreateServiceAccountForm {
+    fn scopes(&self) -> Vec<Scope> {
+        SCOPES
+            .into_iter()
+            .filter(|scope| match scope {
+                Scope::CountTokens => self.scope_count_tokens,
+                Scope::Messages => self.scope_messages,
+                Scope::Models => self.scope_models,
+                Scope::Usage => self.scope_usage,
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateServiceAccountKeyForm {
+    pub authenticity_token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateServiceAccountRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub owner_team_id: Option<Uuid>,
+    /// Any of `count_tokens`, `messages`, `models` and `usage`.
+    #[schema(value_type = Vec<String>)]
+    pub scopes: Vec<Scope>,
+    pub budget_usd_micros: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedServiceAccountResponse {
+    pub user_id: Uuid,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ServiceAccountsResponse {
+    #[schema(value_type = Vec<Object>)]
+    pub service_accounts: Vec<ServiceAccount>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ServiceAccountKeyResponse {
+    pub api_key: String,
+    pub user_id: Uuid,
+}
+
+/// Validates and creates a service account, auditing the creation.
+/// Shared by the admin page and the admin JSON API.
+async fn create_and_audit(
+    state: &AppState,
+    request_info: &RequestInfo,
+    admin_email: &str,
+    account: &NewServiceAccount<'_>,
+) -> Result<Uuid, AppError> {
+    validate_service_account_name(account.name)
+        .map_err(|message| AppError::new(StatusCode::BAD_REQUEST, message))?;
+
+    if account.budget_usd_micros.is_some_and(|budget| budget < 0) {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Budget must not be negative",
+        ));
+    }
+
+    if let Some(team_id) = account.owner_team_id
+        && get_team(&state.db_pool, team_id).await?.is_none()
+    {
+        return Err(AppError::new(StatusCode::BAD_REQUEST, "Unknown team"));
+    }
+
+    let user_id = create_service_account(&state.db_pool, account)
+        .await?
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::CONFLICT,
+                "A service account with this name already exists",
+            )
+        })?;
+
+    record_audit_event(
+        &state.db_pool,
+        request_info,
+        AuditEvent::new(AuditAction::ServiceAccountCreate)
+            .actor(admin_email)
+            .target(account.name)
+            .details(json!({
+                "budget_usd_micros": account.budget_usd_micros,
+                "owner_team_id": account.owner_team_id,
+                "scopes": account.scopes,
+                "user_id": user_id,
+            })),
+    )
+    .await;
+
+    Ok(user_id)
+}
+
+async fn create_key_and_audit(
+    state: &AppState,
+    request_info: &RequestInfo,
+    admin_email: &str,
+    user_id: Uuid,
+) -> Result<Uuid, AppError> {
+    let api_key = create_service_account_api_key(&state.db_pool, user_id)
+        .await?
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::NOT_FOUND,
+                "Service account not found or disabled",
+            )
+        })?;
+
+    record_audit_event(
+        &state.db_pool,
+        request_info,
+        AuditEvent::new(AuditAction::ServiceAccountKeyCreate)
+            .actor(admin_email)
+            .target(&api_key_prefix(&api_key.to_string()))
+            .details(json!({ "user_id": user_id })),
+    )
+    .await;
+
+    Ok(api_key)
+}
+
+pub async fn admin_service_accounts_get(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let authenticity_token = get_authenticity_token(&token, &session).await?;
+
+    let mut rows = String::new();
+    for account in list_service_accounts(&state.db_pool).await? {
+        rows.push_str(&format!(
+            r#"<tr>
+                <td><a href="/admin/users/{user_id}">{}</a></td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>
+                    <form action="/admin/service-accounts/{user_id}/keys" method="post">
+                        <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                        <button type="submit">New API Key</button>
+                    </form>
+                </td>
+            </tr>"#,
+            html_escape(&account.name),
+            html_escape(account.description.as_deref().unwrap_or("")),
+            html_escape(account.owner_team_name.as_deref().unwrap_or("admins")),
+            html_escape(&account.scopes.join(", ")),
+            account
+                .budget_usd_micros
+                .map(format_usd)
+                .unwrap_or_else(|| "unlimited".to_string()),
+            account.api_keys_count_active,
+            if account.is_disabled {
+                "disabled"
+            } else {
+                "active"
+            },
+            user_id = account.user_id,
+        ));
+    }
+
+    let team_options = list_teams(&state.db_pool)
+        .await?
+        .iter()
+        .map(|team| {
+            format!(
+                r#"<option value="{}">{}</option>"#,
+                team.team_id,
+                html_escape(&team.team_name)
+            )
+        })
+        .collect::<String>();
+
+    let scope_checkboxes = SCOPES
+        .iter()
+        .map(|scope| {
+            format!(
+                r#"<label><input type="checkbox" name="scope_{0}" value="true" checked> {0}</label><br>"#,
+                scope.as_str()
+            )
+        })
+        .collect::<String>();
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Service Accounts</h1>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>Name</th>
+                            <th>Description</th>
+                            <th>Owner</th>
+                            <th>Scopes</th>
+                            <th>Monthly budget</th>
+                            <th>Active keys</th>
+                            <th>Status</th>
+                            <th></th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {rows}
+                    </tbody>
+                </table>
+                <h2>Create service account</h2>
+                <form action="/admin/service-accounts" method="post">
+                    <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                    <label for="name">Name (lowercase letters, digits and dashes):</label><br>
+                    <input type="text" id="name" name="name" required><br><br>
+                    <label for="description">Description:</label><br>
+                    <input type="text" id="description" name="description"><br><br>
+                    <label for="owner_team_id">Owner:</label>
+                    <select id="owner_team_id" name="owner_team_id">
+                        <option value="">Admins</option>
+                        {team_options}
+                    </select><br><br>
+                    <p>Scopes:</p>
+                    {scope_checkboxes}<br>
+                    <label for="budget_usd">Monthly budget in USD (leave empty for unlimited):</label><br>
+                    <input type="text" id="budget_usd" name="budget_usd"><br><br>
+                    <button type="submit">Create Service Account</button>
+                </form>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        admin_nav_menu()
+    );
+
+    Ok((token, Html(html)).into_response())
+}
+
+pub async fn admin_service_accounts_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Form(form): Form<CreateServiceAccountForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let owner_team_id = match form.owner_team_id.trim() {
+        "" => None,
+        team_id => Some(
+            Uuid::parse_str(team_id)
+                .map_err(|_| AppError::new(StatusCode::BAD_REQUEST, "Invalid team"))?,
+        ),
+    };
+    let budget_usd_micros = match form.budget_usd.trim() {
+        "" => None,
+        budget_usd => Some(
+            parse_usd_micros(budget_usd)
+                .map_err(|message| AppError::new(StatusCode::BAD_REQUEST, message))?,
+        ),
+    };
+    let description = form.description.trim();
+    let scopes = form.scopes();
+
+    create_and_audit(
+        &state,
+        &request_info,
+        &admin_email,
+        &NewServiceAccount {
+            budget_usd_micros,
+            description: (!description.is_empty()).then_some(description),
+            name: form.name.trim(),
+            owner_team_id,
+            scopes: &scopes,
+        },
+    )
+    .await?;
+
+    Ok(Redirect::to("/admin/service-accounts").into_response())
+}
+
+pub async fn admin_service_account_keys_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Path(user_id): Path<Uuid>,
+    Form(form): Form<CreateServiceAccountKeyForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let api_key = create_key_and_audit(&state, &request_info, &admin_email, user_id).await?;
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Service Account API Key</h1>
+                <p>Please save this key securely. It will not be shown again.</p>
+                <pre>{}</pre>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        api_key,
+        admin_nav_menu()
+    );
+
+    Ok((token, Html(html)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/service-accounts",
+    tag = "service-accounts",
+    responses((status = 200, body = ServiceAccountsResponse))
+)]
+pub async fn admin_service_accounts_api_get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<ServiceAccountsResponse>, AppError> {
+    require_admin_credential(&headers, &state).await?;
+
+    Ok(Json(ServiceAccountsResponse {
+        service_accounts: list_service_accounts(&state.db_pool).await?,
+    }))
+}
+
+/// Creates a service account and returns its user id. Keys are created
+/// separately so they can be rotated.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/service-accounts",
+    tag = "service-accounts",
+    request_body = CreateServiceAccountRequest,
+    responses((status = 201, body = CreatedServiceAccountResponse), (status = 400))
+)]
+pub async fn admin_service_accounts_api_post(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Json(request): Json<CreateServiceAccountRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    let user_id = create_and_audit(
+        &state,
+        &request_info,
+        &admin_email,
+        &NewServiceAccount {
+            budget_usd_micros: request.budget_usd_micros,
+            description: request.description.as_deref(),
+            name: &request.name,
+            owner_team_id: request.owner_team_id,
+            scopes: &request.scopes,
+        },
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedServiceAccountResponse { user_id }),
+    ))
+}
+
+/// Creates an API key for an active service account. The key is only
+/// returned here.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/service-accounts/{user_id}/keys",
+    tag = "service-accounts",
+    params(("user_id" = Uuid, Path)),
+    responses(
+        (status = 201, body = ServiceAccountKeyResponse),
+        (status = 404, description = "No such service account, or it is disabled")
+    )
+)]
+pub async fn admin_service_account_keys_api_post(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+
+    let api_key = create_key_and_audit(&state, &request_info, &admin_email, user_id).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ServiceAccountKeyResponse {
+            api_key: api_key.to_string(),
+            user_id,
+        }),
+    ))
+}
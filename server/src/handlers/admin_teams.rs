@@ -0,0 +1,423 @@
+use apikeys::list_team_api_keys;
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
+use axum::{
+    extract::{Form, Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use axum_csrf::CsrfToken;
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::Deserialize;
+use serde_json::json;
+use teams::{
+    add_team_member, budget_period_start, create_team, get_team, get_team_budget_status,
+    list_team_members, list_teams, remove_team_member, set_team_budget,
+};
+use time::OffsetDateTime;
+use tower_sessions::Session;
+use usage::{GroupBy, UsageFilter, format_usd, get_usage_summary, parse_usd_micros};
+use uuid::Uuid;
+
+use crate::admin::get_admin_email;
+use crate::csrf::{get_authenticity_token, verify_authenticity_token};
+use crate::handlers::usage_dashboard::summary_table;
+use crate::templates::common::{admin_nav_menu, common_styles, html_escape};
+
+#[derive(Deserialize)]
+pub struct CreateTeamForm {
+    pub authenticity_token: String,
+    pub team_name: String,
+    pub budget_usd: String,
+}
+
+#[derive(Deserialize)]
+pub struct TeamBudgetForm {
+    pub authenticity_token: String,
+    pub budget_usd: String,
+}
+
+#[derive(Deserialize)]
+pub struct TeamMemberForm {
+    pub authenticity_token: String,
+    pub user_email: String,
+}
+
+#[derive(Deserialize)]
+pub struct RemoveTeamMemberForm {
+    pub authenticity_token: String,
+}
+
+/// Parses an optional monthly budget; an empty field means unlimited.
+fn parse_budget(budget_usd: &str) -> Result<Option<i64>, AppError> {
+    match budget_usd.trim() {
+        "" => Ok(None),
+        budget_usd => parse_usd_micros(budget_usd)
+            .map(Some)
+            .map_err(|message| AppError::new(StatusCode::BAD_REQUEST, message)),
+    }
+}
+
+fn format_budget(budget_usd_micros: Option<i64>) -> String {
+    budget_usd_micros
+        .map(format_usd)
+        .unwrap_or_else(|| "unlimited".to_string())
+}
+
+pub async fn admin_teams_get(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let authenticity_token = get_authenticity_token(&token, &session).await?;
+
+    let mut rows = String::new();
+    for team in list_teams(&state.db_pool).await? {
+        rows.push_str(&format!(
+            r#"<tr>
+                <td><a href="/admin/teams/{}">{}</a></td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            team.team_id,
+            html_escape(&team.team_name),
+            team.member_count,
+            format_budget(team.budget_usd_micros),
+            team.created_at.date()
+        ));
+    }
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Teams</h1>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>Team</th>
+                            <th>Members</th>
+                            <th>Monthly budget</th>
+                            <th>Created</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {rows}
+                    </tbody>
+                </table>
+                <h2>Create team</h2>
+                <form action="/admin/teams" method="post">
+                    <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                    <label for="team_name">Name:</label><br>
+                    <input type="text" id="team_name" name="team_name" required><br><br>
+                    <label for="budget_usd">Monthly budget in USD (leave empty for unlimited):</label><br>
+                    <input type="text" id="budget_usd" name="budget_usd"><br><br>
+                    <button type="submit">Create Team</button>
+                </form>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        admin_nav_menu()
+    );
+
+    Ok((token, Html(html)).into_response())
+}
+
+pub async fn admin_teams_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Form(form): Form<CreateTeamForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let team_name = form.team_name.trim();
+    if team_name.is_empty() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Team name is required",
+        ));
+    }
+    let budget_usd_micros = parse_budget(&form.budget_usd)?;
+
+    let team_id = create_team(&state.db_pool, team_name, budget_usd_micros).await?;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::TeamCreate)
+            .actor(&admin_email)
+            .target(team_name)
+            .details(json!({ "budget_usd_micros": budget_usd_micros })),
+    )
+    .await;
+
+    Ok(Redirect::to(&format!("/admin/teams/{team_id}")).into_response())
+}
+
+pub async fn admin_team_get(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    Path(team_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let team = get_team(&state.db_pool, team_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Team not found"))?;
+
+    let authenticity_token = get_authenticity_token(&token, &session).await?;
+
+    let mut member_rows = String::new();
+    for member in list_team_members(&state.db_pool, team_id).await? {
+        member_rows.push_str(&format!(
+            r#"<tr>
+                <td><a href="/admin/users/{user_id}">{}</a></td>
+                <td>{}</td>
+                <td>
+                    <form action="/admin/teams/{team_id}/members/{user_id}/remove" method="post">
+                        <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                        <button type="submit">Remove</button>
+                    </form>
+                </td>
+            </tr>"#,
+            html_escape(&member.user_email),
+            member.created_at.date(),
+            user_id = member.user_id,
+        ));
+    }
+
+    let mut api_key_rows = String::new();
+    for api_key in list_team_api_keys(&state.db_pool, team_id).await? {
+        api_key_rows.push_str(&format!(
+            r#"<tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            html_escape(&api_key.api_key_prefix),
+            if api_key.is_disabled {
+                "disabled"
+            } else {
+                "active"
+            },
+            api_key.created_at.date()
+        ));
+    }
+
+    let spent = match get_team_budget_status(&state.db_pool, team_id).await? {
+        Some(status) if status.is_exceeded() => {
+            format!("{} (budget exceeded)", format_usd(status.spent_usd_micros))
+        }
+        Some(status) => format_usd(status.spent_usd_micros),
+        None => "-".to_string(),
+    };
+
+    let now = OffsetDateTime::now_utc();
+    let filter = UsageFilter::new(budget_period_start(now), now).team_id(team_id);
+    let usage_by_user = get_usage_summary(&state.db_pool, &filter, GroupBy::User).await?;
+    let usage_by_model = get_usage_summary(&state.db_pool, &filter, GroupBy::Model).await?;
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>{}</h1>
+                <table>
+                    <tr>
+                        <th>Monthly budget</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Spend this month</th>
+                        <td>{spent}</td>
+                    </tr>
+                </table>
+                <form action="/admin/teams/{team_id}/budget" method="post">
+                    <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                    <label for="budget_usd">Monthly budget in USD (leave empty for unlimited):</label><br>
+                    <input type="text" id="budget_usd" name="budget_usd"><br><br>
+                    <button type="submit">Set Budget</button>
+                </form>
+                <h2>Members</h2>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>Email</th>
+                            <th>Added</th>
+                            <th></th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {member_rows}
+                    </tbody>
+                </table>
+                <form action="/admin/teams/{team_id}/members" method="post">
+                    <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                    <label for="user_email">User email:</label><br>
+                    <input type="text" id="user_email" name="user_email" required><br><br>
+                    <button type="submit">Add Member</button>
+                </form>
+                <h2>Team API keys</h2>
+                <p>Team keys belong to the team and stay active when the member who created them is removed or deactivated.</p>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>Prefix</th>
+                            <th>Status</th>
+                            <th>Created</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {api_key_rows}
+                    </tbody>
+                </table>
+                {}
+                {}
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        html_escape(&team.team_name),
+        format_budget(team.budget_usd_micros),
+        summary_table("Usage this month by user", &usage_by_user),
+        summary_table("Usage this month by model", &usage_by_model),
+        admin_nav_menu()
+    );
+
+    Ok((token, Html(html)).into_response())
+}
+
+pub async fn admin_team_budget_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Path(team_id): Path<Uuid>,
+    Form(form): Form<TeamBudgetForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let team = get_team(&state.db_pool, team_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Team not found"))?;
+    let budget_usd_micros = parse_budget(&form.budget_usd)?;
+
+    set_team_budget(&state.db_pool, team_id, budget_usd_micros).await?;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::TeamUpdate)
+            .actor(&admin_email)
+            .target(&team.team_name)
+            .details(json!({
+                "previous_budget_usd_micros": team.budget_usd_micros,
+                "budget_usd_micros": budget_usd_micros,
+            })),
+    )
+    .await;
+
+    Ok(Redirect::to(&format!("/admin/teams/{team_id}")).into_response())
+}
+
+pub async fn admin_team_members_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Path(team_id): Path<Uuid>,
+    Form(form): Form<TeamMemberForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let team = get_team(&state.db_pool, team_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Team not found"))?;
+    let user_email = form.user_email.trim();
+
+    if !add_team_member(&state.db_pool, team_id, user_email).await? {
+        return Err(AppError::new(StatusCode::NOT_FOUND, "User not found"));
+    }
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::TeamMemberAdd)
+            .actor(&admin_email)
+            .target(&team.team_name)
+            .details(json!({ "user_email": user_email.to_lowercase() })),
+    )
+    .await;
+
+    Ok(Redirect::to(&format!("/admin/teams/{team_id}")).into_response())
+}
+
+pub async fn admin_team_member_remove_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Path((team_id, user_id)): Path<(Uuid, Uuid)>,
+    Form(form): Form<RemoveTeamMemberForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let team = get_team(&state.db_pool, team_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Team not found"))?;
+
+    if remove_team_member(&state.db_pool, team_id, user_id).await? {
+        record_audit_event(
+            &state.db_pool,
+            &request_info,
+            AuditEvent::new(AuditAction::TeamMemberRemove)
+                .actor(&admin_email)
+                .target(&team.team_name)
+                .details(json!({ "user_id": user_id })),
+        )
+        .await;
+    }
+
+    Ok(Redirect::to(&format!("/admin/teams/{team_id}")).into_response())
+}
@@ -0,0 +1,124 @@
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use myerrors::AppError;
+use myhandlers::AppState;
+use tower_sessions::Session;
+use usage::{GROUP_BYS, format_usd, get_usage_summary};
+
+use crate::admin::get_admin_email;
+use crate::handlers::admin_usage_report::{AdminUsageQuery, usage_csv_response};
+use crate::handlers::usage_dashboard::summary_table;
+use crate::templates::common::{admin_nav_menu, common_styles, html_escape};
+
+pub async fn admin_usage_get(
+    session: Session,
+    state: State<AppState>,
+    Query(query): Query<AdminUsageQuery>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let (from, to) = query.date_range()?;
+    let filter = query.usage_filter(from, to);
+    let summaries = get_usage_summary(&state.db_pool, &filter, query.group_by).await?;
+
+    let total_requests: i64 = summaries.iter().map(|s| s.requests).sum();
+    let total_input_tokens: i64 = summaries.iter().map(|s| s.input_tokens).sum();
+    let total_output_tokens: i64 = summaries.iter().map(|s| s.output_tokens).sum();
+    let total_cost_usd_micros: i64 = summaries.iter().map(|s| s.cost_usd_micros).sum();
+
+    let group_by_options = GROUP_BYS
+        .iter()
+        .map(|group_by| {
+            format!(
+                r#"<option value="{0}"{1}>{0}</option>"#,
+                group_by.as_str(),
+                if *group_by == query.group_by {
+                    " selected"
+                } else {
+                    ""
+                }
+            )
+        })
+        .collect::<String>();
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Organization Usage</h1>
+                <form action="/admin/usage" method="get">
+                    <label for="from">From:</label>
+                    <input type="date" id="from" name="from" value="{from}">
+                    <label for="to">To:</label>
+                    <input type="date" id="to" name="to" value="{to}"><br><br>
+                    <label for="user">User email:</label><br>
+                    <input type="text" id="user" name="user" value="{}"><br><br>
+                    <label for="model">Model:</label><br>
+                    <input type="text" id="model" name="model" value="{}"><br><br>
+                    <label for="group_by">Group by:</label>
+                    <select id="group_by" name="group_by">{group_by_options}</select><br><br>
+                    <button type="submit">Apply</button>
+                    <button type="submit" formaction="/admin/usage.csv">Download CSV</button>
+                </form>
+                <table>
+                    <tr>
+                        <th>Requests</th>
+                        <td>{total_requests}</td>
+                    </tr>
+                    <tr>
+                        <th>Input tokens</th>
+                        <td>{total_input_tokens}</td>
+                    </tr>
+                    <tr>
+                        <th>Output tokens</th>
+                        <td>{total_output_tokens}</td>
+                    </tr>
+                    <tr>
+                        <th>Cost</th>
+                        <td>{}</td>
+                    </tr>
+                </table>
+                {}
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        html_escape(query.user().unwrap_or_default()),
+        html_escape(query.model().unwrap_or_default()),
+        format_usd(total_cost_usd_micros),
+        summary_table(&format!("By {}", query.group_by.as_str()), &summaries),
+        admin_nav_menu()
+    );
+
+    Ok(Html(html).into_response())
+}
+
+pub async fn admin_usage_csv_get(
+    session: Session,
+    state: State<AppState>,
+    Query(query): Query<AdminUsageQuery>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let (from, to) = query.date_range()?;
+    let filter = query.usage_filter(from, to);
+
+    Ok(usage_csv_response(
+        &state,
+        filter,
+        &format!("usage-{from}-{to}.csv"),
+    ))
+}
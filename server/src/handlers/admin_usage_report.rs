@@ -0,0 +1,265 @@
+use axum::{
+    Json,
+    body::Body,
+    extract::{Query, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+};
+use futures::{StreamExt, stream};
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::{Deserialize, Serialize};
+use time::{
+    Date, Duration, OffsetDateTime, format_description::BorrowedFormatItem,
+    macros::format_description,
+};
+use tokio::sync::mpsc;
+use tracing::error;
+use usage::{
+    GroupBy, USAGE_CSV_HEADER, UsageFilter, get_usage_summary, stream_usage_rows, usd_micros_to_usd,
+};
+
+use utoipa::{IntoParams, ToSchema};
+
+use crate::admin::{non_empty, require_admin_credential};
+use crate::handlers::usage_report::UsageReportRow;
+
+const DATE_FORMAT: &[BorrowedFormatItem<'static>] = format_description!("[year]-[month]-[day]");
+
+const DEFAULT_RANGE_DAYS: i64 = 30;
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    #[default]
+    Json,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AdminUsageQuery {
+    /// First day, `YYYY-MM-DD`; defaults to 29 days before `to`.
+    pub from: Option<String>,
+    /// Last day, `YYYY-MM-DD`; defaults to today.
+    pub to: Option<String>,
+    pub user: Option<String>,
+    pub model: Option<String>,
+    /// `day` (the default), `user`, `team`, `cost_center`, `model` or
+    /// `api_key`.
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    pub group_by: GroupBy,
+    /// `json` (the default) or `csv`.
+    #[serde(default)]
+    #[param(inline)]
+    pub format: ExportFormat,
+}
+
+/// Resolves optional inclusive `YYYY-MM-DD` bounds into a `(from, to)` date
+/// pair. Missing bounds default to the last 30 days ending `today`.
+pub fn parse_date_range(
+    from: Option<&str>,
+    to: Option<&str>,
+    today: Date,
+) -> Result<(Date, Date), String> {
+    let parse = |name: &str, value: &str| {
+        Date::parse(value, DATE_FORMAT)
+            .map_err(|_| format!("Invalid {name} date \"{value}\", expected YYYY-MM-DD"))
+    };
+
+    let to = match to {
+        Some(to) => parse("to", to)?,
+        None => today,
+    };
+    let from = match from {
+        Some(from) => parse("from", from)?,
+        None => to - Duration::days(DEFAULT_RANGE_DAYS - 1),
+    };
+
+    if from > to {
+        return Err("from must not be after to".to_string());
+    }
+
+    Ok((from, to))
+}
+
+impl AdminUsageQuery {
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref().and_then(non_empty)
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref().and_then(non_empty)
+    }
+
+    pub fn date_range(&self) -> Result<(Date, Date), AppError> {
+        parse_date_range(
+            self.from.as_deref().and_then(non_empty),
+            self.to.as_deref().and_then(non_empty),
+            OffsetDateTime::now_utc().date(),
+        )
+        .map_err(|message| AppError::new(StatusCode::BAD_REQUEST, message))
+    }
+
+    /// Builds a filter covering whole UTC days from `from` through `to`.
+    pub fn usage_filter(&self, from: Date, to: Date) -> UsageFilter {
+        let since = from.midnight().assume_utc();
+        let until = to.midnight().assume_utc() + Duration::days(1);
+
+        let mut filter = UsageFilter::new(since, until);
+        if let Some(user) = self.user() {
+            filter = filter.user_email(user);
+        }
+        if let Some(model) = self.model() {
+            filter = filter.model_name(model);
+        }
+        filter
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminUsageReportResponse {
+    pub data: Vec<UsageReportRow>,
+    pub from: String,
+    #[schema(value_type = String)]
+    pub group_by: GroupBy,
+    pub model: Option<String>,
+    pub to: String,
+    pub total_cost_usd: f64,
+    pub user: Option<String>,
+}
+
+/// Streams the usage selected by `filter` as CSV, one line per day, user,
+/// API key and model, without buffering the whole export in memory.
+pub fn usage_csv_response(state: &AppState, filter: UsageFilter, filename: &str) -> Response {
+    let db_pool = state.db_pool.clone();
+    let (tx, rx) = mpsc::channel::<Result<String, sqlx::Error>>(64);
+
+    tokio::spawn(async move {
+        if tx.send(Ok(USAGE_CSV_HEADER.to_string())).await.is_err() {
+            return;
+        }
+
+        let mut rows = stream_usage_rows(&db_pool, &filter);
+        while let Some(row) = rows.next().await {
+            let line = row.map(|row| row.to_csv_line());
+            let failed = line.is_err();
+            if let Err(e) = &line {
+                error!("Failed to stream usage rows: {:?}", e);
+            }
+            if tx.send(line).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let body = Body::from_stream(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    }));
+
+    (
+        [
+            (CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Returns organization-wide usage and cost between `from` and `to`
+/// (inclusive, `YYYY-MM-DD`, UTC), optionally filtered by `user` and `model`
+/// and grouped by `group_by`. With `format=csv` the per-day breakdown is
+/// streamed as CSV instead.
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/usage",
+    tag = "usage",
+    params(AdminUsageQuery),
+    responses(
+        (status = 200, body = AdminUsageReportResponse, content_type = "application/json"),
+        (status = 200, body = String, content_type = "text/csv"),
+        (status = 400)
+    )
+)]
+pub async fn admin_usage_report(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<AdminUsageQuery>,
+) -> Result<Response, AppError> {
+    require_admin_credential(&headers, &state).await?;
+
+    let (from, to) = query.date_range()?;
+    let filter = query.usage_filter(from, to);
+
+    if query.format == ExportFormat::Csv {
+        return Ok(usage_csv_response(
+            &state,
+            filter,
+            &format!("usage-{from}-{to}.csv"),
+        ));
+    }
+
+    let summaries = get_usage_summary(&state.db_pool, &filter, query.group_by).await?;
+    let total_cost_usd_micros = summaries.iter().map(|s| s.cost_usd_micros).sum();
+
+    Ok((
+        StatusCode::OK,
+        Json(AdminUsageReportResponse {
+            data: summaries.into_iter().map(UsageReportRow::from).collect(),
+            from: from.to_string(),
+            group_by: query.group_by,
+            model: filter.model_name,
+            to: to.to_string(),
+            total_cost_usd: usd_micros_to_usd(total_cost_usd_micros),
+            user: filter.user_email,
+        }),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    #[test]
+    fn parse_date_range_defaults_to_last_30_days() {
+        assert_eq!(
+            parse_date_range(None, None, date!(2026 - 03 - 31)),
+            Ok((date!(2026 - 03 - 02), date!(2026 - 03 - 31)))
+        );
+    }
+
+    #[test]
+    fn parse_date_range_parses_explicit_bounds() {
+        assert_eq!(
+            parse_date_range(
+                Some("2026-01-01"),
+                Some("2026-01-31"),
+                date!(2026 - 03 - 31)
+            ),
+            Ok((date!(2026 - 01 - 01), date!(2026 - 01 - 31)))
+        );
+    }
+
+    #[test]
+    fn parse_date_range_rejects_invalid_input() {
+        assert!(parse_date_range(Some("01/01/2026"), None, date!(2026 - 03 - 31)).is_err());
+        assert!(
+            parse_date_range(
+                Some("2026-02-01"),
+                Some("2026-01-01"),
+                date!(2026 - 03 - 31)
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,344 @@
+use apikeys::list_api_keys;
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
+use axum::{
+    extract::{Form, Path, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use axum_csrf::CsrfToken;
+use inference_profiles::{delete_user_inference_profiles, list_inference_profiles};
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::Deserialize;
+use serde_json::json;
+use time::{Duration, OffsetDateTime};
+use tower_sessions::Session;
+use usage::{GroupBy, UsageFilter, get_usage_summary};
+use users::{UserSummary, deactivate_user, get_user, list_users, reactivate_user};
+use uuid::Uuid;
+
+use crate::admin::get_admin_email;
+use crate::csrf::{get_authenticity_token, verify_authenticity_token};
+use crate::handlers::usage_dashboard::summary_table;
+use crate::templates::common::{admin_nav_menu, common_styles, html_escape};
+
+const LIST_LIMIT: i64 = 500;
+
+const USAGE_RANGE_DAYS: i64 = 30;
+
+#[derive(Deserialize)]
+pub struct UserSearchQuery {
+    pub q: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UserStatusForm {
+    pub authenticity_token: String,
+}
+
+fn status(is_disabled: bool) -> &'static str {
+    if is_disabled { "disabled" } else { "active" }
+}
+
+pub async fn admin_users_get(
+    session: Session,
+    state: State<AppState>,
+    Query(query): Query<UserSearchQuery>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let search = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+
+    let mut rows = String::new();
+    for user in list_users(&state.db_pool, search, 0, LIST_LIMIT).await? {
+        rows.push_str(&format!(
+            r#"<tr>
+                <td><a href="/admin/users/{}">{}</a></td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            user.user_id,
+            html_escape(&user.user_email),
+            html_escape(user.cost_center.as_deref().unwrap_or("-")),
+            user.api_keys_count_active,
+            status(user.is_disabled),
+            user.created_at.date()
+        ));
+    }
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Users</h1>
+                <form action="/admin/users" method="get">
+                    <label for="q">Email contains:</label><br>
+                    <input type="text" id="q" name="q" value="{}"><br><br>
+                    <button type="submit">Search</button>
+                </form>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>Email</th>
+                            <th>Cost center</th>
+                            <th>Active keys</th>
+                            <th>Status</th>
+                            <th>Created</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {rows}
+                    </tbody>
+                </table>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        html_escape(search.unwrap_or_default()),
+        admin_nav_menu()
+    );
+
+    Ok(Html(html).into_response())
+}
+
+pub async fn admin_user_get(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    if get_admin_email(&session, &state).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let user = get_user(&state.db_pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "User not found"))?;
+
+    let authenticity_token = get_authenticity_token(&token, &session).await?;
+
+    let mut api_key_rows = String::new();
+    for api_key in list_api_keys(&state.db_pool, user_id).await? {
+        api_key_rows.push_str(&format!(
+            r#"<tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            html_escape(&api_key.api_key_prefix),
+            api_key.api_key_id,
+            html_escape(api_key.team_name.as_deref().unwrap_or("personal")),
+            status(api_key.is_disabled),
+            match api_key.capture_enabled {
+                Some(true) => "on",
+                Some(false) => "off",
+                None => "inherit",
+            },
+            api_key.created_at.date()
+        ));
+    }
+
+    let mut inference_profile_rows = String::new();
+    for inference_profile in list_inference_profiles(&state.db_pool, user_id).await? {
+        inference_profile_rows.push_str(&format!(
+            r#"<tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            html_escape(&inference_profile.model_name),
+            html_escape(&inference_profile.inference_profile_arn),
+            inference_profile.created_at.date()
+        ));
+    }
+
+    let now = OffsetDateTime::now_utc();
+    let filter =
+        UsageFilter::new(now - Duration::days(USAGE_RANGE_DAYS), now).user_email(&user.user_email);
+    let usage = get_usage_summary(&state.db_pool, &filter, GroupBy::Model).await?;
+
+    let (action, label) = if user.is_disabled {
+        ("reactivate", "Reactivate User")
+    } else {
+        ("deactivate", "Deactivate User (disables all API keys)")
+    };
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>{}</h1>
+                <table>
+                    <tr>
+                        <th>Status</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Cost center</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Created</th>
+                        <td>{}</td>
+                    </tr>
+                </table>
+                <form action="/admin/users/{user_id}/{action}" method="post">
+                    <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                    <button type="submit">{label}</button>
+                </form>
+                <h2>API keys</h2>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>Prefix</th>
+                            <th>Id</th>
+                            <th>Owner</th>
+                            <th>Status</th>
+                            <th>Capture</th>
+                            <th>Created</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {api_key_rows}
+                    </tbody>
+                </table>
+                <h2>Inference profiles</h2>
+                <table>
+                    <thead>
+                        <tr>
+                            <th>Model</th>
+                            <th>ARN</th>
+                            <th>Created</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {inference_profile_rows}
+                    </tbody>
+                </table>
+                {}
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        html_escape(&user.user_email),
+        status(user.is_disabled),
+        html_escape(user.cost_center.as_deref().unwrap_or("-")),
+        user.created_at.date(),
+        summary_table("Usage by model (last 30 days)", &usage),
+        admin_nav_menu()
+    );
+
+    Ok((token, Html(html)).into_response())
+}
+
+/// Disables the user and their API keys, deletes their inference profiles in
+/// Bedrock and audits it. Shared by the admin page and the admin JSON API.
+pub(crate) async fn deactivate_and_audit(
+    state: &AppState,
+    request_info: &RequestInfo,
+    admin_email: &str,
+    user: &UserSummary,
+) -> Result<(), AppError> {
+    let disabled_api_keys_count = deactivate_user(&state.db_pool, user.user_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "User not found"))?;
+    let deleted_inference_profiles_count = delete_user_inference_profiles(
+        &state.bedrock_control_plane,
+        &state.db_pool,
+        user.user_id,
+        state.inference_profile_cleanup_dry_run,
+    )
+    .await?
+    .deleted;
+
+    record_audit_event(
+        &state.db_pool,
+        request_info,
+        AuditEvent::new(AuditAction::UserDeactivate)
+            .actor(admin_email)
+            .target(&user.user_email)
+            .details(json!({
+                "disabled_api_keys_count": disabled_api_keys_count,
+                "deleted_inference_profiles_count": deleted_inference_profiles_count,
+            })),
+    )
+    .await;
+
+    Ok(())
+}
+
+pub async fn admin_user_deactivate_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Path(user_id): Path<Uuid>,
+    Form(form): Form<UserStatusForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let user = get_user(&state.db_pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "User not found"))?;
+
+    deactivate_and_audit(&state, &request_info, &admin_email, &user).await?;
+
+    Ok(Redirect::to(&format!("/admin/users/{user_id}")).into_response())
+}
+
+pub async fn admin_user_reactivate_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Path(user_id): Path<Uuid>,
+    Form(form): Form<UserStatusForm>,
+) -> Result<Response, AppError> {
+    let Some(admin_email) = get_admin_email(&session, &state).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let user = get_user(&state.db_pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "User not found"))?;
+
+    reactivate_user(&state.db_pool, user_id).await?;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::UserReactivate)
+            .actor(&admin_email)
+            .target(&user.user_email),
+    )
+    .await;
+
+    Ok(Redirect::to(&format!("/admin/users/{user_id}")).into_response())
+}
@@ -1,26 +1,35 @@
-use apikeys::get_api_key;
 use axum::{
-    Json,
+    Extension, Json,
     extract::State,
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, sse::Sse},
 };
 use chat::provider::{BedrockChatCompletionsProvider, ChatCompletionsProvider};
-use inference_profiles::create_inference_profile;
-use myerrors::AppError;
+use myerrors::{AppError, bedrock_exception_type};
 use myhandlers::AppState;
+use mymetrics::RequestMetrics;
 use request::ChatCompletionsRequest;
+use service_accounts::Scope;
 use tracing::{debug, error};
 
 use crate::{
-    handlers::usage_callback::create_usage_callback,
-    validation::check_api_key_exists_and_model_exists_and_get_inference_profile_arn,
+    access_log::AccessLog,
+    auth::authenticate,
+    handlers::{
+        metrics::{bedrock_stream_span, metered_stream},
+        usage_callback::{UsageContext, create_usage_callback},
+    },
+    validation::{
+        check_budgets, check_caller_and_model, check_scope, get_or_create_inference_profile_arn,
+    },
 };
 
 #[allow(dead_code)]
 pub async fn chat_completions(
     headers: HeaderMap,
     State(state): State<AppState>,
+    Extension(request_metrics): Extension<RequestMetrics>,
+    Extension(access_log): Extension<AccessLog>,
     Json(mut payload): Json<ChatCompletionsRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     debug!(
@@ -28,19 +37,18 @@ pub async fn chat_completions(
         payload.model
     );
 
-    let api_key = get_api_key(&headers)
-        .await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "Invalid or missing API key"))?;
+    let caller = authenticate(&state, &headers, &access_log).await?;
+
+    let api_key_and_model = check_caller_and_model(&state, &caller, &payload.model).await?;
 
-    let (api_key_exists, model_exists, inference_profile_arn) =
-        check_api_key_exists_and_model_exists_and_get_inference_profile_arn(
-            &state.db_pool,
-            &api_key,
-            &payload.model,
-        )
-        .await?;
+    if let Some(user_id) = api_key_and_model.user_id {
+        request_metrics.set_user(&user_id.to_string());
+    }
+    if let Some(user_email) = &api_key_and_model.user_email {
+        access_log.set_user(user_email);
+    }
 
-    if !api_key_exists {
+    if !api_key_and_model.api_key_exists {
         error!("API key validation failed: Invalid API key");
         return Err(AppError::new(
             StatusCode::UNAUTHORIZED,
@@ -48,13 +56,20 @@ pub async fn chat_completions(
         ));
     }
 
-    if !model_exists {
+    if !api_key_and_model.model_exists {
         error!("Model name validation failed: Invalid model name");
         return Err(AppError::from(anyhow::anyhow!(
             "Invalid or missing model name"
         )));
     }
 
+    check_scope(&api_key_and_model, Scope::Messages)?;
+    check_budgets(&state.db_pool, &state.validation_cache, &api_key_and_model).await?;
+
+    let metrics_model_name = payload.model.to_lowercase();
+    request_metrics.set_model(&metrics_model_name);
+    access_log.set_model(&metrics_model_name);
+
     if payload.stream == Some(false) {
         error!("Streaming is required but was disabled by client (stream: false)");
         return Err(AppError::from(anyhow::anyhow!(
@@ -62,28 +77,48 @@ pub async fn chat_completions(
         )));
     }
 
-    let model_name = if let Some(inference_profile_arn) = inference_profile_arn {
-        inference_profile_arn
-    } else {
-        create_inference_profile(
-            &state.db_pool,
-            &api_key,
-            &payload.model,
-            &state.aws_region,
-            &state.aws_account_id,
-            &state.inference_profile_prefixes,
-        )
-        .await
-        .unwrap_or(payload.model.to_lowercase())
-    };
+    let model_name =
+        get_or_create_inference_profile_arn(&state, &caller, &api_key_and_model, &payload.model)
+            .await?;
+
+    let stream_span = bedrock_stream_span(&metrics_model_name);
 
-    let usage_callback = create_usage_callback(&model_name);
+    let usage_callback = create_usage_callback(
+        state.db_pool.clone(),
+        state.metrics.clone(),
+        stream_span.clone(),
+        access_log.clone(),
+        UsageContext {
+            api_key_id: api_key_and_model.api_key_id,
+            model_id: api_key_and_model.model_id,
+            model_name: payload.model.to_lowercase(),
+            team_id: api_key_and_model.team_id,
+            user_id: api_key_and_model.user_id,
+        },
+    );
 
+    access_log.set_upstream_model(&model_name);
     payload.model = model_name;
 
     let stream = BedrockChatCompletionsProvider::new(state.bedrockruntime_client.clone())
         .chat_completions_stream(payload, usage_callback)
-        .await?;
+        .await
+        .inspect_err(|e| {
+            if let Some(exception) = bedrock_exception_type(e) {
+                state
+                    .metrics
+                    .record_bedrock_error(&metrics_model_name, &exception);
+            }
+        })?;
+
+    let stream = metered_stream(
+        stream,
+        state
+            .metrics
+            .stream_guard(&metrics_model_name, request_metrics.started()),
+        stream_span,
+        access_log,
+    );
 
     Ok((StatusCode::OK, Sse::new(stream)))
 }
@@ -1,12 +1,16 @@
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
 use axum::{
     extract::{Form, State},
+    http::StatusCode,
     response::{Html, IntoResponse, Redirect, Response},
 };
 use axum_csrf::CsrfToken;
+use inference_profiles::delete_model_inference_profiles;
 use models::delete_model;
 use myerrors::AppError;
 use myhandlers::AppState;
 use serde::Deserialize;
+use serde_json::json;
 use tower_sessions::Session;
 
 use crate::csrf::verify_authenticity_token;
@@ -24,17 +28,47 @@ pub async fn delete_model_post(
     token: CsrfToken,
     session: Session,
     state: State<AppState>,
+    request_info: RequestInfo,
     form: Form<DeleteModelForm>,
 ) -> Result<Response, AppError> {
-    let _email = match session.get::<String>("email").await? {
+    let email = match session.get::<String>("email").await? {
         Some(email) => email,
         None => return Ok(Redirect::to("/login").into_response()),
     };
 
     verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
 
+    // The model's rows in inference_profiles would block its deletion.
+    let report = delete_model_inference_profiles(
+        &state.bedrock_control_plane,
+        &state.db_pool,
+        &form.model_name,
+        state.inference_profile_cleanup_dry_run,
+    )
+    .await?;
+    if report.is_incomplete() {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "Model \"{}\" still has {} inference profile(s); see /admin/inference-profiles",
+                form.model_name,
+                report.matched - report.deleted
+            ),
+        ));
+    }
+
     delete_model(&state.db_pool, &form.model_name).await?;
 
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ModelDelete)
+            .actor(&email)
+            .target(&form.model_name)
+            .details(json!({ "deleted_inference_profiles_count": report.deleted })),
+    )
+    .await;
+
     let html = format!(
         r#"
         <!DOCTYPE html>
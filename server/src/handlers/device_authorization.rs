@@ -0,0 +1,387 @@
+use apikeys::api_key_prefix;
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
+use axum::{
+    Json,
+    extract::{Form, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use axum_csrf::CsrfToken;
+use device_auth::{
+    DEVICE_CODE_TTL, DeviceTokenResult, POLL_INTERVAL_SECS, create_device_authorization,
+    decide_device_authorization, get_pending_device_authorization, normalize_user_code,
+    parse_scopes, redeem_device_code,
+};
+use inference_profiles::ProvisionJob;
+use myerrors::AppError;
+use myhandlers::{AppState, LOGIN_REDIRECT};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tower_sessions::Session;
+use tracing::warn;
+use url::Url;
+use users::{ensure_user, find_user};
+
+use crate::csrf::{get_authenticity_token, verify_authenticity_token};
+use crate::templates::common::{common_styles, html_escape, nav_menu};
+
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+#[derive(Deserialize)]
+pub struct DeviceCodeRequest {
+    /// Shown to the user on the approval page, e.g. the CLI's name.
+    pub client_id: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DeviceCodeResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    pub interval: i64,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceTokenRequest {
+    pub grant_type: String,
+    pub device_code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceQuery {
+    pub user_code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceDecisionForm {
+    pub authenticity_token: String,
+    pub user_code: String,
+    pub decision: String,
+}
+
+/// An OAuth 2.0 error response (RFC 6749 section 5.2).
+fn oauth_error(error: &str) -> Response {
+    oauth_error_with_status(StatusCode::BAD_REQUEST, error)
+}
+
+fn oauth_error_with_status(status: StatusCode, error: &str) -> Response {
+    (status, Json(json!({ "error": error }))).into_response()
+}
+
+/// Returns the approval page URL. The gateway's public origin is taken from
+/// the OIDC redirect URI, which must already point at the gateway.
+fn verification_uri(state: &AppState) -> Result<Url, AppError> {
+    Ok(Url::parse(&state.oidc.config().redirect_uri)?.join("/device")?)
+}
+
+/// POST /api/v1/device/code
+///
+/// Starts a device authorization request (RFC 8628). The CLI shows the
+/// user code and verification URI, then polls `/api/v1/device/token`.
+/// Anyone can call this, so requests are limited per client IP address and
+/// in how many may be pending at once.
+pub async fn device_code_post(
+    State(state): State<AppState>,
+    request_info: RequestInfo,
+    Form(request): Form<DeviceCodeRequest>,
+) -> Result<Response, AppError> {
+    if let Some(ip) = request_info
+        .ip_address
+        .as_deref()
+        .and_then(|ip| ip.parse().ok())
+        && !state.device_code_limiter.check(ip)
+    {
+        warn!(%ip, "Device authorization rate limit exceeded");
+        return Ok(oauth_error_with_status(
+            StatusCode::TOO_MANY_REQUESTS,
+            "slow_down",
+        ));
+    }
+
+    let scopes = match parse_scopes(request.scope.as_deref()) {
+        Ok(scopes) => scopes,
+        Err(message) => {
+            warn!("{message}");
+            return Ok(oauth_error("invalid_scope"));
+        }
+    };
+
+    let client_name = request
+        .client_id
+        .as_deref()
+        .map(str::trim)
+        .filter(|client_id| !client_id.is_empty());
+    let Some(authorization) = create_device_authorization(
+        &state.db_pool,
+        client_name,
+        scopes.as_deref(),
+        state.device_code_limiter.max_pending(),
+    )
+    .await?
+    else {
+        warn!("Too many pending device authorizations");
+        return Ok(oauth_error_with_status(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "temporarily_unavailable",
+        ));
+    };
+
+    let verification_uri = verification_uri(&state)?;
+    let mut verification_uri_complete = verification_uri.clone();
+    verification_uri_complete
+        .query_pairs_mut()
+        .append_pair("user_code", &authorization.user_code);
+
+    Ok(Json(DeviceCodeResponse {
+        device_code: authorization.device_code,
+        user_code: authorization.user_code,
+        verification_uri: verification_uri.into(),
+        verification_uri_complete: verification_uri_complete.into(),
+        expires_in: DEVICE_CODE_TTL.whole_seconds(),
+        interval: POLL_INTERVAL_SECS,
+    })
+    .into_response())
+}
+
+/// POST /api/v1/device/token
+///
+/// Polled by the CLI with its device code. Returns `authorization_pending`
+/// until the user decides, then the new API key as an OAuth access token.
+pub async fn device_token_post(
+    State(state): State<AppState>,
+    request_info: RequestInfo,
+    Form(request): Form<DeviceTokenRequest>,
+) -> Result<Response, AppError> {
+    if request.grant_type != DEVICE_CODE_GRANT_TYPE {
+        return Ok(oauth_error("unsupported_grant_type"));
+    }
+
+    let (api_key, scopes, user_email) =
+        match redeem_device_code(&state.db_pool, &request.device_code).await? {
+            DeviceTokenResult::AuthorizationPending => {
+                return Ok(oauth_error("authorization_pending"));
+            }
+            DeviceTokenResult::SlowDown => return Ok(oauth_error("slow_down")),
+            DeviceTokenResult::AccessDenied => return Ok(oauth_error("access_denied")),
+            DeviceTokenResult::ExpiredToken => return Ok(oauth_error("expired_token")),
+            DeviceTokenResult::InvalidGrant => return Ok(oauth_error("invalid_grant")),
+            DeviceTokenResult::Issued {
+                api_key,
+                scopes,
+                user_email,
+            } => (api_key, scopes, user_email),
+        };
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ApiKeyProvision)
+            .actor(&user_email)
+            .target(&api_key_prefix(&api_key.to_string()))
+            .details(json!({ "via": "device_authorization", "scopes": scopes })),
+    )
+    .await;
+
+    let mut response = json!({
+        "access_token": api_key.to_string(),
+        "token_type": "Bearer",
+    });
+    if let Some(scopes) = scopes {
+        response["scope"] = json!(scopes.join(" "));
+    }
+
+    Ok(Json(response).into_response())
+}
+
+/// GET /device
+///
+/// Lets a signed-in user enter a user code and approve or deny the request.
+pub async fn device_get(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    Query(query): Query<DeviceQuery>,
+) -> Result<Response, AppError> {
+    let user_code = query.user_code.as_deref().and_then(normalize_user_code);
+
+    if session.get::<String>("email").await?.is_none() {
+        let redirect = match &user_code {
+            Some(user_code) => format!("/device?user_code={user_code}"),
+            None => "/device".to_string(),
+        };
+        session.insert(LOGIN_REDIRECT, redirect).await?;
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let authenticity_token = get_authenticity_token(&token, &session).await?;
+
+    let pending = match &user_code {
+        Some(user_code) => get_pending_device_authorization(&state.db_pool, user_code).await?,
+        None => None,
+    };
+
+    let content = match pending {
+        Some(pending) => format!(
+            r#"
+                <p>A device is asking for an API key on your behalf. Only approve
+                if you started this request and the code matches the one shown
+                on your device.</p>
+                <table>
+                    <tr>
+                        <th>Code</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Client</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Scopes</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Requested</th>
+                        <td>{}</td>
+                    </tr>
+                </table>
+                <form action="/device" method="post">
+                    <input type="hidden" name="authenticity_token" value="{authenticity_token}">
+                    <input type="hidden" name="user_code" value="{}">
+                    <button type="submit" name="decision" value="approve">Approve</button>
+                    <button type="submit" name="decision" value="deny">Deny</button>
+                </form>"#,
+            html_escape(&pending.user_code),
+            html_escape(pending.client_name.as_deref().unwrap_or("unknown")),
+            html_escape(
+                &pending
+                    .scopes
+                    .map(|scopes| scopes.join(", "))
+                    .unwrap_or_else(|| "all".to_string())
+            ),
+            pending.created_at,
+            html_escape(&pending.user_code),
+        ),
+        None => {
+            let message = if query.user_code.is_some() {
+                "<p>That code is invalid or has expired.</p>"
+            } else {
+                ""
+            };
+            format!(
+                r#"
+                {message}
+                <form action="/device" method="get">
+                    <label for="user_code">Enter the code shown on your device:</label><br>
+                    <input type="text" id="user_code" name="user_code" autocomplete="off" required><br><br>
+                    <button type="submit">Continue</button>
+                </form>"#
+            )
+        }
+    };
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Connect a Device</h1>
+                {content}
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        nav_menu()
+    );
+
+    Ok((token, Html(html)).into_response())
+}
+
+/// POST /device
+pub async fn device_post(
+    token: CsrfToken,
+    session: Session,
+    state: State<AppState>,
+    request_info: RequestInfo,
+    Form(form): Form<DeviceDecisionForm>,
+) -> Result<Response, AppError> {
+    let Some(email) = session.get::<String>("email").await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
+
+    let approve = match form.decision.as_str() {
+        "approve" => true,
+        "deny" => false,
+        _ => return Err(AppError::new(StatusCode::BAD_REQUEST, "Invalid decision")),
+    };
+    let user_code = normalize_user_code(&form.user_code)
+        .ok_or_else(|| AppError::new(StatusCode::BAD_REQUEST, "Invalid code"))?;
+
+    if let Some(user_id) = ensure_user(&state.db_pool, &email).await? {
+        state.provision_inference_profiles(ProvisionJob::User(user_id));
+    }
+    let user = find_user(&state.db_pool, Some(&email), None)
+        .await?
+        .ok_or_else(|| AppError::new(StatusCode::FORBIDDEN, "User not found"))?;
+    if approve && user.is_disabled {
+        return Err(AppError::new(StatusCode::FORBIDDEN, "User is disabled"));
+    }
+
+    if !decide_device_authorization(&state.db_pool, &user_code, user.user_id, approve).await? {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            "That code is invalid or has expired",
+        ));
+    }
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(if approve {
+            AuditAction::DeviceApprove
+        } else {
+            AuditAction::DeviceDeny
+        })
+        .actor(&email)
+        .target(&user_code),
+    )
+    .await;
+
+    let message = if approve {
+        "Device approved. You can return to your terminal."
+    } else {
+        "Request denied."
+    };
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Connect a Device</h1>
+                <p>{message}</p>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        nav_menu()
+    );
+
+    Ok((token, Html(html)).into_response())
+}
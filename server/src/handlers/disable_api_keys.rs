@@ -1,13 +1,17 @@
 use apikeys::disable_all_api_keys;
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
 use axum::{
     extract::{Form, State},
     response::{Html, IntoResponse, Redirect, Response},
 };
 use axum_csrf::CsrfToken;
+use inference_profiles::delete_user_inference_profiles;
 use myerrors::AppError;
 use myhandlers::AppState;
 use serde::Deserialize;
+use serde_json::json;
 use tower_sessions::Session;
+use users::find_user;
 
 use crate::csrf::{get_authenticity_token, verify_authenticity_token};
 use crate::templates::common::{common_styles, nav_menu};
@@ -61,6 +65,7 @@ pub async fn disable_api_keys_post(
     token: CsrfToken,
     session: Session,
     state: State<AppState>,
+    request_info: RequestInfo,
     Form(form): Form<DisableApiKeysForm>,
 ) -> Result<Response, AppError> {
     let email = match session.get::<String>("email").await? {
@@ -72,6 +77,35 @@ pub async fn disable_api_keys_post(
 
     let disabled_api_keys_count = disable_all_api_keys(&state.db_pool, &email).await?;
 
+    // Profiles are recreated on the next request if the user signs in again.
+    let deleted_inference_profiles_count =
+        match find_user(&state.db_pool, Some(&email), None).await? {
+            Some(user) => {
+                delete_user_inference_profiles(
+                    &state.bedrock_control_plane,
+                    &state.db_pool,
+                    user.user_id,
+                    state.inference_profile_cleanup_dry_run,
+                )
+                .await?
+                .deleted
+            }
+            None => 0,
+        };
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ApiKeysDisable)
+            .actor(&email)
+            .target(&email)
+            .details(json!({
+                "disabled_api_keys_count": disabled_api_keys_count,
+                "deleted_inference_profiles_count": deleted_inference_profiles_count,
+            })),
+    )
+    .await;
+
     let html = format!(
         r#"
         <!DOCTYPE html>
@@ -1,3 +1,4 @@
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
 use axum::{
     extract::{Form, State},
     response::{Html, IntoResponse, Redirect, Response},
@@ -24,9 +25,10 @@ pub async fn disable_model_post(
     token: CsrfToken,
     session: Session,
     state: State<AppState>,
+    request_info: RequestInfo,
     form: Form<DisableModelForm>,
 ) -> Result<Response, AppError> {
-    let _email = match session.get::<String>("email").await? {
+    let email = match session.get::<String>("email").await? {
         Some(email) => email,
         None => return Ok(Redirect::to("/login").into_response()),
     };
@@ -35,6 +37,15 @@ pub async fn disable_model_post(
 
     disable_model(&state.db_pool, &form.model_name).await?;
 
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ModelDisable)
+            .actor(&email)
+            .target(&form.model_name),
+    )
+    .await;
+
     let html = format!(
         r#"
         <!DOCTYPE html>
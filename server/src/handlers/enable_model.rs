@@ -1,8 +1,10 @@
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
 use axum::{
     extract::{Form, State},
     response::{Html, IntoResponse, Redirect, Response},
 };
 use axum_csrf::CsrfToken;
+use inference_profiles::ProvisionJob;
 use models::enable_model;
 use myerrors::AppError;
 use myhandlers::AppState;
@@ -24,9 +26,10 @@ pub async fn enable_model_post(
     token: CsrfToken,
     session: Session,
     state: State<AppState>,
+    request_info: RequestInfo,
     form: Form<EnableModelForm>,
 ) -> Result<Response, AppError> {
-    let _email = match session.get::<String>("email").await? {
+    let email = match session.get::<String>("email").await? {
         Some(email) => email,
         None => return Ok(Redirect::to("/login").into_response()),
     };
@@ -34,6 +37,16 @@ pub async fn enable_model_post(
     verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
 
     enable_model(&state.db_pool, &form.model_name).await?;
+    state.provision_inference_profiles(ProvisionJob::Model(form.model_name.clone()));
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ModelEnable)
+            .actor(&email)
+            .target(&form.model_name),
+    )
+    .await;
 
     let html = format!(
         r#"
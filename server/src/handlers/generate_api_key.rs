@@ -1,33 +1,54 @@
-use apikeys::create_api_key;
+use apikeys::{api_key_prefix, create_api_key, create_team_api_key};
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
 use axum::{
     extract::{Form, State},
+    http::StatusCode,
     response::{Html, IntoResponse, Redirect, Response},
 };
 use axum_csrf::CsrfToken;
 use myerrors::AppError;
 use myhandlers::AppState;
 use serde::Deserialize;
+use serde_json::json;
+use teams::{is_team_member, list_user_teams};
 use tower_sessions::Session;
+use users::is_user_disabled;
+use uuid::Uuid;
 
 use crate::csrf::{get_authenticity_token, verify_authenticity_token};
-use crate::templates::common::{common_styles, nav_menu};
+use crate::templates::common::{common_styles, html_escape, nav_menu};
 
 #[derive(Deserialize)]
 pub struct ApiKeyForm {
     pub authenticity_token: String,
+    #[serde(default)]
+    pub team_id: String,
 }
 
 pub async fn generate_api_key_get(
     token: CsrfToken,
     session: Session,
+    state: State<AppState>,
 ) -> Result<Response, AppError> {
-    let _email = match session.get::<String>("email").await? {
+    let email = match session.get::<String>("email").await? {
         Some(email) => email,
         None => return Ok(Redirect::to("/login").into_response()),
     };
 
     let authenticity_token = get_authenticity_token(&token, &session).await?;
 
+    let team_options = list_user_teams(&state.db_pool, &email)
+        .await?
+        .iter()
+        .map(|team| {
+            format!(
+                r#"<option value="{}">{}</option>"#,
+                team.team_id,
+                html_escape(&team.team_name)
+            )
+        })
+        .collect::<String>();
+
     let html = format!(
         r#"
         <!DOCTYPE html>
@@ -41,6 +62,11 @@ pub async fn generate_api_key_get(
                 <p>Click the button below to generate a new API key.</p>
                 <form action="/generate-api-key" method="post">
                     <input type="hidden" name="authenticity_token" value="{}">
+                    <label for="team_id">Owner:</label>
+                    <select id="team_id" name="team_id">
+                        <option value="">Personal</option>
+                        {team_options}
+                    </select><br><br>
                     <button type="submit">Generate API Key</button>
                 </form>
                 {}
@@ -60,6 +86,7 @@ pub async fn generate_api_key_post(
     token: CsrfToken,
     session: Session,
     state: State<AppState>,
+    request_info: RequestInfo,
     form: Form<ApiKeyForm>,
 ) -> Result<Response, AppError> {
     let email = match session.get::<String>("email").await? {
@@ -69,7 +96,42 @@ pub async fn generate_api_key_post(
 
     verify_authenticity_token(&token, &session, &form.authenticity_token).await?;
 
-    let api_key = create_api_key(&state.db_pool, &email).await?;
+    if is_user_disabled(&state.db_pool, &email).await? {
+        return Err(AppError::new(StatusCode::FORBIDDEN, "User is disabled"));
+    }
+
+    let team_id = match form.team_id.trim() {
+        "" => None,
+        team_id => Some(
+            Uuid::parse_str(team_id)
+                .map_err(|_| AppError::new(StatusCode::BAD_REQUEST, "Invalid team"))?,
+        ),
+    };
+
+    let api_key = match team_id {
+        None => create_api_key(&state.db_pool, &email).await?.api_key,
+        Some(team_id) => {
+            if !is_team_member(&state.db_pool, team_id, &email).await? {
+                return Err(AppError::new(
+                    StatusCode::FORBIDDEN,
+                    "You are not a member of this team",
+                ));
+            }
+            create_team_api_key(&state.db_pool, &email, team_id)
+                .await?
+                .api_key
+        }
+    };
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ApiKeyCreate)
+            .actor(&email)
+            .target(&api_key_prefix(&api_key.to_string()))
+            .details(json!({ "team_id": team_id })),
+    )
+    .await;
 
     let html = format!(
         r#"
@@ -3,11 +3,15 @@ use axum::{
     extract::State,
     response::{Html, IntoResponse, Response},
 };
+use inference_profiles::ProvisionJob;
 use myerrors::AppError;
 use myhandlers::AppState;
+use time::{Duration, OffsetDateTime};
 use tower_sessions::Session;
-use users::create_user;
+use usage::{format_usd, get_total_cost_usd_micros};
+use users::ensure_user;
 
+use crate::admin::is_session_admin;
 use crate::templates::common::{common_styles, nav_menu};
 
 pub async fn index(session: Session, state: State<AppState>) -> Result<Response, AppError> {
@@ -20,6 +24,20 @@ pub async fn index(session: Session, state: State<AppState>) -> Result<Response,
                     .await
                     .unwrap_or((0, 0));
 
+            let cost_usd_micros = get_total_cost_usd_micros(
+                &state.db_pool,
+                email,
+                OffsetDateTime::now_utc() - Duration::days(30),
+            )
+            .await
+            .unwrap_or(0);
+
+            let admin_link = if is_session_admin(&session, &state, email).await? {
+                r#"<a href="/admin/usage">Admin</a>"#
+            } else {
+                ""
+            };
+
             format!(
                 r#"
                 <!DOCTYPE html>
@@ -35,8 +53,13 @@ pub async fn index(session: Session, state: State<AppState>) -> Result<Response,
                                 <th>API keys</th>
                                 <td>{} active ({} total)</td>
                             </tr>
+                            <tr>
+                                <th>Spend (last 30 days)</th>
+                                <td>{}</td>
+                            </tr>
                         </table>
                         {}
+                        {admin_link}
                     </div>
                 </body>
                 </html>
@@ -44,6 +67,7 @@ pub async fn index(session: Session, state: State<AppState>) -> Result<Response,
                 common_styles(),
                 api_keys_count_active,
                 api_keys_count,
+                format_usd(cost_usd_micros),
                 nav_menu()
             )
         }
@@ -65,8 +89,10 @@ pub async fn index(session: Session, state: State<AppState>) -> Result<Response,
         ),
     };
 
-    if let Some(ref email) = email {
-        let _ = create_user(&state.db_pool, email).await;
+    if let Some(ref email) = email
+        && let Ok(Some(user_id)) = ensure_user(&state.db_pool, email).await
+    {
+        state.provision_inference_profiles(ProvisionJob::User(user_id));
     }
 
     Ok(Html(html).into_response())
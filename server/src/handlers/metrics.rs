@@ -0,0 +1,96 @@
+use axum::{
+    extract::State,
+    http::{
+        HeaderMap, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+};
+use futures::{Stream, StreamExt};
+use myerrors::{AppError, bedrock_exception_type};
+use myhandlers::{AppState, token_matches};
+use mymetrics::StreamGuard;
+use tracing::{Span, field::Empty, info_span, warn};
+
+use crate::access_log::AccessLog;
+
+/// GET /metrics
+///
+/// Exposes gateway metrics in the Prometheus text exposition format to
+/// scrapers presenting the configured bearer token. Not found when no token
+/// is configured.
+pub async fn metrics_get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let Some(expected) = state.metrics_bearer_token.as_deref() else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let authorized = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token_matches(token.trim(), expected));
+    if !authorized {
+        warn!("Metrics request with invalid bearer token");
+        return Err(AppError::new(
+            StatusCode::UNAUTHORIZED,
+            "Invalid or missing bearer token",
+        ));
+    }
+
+    state
+        .metrics
+        .set_db_pool_connections(state.db_pool.size(), state.db_pool.num_idle());
+
+    Ok((
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render()?,
+    )
+        .into_response())
+}
+
+/// Creates the span covering a Bedrock response stream. Token counts are
+/// recorded on it by the usage callback.
+pub fn bedrock_stream_span(model: &str) -> Span {
+    info_span!(
+        "bedrock.stream",
+        model,
+        time_to_first_token_ms = Empty,
+        input_tokens = Empty,
+        output_tokens = Empty,
+        cache_read_input_tokens = Empty,
+        cache_write_input_tokens = Empty,
+    )
+}
+
+/// Wraps a response stream so that time-to-first-token, stream duration, the
+/// active stream gauge and mid-stream Bedrock errors are recorded. `span` and
+/// `access_log` are kept open until the stream is dropped.
+pub fn metered_stream<S, T>(
+    stream: S,
+    mut guard: StreamGuard,
+    span: Span,
+    access_log: AccessLog,
+) -> impl Stream<Item = Result<T, anyhow::Error>>
+where
+    S: Stream<Item = Result<T, anyhow::Error>>,
+{
+    stream.map(move |item| {
+        match &item {
+            Ok(_) => {
+                if let Some(time_to_first_token) = guard.on_event() {
+                    span.record(
+                        "time_to_first_token_ms",
+                        time_to_first_token.as_millis() as u64,
+                    );
+                    access_log.set_time_to_first_token(time_to_first_token);
+                }
+            }
+            Err(e) => guard
+                .on_error(&bedrock_exception_type(e).unwrap_or_else(|| "StreamError".to_string())),
+        }
+        item
+    })
+}
@@ -1,16 +1,32 @@
 pub mod add_model;
+pub mod admin_api;
+pub mod admin_api_tokens;
+pub mod admin_audit;
+pub mod admin_captures;
+pub mod admin_inference_profiles;
+pub mod admin_service_accounts;
+pub mod admin_teams;
+pub mod admin_usage_dashboard;
+pub mod admin_usage_report;
+pub mod admin_users;
 pub mod browse_models;
 pub mod chat_completions;
 pub mod delete_model;
+pub mod device_authorization;
 pub mod disable_api_keys;
 pub mod disable_model;
 pub mod enable_model;
 pub mod generate_api_key;
 pub mod health;
 pub mod index;
+pub mod metrics;
 pub mod models;
 pub mod provision_api_key;
+pub mod scim_users;
+pub mod session_tokens;
 pub mod usage_callback;
+pub mod usage_dashboard;
+pub mod usage_report;
 pub mod v1_messages;
 pub mod v1_messages_count_tokens;
 pub mod v1_models;
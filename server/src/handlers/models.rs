@@ -4,8 +4,9 @@ use axum::{Json, extract::State, http::HeaderMap, response::IntoResponse};
 use models::{get_enabled_model_names, to_models_response};
 use myerrors::AppError;
 use myhandlers::AppState;
+use service_accounts::Scope;
 
-use crate::validation::check_api_key_exists;
+use crate::validation::{check_api_key_exists, check_api_key_scope};
 
 #[allow(dead_code)]
 pub async fn models(
@@ -24,6 +25,8 @@ pub async fn models(
         )));
     }
 
+    check_api_key_scope(&state.db_pool, &api_key, Scope::Models).await?;
+
     let model_names = get_enabled_model_names(&state.db_pool).await?;
 
     let models_response = to_models_response(&model_names);
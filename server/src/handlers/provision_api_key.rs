@@ -1,65 +1,82 @@
 use anyhow::anyhow;
-use apikeys::{create_api_key, get_active_api_key};
+use apikeys::{api_key_prefix, create_api_key, get_active_api_key};
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
 use axum::{
     Json,
     extract::State,
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
 };
-use jsonwebtoken::{decode, decode_header};
-use jwks::{Jwks, jwk_to_decoding_key};
 use myhandlers::AppState;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use serde_json::json;
 use tracing::{error, info, warn};
-use users::create_user;
-use validation::ValidationBuilder;
+use users::is_user_disabled;
 
 #[derive(Serialize)]
 struct ApiKeyResponse {
     api_key: String,
 }
 
-#[derive(Deserialize)]
-struct CognitoClaims {
-    email: Option<String>,
-}
-
 /// POST /api/v1/api-key
 ///
-/// Accepts `Authorization: Bearer <cognito_access_token>`.
-/// Validates the JWT against gateway Cognito JWKS, extracts the user email,
-/// creates the user if needed, and returns an existing active API key or
-/// creates a new one.
+/// Accepts `Authorization: Bearer <access_token>` from the configured OIDC
+/// provider. Validates the JWT against the provider's JWKS, extracts the
+/// user email, creates the user if needed, and returns an existing active
+/// API key or creates a new one.
 pub async fn provision_api_key(
     headers: HeaderMap,
     State(state): State<AppState>,
+    request_info: RequestInfo,
 ) -> Result<Response, Response> {
-    let token = extract_bearer_token(&headers).map_err(|_| {
-        warn!("bad Authorization header");
-        (
-            StatusCode::UNAUTHORIZED,
-            "Missing or invalid Authorization header",
-        )
-            .into_response()
-    })?;
-
-    let email = validate_jwt_and_extract_email(&token, &state)
-        .await
-        .map_err(|_| {
-            warn!("JWT validation failed");
-            (StatusCode::UNAUTHORIZED, "Invalid or expired token").into_response()
-        })?;
+    let token = match extract_bearer_token(&headers) {
+        Ok(token) => token,
+        Err(_) => {
+            warn!("bad Authorization header");
+            record_provision_denied(&state, &request_info, "invalid_authorization_header").await;
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "Missing or invalid Authorization header",
+            )
+                .into_response());
+        }
+    };
+
+    let identity = match state.oidc.verify_access_token(&token).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            warn!("JWT validation failed: {e:#}");
+            record_provision_denied(&state, &request_info, "invalid_token").await;
+            return Err((StatusCode::UNAUTHORIZED, "Invalid or expired token").into_response());
+        }
+    };
 
     info!("provisioning API key for user");
 
-    // Create user if not exists (ignore unique constraint violation)
-    if let Err(e) = create_user(&state.db_pool, &email).await {
-        let is_duplicate = e
-            .downcast_ref::<sqlx::Error>()
-            .and_then(|se| se.as_database_error())
-            .is_some_and(|de| de.is_unique_violation());
-        if !is_duplicate {
-            error!("create_user failed");
+    let email = identity.email.clone();
+
+    // Create user if not exists
+    if state.ensure_identity_user(&identity).await.is_err() {
+        error!("ensure_identity_user failed");
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response());
+    }
+
+    match is_user_disabled(&state.db_pool, &email).await {
+        Ok(false) => {}
+        Ok(true) => {
+            warn!("API key provisioning denied for disabled user");
+            record_audit_event(
+                &state.db_pool,
+                &request_info,
+                AuditEvent::new(AuditAction::ApiKeyProvisionDenied)
+                    .actor(&email)
+                    .details(json!({ "reason": "user_disabled" })),
+            )
+            .await;
+            return Err((StatusCode::FORBIDDEN, "User is disabled").into_response());
+        }
+        Err(_) => {
+            error!("is_user_disabled failed");
             return Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response());
         }
     }
@@ -77,10 +94,22 @@ pub async fn provision_api_key(
     }
 
     // Create new key
-    let api_key = create_api_key(&state.db_pool, &email).await.map_err(|_| {
-        error!("create_api_key failed");
-        (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response()
-    })?;
+    let api_key = create_api_key(&state.db_pool, &email)
+        .await
+        .map_err(|_| {
+            error!("create_api_key failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response()
+        })?
+        .api_key;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::ApiKeyProvision)
+            .actor(&email)
+            .target(&api_key_prefix(&api_key.to_string())),
+    )
+    .await;
 
     Ok(Json(ApiKeyResponse {
         api_key: api_key.to_string(),
@@ -88,6 +117,15 @@ pub async fn provision_api_key(
     .into_response())
 }
 
+async fn record_provision_denied(state: &AppState, request_info: &RequestInfo, reason: &str) {
+    record_audit_event(
+        &state.db_pool,
+        request_info,
+        AuditEvent::new(AuditAction::ApiKeyProvisionDenied).details(json!({ "reason": reason })),
+    )
+    .await;
+}
+
 fn extract_bearer_token(headers: &HeaderMap) -> anyhow::Result<String> {
     let value = headers
         .get("Authorization")
@@ -101,40 +139,6 @@ fn extract_bearer_token(headers: &HeaderMap) -> anyhow::Result<String> {
     Ok(token.trim().to_string())
 }
 
-async fn validate_jwt_and_extract_email(token: &str, state: &AppState) -> anyhow::Result<String> {
-    // Decode header to get kid
-    let header = decode_header(token)?;
-    let kid = header.kid.ok_or_else(|| anyhow!("JWT missing kid"))?;
-
-    // Fetch JWKS
-    let jwks = Jwks::builder()
-        .region(&state.cognito_region)
-        .user_pool_id(&state.cognito_user_pool_id)
-        .build()
-        .await?;
-
-    let jwk = jwks
-        .find_jwk(&kid)
-        .ok_or_else(|| anyhow!("No matching key found in JWKS"))?;
-
-    let decoding_key = jwk_to_decoding_key(&jwk)?;
-
-    // Cognito access tokens don't have aud claim, so skip client_id
-    let validation = ValidationBuilder::new()
-        .client_id(&state.cognito_client_id)
-        .region(&state.cognito_region)
-        .user_pool_id(&state.cognito_user_pool_id)
-        .build()?;
-
-    let token_data = decode::<CognitoClaims>(token, &decoding_key, &validation)?;
-
-    token_data
-        .claims
-        .email
-        .filter(|e| !e.is_empty())
-        .ok_or_else(|| anyhow!("No email found in token claims"))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
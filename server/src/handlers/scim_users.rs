@@ -0,0 +1,360 @@
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header::AUTHORIZATION},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use inference_profiles::{ProvisionJob, delete_user_inference_profiles};
+use myhandlers::{AppState, token_matches};
+use scim::{
+    Email, EnterpriseUser, ListResponse, Meta, PatchRequest, SCHEMA_ENTERPRISE_USER, SCHEMA_USER,
+    ScimError, ScimUser, ScimUserRequest, UserAttributes, UserFilter, apply_patch, parse_filter,
+    scim_response,
+};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::json;
+use tracing::{info, warn};
+use users::{
+    UserSummary, count_users, deactivate_user, find_user, get_user, insert_user, list_users,
+    reactivate_user, update_user,
+};
+use uuid::Uuid;
+
+const SCIM_ACTOR: &str = "scim";
+
+const DEFAULT_COUNT: i64 = 100;
+
+const MAX_COUNT: i64 = 1000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimListQuery {
+    pub filter: Option<String>,
+    pub start_index: Option<i64>,
+    pub count: Option<i64>,
+}
+
+/// Rejects requests that do not present the configured SCIM bearer token.
+/// SCIM is disabled entirely when no token is configured.
+pub async fn require_scim_token(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(expected) = state.scim_bearer_token.as_deref() else {
+        return ScimError::not_found().into_response();
+    };
+
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token_matches(token.trim(), expected));
+
+    if !authorized {
+        warn!("SCIM request with invalid bearer token");
+        return ScimError::new(StatusCode::UNAUTHORIZED, "Invalid or missing bearer token")
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+fn to_scim_user(user: &UserSummary) -> ScimUser {
+    ScimUser {
+        schemas: vec![SCHEMA_USER, SCHEMA_ENTERPRISE_USER],
+        id: user.user_id.to_string(),
+        external_id: user.external_id.clone(),
+        user_name: user.user_email.clone(),
+        active: !user.is_disabled,
+        emails: vec![Email {
+            value: user.user_email.clone(),
+            primary: true,
+        }],
+        enterprise: EnterpriseUser {
+            cost_center: user.cost_center.clone(),
+        },
+        meta: Meta {
+            resource_type: "User",
+            created: user.created_at,
+            last_modified: user.updated_at,
+            location: format!("/scim/v2/Users/{}", user.user_id),
+        },
+    }
+}
+
+fn to_attributes(user: &UserSummary) -> UserAttributes {
+    UserAttributes {
+        active: !user.is_disabled,
+        cost_center: user.cost_center.clone(),
+        external_id: user.external_id.clone(),
+        user_name: user.user_email.clone(),
+    }
+}
+
+fn parse_body<T: DeserializeOwned>(body: &Bytes) -> Result<T, ScimError> {
+    serde_json::from_slice(body).map_err(|e| ScimError::bad_request("invalidSyntax", e.to_string()))
+}
+
+pub(crate) fn is_unique_violation(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<sqlx::Error>()
+        .and_then(|e| e.as_database_error())
+        .is_some_and(|e| e.is_unique_violation())
+}
+
+fn uniqueness_error() -> ScimError {
+    ScimError {
+        status: StatusCode::CONFLICT,
+        scim_type: Some("uniqueness"),
+        detail: "A user with this userName or externalId already exists".to_string(),
+    }
+}
+
+async fn load_user(state: &AppState, id: &str) -> Result<UserSummary, ScimError> {
+    let user_id = Uuid::parse_str(id).map_err(|_| ScimError::not_found())?;
+    get_user(&state.db_pool, user_id)
+        .await?
+        .filter(|user| !user.is_service_account)
+        .ok_or_else(ScimError::not_found)
+}
+
+/// Disables the user and their API keys, then deletes their inference
+/// profiles in Bedrock.
+async fn deactivate(
+    state: &AppState,
+    request_info: &RequestInfo,
+    user: &UserSummary,
+) -> Result<(), ScimError> {
+    let disabled_api_keys_count = deactivate_user(&state.db_pool, user.user_id)
+        .await?
+        .ok_or_else(ScimError::not_found)?;
+    let deleted_inference_profiles_count = delete_user_inference_profiles(
+        &state.bedrock_control_plane,
+        &state.db_pool,
+        user.user_id,
+        state.inference_profile_cleanup_dry_run,
+    )
+    .await?
+    .deleted;
+
+    info!(
+        disabled_api_keys_count,
+        deleted_inference_profiles_count, "Deactivated user via SCIM"
+    );
+
+    record_audit_event(
+        &state.db_pool,
+        request_info,
+        AuditEvent::new(AuditAction::UserDeactivate)
+            .actor(SCIM_ACTOR)
+            .target(&user.user_email)
+            .details(json!({
+                "disabled_api_keys_count": disabled_api_keys_count,
+                "deleted_inference_profiles_count": deleted_inference_profiles_count,
+            })),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Writes `attributes` over the stored user, deactivating or reactivating
+/// them if `active` changed.
+async fn apply_attributes(
+    state: &AppState,
+    request_info: &RequestInfo,
+    user: &UserSummary,
+    attributes: UserAttributes,
+) -> Result<Response, ScimError> {
+    let current = to_attributes(user);
+
+    if attributes.user_name != current.user_name
+        || attributes.external_id != current.external_id
+        || attributes.cost_center != current.cost_center
+    {
+        update_user(
+            &state.db_pool,
+            user.user_id,
+            &attributes.user_name,
+            attributes.external_id.as_deref(),
+            attributes.cost_center.as_deref(),
+        )
+        .await
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                uniqueness_error()
+            } else {
+                ScimError::from(e)
+            }
+        })?;
+
+        record_audit_event(
+            &state.db_pool,
+            request_info,
+            AuditEvent::new(AuditAction::UserUpdate)
+                .actor(SCIM_ACTOR)
+                .target(&attributes.user_name)
+                .details(json!({ "previous_user_email": user.user_email })),
+        )
+        .await;
+    }
+
+    if attributes.active != current.active {
+        if attributes.active {
+            reactivate_user(&state.db_pool, user.user_id).await?;
+            record_audit_event(
+                &state.db_pool,
+                request_info,
+                AuditEvent::new(AuditAction::UserReactivate)
+                    .actor(SCIM_ACTOR)
+                    .target(&attributes.user_name),
+            )
+            .await;
+        } else {
+            deactivate(state, request_info, user).await?;
+        }
+    }
+
+    let user = load_user(state, &user.user_id.to_string()).await?;
+    Ok(scim_response(StatusCode::OK, to_scim_user(&user)))
+}
+
+pub async fn scim_users_get(
+    State(state): State<AppState>,
+    Query(query): Query<ScimListQuery>,
+) -> Result<Response, ScimError> {
+    let start_index = query.start_index.unwrap_or(1).max(1);
+    let count = query.count.unwrap_or(DEFAULT_COUNT).clamp(0, MAX_COUNT);
+
+    let response = match query.filter.as_deref() {
+        Some(filter) => {
+            let user = match parse_filter(filter)? {
+                UserFilter::ExternalId(external_id) => {
+                    find_user(&state.db_pool, None, Some(&external_id)).await?
+                }
+                UserFilter::UserName(user_name) => {
+                    find_user(&state.db_pool, Some(&user_name), None).await?
+                }
+            };
+            let resources = user.iter().map(to_scim_user).collect::<Vec<_>>();
+            let total_results = resources.len();
+            ListResponse::new(resources, total_results, 1)
+        }
+        None => {
+            let users = list_users(&state.db_pool, None, start_index - 1, count).await?;
+            let total_results = count_users(&state.db_pool).await?;
+            ListResponse::new(
+                users.iter().map(to_scim_user).collect(),
+                total_results as usize,
+                start_index as usize,
+            )
+        }
+    };
+
+    Ok(scim_response(StatusCode::OK, response))
+}
+
+pub async fn scim_user_get(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, ScimError> {
+    let user = load_user(&state, &id).await?;
+    Ok(scim_response(StatusCode::OK, to_scim_user(&user)))
+}
+
+pub async fn scim_users_post(
+    State(state): State<AppState>,
+    request_info: RequestInfo,
+    body: Bytes,
+) -> Result<Response, ScimError> {
+    let attributes = parse_body::<ScimUserRequest>(&body)?.into_attributes();
+
+    if find_user(
+        &state.db_pool,
+        Some(&attributes.user_name),
+        attributes.external_id.as_deref(),
+    )
+    .await?
+    .is_some()
+    {
+        return Err(uniqueness_error());
+    }
+
+    let user_id = insert_user(
+        &state.db_pool,
+        &attributes.user_name,
+        attributes.external_id.as_deref(),
+        attributes.cost_center.as_deref(),
+    )
+    .await
+    .map_err(|e| {
+        if is_unique_violation(&e) {
+            uniqueness_error()
+        } else {
+            ScimError::from(e)
+        }
+    })?;
+    if attributes.active {
+        state.provision_inference_profiles(ProvisionJob::User(user_id));
+    }
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::UserCreate)
+            .actor(SCIM_ACTOR)
+            .target(&attributes.user_name),
+    )
+    .await;
+
+    let mut user = load_user(&state, &user_id.to_string()).await?;
+    if !attributes.active {
+        deactivate(&state, &request_info, &user).await?;
+        user = load_user(&state, &user_id.to_string()).await?;
+    }
+
+    Ok(scim_response(StatusCode::CREATED, to_scim_user(&user)))
+}
+
+pub async fn scim_user_put(
+    State(state): State<AppState>,
+    request_info: RequestInfo,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Result<Response, ScimError> {
+    let user = load_user(&state, &id).await?;
+    let attributes = parse_body::<ScimUserRequest>(&body)?.into_attributes();
+    apply_attributes(&state, &request_info, &user, attributes).await
+}
+
+pub async fn scim_user_patch(
+    State(state): State<AppState>,
+    request_info: RequestInfo,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Result<Response, ScimError> {
+    let user = load_user(&state, &id).await?;
+    let patch = parse_body::<PatchRequest>(&body)?;
+
+    let mut attributes = to_attributes(&user);
+    apply_patch(&mut attributes, &patch)?;
+
+    apply_attributes(&state, &request_info, &user, attributes).await
+}
+
+/// Users are never hard-deleted because usage records reference them;
+/// DELETE deactivates the user instead.
+pub async fn scim_user_delete(
+    State(state): State<AppState>,
+    request_info: RequestInfo,
+    Path(id): Path<String>,
+) -> Result<Response, ScimError> {
+    let user = load_user(&state, &id).await?;
+    if !user.is_disabled {
+        deactivate(&state, &request_info, &user).await?;
+    }
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
@@ -0,0 +1,207 @@
+use audit::{AuditAction, AuditEvent, RequestInfo, record_audit_event};
+use axum::{
+    Extension, Json,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use service_accounts::Scope;
+use session_tokens::{
+    SessionTokenIssuer, SessionTokenSubject, get_api_key_subject, get_user_subject, narrow_scopes,
+};
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    access_log::AccessLog,
+    admin::require_admin_credential,
+    auth::{Caller, authenticate},
+};
+
+#[derive(Default, Deserialize)]
+pub struct SessionTokenRequest {
+    /// Requested lifetime in seconds, capped at `session_token_max_ttl_secs`.
+    pub expires_in: Option<i64>,
+    /// Restricts the token to these scopes, which the credential must have.
+    pub scopes: Option<Vec<Scope>>,
+}
+
+#[derive(Serialize)]
+pub struct SessionTokenResponse {
+    pub access_token: String,
+    pub expires_in: i64,
+    pub scope: Option<String>,
+    pub token_type: &'static str,
+}
+
+#[derive(Deserialize)]
+pub struct RevokeSessionTokenRequest {
+    pub token: String,
+}
+
+fn session_token_issuer(state: &AppState) -> Result<Arc<SessionTokenIssuer>, AppError> {
+    state
+        .session_tokens
+        .clone()
+        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "Session tokens are not enabled"))
+}
+
+/// POST /api/v1/token
+///
+/// Exchanges an API key or identity provider access token for a short-lived
+/// session token, so long-lived keys need not be stored on laptops.
+pub async fn session_token_post(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Extension(access_log): Extension<AccessLog>,
+    request_info: RequestInfo,
+    body: Bytes,
+) -> Result<impl IntoResponse, AppError> {
+    let issuer = session_token_issuer(&state)?;
+
+    let request = if body.is_empty() {
+        SessionTokenRequest::default()
+    } else {
+        serde_json::from_slice::<SessionTokenRequest>(&body)
+            .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, format!("Invalid request: {e}")))?
+    };
+
+    let caller = authenticate(&state, &headers, &access_log).await?;
+    let (subject, via) = match &caller {
+        Caller::ApiKey(api_key) => (
+            get_api_key_subject(&state.db_pool, api_key).await?,
+            "api_key",
+        ),
+        Caller::Oidc(identity) => {
+            state.ensure_identity_user(identity).await?;
+            (
+                get_user_subject(&state.db_pool, &identity.email).await?,
+                "oidc",
+            )
+        }
+        Caller::SessionToken(_) => {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                "Session tokens cannot be exchanged for new tokens",
+            ));
+        }
+    };
+    let Some(subject) = subject else {
+        warn!("Session token requested with an invalid or disabled credential");
+        return Err(AppError::new(
+            StatusCode::UNAUTHORIZED,
+            "Invalid or missing API key",
+        ));
+    };
+    access_log.set_user(&subject.email);
+
+    let scopes = narrow_scopes(subject.scopes.as_deref(), request.scopes.as_deref())
+        .map_err(|e| AppError::new(StatusCode::FORBIDDEN, e))?;
+    let issued = issuer.issue(
+        SessionTokenSubject { scopes, ..subject },
+        request.expires_in.map(Duration::seconds),
+    )?;
+    let claims = &issued.claims;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::SessionTokenIssue)
+            .actor(&claims.email)
+            .target(&claims.jti.to_string())
+            .details(json!({
+                "expires_at": claims.exp,
+                "scopes": claims.scopes,
+                "via": via,
+            })),
+    )
+    .await;
+
+    Ok(Json(SessionTokenResponse {
+        expires_in: claims.exp - claims.iat,
+        scope: claims.scopes.as_ref().map(|scopes| scopes.join(" ")),
+        token_type: "Bearer",
+        access_token: issued.token,
+    }))
+}
+
+/// POST /api/v1/token/revoke
+///
+/// Revokes a session token (RFC 7009 style): holding the token is enough to
+/// revoke it. Unknown or expired tokens are accepted and ignored.
+pub async fn session_token_revoke_post(
+    State(state): State<AppState>,
+    request_info: RequestInfo,
+    Json(request): Json<RevokeSessionTokenRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let issuer = session_token_issuer(&state)?;
+
+    let Ok(claims) = issuer.decode(&request.token) else {
+        return Ok(StatusCode::OK);
+    };
+
+    issuer
+        .revoke(&state.db_pool, claims.jti, claims.expires_at())
+        .await?;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::SessionTokenRevoke)
+            .actor(&claims.email)
+            .target(&claims.jti.to_string()),
+    )
+    .await;
+
+    Ok(StatusCode::OK)
+}
+
+/// Revokes a session token by id, as recorded in the `session_token.issue`
+/// audit events.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/session-tokens/{jti}/revoke",
+    tag = "session-tokens",
+    params(("jti" = Uuid, Path)),
+    responses(
+        (status = 200),
+        (status = 404, description = "Session tokens are not enabled")
+    )
+)]
+pub async fn admin_session_token_revoke_post(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request_info: RequestInfo,
+    Path(jti): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let admin_email = require_admin_credential(&headers, &state).await?;
+    let issuer = session_token_issuer(&state)?;
+
+    // The token itself is not at hand, so keep the entry for the longest
+    // lifetime a token could have.
+    issuer
+        .revoke(
+            &state.db_pool,
+            jti,
+            OffsetDateTime::now_utc() + issuer.max_ttl(),
+        )
+        .await?;
+
+    record_audit_event(
+        &state.db_pool,
+        &request_info,
+        AuditEvent::new(AuditAction::SessionTokenRevoke)
+            .actor(&admin_email)
+            .target(&jti.to_string()),
+    )
+    .await;
+
+    Ok(StatusCode::OK)
+}
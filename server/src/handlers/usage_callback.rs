@@ -1,9 +1,110 @@
 use aws_sdk_bedrockruntime::types::TokenUsage;
-use tracing::info;
+use mymetrics::Metrics;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{Span, error, info};
+use usage::{NewUsageRecord, TokenCounts, record_usage};
+use uuid::Uuid;
 
-pub fn create_usage_callback(model_name: &str) -> impl Fn(&TokenUsage) + Send + Sync + 'static {
-    let model_name = model_name.to_string();
+use crate::access_log::AccessLog;
+
+pub struct UsageContext {
+    pub api_key_id: Option<Uuid>,
+    pub model_id: Option<Uuid>,
+    pub model_name: String,
+    pub team_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+}
+
+pub fn to_token_counts(token_usage: &TokenUsage) -> TokenCounts {
+    TokenCounts {
+        input_tokens: token_usage.input_tokens().into(),
+        output_tokens: token_usage.output_tokens().into(),
+        cache_read_input_tokens: token_usage.cache_read_input_tokens().unwrap_or(0).into(),
+        cache_write_input_tokens: token_usage.cache_write_input_tokens().unwrap_or(0).into(),
+    }
+}
+
+pub fn create_usage_callback(
+    db_pool: Arc<PgPool>,
+    metrics: Arc<Metrics>,
+    span: Span,
+    access_log: AccessLog,
+    usage_context: UsageContext,
+) -> impl Fn(&TokenUsage) + Send + Sync + 'static {
     move |token_usage: &TokenUsage| {
-        info!("Usage for model {}: {:?}", model_name, token_usage);
+        let tokens = to_token_counts(token_usage);
+        info!(
+            model = %usage_context.model_name,
+            input_tokens = tokens.input_tokens,
+            output_tokens = tokens.output_tokens,
+            cache_read_input_tokens = tokens.cache_read_input_tokens,
+            cache_write_input_tokens = tokens.cache_write_input_tokens,
+            "usage"
+        );
+
+        access_log.set_tokens(&tokens);
+
+        span.record("input_tokens", tokens.input_tokens);
+        span.record("output_tokens", tokens.output_tokens);
+        span.record("cache_read_input_tokens", tokens.cache_read_input_tokens);
+        span.record("cache_write_input_tokens", tokens.cache_write_input_tokens);
+
+        metrics.record_tokens(
+            &usage_context.model_name,
+            &usage_context
+                .user_id
+                .map(|user_id| user_id.to_string())
+                .unwrap_or_default(),
+            &tokens,
+        );
+
+        let Some(user_id) = usage_context.user_id else {
+            return;
+        };
+
+        let record = NewUsageRecord {
+            api_key_id: usage_context.api_key_id,
+            model_id: usage_context.model_id,
+            model_name: usage_context.model_name.clone(),
+            team_id: usage_context.team_id,
+            tokens,
+            user_id,
+        };
+        let db_pool = db_pool.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = record_usage(&db_pool, &record).await {
+                error!(
+                    "Failed to record usage for model {}: {:?}",
+                    record.model_name, e
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_token_counts_defaults_missing_cache_tokens_to_zero() {
+        let token_usage = TokenUsage::builder()
+            .input_tokens(10)
+            .output_tokens(20)
+            .total_tokens(30)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            to_token_counts(&token_usage),
+            TokenCounts {
+                input_tokens: 10,
+                output_tokens: 20,
+                cache_read_input_tokens: 0,
+                cache_write_input_tokens: 0,
+            }
+        );
     }
 }
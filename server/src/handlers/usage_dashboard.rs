@@ -0,0 +1,266 @@
+use axum::{
+    extract::{Query, State},
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+use tower_sessions::Session;
+use usage::{
+    GroupBy, USAGE_CSV_HEADER, UsageFilter, UsageRow, UsageSummary, format_usd, get_usage_rows,
+    summarize_rows,
+};
+
+use crate::templates::common::{common_styles, html_escape, nav_menu};
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+pub enum UsageRange {
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+    #[default]
+    #[serde(rename = "30d")]
+    Month,
+    #[serde(rename = "90d")]
+    Quarter,
+}
+
+const USAGE_RANGES: [UsageRange; 4] = [
+    UsageRange::Day,
+    UsageRange::Week,
+    UsageRange::Month,
+    UsageRange::Quarter,
+];
+
+impl UsageRange {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UsageRange::Day => "24h",
+            UsageRange::Week => "7d",
+            UsageRange::Month => "30d",
+            UsageRange::Quarter => "90d",
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        match self {
+            UsageRange::Day => Duration::hours(24),
+            UsageRange::Week => Duration::days(7),
+            UsageRange::Month => Duration::days(30),
+            UsageRange::Quarter => Duration::days(90),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UsageDashboardQuery {
+    #[serde(default)]
+    pub range: UsageRange,
+}
+
+async fn get_user_usage_rows(
+    state: &AppState,
+    email: &str,
+    range: UsageRange,
+) -> Result<Vec<UsageRow>, AppError> {
+    let until = OffsetDateTime::now_utc();
+    let since = until - range.duration();
+    let filter = UsageFilter::new(since, until).user_email(email);
+    Ok(get_usage_rows(&state.db_pool, &filter).await?)
+}
+
+pub fn summary_table(title: &str, summaries: &[UsageSummary]) -> String {
+    let mut rows = String::new();
+    for summary in summaries {
+        rows.push_str(&format!(
+            r#"<tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            html_escape(&summary.group),
+            summary.requests,
+            summary.input_tokens,
+            summary.output_tokens,
+            format_usd(summary.cost_usd_micros)
+        ));
+    }
+
+    format!(
+        r#"
+        <h2>{title}</h2>
+        <table>
+            <thead>
+                <tr>
+                    <th></th>
+                    <th>Requests</th>
+                    <th>Input tokens</th>
+                    <th>Output tokens</th>
+                    <th>Cost</th>
+                </tr>
+            </thead>
+            <tbody>
+                {rows}
+            </tbody>
+        </table>
+        "#
+    )
+}
+
+fn daily_cost_chart(summaries: &[UsageSummary]) -> String {
+    let max_cost_usd_micros = summaries
+        .iter()
+        .map(|s| s.cost_usd_micros)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let mut rows = String::new();
+    for summary in summaries {
+        let width = summary.cost_usd_micros * 100 / max_cost_usd_micros;
+        rows.push_str(&format!(
+            r#"<tr>
+                <td>{}</td>
+                <td><div style="background-color: #4a90d9; height: 12px; width: {}%"></div></td>
+                <td>{}</td>
+            </tr>"#,
+            summary.group,
+            width,
+            format_usd(summary.cost_usd_micros)
+        ));
+    }
+
+    format!(
+        r#"
+        <h2>Daily cost</h2>
+        <table>
+            <tbody>
+                {rows}
+            </tbody>
+        </table>
+        "#
+    )
+}
+
+pub async fn usage_dashboard_get(
+    session: Session,
+    state: State<AppState>,
+    Query(query): Query<UsageDashboardQuery>,
+) -> Result<Response, AppError> {
+    let email = match session.get::<String>("email").await? {
+        Some(email) => email,
+        None => return Ok(Redirect::to("/login").into_response()),
+    };
+
+    let rows = get_user_usage_rows(&state, &email, query.range).await?;
+    let totals = summarize_rows(&rows, GroupBy::User);
+    let total = totals.first();
+
+    let range_links = USAGE_RANGES
+        .iter()
+        .map(|range| {
+            if *range == query.range {
+                format!("<strong>{}</strong>", range.as_str())
+            } else {
+                format!(r#"<a href="/usage?range={0}">{0}</a>"#, range.as_str())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            {}
+        </head>
+        <body>
+            <div>
+                <h1>Usage</h1>
+                <p>{range_links}</p>
+                <table>
+                    <tr>
+                        <th>Requests</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Input tokens</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Cache read / write tokens</th>
+                        <td>{} / {}</td>
+                    </tr>
+                    <tr>
+                        <th>Output tokens</th>
+                        <td>{}</td>
+                    </tr>
+                    <tr>
+                        <th>Cost</th>
+                        <td>{}</td>
+                    </tr>
+                </table>
+                {}
+                {}
+                {}
+                <p><a href="/usage.csv?range={}">Download CSV</a></p>
+                {}
+            </div>
+        </body>
+        </html>
+        "#,
+        common_styles(),
+        total.map_or(0, |t| t.requests),
+        total.map_or(0, |t| t.input_tokens),
+        total.map_or(0, |t| t.cache_read_input_tokens),
+        total.map_or(0, |t| t.cache_write_input_tokens),
+        total.map_or(0, |t| t.output_tokens),
+        format_usd(total.map_or(0, |t| t.cost_usd_micros)),
+        daily_cost_chart(&summarize_rows(&rows, GroupBy::Day)),
+        summary_table("By model", &summarize_rows(&rows, GroupBy::Model)),
+        summary_table("By API key", &summarize_rows(&rows, GroupBy::ApiKey)),
+        query.range.as_str(),
+        nav_menu()
+    );
+
+    Ok(Html(html).into_response())
+}
+
+pub async fn usage_dashboard_csv_get(
+    session: Session,
+    state: State<AppState>,
+    Query(query): Query<UsageDashboardQuery>,
+) -> Result<Response, AppError> {
+    let email = match session.get::<String>("email").await? {
+        Some(email) => email,
+        None => return Ok(Redirect::to("/login").into_response()),
+    };
+
+    let rows = get_user_usage_rows(&state, &email, query.range).await?;
+
+    let mut csv = USAGE_CSV_HEADER.to_string();
+    for row in &rows {
+        csv.push_str(&row.to_csv_line());
+    }
+
+    Ok((
+        [
+            (CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"usage-{}.csv\"",
+                    query.range.as_str()
+                ),
+            ),
+        ],
+        csv,
+    )
+        .into_response())
+}
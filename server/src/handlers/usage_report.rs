@@ -0,0 +1,106 @@
+use apikeys::{get_api_key, get_user_email_by_api_key};
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use myerrors::AppError;
+use myhandlers::AppState;
+use serde::{Deserialize, Serialize};
+use service_accounts::Scope;
+use time::{Duration, OffsetDateTime};
+use tracing::error;
+use usage::{GroupBy, UsageFilter, UsageSummary, get_usage_summary, usd_micros_to_usd};
+
+use utoipa::ToSchema;
+
+use crate::validation::check_api_key_scope;
+
+#[derive(Deserialize)]
+pub struct UsageReportQuery {
+    #[serde(default)]
+    pub group_by: GroupBy,
+    #[serde(default = "default_days")]
+    pub days: i64,
+}
+
+fn default_days() -> i64 {
+    30
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UsageReportRow {
+    pub group: String,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cache_read_input_tokens: i64,
+    pub cache_write_input_tokens: i64,
+    pub cost_usd: f64,
+}
+
+#[derive(Serialize)]
+pub struct UsageReportResponse {
+    pub data: Vec<UsageReportRow>,
+    pub days: i64,
+    pub group_by: GroupBy,
+    pub total_cost_usd: f64,
+}
+
+impl From<UsageSummary> for UsageReportRow {
+    fn from(summary: UsageSummary) -> Self {
+        Self {
+            group: summary.group,
+            requests: summary.requests,
+            input_tokens: summary.input_tokens,
+            output_tokens: summary.output_tokens,
+            cache_read_input_tokens: summary.cache_read_input_tokens,
+            cache_write_input_tokens: summary.cache_write_input_tokens,
+            cost_usd: usd_micros_to_usd(summary.cost_usd_micros),
+        }
+    }
+}
+
+/// GET /api/v1/usage
+///
+/// Returns the calling API key owner's usage and cost over the last `days`
+/// days, grouped by `group_by` (`day`, `model`, `api_key`, `user` or
+/// `cost_center`).
+pub async fn usage_report(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<UsageReportQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let api_key = get_api_key(&headers)
+        .await
+        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "Invalid or missing API key"))?;
+
+    let email = get_user_email_by_api_key(&state.db_pool, &api_key)
+        .await?
+        .ok_or_else(|| {
+            error!("API key validation failed: Invalid API key");
+            AppError::new(StatusCode::UNAUTHORIZED, "Invalid or missing API key")
+        })?;
+
+    check_api_key_scope(&state.db_pool, &api_key, Scope::Usage).await?;
+
+    let days = query.days.clamp(1, 366);
+    let until = OffsetDateTime::now_utc();
+    let since = until - Duration::days(days);
+
+    let filter = UsageFilter::new(since, until).user_email(&email);
+    let summaries = get_usage_summary(&state.db_pool, &filter, query.group_by).await?;
+
+    let total_cost_usd_micros = summaries.iter().map(|s| s.cost_usd_micros).sum();
+
+    Ok((
+        StatusCode::OK,
+        Json(UsageReportResponse {
+            data: summaries.into_iter().map(UsageReportRow::from).collect(),
+            days,
+            group_by: query.group_by,
+            total_cost_usd: usd_micros_to_usd(total_cost_usd_micros),
+        }),
+    ))
+}
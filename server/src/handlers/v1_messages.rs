@@ -1,53 +1,60 @@
 use anthropic_request::V1MessagesRequest;
-use apikeys::get_api_key;
 use axum::{
-    Json,
+    Extension, Json,
+    body::{Body, Bytes},
     extract::State,
     http::{HeaderMap, StatusCode},
-    response::sse::Sse,
+    response::{IntoResponse, Response, sse::Sse},
 };
+use captures::{CaptureRecorder, NewCapture};
 use chat::provider::{BedrockV1MessagesProvider, V1MessagesProvider};
 use common::filter_anthropic_beta;
-use futures::Stream;
-use inference_profiles::create_inference_profile;
-use myerrors::AppError;
+use futures::StreamExt;
+use myerrors::{AppError, bedrock_exception_type};
 use myhandlers::{AppState, get_bedrock_model_id};
-use tracing::{debug, error, info};
+use mymetrics::RequestMetrics;
+use service_accounts::Scope;
+use tracing::{debug, error};
 
 use crate::{
-    handlers::usage_callback::create_usage_callback,
-    validation::check_api_key_exists_and_model_exists_and_get_inference_profile_arn,
+    access_log::AccessLog,
+    auth::authenticate,
+    handlers::{
+        metrics::{bedrock_stream_span, metered_stream},
+        usage_callback::{UsageContext, create_usage_callback},
+    },
+    validation::{
+        check_budgets, check_caller_and_model, check_scope, get_or_create_inference_profile_arn,
+    },
 };
 
 pub async fn v1_messages(
     State(state): State<AppState>,
+    Extension(request_metrics): Extension<RequestMetrics>,
+    Extension(access_log): Extension<AccessLog>,
     headers: HeaderMap,
-    Json(mut payload): Json<V1MessagesRequest>,
-) -> Result<
-    (
-        StatusCode,
-        Sse<impl Stream<Item = Result<axum::response::sse::Event, anyhow::Error>>>,
-    ),
-    AppError,
-> {
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let Json(mut payload) = Json::<V1MessagesRequest>::from_bytes(&body)
+        .map_err(|rejection| AppError::new(rejection.status(), rejection.body_text()))?;
+
     debug!("Received v1/messages request for model: {}", payload.model);
 
-    let api_key = get_api_key(&headers)
-        .await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "Invalid or missing API key"))?;
+    let caller = authenticate(&state, &headers, &access_log).await?;
 
     let response_model_id = payload.model.clone();
     payload.model = get_bedrock_model_id(&state.anthropic_to_bedrock, &payload.model);
 
-    let (api_key_exists, model_exists, inference_profile_arn) =
-        check_api_key_exists_and_model_exists_and_get_inference_profile_arn(
-            &state.db_pool,
-            &api_key,
-            &payload.model,
-        )
-        .await?;
+    let api_key_and_model = check_caller_and_model(&state, &caller, &payload.model).await?;
 
-    if !api_key_exists {
+    if let Some(user_id) = api_key_and_model.user_id {
+        request_metrics.set_user(&user_id.to_string());
+    }
+    if let Some(user_email) = &api_key_and_model.user_email {
+        access_log.set_user(user_email);
+    }
+
+    if !api_key_and_model.api_key_exists {
         error!("API key validation failed: Invalid API key");
         return Err(AppError::new(
             StatusCode::UNAUTHORIZED,
@@ -55,13 +62,20 @@ pub async fn v1_messages(
         ));
     }
 
-    if !model_exists {
+    if !api_key_and_model.model_exists {
         error!("Model name validation failed: Invalid model name");
         return Err(AppError::from(anyhow::anyhow!(
             "Invalid or missing model name"
         )));
     }
 
+    check_scope(&api_key_and_model, Scope::Messages)?;
+    check_budgets(&state.db_pool, &state.validation_cache, &api_key_and_model).await?;
+
+    let metrics_model_name = payload.model.to_lowercase();
+    request_metrics.set_model(&metrics_model_name);
+    access_log.set_model(&metrics_model_name);
+
     if payload.stream == Some(false) {
         error!("Streaming is required but was disabled by client (stream: false)");
         return Err(AppError::from(anyhow::anyhow!(
@@ -69,26 +83,31 @@ pub async fn v1_messages(
         )));
     }
 
-    let model_name = if let Some(inference_profile_arn) = inference_profile_arn {
-        inference_profile_arn
-    } else {
-        create_inference_profile(
-            &state.db_pool,
-            &api_key,
-            &payload.model,
-            &state.aws_region,
-            &state.aws_account_id,
-            &state.inference_profile_prefixes,
-        )
-        .await
-        .unwrap_or(payload.model.to_lowercase())
-    };
+    let model_name =
+        get_or_create_inference_profile_arn(&state, &caller, &api_key_and_model, &payload.model)
+            .await?;
+
+    let stream_span = bedrock_stream_span(&metrics_model_name);
 
-    let usage_callback = create_usage_callback(&model_name);
+    let usage_callback = create_usage_callback(
+        state.db_pool.clone(),
+        state.metrics.clone(),
+        stream_span.clone(),
+        access_log.clone(),
+        UsageContext {
+            api_key_id: api_key_and_model.api_key_id,
+            model_id: api_key_and_model.model_id,
+            model_name: payload.model.to_lowercase(),
+            team_id: api_key_and_model.team_id,
+            user_id: api_key_and_model.user_id,
+        },
+    );
 
     let anthropic_beta = filter_anthropic_beta(&headers, &state.anthropic_beta_whitelist);
-    info!("anthropic_beta: {:?}", anthropic_beta);
+    debug!("anthropic_beta: {:?}", anthropic_beta);
+    access_log.set_anthropic_beta(&anthropic_beta);
 
+    access_log.set_upstream_model(&model_name);
     payload.model = model_name;
 
     let stream = BedrockV1MessagesProvider::new(state.bedrockruntime_client.clone())
@@ -98,7 +117,53 @@ pub async fn v1_messages(
             anthropic_beta,
             usage_callback,
         )
-        .await?;
+        .await
+        .inspect_err(|e| {
+            if let Some(exception) = bedrock_exception_type(e) {
+                state
+                    .metrics
+                    .record_bedrock_error(&metrics_model_name, &exception);
+            }
+        })?;
+
+    let stream = metered_stream(
+        stream,
+        state
+            .metrics
+            .stream_guard(&metrics_model_name, request_metrics.started()),
+        stream_span,
+        access_log,
+    );
+
+    let response = (StatusCode::OK, Sse::new(stream)).into_response();
+
+    match api_key_and_model.user_id {
+        Some(user_id) if api_key_and_model.capture_enabled => Ok(capture_response(
+            response,
+            CaptureRecorder::new(
+                state.capture_sink.clone(),
+                NewCapture {
+                    api_key_id: api_key_and_model.api_key_id,
+                    model_name: metrics_model_name,
+                    request_body: String::from_utf8_lossy(&body).into_owned(),
+                    response_text: String::new(),
+                    stop_reason: None,
+                    user_id,
+                },
+            ),
+        )),
+        _ => Ok(response),
+    }
+}
 
-    Ok((StatusCode::OK, Sse::new(stream)))
+/// Tees the response body into `recorder`, which writes the capture once the
+/// body is dropped.
+fn capture_response(response: Response, recorder: CaptureRecorder) -> Response {
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().inspect(move |chunk| {
+        if let Ok(bytes) = chunk {
+            recorder.push(bytes);
+        }
+    });
+    Response::from_parts(parts, Body::from_stream(body))
 }
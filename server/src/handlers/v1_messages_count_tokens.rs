@@ -1,21 +1,28 @@
 use anthropic_request::V1MessagesCountTokensRequest;
 use anthropic_response::V1MessagesCountTokensResponse;
-use apikeys::get_api_key;
 use axum::{
-    Json,
+    Extension, Json,
     extract::State,
     http::{HeaderMap, StatusCode},
     response::IntoResponse,
 };
 use chat::provider::{BedrockV1MessagesProvider, V1MessagesProvider};
-use myerrors::AppError;
+use myerrors::{AppError, bedrock_exception_type};
 use myhandlers::{AppState, get_bedrock_model_id};
+use mymetrics::RequestMetrics;
+use service_accounts::Scope;
 use tracing::{error, info};
 
-use crate::validation::check_api_key_exists_and_model_exists;
+use crate::{
+    access_log::AccessLog,
+    auth::authenticate,
+    validation::{check_caller_exists_and_model_exists, check_caller_scope},
+};
 
 pub async fn v1_messages_count_tokens(
     State(state): State<AppState>,
+    Extension(request_metrics): Extension<RequestMetrics>,
+    Extension(access_log): Extension<AccessLog>,
     headers: HeaderMap,
     Json(mut payload): Json<V1MessagesCountTokensRequest>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -24,14 +31,12 @@ pub async fn v1_messages_count_tokens(
         payload.model
     );
 
-    let api_key = get_api_key(&headers)
-        .await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "Invalid or missing API key"))?;
+    let caller = authenticate(&state, &headers, &access_log).await?;
 
     payload.model = get_bedrock_model_id(&state.anthropic_to_bedrock, &payload.model);
 
     let (api_key_exists, model_exists) =
-        check_api_key_exists_and_model_exists(&state.db_pool, &api_key, &payload.model).await?;
+        check_caller_exists_and_model_exists(&state, &caller, &payload.model).await?;
 
     if !api_key_exists {
         error!("API key validation failed: Invalid API key");
@@ -48,12 +53,23 @@ pub async fn v1_messages_count_tokens(
         )));
     }
 
+    check_caller_scope(&state.db_pool, &caller, Scope::CountTokens).await?;
+
     payload.model = payload.model.to_lowercase();
+    request_metrics.set_model(&payload.model);
+    access_log.set_model(&payload.model);
 
     let provider = BedrockV1MessagesProvider::new(state.bedrockruntime_client.clone());
     let count = provider
         .v1_messages_count_tokens(&payload, &state.inference_profile_prefixes)
-        .await?;
+        .await
+        .inspect_err(|e| {
+            if let Some(exception) = bedrock_exception_type(e) {
+                state
+                    .metrics
+                    .record_bedrock_error(&payload.model, &exception);
+            }
+        })?;
 
     Ok((
         StatusCode::OK,
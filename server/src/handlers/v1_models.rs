@@ -1,6 +1,5 @@
-use apikeys::get_api_key;
 use axum::{
-    Json,
+    Extension, Json,
     extract::State,
     http::{HeaderMap, StatusCode},
     response::IntoResponse,
@@ -8,21 +7,25 @@ use axum::{
 use chrono::DateTime;
 use myerrors::AppError;
 use myhandlers::{AppState, ModelInfo, ModelsResponse};
+use service_accounts::Scope;
 use tracing::error;
 
-use crate::validation::check_api_key_exists;
+use crate::{
+    access_log::AccessLog,
+    auth::authenticate,
+    validation::{check_caller_exists, check_caller_scope},
+};
 
 pub async fn v1_models(
     State(state): State<AppState>,
+    Extension(access_log): Extension<AccessLog>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
-    let api_key = get_api_key(&headers)
-        .await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "Invalid or missing API key"))?;
+    let caller = authenticate(&state, &headers, &access_log).await?;
 
-    let api_key_exists = check_api_key_exists(&state.db_pool, &api_key).await?;
+    let caller_exists = check_caller_exists(&state, &caller).await?;
 
-    if !api_key_exists {
+    if !caller_exists {
         error!("API key validation failed: Invalid API key");
         return Err(AppError::new(
             StatusCode::UNAUTHORIZED,
@@ -30,6 +33,8 @@ pub async fn v1_models(
         ));
     }
 
+    check_caller_scope(&state.db_pool, &caller, Scope::Models).await?;
+
     let model_infos: Vec<ModelInfo> = state
         .model_configs
         .iter()
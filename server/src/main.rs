@@ -1,7 +1,11 @@
+mod access_log;
+mod admin;
+mod auth;
 mod config;
 mod csrf;
 mod database;
 mod handlers;
+mod telemetry;
 mod templates;
 mod validation;
 
@@ -9,65 +13,121 @@ use aws_sdk_bedrockruntime::Client;
 use axum::{
     Router,
     extract::DefaultBodyLimit,
-    routing::{get, post},
+    middleware,
+    routing::{delete, get, patch, post, put},
 };
 use axum_csrf::{CsrfConfig, CsrfLayer, Key};
+use captures::{CaptureSink, CaptureSinkKind, JsonlSink};
+use device_auth::{DeviceCodeLimiter, continuously_purge_expired_device_authorizations};
 use dotenv::dotenv;
 use http::header::{AUTHORIZATION, CONTENT_TYPE, HeaderName};
+use inference_profiles::{
+    BedrockControlPlane, InferenceProfileProvisioner, ReconcileOptions,
+    continuously_reconcile_inference_profiles,
+};
 use myhandlers::{AppState, callback, login, logout};
+use mymetrics::{Metrics, track_metrics};
+use oidc::OidcClient;
+use session_tokens::SessionTokenIssuer;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::signal;
 use tokio::task::AbortHandle;
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::{
+    cors::{Any, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 use tower_sessions::{ExpiredDeletion, Expiry, SessionManagerLayer};
 use tower_sessions_sqlx_store::PostgresStore;
 use tracing::{error, info};
+use validation_cache::ValidationCache;
 
+use crate::access_log::{RedactionPolicy, log_access};
 use crate::config::load_config;
 use crate::database::setup_database;
 #[allow(unused_imports)]
 use crate::handlers::{
     add_model::{add_model_get, add_model_post},
+    admin_api::{
+        admin_api_api_key_delete, admin_api_inference_profiles_get, admin_api_model_delete,
+        admin_api_model_patch, admin_api_models_get, admin_api_models_post, admin_api_openapi_get,
+        admin_api_service_account_budget_put, admin_api_team_budget_put,
+        admin_api_user_api_keys_get, admin_api_user_api_keys_post, admin_api_user_get,
+        admin_api_user_inference_profiles_delete, admin_api_user_patch, admin_api_users_get,
+        admin_api_users_post,
+    },
+    admin_api_tokens::{admin_api_token_revoke_post, admin_api_tokens_get, admin_api_tokens_post},
+    admin_audit::{admin_audit_get, admin_audit_json_get},
+    admin_captures::{admin_capture_get, admin_capture_policy_post, admin_captures_get},
+    admin_inference_profiles::admin_inference_profiles_get,
+    admin_service_accounts::{
+        admin_service_account_keys_api_post, admin_service_account_keys_post,
+        admin_service_accounts_api_get, admin_service_accounts_api_post,
+        admin_service_accounts_get, admin_service_accounts_post,
+    },
+    admin_teams::{
+        admin_team_budget_post, admin_team_get, admin_team_member_remove_post,
+        admin_team_members_post, admin_teams_get, admin_teams_post,
+    },
+    admin_usage_dashboard::{admin_usage_csv_get, admin_usage_get},
+    admin_usage_report::admin_usage_report,
+    admin_users::{
+        admin_user_deactivate_post, admin_user_get, admin_user_reactivate_post, admin_users_get,
+    },
     browse_models::browse_models_get,
     chat_completions::chat_completions,
+    device_authorization::{device_code_post, device_get, device_post, device_token_post},
     disable_api_keys::{disable_api_keys_get, disable_api_keys_post},
     generate_api_key::{generate_api_key_get, generate_api_key_post},
     health::health,
     index::index,
+    metrics::metrics_get,
     provision_api_key::provision_api_key,
+    scim_users::{
+        require_scim_token, scim_user_delete, scim_user_get, scim_user_patch, scim_user_put,
+        scim_users_get, scim_users_post,
+    },
+    session_tokens::{
+        admin_session_token_revoke_post, session_token_post, session_token_revoke_post,
+    },
+    usage_dashboard::{usage_dashboard_csv_get, usage_dashboard_get},
+    usage_report::usage_report,
     v1_messages::v1_messages,
     v1_messages_count_tokens::v1_messages_count_tokens,
     v1_models::v1_models,
 };
+use crate::telemetry::{REQUEST_ID, init_tracing, make_request_span, record_response};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
-    tracing_subscriber::fmt::init();
-    info!("Initializing LLM proxy server");
 
     let app_config = load_config().await?;
+    let tracer_provider = init_tracing(
+        app_config.log_format,
+        app_config.otlp_endpoint.as_deref(),
+        &app_config.otel_service_name,
+    )?;
+    info!("Initializing LLM proxy server");
     info!("Starting server on {}:{}", app_config.host, app_config.port);
 
     let db_pool = setup_database(&app_config.database_url).await?;
     info!("Database connection pool established");
 
-    if app_config.cognito_client_id.is_empty()
-        || app_config.cognito_client_secret.is_empty()
-        || app_config.cognito_domain.is_empty()
-    {
-        error!(
-            "Missing required Cognito configuration. Please check your config file or environment variables."
-        );
-    } else {
-        info!("Cognito configuration loaded successfully");
-    }
+    let oidc_config = app_config.oidc_config()?;
+    info!(issuer = %oidc_config.issuer_url, "Identity provider configuration loaded");
+    let oidc = Arc::new(OidcClient::new(oidc_config));
 
     let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
     let bedrockruntime_client = Client::new(&aws_config);
+    let bedrock_control_plane =
+        BedrockControlPlane::from_config(&aws_config, &app_config.aws_region);
     info!("AWS Bedrock Runtime client initialized");
 
+    let inference_profile_settings = Arc::new(app_config.inference_profile_settings()?);
+
     let anthropic_to_bedrock: HashMap<String, String> = app_config
         .models
         .iter()
@@ -75,20 +135,108 @@ async fn main() -> anyhow::Result<()> {
         .collect();
     info!("Loaded {} model mappings", anthropic_to_bedrock.len());
 
+    let metrics = Arc::new(Metrics::new(app_config.metrics_user_labels)?);
+
+    let shared_db_pool = Arc::new(db_pool.clone());
+
+    let capture_sink = Arc::new(match app_config.capture_sink {
+        CaptureSinkKind::Jsonl => CaptureSink::Jsonl(JsonlSink::new(
+            &app_config.capture_dir,
+            app_config.capture_max_file_bytes,
+        )),
+        CaptureSinkKind::Postgres => CaptureSink::Postgres(shared_db_pool.clone()),
+    });
+    tokio::spawn(capture_sink.clone().continuously_purge_expired(
+        time::Duration::days(app_config.capture_retention_days),
+        std::time::Duration::from_secs(3600),
+    ));
+
+    tokio::spawn(continuously_purge_expired_device_authorizations(
+        shared_db_pool.clone(),
+        std::time::Duration::from_secs(3600),
+    ));
+
+    let session_tokens = match &app_config.session_token_secret {
+        Some(secret) => {
+            let issuer = Arc::new(SessionTokenIssuer::new(
+                secret.as_bytes(),
+                time::Duration::seconds(app_config.session_token_max_ttl_secs),
+            )?);
+            tokio::spawn(issuer.clone().continuously_refresh_denylist(
+                shared_db_pool.clone(),
+                std::time::Duration::from_secs(30),
+            ));
+            info!("Session tokens enabled");
+            Some(issuer)
+        }
+        None => None,
+    };
+
+    let validation_cache = Arc::new(ValidationCache::new(
+        app_config.validation_cache_capacity,
+        std::time::Duration::from_secs(app_config.validation_cache_ttl_secs),
+        std::time::Duration::from_secs(app_config.validation_cache_stale_secs),
+    ));
+    tokio::spawn(
+        validation_cache
+            .clone()
+            .continuously_listen(shared_db_pool.clone()),
+    );
+
+    if app_config.inference_profile_reconcile_interval_secs > 0 {
+        tokio::spawn(continuously_reconcile_inference_profiles(
+            shared_db_pool.clone(),
+            bedrock_control_plane.clone(),
+            ReconcileOptions {
+                delete_orphans: app_config.inference_profile_reconcile_delete_orphans,
+                dry_run: app_config.inference_profile_reconcile_dry_run,
+                max_removal_fraction: app_config.inference_profile_reconcile_max_removal_fraction,
+                orphan_grace_period: time::Duration::seconds(
+                    app_config.inference_profile_orphan_grace_period_secs as i64,
+                ),
+                settings: (*inference_profile_settings).clone(),
+            },
+            std::time::Duration::from_secs(app_config.inference_profile_reconcile_interval_secs),
+        ));
+    }
+
+    let inference_profile_provisioner = if app_config.inference_profile_preprovision {
+        let (provisioner, worker) = InferenceProfileProvisioner::new(
+            shared_db_pool.clone(),
+            bedrock_control_plane.clone(),
+            (*inference_profile_settings).clone(),
+        );
+        tokio::spawn(worker);
+        info!("Inference profile pre-provisioning enabled");
+        Some(provisioner)
+    } else {
+        None
+    };
+
     let app_state = AppState {
+        admin_emails: app_config.admin_emails,
+        admin_groups: app_config.admin_groups,
         anthropic_beta_whitelist: app_config.anthropic_beta_whitelist,
         anthropic_to_bedrock,
-        aws_account_id: app_config.aws_account_id,
-        aws_region: app_config.aws_region,
+        bedrock_control_plane,
         bedrockruntime_client,
-        cognito_client_id: app_config.cognito_client_id,
-        cognito_client_secret: app_config.cognito_client_secret,
-        cognito_domain: app_config.cognito_domain,
-        cognito_redirect_uri: app_config.cognito_redirect_uri,
-        cognito_region: app_config.cognito_region,
-        cognito_user_pool_id: app_config.cognito_user_pool_id,
-        db_pool: Arc::new(db_pool.clone()),
+        capture_sink,
+        db_pool: shared_db_pool,
+        device_code_limiter: Arc::new(DeviceCodeLimiter::new(
+            app_config.device_code_requests_per_minute,
+            app_config.device_code_max_pending,
+        )),
+        inference_profile_cleanup_dry_run: app_config.inference_profile_cleanup_dry_run,
+        inference_profile_failure_policy: app_config.inference_profile_failure_policy,
         inference_profile_prefixes: app_config.inference_profile_prefixes,
+        inference_profile_provisioner,
+        inference_profile_settings,
+        metrics: metrics.clone(),
+        metrics_bearer_token: app_config.metrics_bearer_token,
+        oidc,
+        scim_bearer_token: app_config.scim_bearer_token,
+        session_tokens,
+        validation_cache,
         model_configs: app_config.models,
     };
 
@@ -115,7 +263,66 @@ async fn main() -> anyhow::Result<()> {
 
     let api = Router::new()
         //.route("/chat/completions", post(chat_completions))
+        .route(
+            "/api/v1/admin/api-keys/{api_key_id}",
+            delete(admin_api_api_key_delete),
+        )
+        .route(
+            "/api/v1/admin/inference-profiles",
+            get(admin_api_inference_profiles_get),
+        )
+        .route(
+            "/api/v1/admin/models",
+            get(admin_api_models_get).post(admin_api_models_post),
+        )
+        .route(
+            "/api/v1/admin/models/{model_name}",
+            patch(admin_api_model_patch).delete(admin_api_model_delete),
+        )
+        .route("/api/v1/admin/openapi.json", get(admin_api_openapi_get))
+        .route(
+            "/api/v1/admin/service-accounts",
+            get(admin_service_accounts_api_get).post(admin_service_accounts_api_post),
+        )
+        .route(
+            "/api/v1/admin/service-accounts/{user_id}/keys",
+            post(admin_service_account_keys_api_post),
+        )
+        .route(
+            "/api/v1/admin/service-accounts/{user_id}/budget",
+            put(admin_api_service_account_budget_put),
+        )
+        .route(
+            "/api/v1/admin/session-tokens/{jti}/revoke",
+            post(admin_session_token_revoke_post),
+        )
+        .route(
+            "/api/v1/admin/teams/{team_id}/budget",
+            put(admin_api_team_budget_put),
+        )
+        .route("/api/v1/admin/usage", get(admin_usage_report))
+        .route(
+            "/api/v1/admin/users",
+            get(admin_api_users_get).post(admin_api_users_post),
+        )
+        .route(
+            "/api/v1/admin/users/{user_id}",
+            get(admin_api_user_get).patch(admin_api_user_patch),
+        )
+        .route(
+            "/api/v1/admin/users/{user_id}/api-keys",
+            get(admin_api_user_api_keys_get).post(admin_api_user_api_keys_post),
+        )
+        .route(
+            "/api/v1/admin/users/{user_id}/inference-profiles",
+            delete(admin_api_user_inference_profiles_delete),
+        )
         .route("/api/v1/api-key", post(provision_api_key))
+        .route("/api/v1/device/code", post(device_code_post))
+        .route("/api/v1/device/token", post(device_token_post))
+        .route("/api/v1/token", post(session_token_post))
+        .route("/api/v1/token/revoke", post(session_token_revoke_post))
+        .route("/api/v1/usage", get(usage_report))
         .route("/v1/messages", post(v1_messages))
         .route("/v1/messages/count_tokens", post(v1_messages_count_tokens))
         .route("/v1/models", get(v1_models))
@@ -123,6 +330,20 @@ async fn main() -> anyhow::Result<()> {
         .layer(cors_layer)
         .layer(DefaultBodyLimit::max(20 * 1024 * 1024)); // 20 MB
 
+    let scim = Router::new()
+        .route("/scim/v2/Users", get(scim_users_get).post(scim_users_post))
+        .route(
+            "/scim/v2/Users/{id}",
+            get(scim_user_get)
+                .put(scim_user_put)
+                .patch(scim_user_patch)
+                .delete(scim_user_delete),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            require_scim_token,
+        ));
+
     let mut csrf_config = CsrfConfig::default().with_salt(app_config.csrf_salt);
 
     let key_bytes: Result<Vec<u8>, _> = app_config
@@ -147,8 +368,60 @@ async fn main() -> anyhow::Result<()> {
     let app = Router::new()
         .route("/", get(index))
         //.route("/add-model", get(add_model_get).post(add_model_post))
+        .route(
+            "/admin/api-tokens",
+            get(admin_api_tokens_get).post(admin_api_tokens_post),
+        )
+        .route(
+            "/admin/api-tokens/{admin_api_token_id}/revoke",
+            post(admin_api_token_revoke_post),
+        )
+        .route("/admin/audit", get(admin_audit_get))
+        .route("/admin/audit.json", get(admin_audit_json_get))
+        .route("/admin/captures", get(admin_captures_get))
+        .route("/admin/captures/policy", post(admin_capture_policy_post))
+        .route("/admin/captures/{capture_id}", get(admin_capture_get))
+        .route(
+            "/admin/inference-profiles",
+            get(admin_inference_profiles_get),
+        )
+        .route(
+            "/admin/service-accounts",
+            get(admin_service_accounts_get).post(admin_service_accounts_post),
+        )
+        .route(
+            "/admin/service-accounts/{user_id}/keys",
+            post(admin_service_account_keys_post),
+        )
+        .route("/admin/teams", get(admin_teams_get).post(admin_teams_post))
+        .route("/admin/teams/{team_id}", get(admin_team_get))
+        .route(
+            "/admin/teams/{team_id}/budget",
+            post(admin_team_budget_post),
+        )
+        .route(
+            "/admin/teams/{team_id}/members",
+            post(admin_team_members_post),
+        )
+        .route(
+            "/admin/teams/{team_id}/members/{user_id}/remove",
+            post(admin_team_member_remove_post),
+        )
+        .route("/admin/usage", get(admin_usage_get))
+        .route("/admin/usage.csv", get(admin_usage_csv_get))
+        .route("/admin/users", get(admin_users_get))
+        .route("/admin/users/{user_id}", get(admin_user_get))
+        .route(
+            "/admin/users/{user_id}/deactivate",
+            post(admin_user_deactivate_post),
+        )
+        .route(
+            "/admin/users/{user_id}/reactivate",
+            post(admin_user_reactivate_post),
+        )
         .route("/browse-models", get(browse_models_get))
         .route("/callback", get(callback))
+        .route("/device", get(device_get).post(device_post))
         //.route("/delete-model", post(delete_model_post))
         .route(
             "/disable-api-keys",
@@ -163,9 +436,27 @@ async fn main() -> anyhow::Result<()> {
         .route("/health", get(health))
         .route("/login", get(login))
         .route("/logout", get(logout))
+        .route("/metrics", get(metrics_get))
+        .route("/usage", get(usage_dashboard_get))
+        .route("/usage.csv", get(usage_dashboard_csv_get))
         .merge(api)
+        .merge(scim)
+        .route_layer(middleware::from_fn_with_state(metrics, track_metrics))
         .layer(CsrfLayer::new(csrf_config))
         .layer(session_layer)
+        .layer(middleware::from_fn_with_state(
+            RedactionPolicy {
+                redact_emails: app_config.log_redact_emails,
+            },
+            log_access,
+        ))
+        .layer(PropagateRequestIdLayer::new(REQUEST_ID))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(make_request_span)
+                .on_response(record_response),
+        )
+        .layer(SetRequestIdLayer::new(REQUEST_ID, MakeRequestUuid))
         .with_state(app_state);
 
     info!(
@@ -176,9 +467,16 @@ async fn main() -> anyhow::Result<()> {
         tokio::net::TcpListener::bind(format!("{}:{}", app_config.host, app_config.port)).await?;
     info!("Server started successfully, listening for requests");
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal(deletion_task.abort_handle()))
-        .await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal(deletion_task.abort_handle()))
+    .await?;
+
+    if let Some(tracer_provider) = tracer_provider {
+        tracer_provider.shutdown()?;
+    }
 
     deletion_task.await??;
 
@@ -0,0 +1,106 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderName, Request, Response},
+};
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider as _};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use std::time::Duration;
+use tracing::{Span, field::Empty, info_span, warn};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::access_log::LogFormat;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("request-id");
+
+/// Installs the global tracing subscriber, writing logs as text or JSON lines
+/// filtered by `RUST_LOG` (default `info`).
+/// When `otlp_endpoint` is set, spans are also exported over OTLP/gRPC; the
+/// returned provider must be shut down on exit so that buffered spans are
+/// flushed.
+pub fn init_tracing(
+    log_format: LogFormat,
+    otlp_endpoint: Option<&str>,
+    service_name: &str,
+) -> anyhow::Result<Option<SdkTracerProvider>> {
+    let tracer_provider = otlp_endpoint
+        .map(|endpoint| -> anyhow::Result<SdkTracerProvider> {
+            let exporter = SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .build()?;
+
+            Ok(SdkTracerProvider::builder()
+                .with_resource(
+                    Resource::builder()
+                        .with_service_name(service_name.to_string())
+                        .build(),
+                )
+                .with_batch_exporter(exporter)
+                .build())
+        })
+        .transpose()?;
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let otel_layer = tracer_provider.as_ref().map(|tracer_provider| {
+        tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("gateway"))
+    });
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(matches!(log_format, LogFormat::Text).then(tracing_subscriber::fmt::layer))
+        .with(
+            matches!(log_format, LogFormat::Json).then(|| tracing_subscriber::fmt::layer().json()),
+        )
+        .with(otel_layer)
+        .init();
+
+    Ok(tracer_provider)
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Creates the span for an incoming request, continuing the caller's trace
+/// when a W3C `traceparent` header is present.
+pub fn make_request_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let span = info_span!(
+        "request",
+        otel.name = %format!("{} {}", request.method(), request.uri().path()),
+        otel.kind = "server",
+        http.request.method = %request.method(),
+        url.path = %request.uri().path(),
+        request_id,
+        http.response.status_code = Empty,
+    );
+
+    let parent_context = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    if let Err(e) = span.set_parent(parent_context) {
+        warn!("Failed to set parent trace context: {:?}", e);
+    }
+
+    span
+}
+
+pub fn record_response<B>(response: &Response<B>, _latency: Duration, span: &Span) {
+    span.record("http.response.status_code", response.status().as_u16());
+}
@@ -41,6 +41,52 @@ pub fn nav_menu() -> &'static str {
         <a href="/generate-api-key">Generate API Key</a>
         <a href="/disable-api-keys">Disable API Keys</a>
         <a href="/browse-models">Browse Models</a>
+        <a href="/usage">Usage</a>
         <a href="/logout">Logout</a>
     "#
 }
+
+pub fn admin_nav_menu() -> &'static str {
+    r#"<br>
+        <a href="/">Home</a>
+        <a href="/admin/usage">Organization Usage</a>
+        <a href="/admin/users">Users</a>
+        <a href="/admin/teams">Teams</a>
+        <a href="/admin/service-accounts">Service Accounts</a>
+        <a href="/admin/inference-profiles">Inference Profiles</a>
+        <a href="/admin/captures">Captures</a>
+        <a href="/admin/audit">Audit Log</a>
+        <a href="/admin/api-tokens">API Tokens</a>
+        <a href="/logout">Logout</a>
+    "#
+}
+
+/// Escapes user-supplied text for interpolation into HTML.
+pub fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_escape_escapes_markup() {
+        assert_eq!(
+            html_escape(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+        assert_eq!(html_escape("alice@example.com"), "alice@example.com");
+    }
+}
@@ -1,5 +1,22 @@
+use std::borrow::Cow;
+
+use axum::http::StatusCode;
+use inference_profiles::{
+    InferenceProfileFailurePolicy, InferenceProfileGranularity, create_inference_profile,
+};
+use myerrors::AppError;
+use myhandlers::AppState;
+use oidc::Identity;
+use service_accounts::{Scope, get_api_key_scopes, get_service_account_budget_status, has_scope};
+use session_tokens::SessionTokenClaims;
 use sqlx::PgPool;
+use teams::get_team_budget_status;
+use tracing::{error, instrument, warn};
+use validation_cache::{ApiKeyAndModel, BudgetOwner, ValidationCache};
+
+use crate::auth::Caller;
 
+#[instrument(skip_all, fields(model = %model_name))]
 pub async fn check_api_key_exists_and_model_exists(
     pool: &PgPool,
     api_key: &str,
@@ -20,37 +37,532 @@ pub async fn check_api_key_exists_and_model_exists(
     Ok((result.api_key_exists, result.model_exists))
 }
 
+/// The inference profile looked up is chosen as
+/// [`InferenceProfileGranularity::owner`] does.
+#[instrument(skip_all, fields(model = %model_name))]
 pub async fn check_api_key_exists_and_model_exists_and_get_inference_profile_arn(
     pool: &PgPool,
     api_key: &str,
     model_name: &str,
-) -> anyhow::Result<(bool, bool, Option<String>)> {
+    granularity: InferenceProfileGranularity,
+) -> anyhow::Result<ApiKeyAndModel> {
     let result = sqlx::query!(
         r#"
         SELECT
             EXISTS (SELECT 1 FROM api_keys WHERE api_key = $1 AND is_disabled = FALSE) as "api_key_exists!",
             EXISTS (SELECT 1 FROM models WHERE model_name = $2 AND is_disabled = FALSE) as "model_exists!",
             (
-                SELECT inference_profile_arn
-                FROM inference_profiles
-                WHERE user_id = (SELECT user_id FROM api_keys WHERE api_key = $1)
-                  AND model_id = (SELECT model_id FROM models WHERE model_name = $2 AND is_disabled = FALSE)
+                SELECT ip.inference_profile_arn
+                FROM inference_profiles ip
+                JOIN api_keys ak ON ak.api_key = $1
+                WHERE ip.model_id = (SELECT model_id FROM models WHERE model_name = $2 AND is_disabled = FALSE)
+                  AND CASE
+                    WHEN $3 = 'key' THEN ip.owner_type = 'key' AND ip.api_key_id = ak.api_key_id
+                    WHEN $3 = 'team' AND ak.team_id IS NOT NULL THEN ip.owner_type = 'team' AND ip.team_id = ak.team_id
+                    ELSE ip.owner_type = 'user' AND ip.user_id = ak.user_id
+                  END
                 LIMIT 1
-            ) as inference_profile_arn
+            ) as inference_profile_arn,
+            (SELECT api_key_id FROM api_keys WHERE api_key = $1) as api_key_id,
+            (SELECT model_id FROM models WHERE model_name = $2 AND is_disabled = FALSE) as model_id,
+            (
+                SELECT COALESCE(ak.team_id, u.owner_team_id)
+                FROM api_keys ak
+                JOIN users u ON u.user_id = ak.user_id
+                WHERE ak.api_key = $1
+            ) as team_id,
+            (
+                SELECT u.is_service_account
+                FROM users u
+                JOIN api_keys ak ON ak.user_id = u.user_id
+                WHERE ak.api_key = $1
+            ) as is_service_account,
+            (SELECT user_id FROM api_keys WHERE api_key = $1) as user_id,
+            (
+                SELECT u.user_email
+                FROM users u
+                JOIN api_keys ak ON ak.user_id = u.user_id
+                WHERE ak.api_key = $1
+            ) as user_email,
+            (
+                SELECT COALESCE(ak.capture_enabled, u.capture_enabled)
+                FROM users u
+                JOIN api_keys ak ON ak.user_id = u.user_id
+                WHERE ak.api_key = $1
+            ) as capture_enabled,
+            (
+                SELECT COALESCE(ak.scopes, u.scopes)
+                FROM users u
+                JOIN api_keys ak ON ak.user_id = u.user_id
+                WHERE ak.api_key = $1
+            ) as scopes
         "#,
         api_key.to_lowercase(),
+        model_name.to_lowercase(),
+        granularity.as_str(),
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(ApiKeyAndModel {
+        api_key_exists: result.api_key_exists,
+        model_exists: result.model_exists,
+        inference_profile_arn: result.inference_profile_arn,
+        api_key_id: result.api_key_id,
+        model_id: result.model_id,
+        team_id: result.team_id,
+        is_service_account: result.is_service_account.unwrap_or(false),
+        user_id: result.user_id,
+        user_email: result.user_email,
+        capture_enabled: result.capture_enabled.unwrap_or(false),
+        scopes: result.scopes,
+    })
+}
+
+/// Like [`check_api_key_exists_and_model_exists_and_get_inference_profile_arn`]
+/// for a user authenticated with an identity provider token. Such requests
+/// have no key, so they bill the user personally and use their personal
+/// inference profile.
+#[instrument(skip_all, fields(model = %model_name))]
+pub async fn check_user_exists_and_model_exists_and_get_inference_profile_arn(
+    pool: &PgPool,
+    email: &str,
+    model_name: &str,
+) -> anyhow::Result<ApiKeyAndModel> {
+    let result = sqlx::query!(
+        r#"
+        SELECT
+            EXISTS (
+                SELECT 1 FROM users
+                WHERE user_email = $1 AND is_disabled = FALSE AND NOT is_service_account
+            ) as "user_exists!",
+            EXISTS (SELECT 1 FROM models WHERE model_name = $2 AND is_disabled = FALSE) as "model_exists!",
+            (
+                SELECT ip.inference_profile_arn
+                FROM inference_profiles ip
+                JOIN users u ON u.user_id = ip.user_id
+                WHERE u.user_email = $1
+                  AND ip.owner_type = 'user'
+                  AND ip.model_id = (SELECT model_id FROM models WHERE model_name = $2 AND is_disabled = FALSE)
+                LIMIT 1
+            ) as inference_profile_arn,
+            (SELECT model_id FROM models WHERE model_name = $2 AND is_disabled = FALSE) as model_id,
+            (SELECT user_id FROM users WHERE user_email = $1) as user_id,
+            (SELECT capture_enabled FROM users WHERE user_email = $1) as capture_enabled
+        "#,
+        email.to_lowercase(),
         model_name.to_lowercase()
     )
     .fetch_one(pool)
     .await?;
 
-    Ok((
-        result.api_key_exists,
-        result.model_exists,
-        result.inference_profile_arn,
-    ))
+    Ok(ApiKeyAndModel {
+        api_key_exists: result.user_exists,
+        model_exists: result.model_exists,
+        inference_profile_arn: result.inference_profile_arn,
+        api_key_id: None,
+        model_id: result.model_id,
+        team_id: None,
+        is_service_account: false,
+        user_id: result.user_id,
+        user_email: Some(email.to_lowercase()),
+        capture_enabled: result.capture_enabled.unwrap_or(false),
+        scopes: None,
+    })
 }
 
+/// Like [`check_api_key_exists_and_model_exists_and_get_inference_profile_arn`]
+/// for a session token. The token's claims stand in for the `api_keys` row.
+/// Disabled keys and users are on the session token denylist, so a verified
+/// token needs no check that they are still active.
+#[instrument(skip_all, fields(model = %model_name))]
+pub async fn check_session_token_and_model_exists_and_get_inference_profile_arn(
+    pool: &PgPool,
+    claims: &SessionTokenClaims,
+    model_name: &str,
+    granularity: InferenceProfileGranularity,
+) -> anyhow::Result<ApiKeyAndModel> {
+    let result = sqlx::query!(
+        r#"
+        SELECT
+            EXISTS (SELECT 1 FROM models WHERE model_name = $3 AND is_disabled = FALSE) as "model_exists!",
+            (
+                SELECT ip.inference_profile_arn
+                FROM inference_profiles ip
+                WHERE ip.model_id = (SELECT model_id FROM models WHERE model_name = $3 AND is_disabled = FALSE)
+                  AND CASE
+                    WHEN $6 = 'key' AND $5::uuid IS NOT NULL THEN ip.owner_type = 'key' AND ip.api_key_id = $5
+                    WHEN $6 IN ('key', 'team') AND $2::uuid IS NOT NULL THEN ip.owner_type = 'team' AND ip.team_id = $2
+                    ELSE ip.owner_type = 'user' AND ip.user_id = $1
+                  END
+                LIMIT 1
+            ) as inference_profile_arn,
+            (SELECT model_id FROM models WHERE model_name = $3 AND is_disabled = FALSE) as model_id,
+            (SELECT COALESCE($2::uuid, owner_team_id) FROM users WHERE user_id = $1) as team_id,
+            (SELECT is_service_account FROM users WHERE user_id = $1) as is_service_account,
+            (SELECT COALESCE($4, capture_enabled) FROM users WHERE user_id = $1) as capture_enabled
+        "#,
+        claims.sub,
+        claims.team_id,
+        model_name.to_lowercase(),
+        claims.capture_enabled,
+        claims.api_key_id,
+        granularity.as_str(),
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(ApiKeyAndModel {
+        api_key_exists: true,
+        model_exists: result.model_exists,
+        inference_profile_arn: result.inference_profile_arn,
+        api_key_id: claims.api_key_id,
+        model_id: result.model_id,
+        team_id: result.team_id,
+        is_service_account: result.is_service_account.unwrap_or(false),
+        user_id: Some(claims.sub),
+        user_email: Some(claims.email.clone()),
+        capture_enabled: result.capture_enabled.unwrap_or(false),
+        scopes: claims.scopes.clone(),
+    })
+}
+
+/// Like [`check_api_key_exists_and_model_exists_and_get_inference_profile_arn`],
+/// answered from `cache` when possible. Only valid keys and models are
+/// cached. If the query fails, a stale entry is served instead so recently
+/// seen keys keep working through a database hiccup.
+pub async fn check_api_key_exists_and_model_exists_cached(
+    pool: &PgPool,
+    cache: &ValidationCache,
+    api_key: &str,
+    model_name: &str,
+    granularity: InferenceProfileGranularity,
+) -> anyhow::Result<ApiKeyAndModel> {
+    validate_cached(
+        cache,
+        api_key,
+        model_name,
+        check_api_key_exists_and_model_exists_and_get_inference_profile_arn(
+            pool,
+            api_key,
+            model_name,
+            granularity,
+        ),
+    )
+    .await
+}
+
+/// Answers a validation of `credential` from `cache` when possible, running
+/// `validate` otherwise. Only valid credentials and models are cached. If
+/// `validate` fails, a stale entry is served instead.
+async fn validate_cached(
+    cache: &ValidationCache,
+    credential: &str,
+    model_name: &str,
+    validate: impl Future<Output = anyhow::Result<ApiKeyAndModel>>,
+) -> anyhow::Result<ApiKeyAndModel> {
+    if let Some(api_key_and_model) = cache.get(credential, model_name) {
+        return Ok(api_key_and_model);
+    }
+    let generation = cache.generation();
+
+    match validate.await {
+        Ok(api_key_and_model) => {
+            if api_key_and_model.api_key_exists && api_key_and_model.model_exists {
+                cache.insert(
+                    credential,
+                    model_name,
+                    api_key_and_model.clone(),
+                    generation,
+                );
+            }
+            Ok(api_key_and_model)
+        }
+        Err(e) => match cache.get_stale(credential, model_name) {
+            Some(api_key_and_model) => {
+                warn!("Caller validation failed, using cached result: {e:#}");
+                Ok(api_key_and_model)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// The credential `caller`'s validations are cached under. Token callers are
+/// prefixed, which no API key is, so they cannot collide with keys.
+fn cached_credential(caller: &Caller) -> Cow<'_, str> {
+    match caller {
+        Caller::ApiKey(api_key) => Cow::Borrowed(api_key),
+        Caller::Oidc(identity) => Cow::Owned(format!("oidc:{}", identity.email)),
+        Caller::SessionToken(claims) => Cow::Owned(format!("session_token:{}", claims.jti)),
+    }
+}
+
+pub async fn check_caller_and_model(
+    state: &AppState,
+    caller: &Caller,
+    model_name: &str,
+) -> anyhow::Result<ApiKeyAndModel> {
+    let granularity = state.inference_profile_settings.granularity;
+    match caller {
+        Caller::ApiKey(api_key) => {
+            check_api_key_exists_and_model_exists_cached(
+                &state.db_pool,
+                &state.validation_cache,
+                api_key,
+                model_name,
+                granularity,
+            )
+            .await
+        }
+        Caller::Oidc(identity) => {
+            validate_cached(
+                &state.validation_cache,
+                &cached_credential(caller),
+                model_name,
+                check_identity_user_and_model(state, identity, model_name),
+            )
+            .await
+        }
+        Caller::SessionToken(claims) => {
+            validate_cached(
+                &state.validation_cache,
+                &cached_credential(caller),
+                model_name,
+                check_session_token_and_model_exists_and_get_inference_profile_arn(
+                    &state.db_pool,
+                    claims,
+                    model_name,
+                    granularity,
+                ),
+            )
+            .await
+        }
+    }
+}
+
+/// Validates an identity provider token user and the model. The user is
+/// created the first time they are not found, so requests from existing
+/// users never write to the database.
+async fn check_identity_user_and_model(
+    state: &AppState,
+    identity: &Identity,
+    model_name: &str,
+) -> anyhow::Result<ApiKeyAndModel> {
+    let api_key_and_model = check_user_exists_and_model_exists_and_get_inference_profile_arn(
+        &state.db_pool,
+        &identity.email,
+        model_name,
+    )
+    .await?;
+    if api_key_and_model.user_id.is_some() {
+        return Ok(api_key_and_model);
+    }
+
+    state.ensure_identity_user(identity).await?;
+    check_user_exists_and_model_exists_and_get_inference_profile_arn(
+        &state.db_pool,
+        &identity.email,
+        model_name,
+    )
+    .await
+}
+
+/// Returns whether the caller may make requests and whether the model exists.
+pub async fn check_caller_exists_and_model_exists(
+    state: &AppState,
+    caller: &Caller,
+    model_name: &str,
+) -> anyhow::Result<(bool, bool)> {
+    match caller {
+        Caller::ApiKey(api_key) => {
+            check_api_key_exists_and_model_exists(&state.db_pool, api_key, model_name).await
+        }
+        Caller::Oidc(_) | Caller::SessionToken(_) => {
+            let model_and_user = check_caller_and_model(state, caller, model_name).await?;
+            Ok((model_and_user.api_key_exists, model_and_user.model_exists))
+        }
+    }
+}
+
+/// Rejects requests once the billed team, or the calling service account,
+/// has spent its monthly budget. Owners without a budget are unlimited.
+/// Checks are cached briefly, and requests are let through if the budget
+/// cannot be checked.
+#[instrument(skip_all)]
+pub async fn check_budgets(
+    pool: &PgPool,
+    cache: &ValidationCache,
+    api_key_and_model: &ApiKeyAndModel,
+) -> Result<(), AppError> {
+    if let Some(team_id) = api_key_and_model.team_id
+        && is_budget_exceeded(pool, cache, BudgetOwner::Team(team_id)).await
+    {
+        warn!(%team_id, "Team budget exceeded");
+        return Err(AppError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Team budget exceeded for this month",
+        ));
+    }
+
+    if api_key_and_model.is_service_account
+        && let Some(user_id) = api_key_and_model.user_id
+        && is_budget_exceeded(pool, cache, BudgetOwner::ServiceAccount(user_id)).await
+    {
+        warn!(%user_id, "Service account budget exceeded");
+        return Err(AppError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Service account budget exceeded for this month",
+        ));
+    }
+
+    Ok(())
+}
+
+async fn is_budget_exceeded(pool: &PgPool, cache: &ValidationCache, owner: BudgetOwner) -> bool {
+    if let Some(exceeded) = cache.get_budget_exceeded(owner) {
+        return exceeded;
+    }
+    let generation = cache.generation();
+
+    let status = match owner {
+        BudgetOwner::ServiceAccount(user_id) => {
+            get_service_account_budget_status(pool, user_id).await
+        }
+        BudgetOwner::Team(team_id) => get_team_budget_status(pool, team_id).await,
+    };
+
+    match status {
+        Ok(status) => {
+            let exceeded = status.is_some_and(|status| status.is_exceeded());
+            cache.insert_budget_exceeded(owner, exceeded, generation);
+            exceeded
+        }
+        Err(e) => {
+            warn!(?owner, "Budget check failed, allowing the request: {e:#}");
+            false
+        }
+    }
+}
+
+/// Rejects API keys whose service account lacks `scope`.
+#[instrument(skip_all, fields(scope = scope.as_str()))]
+pub async fn check_api_key_scope(
+    pool: &PgPool,
+    api_key: &str,
+    scope: Scope,
+) -> Result<(), AppError> {
+    let scopes = get_api_key_scopes(pool, api_key).await?;
+    if !has_scope(scopes.as_deref(), scope) {
+        warn!("API key is missing the required scope");
+        return Err(AppError::new(
+            StatusCode::FORBIDDEN,
+            format!("API key is missing the {} scope", scope.as_str()),
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects requests whose validated key or token lacks `scope`, without a
+/// further query.
+pub fn check_scope(api_key_and_model: &ApiKeyAndModel, scope: Scope) -> Result<(), AppError> {
+    if !has_scope(api_key_and_model.scopes.as_deref(), scope) {
+        warn!("Caller is missing the required scope");
+        return Err(AppError::new(
+            StatusCode::FORBIDDEN,
+            format!("API key is missing the {} scope", scope.as_str()),
+        ));
+    }
+    Ok(())
+}
+
+/// Drops the cached validation of `caller` for `model_name`, e.g. once an
+/// inference profile has been created for it.
+pub fn forget_cached_validation(cache: &ValidationCache, caller: &Caller, model_name: &str) {
+    cache.remove(&cached_credential(caller), model_name);
+}
+
+/// Returns the inference profile to invoke for the caller and model, creating
+/// it on first use. If that fails, the request is rejected or sent to the
+/// model directly, without cost attribution, according to
+/// `inference_profile_failure_policy`. Under the fallback policy, a creation
+/// already in progress for another request is not waited for.
+pub async fn get_or_create_inference_profile_arn(
+    state: &AppState,
+    caller: &Caller,
+    api_key_and_model: &ApiKeyAndModel,
+    model_name: &str,
+) -> Result<String, AppError> {
+    if let Some(inference_profile_arn) = &api_key_and_model.inference_profile_arn {
+        return Ok(inference_profile_arn.clone());
+    }
+
+    let inference_profile_arn = async {
+        let owner = caller.profile_owner(
+            api_key_and_model.user_id,
+            state.inference_profile_settings.granularity,
+        )?;
+        create_inference_profile(
+            &state.bedrock_control_plane,
+            &state.db_pool,
+            owner,
+            model_name,
+            &state.inference_profile_settings,
+            // Falling back beats waiting on another request's creation.
+            state.inference_profile_failure_policy == InferenceProfileFailurePolicy::Fail,
+        )
+        .await
+    }
+    .await;
+    state
+        .metrics
+        .record_inference_profile_creation(inference_profile_arn.is_ok());
+    forget_cached_validation(&state.validation_cache, caller, model_name);
+
+    match inference_profile_arn {
+        Ok(inference_profile_arn) => Ok(inference_profile_arn),
+        Err(e) => {
+            error!("Failed to create inference profile: {:?}", e);
+            match state.inference_profile_failure_policy {
+                InferenceProfileFailurePolicy::Fail => Err(AppError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Failed to create an inference profile for this model",
+                )),
+                InferenceProfileFailurePolicy::Fallback => {
+                    let model_name = model_name.to_lowercase();
+                    state.metrics.record_inference_profile_fallback(&model_name);
+                    Ok(model_name)
+                }
+            }
+        }
+    }
+}
+
+/// Like [`check_api_key_scope`]. Session tokens carry their scopes;
+/// identity provider token users are unrestricted.
+pub async fn check_caller_scope(
+    pool: &PgPool,
+    caller: &Caller,
+    scope: Scope,
+) -> Result<(), AppError> {
+    match caller {
+        Caller::ApiKey(api_key) => check_api_key_scope(pool, api_key, scope).await,
+        Caller::Oidc(_) => Ok(()),
+        Caller::SessionToken(claims) => {
+            if has_scope(claims.scopes.as_deref(), scope) {
+                Ok(())
+            } else {
+                warn!("Session token is missing the required scope");
+                Err(AppError::new(
+                    StatusCode::FORBIDDEN,
+                    format!("Session token is missing the {} scope", scope.as_str()),
+                ))
+            }
+        }
+    }
+}
+
+#[instrument(skip_all)]
 pub async fn check_api_key_exists(pool: &PgPool, api_key: &str) -> anyhow::Result<bool> {
     let result = sqlx::query_scalar!(
         r#"
@@ -63,3 +575,41 @@ pub async fn check_api_key_exists(pool: &PgPool, api_key: &str) -> anyhow::Resul
 
     Ok(result.unwrap_or(false))
 }
+
+/// Returns whether the user may make requests, or `None` if there is no
+/// such user.
+#[instrument(skip_all)]
+pub async fn check_user_is_active(pool: &PgPool, email: &str) -> anyhow::Result<Option<bool>> {
+    let result = sqlx::query_scalar!(
+        r#"
+        SELECT is_disabled = FALSE AND NOT is_service_account AS "is_active!"
+        FROM users
+        WHERE user_email = $1
+        "#,
+        email.to_lowercase()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result)
+}
+
+/// Returns whether the caller may make requests. Identity provider token
+/// users are created the first time they are not found.
+pub async fn check_caller_exists(state: &AppState, caller: &Caller) -> anyhow::Result<bool> {
+    let pool = &state.db_pool;
+    match caller {
+        Caller::ApiKey(api_key) => check_api_key_exists(pool, api_key).await,
+        Caller::Oidc(identity) => {
+            if let Some(is_active) = check_user_is_active(pool, &identity.email).await? {
+                return Ok(is_active);
+            }
+            state.ensure_identity_user(identity).await?;
+            Ok(check_user_is_active(pool, &identity.email)
+                .await?
+                .unwrap_or(false))
+        }
+        // Verified against the denylist when the caller was authenticated.
+        Caller::SessionToken(_) => Ok(true),
+    }
+}
@@ -0,0 +1,322 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use teams::{BudgetStatus, budget_period_start};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Service accounts are stored as `users` rows whose email is the account
+/// name with this prefix, so they stand out wherever users are listed.
+pub const SERVICE_ACCOUNT_EMAIL_PREFIX: &str = "svc:";
+
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    CountTokens,
+    Messages,
+    Models,
+    Usage,
+}
+
+pub const SCOPES: [Scope; 4] = [
+    Scope::Messages,
+    Scope::CountTokens,
+    Scope::Models,
+    Scope::Usage,
+];
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::CountTokens => "count_tokens",
+            Scope::Messages => "messages",
+            Scope::Models => "models",
+            Scope::Usage => "usage",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        SCOPES.into_iter().find(|scope| scope.as_str() == value)
+    }
+}
+
+/// Returns true if a key with `scopes` may use `scope`. Keys without scopes
+/// (all human users) are unrestricted.
+pub fn has_scope(scopes: Option<&[String]>, scope: Scope) -> bool {
+    scopes.is_none_or(|scopes| scopes.iter().any(|s| s == scope.as_str()))
+}
+
+pub fn service_account_email(name: &str) -> String {
+    format!("{SERVICE_ACCOUNT_EMAIL_PREFIX}{name}")
+}
+
+/// Names are lowercase letters, digits and dashes so they are safe to show
+/// and to use in inference profile tags.
+pub fn validate_service_account_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(format!(
+            "Service account name must be 1 to {MAX_NAME_LEN} characters"
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(
+            "Service account name may only contain lowercase letters, digits and dashes"
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ServiceAccount {
+    pub api_keys_count_active: i64,
+    pub budget_usd_micros: Option<i64>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    pub description: Option<String>,
+    pub is_disabled: bool,
+    pub name: String,
+    pub owner_team_id: Option<Uuid>,
+    pub owner_team_name: Option<String>,
+    pub scopes: Vec<String>,
+    pub user_id: Uuid,
+}
+
+pub struct NewServiceAccount<'a> {
+    pub budget_usd_micros: Option<i64>,
+    pub description: Option<&'a str>,
+    pub name: &'a str,
+    pub owner_team_id: Option<Uuid>,
+    pub scopes: &'a [Scope],
+}
+
+/// Creates a service account. Returns `None` if the name is already taken.
+pub async fn create_service_account(
+    pool: &PgPool,
+    account: &NewServiceAccount<'_>,
+) -> Result<Option<Uuid>> {
+    validate_service_account_name(account.name).map_err(anyhow::Error::msg)?;
+
+    let scopes = account
+        .scopes
+        .iter()
+        .map(|scope| scope.as_str().to_string())
+        .collect::<Vec<_>>();
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (user_email, is_service_account, description, owner_team_id, scopes, budget_usd_micros)
+        VALUES ($1, TRUE, $2, $3, $4, $5)
+        ON CONFLICT (user_email) DO NOTHING
+        RETURNING user_id
+        "#,
+        service_account_email(account.name),
+        account.description,
+        account.owner_team_id,
+        &scopes,
+        account.budget_usd_micros,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user_id)
+}
+
+pub async fn list_service_accounts(pool: &PgPool) -> Result<Vec<ServiceAccount>> {
+    let accounts = sqlx::query_as!(
+        ServiceAccount,
+        r#"
+        SELECT
+            (
+                SELECT COUNT(*) FROM api_keys ak
+                WHERE ak.user_id = u.user_id AND ak.is_disabled = FALSE
+            ) AS "api_keys_count_active!",
+            u.budget_usd_micros,
+            u.created_at,
+            u.description,
+            u.is_disabled,
+            substr(u.user_email, length($1) + 1) AS "name!",
+            u.owner_team_id,
+            t.team_name AS "owner_team_name?",
+            COALESCE(u.scopes, '{}') AS "scopes!",
+            u.user_id
+        FROM users u
+        LEFT JOIN teams t ON t.team_id = u.owner_team_id
+        WHERE u.is_service_account
+        ORDER BY u.user_email
+        "#,
+        SERVICE_ACCOUNT_EMAIL_PREFIX,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(accounts)
+}
+
+pub async fn get_service_account(pool: &PgPool, user_id: Uuid) -> Result<Option<ServiceAccount>> {
+    let account = sqlx::query_as!(
+        ServiceAccount,
+        r#"
+        SELECT
+            (
+                SELECT COUNT(*) FROM api_keys ak
+                WHERE ak.user_id = u.user_id AND ak.is_disabled = FALSE
+            ) AS "api_keys_count_active!",
+            u.budget_usd_micros,
+            u.created_at,
+            u.description,
+            u.is_disabled,
+            substr(u.user_email, length($1) + 1) AS "name!",
+            u.owner_team_id,
+            t.team_name AS "owner_team_name?",
+            COALESCE(u.scopes, '{}') AS "scopes!",
+            u.user_id
+        FROM users u
+        LEFT JOIN teams t ON t.team_id = u.owner_team_id
+        WHERE u.is_service_account AND u.user_id = $2
+        "#,
+        SERVICE_ACCOUNT_EMAIL_PREFIX,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(account)
+}
+
+/// Sets the service account's monthly budget; `None` removes the limit.
+/// Returns false if there is no such service account.
+pub async fn set_service_account_budget(
+    pool: &PgPool,
+    user_id: Uuid,
+    budget_usd_micros: Option<i64>,
+) -> Result<bool> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET budget_usd_micros = $2, updated_at = now()
+        WHERE user_id = $1 AND is_service_account
+        "#,
+        user_id,
+        budget_usd_micros,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(updated.rows_affected() > 0)
+}
+
+/// Creates an API key for an active service account. Returns `None` if the
+/// account does not exist or is disabled.
+pub async fn create_service_account_api_key(pool: &PgPool, user_id: Uuid) -> Result<Option<Uuid>> {
+    let api_key = Uuid::new_v4();
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO api_keys (api_key, user_id)
+        SELECT $1, user_id FROM users
+        WHERE user_id = $2 AND is_service_account AND is_disabled = FALSE
+        "#,
+        api_key.to_string(),
+        user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok((inserted.rows_affected() > 0).then_some(api_key))
+}
+
+/// Returns the service account's budget and its spend in the current month,
+/// or `None` if it has no budget. Spend is only summed for accounts with a
+/// budget.
+pub async fn get_service_account_budget_status(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<BudgetStatus>> {
+    let budget_usd_micros = sqlx::query_scalar!(
+        "SELECT budget_usd_micros FROM users WHERE user_id = $1 AND is_service_account",
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    let Some(budget_usd_micros) = budget_usd_micros else {
+        return Ok(None);
+    };
+
+    let spent_usd_micros = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(SUM(cost_usd_micros), 0)::bigint AS "spent_usd_micros!"
+        FROM usage_records
+        WHERE user_id = $1 AND created_at >= $2
+        "#,
+        user_id,
+        budget_period_start(OffsetDateTime::now_utc()),
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Some(BudgetStatus {
+        budget_usd_micros,
+        spent_usd_micros,
+    }))
+}
+
+/// Returns the scopes of `api_key`: its own, if it was issued with scopes,
+/// otherwise those of the owning account. `None` means unrestricted.
+pub async fn get_api_key_scopes(pool: &PgPool, api_key: &str) -> Result<Option<Vec<String>>> {
+    let scopes = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(ak.scopes, u.scopes)
+        FROM api_keys ak
+        JOIN users u ON u.user_id = ak.user_id
+        WHERE ak.api_key = $1
+        "#,
+        api_key.to_lowercase(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(scopes.flatten())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn has_scope_treats_missing_scopes_as_unrestricted() {
+        assert!(has_scope(None, Scope::Messages));
+
+        let scopes = vec!["messages".to_string()];
+        assert!(has_scope(Some(&scopes), Scope::Messages));
+        assert!(!has_scope(Some(&scopes), Scope::Usage));
+        assert!(!has_scope(Some(&[]), Scope::Models));
+    }
+
+    #[test]
+    fn scope_round_trips_through_str() {
+        for scope in SCOPES {
+            assert_eq!(Scope::parse(scope.as_str()), Some(scope));
+        }
+        assert_eq!(Scope::parse("admin"), None);
+        assert_eq!(
+            serde_json::from_str::<Scope>(r#""count_tokens""#).unwrap(),
+            Scope::CountTokens
+        );
+    }
+
+    #[test]
+    fn validate_service_account_name_rejects_unsafe_names() {
+        assert!(validate_service_account_name("ci-bot-2").is_ok());
+        assert!(validate_service_account_name("").is_err());
+        assert!(validate_service_account_name("CI Bot").is_err());
+        assert!(validate_service_account_name(&"a".repeat(65)).is_err());
+    }
+}
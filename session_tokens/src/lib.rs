@@ -0,0 +1,493 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Result, anyhow, bail};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+};
+use serde::{Deserialize, Serialize};
+use service_accounts::{Scope, has_scope};
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Key id placed in the header of every session token, so they can be told
+/// apart from identity provider JWTs without checking a signature.
+pub const SESSION_TOKEN_KID: &str = "gateway-session";
+
+const ISSUER: &str = "llm-gateway";
+const MIN_SECRET_LEN: usize = 32;
+const MIN_TTL: Duration = Duration::minutes(1);
+
+/// Claims of a session token. Everything needed to authorize an inference
+/// request is embedded, so validation needs no `api_keys` or `users` lookup.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SessionTokenClaims {
+    /// The API key the token was exchanged for, if any; usage is recorded
+    /// against it.
+    pub api_key_id: Option<Uuid>,
+    /// The key's capture override when the token was issued.
+    pub capture_enabled: Option<bool>,
+    pub email: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub iss: String,
+    pub jti: Uuid,
+    /// `None` means unrestricted, as for API keys.
+    pub scopes: Option<Vec<String>>,
+    /// The user id.
+    pub sub: Uuid,
+    /// The team of a team-owned API key.
+    pub team_id: Option<Uuid>,
+}
+
+impl SessionTokenClaims {
+    pub fn expires_at(&self) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(self.exp).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+    }
+}
+
+/// Who a new session token is issued to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionTokenSubject {
+    pub api_key_id: Option<Uuid>,
+    pub capture_enabled: Option<bool>,
+    pub email: String,
+    pub scopes: Option<Vec<String>>,
+    pub team_id: Option<Uuid>,
+    pub user_id: Uuid,
+}
+
+pub struct IssuedSessionToken {
+    pub claims: SessionTokenClaims,
+    pub token: String,
+}
+
+/// Returns true if `token` is a compact JWT carrying the session token key
+/// id. The signature is not checked.
+pub fn is_session_token(token: &str) -> bool {
+    decode_header(token).is_ok_and(|header| header.kid.as_deref() == Some(SESSION_TOKEN_KID))
+}
+
+/// Limits a token to `requested` scopes, which must all be granted to the
+/// credential it is exchanged for. Without a request the token inherits the
+/// credential's scopes.
+pub fn narrow_scopes(
+    granted: Option<&[String]>,
+    requested: Option<&[Scope]>,
+) -> Result<Option<Vec<String>>, String> {
+    let Some(requested) = requested else {
+        return Ok(granted.map(<[String]>::to_vec));
+    };
+
+    requested
+        .iter()
+        .map(|&scope| {
+            if has_scope(granted, scope) {
+                Ok(scope.as_str().to_string())
+            } else {
+                Err(format!("Scope not granted: {}", scope.as_str()))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+/// Signs and validates session tokens (HS256) and keeps the in-memory copy
+/// of the revocation denylist.
+pub struct SessionTokenIssuer {
+    decoding_key: DecodingKey,
+    /// Ids of revoked tokens that have not yet expired.
+    denylist: RwLock<HashSet<Uuid>>,
+    encoding_key: EncodingKey,
+    max_ttl: Duration,
+    /// Ids of API keys and users disabled or deleted within the maximum TTL,
+    /// with when. Tokens issued to them up to then are revoked.
+    revoked_credentials: RwLock<HashMap<Uuid, i64>>,
+}
+
+impl SessionTokenIssuer {
+    pub fn new(secret: &[u8], max_ttl: Duration) -> Result<Self> {
+        if secret.len() < MIN_SECRET_LEN {
+            bail!("Session token secret must be at least {MIN_SECRET_LEN} bytes");
+        }
+        if max_ttl < MIN_TTL {
+            bail!(
+                "Session token TTL must be at least {} seconds",
+                MIN_TTL.whole_seconds()
+            );
+        }
+
+        Ok(Self {
+            decoding_key: DecodingKey::from_secret(secret),
+            denylist: RwLock::new(HashSet::new()),
+            encoding_key: EncodingKey::from_secret(secret),
+            max_ttl,
+            revoked_credentials: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub fn max_ttl(&self) -> Duration {
+        self.max_ttl
+    }
+
+    /// Issues a token for `subject`. `ttl` is clamped to between one minute
+    /// and the configured maximum, which is also the default.
+    pub fn issue(
+        &self,
+        subject: SessionTokenSubject,
+        ttl: Option<Duration>,
+    ) -> Result<IssuedSessionToken> {
+        let ttl = ttl.unwrap_or(self.max_ttl).clamp(MIN_TTL, self.max_ttl);
+        let now = OffsetDateTime::now_utc();
+
+        let claims = SessionTokenClaims {
+            api_key_id: subject.api_key_id,
+            capture_enabled: subject.capture_enabled,
+            email: subject.email,
+            exp: (now + ttl).unix_timestamp(),
+            iat: now.unix_timestamp(),
+            iss: ISSUER.to_string(),
+            jti: Uuid::new_v4(),
+            scopes: subject.scopes,
+            sub: subject.user_id,
+            team_id: subject.team_id,
+        };
+
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(SESSION_TOKEN_KID.to_string());
+        let token = encode(&header, &claims, &self.encoding_key)?;
+
+        Ok(IssuedSessionToken { claims, token })
+    }
+
+    /// Checks the signature and expiry of `token`, ignoring the denylist.
+    pub fn decode(&self, token: &str) -> Result<SessionTokenClaims> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 5;
+        validation.validate_aud = false;
+        validation.set_issuer(&[ISSUER]);
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+
+        Ok(decode::<SessionTokenClaims>(token, &self.decoding_key, &validation)?.claims)
+    }
+
+    /// Validates `token` without touching the database: its signature, its
+    /// expiry and the denylist.
+    pub fn verify(&self, token: &str) -> Result<SessionTokenClaims> {
+        let claims = self.decode(token)?;
+        if self.is_revoked(claims.jti) {
+            return Err(anyhow!("Session token has been revoked"));
+        }
+        if self.is_credential_revoked(&claims) {
+            return Err(anyhow!("Session token's API key or user has been disabled"));
+        }
+        Ok(claims)
+    }
+
+    pub fn is_revoked(&self, jti: Uuid) -> bool {
+        self.denylist
+            .read()
+            .is_ok_and(|denylist| denylist.contains(&jti))
+    }
+
+    /// Returns true if the token's API key or user was disabled or deleted
+    /// after it was issued. Tokens issued in the same second are revoked too.
+    fn is_credential_revoked(&self, claims: &SessionTokenClaims) -> bool {
+        self.revoked_credentials.read().is_ok_and(|revoked| {
+            claims
+                .api_key_id
+                .into_iter()
+                .chain([claims.sub])
+                .filter_map(|credential_id| revoked.get(&credential_id))
+                .any(|&revoked_at| claims.iat <= revoked_at)
+        })
+    }
+
+    /// Adds a token to the denylist. It takes effect immediately on this
+    /// instance and on others at their next refresh.
+    pub async fn revoke(&self, pool: &PgPool, jti: Uuid, expires_at: OffsetDateTime) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO revoked_session_tokens (jti, expires_at)
+            VALUES ($1, $2)
+            ON CONFLICT (jti) DO NOTHING
+            "#,
+            jti,
+            expires_at,
+        )
+        .execute(pool)
+        .await?;
+
+        if let Ok(mut denylist) = self.denylist.write() {
+            denylist.insert(jti);
+        }
+
+        Ok(())
+    }
+
+    /// Reloads the denylist from the database and deletes entries for
+    /// tokens that have expired anyway. Disabled or deleted API keys and
+    /// users are recorded by database triggers, so every way of disabling
+    /// one revokes its tokens.
+    pub async fn refresh_denylist(&self, pool: &PgPool) -> Result<()> {
+        sqlx::query!("DELETE FROM revoked_session_tokens WHERE expires_at < now()")
+            .execute(pool)
+            .await?;
+
+        let revoked = sqlx::query_scalar!("SELECT jti FROM revoked_session_tokens")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        // Tokens issued before a revocation older than the maximum TTL have
+        // all expired.
+        let oldest = OffsetDateTime::now_utc() - self.max_ttl;
+        sqlx::query!(
+            "DELETE FROM revoked_session_token_credentials WHERE revoked_at < $1",
+            oldest,
+        )
+        .execute(pool)
+        .await?;
+
+        let revoked_credentials =
+            sqlx::query!("SELECT credential_id, revoked_at FROM revoked_session_token_credentials")
+                .fetch_all(pool)
+                .await?
+                .into_iter()
+                .map(|row| (row.credential_id, row.revoked_at.unix_timestamp()))
+                .collect::<HashMap<_, _>>();
+
+        if let Ok(mut denylist) = self.denylist.write() {
+            *denylist = revoked;
+        }
+        if let Ok(mut revoked) = self.revoked_credentials.write() {
+            *revoked = revoked_credentials;
+        }
+
+        Ok(())
+    }
+
+    /// Refreshes the denylist every `period` until aborted.
+    pub async fn continuously_refresh_denylist(
+        self: Arc<Self>,
+        pool: Arc<PgPool>,
+        period: std::time::Duration,
+    ) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match self.refresh_denylist(&pool).await {
+                Ok(()) => {
+                    let revoked = self.denylist.read().map_or(0, |denylist| denylist.len())
+                        + self
+                            .revoked_credentials
+                            .read()
+                            .map_or(0, |revoked| revoked.len());
+                    if revoked > 0 {
+                        info!("Loaded {} revoked session tokens and credentials", revoked);
+                    }
+                }
+                Err(e) => error!("Failed to refresh session token denylist: {:?}", e),
+            }
+        }
+    }
+}
+
+/// Returns the subject for an active API key of an active user.
+pub async fn get_api_key_subject(
+    pool: &PgPool,
+    api_key: &str,
+) -> Result<Option<SessionTokenSubject>> {
+    let subject = sqlx::query_as!(
+        SessionTokenSubject,
+        r#"
+        SELECT
+            ak.api_key_id AS "api_key_id?",
+            ak.capture_enabled,
+            u.user_email AS email,
+            COALESCE(ak.scopes, u.scopes) AS scopes,
+            ak.team_id,
+            u.user_id
+        FROM api_keys ak
+        JOIN users u ON u.user_id = ak.user_id
+        WHERE ak.api_key = $1 AND ak.is_disabled = FALSE AND u.is_disabled = FALSE
+        "#,
+        api_key.to_lowercase(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(subject)
+}
+
+/// Returns the subject for an active human user signed in with the identity
+/// provider. Such users have no scopes or team key.
+pub async fn get_user_subject(pool: &PgPool, email: &str) -> Result<Option<SessionTokenSubject>> {
+    let subject = sqlx::query_as!(
+        SessionTokenSubject,
+        r#"
+        SELECT
+            NULL::uuid AS api_key_id,
+            NULL::boolean AS capture_enabled,
+            user_email AS email,
+            NULL::text[] AS scopes,
+            NULL::uuid AS team_id,
+            user_id
+        FROM users
+        WHERE user_email = $1 AND is_disabled = FALSE AND NOT is_service_account
+        "#,
+        email.to_lowercase(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(subject)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn subject() -> SessionTokenSubject {
+        SessionTokenSubject {
+            api_key_id: Some(Uuid::new_v4()),
+            capture_enabled: None,
+            email: "user@example.com".to_string(),
+            scopes: Some(vec!["messages".to_string()]),
+            team_id: None,
+            user_id: Uuid::new_v4(),
+        }
+    }
+
+    #[test]
+    fn issued_tokens_verify_and_carry_the_subject() {
+        let issuer = SessionTokenIssuer::new(SECRET, Duration::hours(1)).unwrap();
+        let subject = subject();
+        let issued = issuer.issue(subject.clone(), None).unwrap();
+
+        assert!(is_session_token(&issued.token));
+        let claims = issuer.verify(&issued.token).unwrap();
+        assert_eq!(claims, issued.claims);
+        assert_eq!(claims.sub, subject.user_id);
+        assert_eq!(claims.api_key_id, subject.api_key_id);
+        assert_eq!(claims.scopes, subject.scopes);
+        assert_eq!(claims.exp - claims.iat, 3600);
+    }
+
+    #[test]
+    fn ttl_is_clamped_to_the_maximum() {
+        let issuer = SessionTokenIssuer::new(SECRET, Duration::minutes(15)).unwrap();
+        let claims = issuer
+            .issue(subject(), Some(Duration::days(1)))
+            .unwrap()
+            .claims;
+        assert_eq!(claims.exp - claims.iat, 900);
+
+        let claims = issuer
+            .issue(subject(), Some(Duration::seconds(1)))
+            .unwrap()
+            .claims;
+        assert_eq!(claims.exp - claims.iat, 60);
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_rejected() {
+        let issuer = SessionTokenIssuer::new(SECRET, Duration::hours(1)).unwrap();
+        let other = SessionTokenIssuer::new(&[b'x'; 32], Duration::hours(1)).unwrap();
+        let issued = other.issue(subject(), None).unwrap();
+        assert!(issuer.verify(&issued.token).is_err());
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let issuer = SessionTokenIssuer::new(SECRET, Duration::hours(1)).unwrap();
+        let mut claims = issuer.issue(subject(), None).unwrap().claims;
+        claims.exp = claims.iat - 60;
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(SESSION_TOKEN_KID.to_string());
+        let token = encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap();
+        assert!(issuer.verify(&token).is_err());
+    }
+
+    #[test]
+    fn revoked_tokens_are_rejected() {
+        let issuer = SessionTokenIssuer::new(SECRET, Duration::hours(1)).unwrap();
+        let issued = issuer.issue(subject(), None).unwrap();
+        issuer.denylist.write().unwrap().insert(issued.claims.jti);
+
+        assert!(issuer.verify(&issued.token).is_err());
+        assert!(issuer.decode(&issued.token).is_ok());
+    }
+
+    #[test]
+    fn tokens_of_revoked_credentials_are_rejected_until_reissued() {
+        let issuer = SessionTokenIssuer::new(SECRET, Duration::hours(1)).unwrap();
+        let issued = issuer.issue(subject(), None).unwrap();
+        let api_key_id = issued.claims.api_key_id.unwrap();
+        issuer
+            .revoked_credentials
+            .write()
+            .unwrap()
+            .insert(api_key_id, issued.claims.iat);
+        assert!(issuer.verify(&issued.token).is_err());
+
+        // A token of the same key issued after the key was re-enabled.
+        let mut claims = issued.claims.clone();
+        claims.iat += 1;
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(SESSION_TOKEN_KID.to_string());
+        let token = encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap();
+        assert!(issuer.verify(&token).is_ok());
+
+        let user_token = issuer.issue(subject(), None).unwrap();
+        issuer
+            .revoked_credentials
+            .write()
+            .unwrap()
+            .insert(user_token.claims.sub, user_token.claims.iat);
+        assert!(issuer.verify(&user_token.token).is_err());
+    }
+
+    #[test]
+    fn short_secrets_are_rejected() {
+        assert!(SessionTokenIssuer::new(b"short", Duration::hours(1)).is_err());
+    }
+
+    #[test]
+    fn is_session_token_ignores_other_tokens() {
+        assert!(!is_session_token("not-a-jwt"));
+        assert!(!is_session_token("0a1b2c3d-4e5f-6789-abcd-ef0123456789"));
+
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("idp-key".to_string());
+        let claims = HashMap::from([("sub", "user")]);
+        let token = encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap();
+        assert!(!is_session_token(&token));
+    }
+
+    #[test]
+    fn narrow_scopes_only_grants_a_subset() {
+        let granted = vec!["messages".to_string(), "models".to_string()];
+
+        assert_eq!(narrow_scopes(None, None), Ok(None));
+        assert_eq!(
+            narrow_scopes(Some(&granted), None),
+            Ok(Some(granted.clone()))
+        );
+        assert_eq!(
+            narrow_scopes(None, Some(&[Scope::Usage])),
+            Ok(Some(vec!["usage".to_string()]))
+        );
+        assert_eq!(
+            narrow_scopes(Some(&granted), Some(&[Scope::Messages])),
+            Ok(Some(vec!["messages".to_string()]))
+        );
+        assert!(narrow_scopes(Some(&granted), Some(&[Scope::Usage])).is_err());
+    }
+}
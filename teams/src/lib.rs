@@ -0,0 +1,283 @@
+use anyhow::Result;
+use sqlx::PgPool;
+use time::{Date, OffsetDateTime};
+use uuid::Uuid;
+
+pub struct TeamSummary {
+    pub budget_usd_micros: Option<i64>,
+    pub created_at: OffsetDateTime,
+    pub member_count: i64,
+    pub team_id: Uuid,
+    pub team_name: String,
+}
+
+pub struct TeamMember {
+    pub created_at: OffsetDateTime,
+    pub user_email: String,
+    pub user_id: Uuid,
+}
+
+pub struct TeamRef {
+    pub team_id: Uuid,
+    pub team_name: String,
+}
+
+pub async fn create_team(
+    pool: &PgPool,
+    team_name: &str,
+    budget_usd_micros: Option<i64>,
+) -> Result<Uuid> {
+    let team_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO teams (team_name, budget_usd_micros)
+        VALUES ($1, $2)
+        RETURNING team_id
+        "#,
+        team_name,
+        budget_usd_micros,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(team_id)
+}
+
+pub async fn list_teams(pool: &PgPool) -> Result<Vec<TeamSummary>> {
+    let teams = sqlx::query_as!(
+        TeamSummary,
+        r#"
+        SELECT
+            t.budget_usd_micros,
+            t.created_at,
+            (SELECT COUNT(*) FROM team_members tm WHERE tm.team_id = t.team_id) AS "member_count!",
+            t.team_id,
+            t.team_name
+        FROM teams t
+        ORDER BY t.team_name
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(teams)
+}
+
+pub async fn get_team(pool: &PgPool, team_id: Uuid) -> Result<Option<TeamSummary>> {
+    let team = sqlx::query_as!(
+        TeamSummary,
+        r#"
+        SELECT
+            t.budget_usd_micros,
+            t.created_at,
+            (SELECT COUNT(*) FROM team_members tm WHERE tm.team_id = t.team_id) AS "member_count!",
+            t.team_id,
+            t.team_name
+        FROM teams t
+        WHERE t.team_id = $1
+        "#,
+        team_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(team)
+}
+
+/// Sets the team's monthly budget; `None` removes the limit.
+pub async fn set_team_budget(
+    pool: &PgPool,
+    team_id: Uuid,
+    budget_usd_micros: Option<i64>,
+) -> Result<bool> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE teams
+        SET budget_usd_micros = $2, updated_at = now()
+        WHERE team_id = $1
+        "#,
+        team_id,
+        budget_usd_micros,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(updated.rows_affected() > 0)
+}
+
+/// Adds the user with `user_email` to the team. Returns false if no such user
+/// exists.
+pub async fn add_team_member(pool: &PgPool, team_id: Uuid, user_email: &str) -> Result<bool> {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO team_members (team_id, user_id)
+        SELECT $1, user_id FROM users WHERE user_email = $2
+        ON CONFLICT DO NOTHING
+        "#,
+        team_id,
+        user_email.to_lowercase(),
+    )
+    .execute(pool)
+    .await?;
+
+    if inserted.rows_affected() > 0 {
+        return Ok(true);
+    }
+
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM users WHERE user_email = $1) AS "exists!""#,
+        user_email.to_lowercase(),
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists)
+}
+
+/// Removes the user from the team. Team keys they created belong to the
+/// team and stay active. Returns `false` if the user was not a member.
+pub async fn remove_team_member(pool: &PgPool, team_id: Uuid, user_id: Uuid) -> Result<bool> {
+    let deleted = sqlx::query!(
+        "DELETE FROM team_members WHERE team_id = $1 AND user_id = $2",
+        team_id,
+        user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(deleted.rows_affected() > 0)
+}
+
+pub async fn list_team_members(pool: &PgPool, team_id: Uuid) -> Result<Vec<TeamMember>> {
+    let members = sqlx::query_as!(
+        TeamMember,
+        r#"
+        SELECT tm.created_at, u.user_email, u.user_id
+        FROM team_members tm
+        JOIN users u ON u.user_id = tm.user_id
+        WHERE tm.team_id = $1
+        ORDER BY u.user_email
+        "#,
+        team_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(members)
+}
+
+/// Lists the teams `user_email` belongs to.
+pub async fn list_user_teams(pool: &PgPool, user_email: &str) -> Result<Vec<TeamRef>> {
+    let teams = sqlx::query_as!(
+        TeamRef,
+        r#"
+        SELECT t.team_id, t.team_name
+        FROM teams t
+        JOIN team_members tm ON tm.team_id = t.team_id
+        JOIN users u ON u.user_id = tm.user_id
+        WHERE u.user_email = $1
+        ORDER BY t.team_name
+        "#,
+        user_email.to_lowercase(),
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(teams)
+}
+
+pub async fn is_team_member(pool: &PgPool, team_id: Uuid, user_email: &str) -> Result<bool> {
+    let is_member = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM team_members tm
+            JOIN users u ON u.user_id = tm.user_id
+            WHERE tm.team_id = $1 AND u.user_email = $2
+        ) AS "is_member!"
+        "#,
+        team_id,
+        user_email.to_lowercase(),
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(is_member)
+}
+
+/// Returns midnight UTC on the first day of `now`'s month, the start of the
+/// current budget period.
+pub fn budget_period_start(now: OffsetDateTime) -> OffsetDateTime {
+    Date::from_calendar_date(now.year(), now.month(), 1)
+        .expect("the first of the month is always a valid date")
+        .midnight()
+        .assume_utc()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BudgetStatus {
+    pub budget_usd_micros: i64,
+    pub spent_usd_micros: i64,
+}
+
+impl BudgetStatus {
+    pub fn is_exceeded(&self) -> bool {
+        self.spent_usd_micros >= self.budget_usd_micros
+    }
+}
+
+/// Returns the team's budget and its spend in the current month, or `None`
+/// if the team has no budget. Spend is only summed for teams with a budget.
+pub async fn get_team_budget_status(pool: &PgPool, team_id: Uuid) -> Result<Option<BudgetStatus>> {
+    let budget_usd_micros = sqlx::query_scalar!(
+        "SELECT budget_usd_micros FROM teams WHERE team_id = $1",
+        team_id,
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    let Some(budget_usd_micros) = budget_usd_micros else {
+        return Ok(None);
+    };
+
+    let spent_usd_micros = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(SUM(cost_usd_micros), 0)::bigint AS "spent_usd_micros!"
+        FROM usage_records
+        WHERE team_id = $1 AND created_at >= $2
+        "#,
+        team_id,
+        budget_period_start(OffsetDateTime::now_utc()),
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Some(BudgetStatus {
+        budget_usd_micros,
+        spent_usd_micros,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn budget_period_starts_on_first_of_month() {
+        assert_eq!(
+            budget_period_start(datetime!(2026-03-17 13:45 UTC)),
+            datetime!(2026-03-01 00:00 UTC)
+        );
+    }
+
+    #[test]
+    fn budget_is_exceeded_once_spend_reaches_budget() {
+        let status = |spent_usd_micros| BudgetStatus {
+            budget_usd_micros: 100,
+            spent_usd_micros,
+        };
+        assert!(!status(99).is_exceeded());
+        assert!(status(100).is_exceeded());
+    }
+}
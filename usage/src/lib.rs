@@ -0,0 +1,604 @@
+use anyhow::Result;
+use futures::{Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Requests whose total input exceeds this many tokens are billed at the
+/// model's long-context rates, when it has them.
+pub const LONG_CONTEXT_THRESHOLD_TOKENS: i64 = 200_000;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModelPrice {
+    pub input_usd_per_mtok: f64,
+    pub output_usd_per_mtok: f64,
+    pub cache_read_usd_per_mtok: f64,
+    pub cache_write_usd_per_mtok: f64,
+    pub long_context_input_usd_per_mtok: Option<f64>,
+    pub long_context_output_usd_per_mtok: Option<f64>,
+    pub long_context_cache_read_usd_per_mtok: Option<f64>,
+    pub long_context_cache_write_usd_per_mtok: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TokenCounts {
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cache_read_input_tokens: i64,
+    pub cache_write_input_tokens: i64,
+}
+
+impl TokenCounts {
+    pub fn total_input_tokens(&self) -> i64 {
+        self.input_tokens + self.cache_read_input_tokens + self.cache_write_input_tokens
+    }
+}
+
+impl ModelPrice {
+    /// Returns the cost of `tokens` in micro-dollars.
+    ///
+    /// Prices are per million tokens, so `tokens * usd_per_mtok` is already
+    /// expressed in micro-dollars.
+    pub fn cost_usd_micros(&self, tokens: &TokenCounts) -> i64 {
+        let long_context = tokens.total_input_tokens() > LONG_CONTEXT_THRESHOLD_TOKENS;
+        let rate = |base: f64, long_context_rate: Option<f64>| {
+            if long_context {
+                long_context_rate.unwrap_or(base)
+            } else {
+                base
+            }
+        };
+
+        let cost = tokens.input_tokens as f64
+            * rate(
+                self.input_usd_per_mtok,
+                self.long_context_input_usd_per_mtok,
+            )
+            + tokens.output_tokens as f64
+                * rate(
+                    self.output_usd_per_mtok,
+                    self.long_context_output_usd_per_mtok,
+                )
+            + tokens.cache_read_input_tokens as f64
+                * rate(
+                    self.cache_read_usd_per_mtok,
+                    self.long_context_cache_read_usd_per_mtok,
+                )
+            + tokens.cache_write_input_tokens as f64
+                * rate(
+                    self.cache_write_usd_per_mtok,
+                    self.long_context_cache_write_usd_per_mtok,
+                );
+
+        cost.round() as i64
+    }
+}
+
+pub fn usd_micros_to_usd(usd_micros: i64) -> f64 {
+    usd_micros as f64 / 1_000_000.0
+}
+
+pub fn format_usd(usd_micros: i64) -> String {
+    format!("${:.2}", usd_micros_to_usd(usd_micros))
+}
+
+/// Parses a dollar amount such as `"250"` or `"$1,000.50"` into micro-dollars.
+pub fn parse_usd_micros(value: &str) -> Result<i64, String> {
+    let cleaned = value.trim().trim_start_matches('$').replace(',', "");
+    match cleaned.parse::<f64>() {
+        Ok(usd) if usd.is_finite() && usd >= 0.0 => Ok((usd * 1_000_000.0).round() as i64),
+        _ => Err(format!("Invalid dollar amount \"{value}\"")),
+    }
+}
+
+pub struct NewUsageRecord {
+    pub api_key_id: Option<Uuid>,
+    pub model_id: Option<Uuid>,
+    pub model_name: String,
+    pub team_id: Option<Uuid>,
+    pub tokens: TokenCounts,
+    pub user_id: Uuid,
+}
+
+/// Selects usage between `since` (inclusive) and `until` (exclusive),
+/// optionally restricted to a single user, team and/or model.
+#[derive(Clone, Debug)]
+pub struct UsageFilter {
+    pub model_name: Option<String>,
+    pub since: OffsetDateTime,
+    pub team_id: Option<Uuid>,
+    pub until: OffsetDateTime,
+    pub user_email: Option<String>,
+}
+
+impl UsageFilter {
+    pub fn new(since: OffsetDateTime, until: OffsetDateTime) -> Self {
+        Self {
+            model_name: None,
+            since,
+            team_id: None,
+            until,
+            user_email: None,
+        }
+    }
+
+    pub fn team_id(mut self, team_id: Uuid) -> Self {
+        self.team_id = Some(team_id);
+        self
+    }
+
+    pub fn user_email(mut self, user_email: &str) -> Self {
+        self.user_email = Some(user_email.to_lowercase());
+        self
+    }
+
+    pub fn model_name(mut self, model_name: &str) -> Self {
+        self.model_name = Some(model_name.to_lowercase());
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    ApiKey,
+    CostCenter,
+    #[default]
+    Day,
+    Model,
+    Team,
+    User,
+}
+
+pub const GROUP_BYS: [GroupBy; 6] = [
+    GroupBy::Day,
+    GroupBy::User,
+    GroupBy::Team,
+    GroupBy::CostCenter,
+    GroupBy::Model,
+    GroupBy::ApiKey,
+];
+
+impl GroupBy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupBy::ApiKey => "api_key",
+            GroupBy::CostCenter => "cost_center",
+            GroupBy::Day => "day",
+            GroupBy::Model => "model",
+            GroupBy::Team => "team",
+            GroupBy::User => "user",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UsageSummary {
+    pub group: String,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cache_read_input_tokens: i64,
+    pub cache_write_input_tokens: i64,
+    pub cost_usd_micros: i64,
+}
+
+pub async fn get_model_price(pool: &PgPool, model_id: Uuid) -> Result<Option<ModelPrice>> {
+    let price = sqlx::query_as!(
+        ModelPrice,
+        r#"
+        SELECT
+            input_usd_per_mtok,
+            output_usd_per_mtok,
+            cache_read_usd_per_mtok,
+            cache_write_usd_per_mtok,
+            long_context_input_usd_per_mtok,
+            long_context_output_usd_per_mtok,
+            long_context_cache_read_usd_per_mtok,
+            long_context_cache_write_usd_per_mtok
+        FROM model_prices
+        WHERE model_id = $1
+        "#,
+        model_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(price)
+}
+
+/// Prices `record` with the model's current rates and stores it.
+/// Returns the computed cost in micro-dollars.
+pub async fn record_usage(pool: &PgPool, record: &NewUsageRecord) -> Result<i64> {
+    let price = match record.model_id {
+        Some(model_id) => get_model_price(pool, model_id).await?,
+        None => None,
+    };
+    let cost_usd_micros = price
+        .map(|price| price.cost_usd_micros(&record.tokens))
+        .unwrap_or(0);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO usage_records (
+            api_key_id,
+            cache_read_input_tokens,
+            cache_write_input_tokens,
+            cost_usd_micros,
+            input_tokens,
+            model_id,
+            model_name,
+            output_tokens,
+            team_id,
+            user_id
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+        record.api_key_id,
+        record.tokens.cache_read_input_tokens,
+        record.tokens.cache_write_input_tokens,
+        cost_usd_micros,
+        record.tokens.input_tokens,
+        record.model_id,
+        record.model_name.to_lowercase(),
+        record.tokens.output_tokens,
+        record.team_id,
+        record.user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(cost_usd_micros)
+}
+
+/// Aggregates the usage selected by `filter` into one row per `group_by`
+/// value.
+pub async fn get_usage_summary(
+    pool: &PgPool,
+    filter: &UsageFilter,
+    group_by: GroupBy,
+) -> Result<Vec<UsageSummary>> {
+    let summaries = sqlx::query_as!(
+        UsageSummary,
+        r#"
+        SELECT
+            CASE $3
+                WHEN 'api_key' THEN COALESCE(left(ak.api_key, 8), '-')
+                WHEN 'cost_center' THEN COALESCE(u.cost_center, '-')
+                WHEN 'model' THEN ur.model_name
+                WHEN 'team' THEN COALESCE(t.team_name, '-')
+                WHEN 'user' THEN u.user_email
+                ELSE to_char(ur.created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD')
+            END AS "group!",
+            COUNT(*) AS "requests!",
+            COALESCE(SUM(ur.input_tokens), 0)::bigint AS "input_tokens!",
+            COALESCE(SUM(ur.output_tokens), 0)::bigint AS "output_tokens!",
+            COALESCE(SUM(ur.cache_read_input_tokens), 0)::bigint AS "cache_read_input_tokens!",
+            COALESCE(SUM(ur.cache_write_input_tokens), 0)::bigint AS "cache_write_input_tokens!",
+            COALESCE(SUM(ur.cost_usd_micros), 0)::bigint AS "cost_usd_micros!"
+        FROM usage_records ur
+        JOIN users u ON u.user_id = ur.user_id
+        LEFT JOIN api_keys ak ON ak.api_key_id = ur.api_key_id
+        LEFT JOIN teams t ON t.team_id = ur.team_id
+        WHERE ($1::text IS NULL OR u.user_email = $1)
+          AND ($2::text IS NULL OR ur.model_name = $2)
+          AND ur.created_at >= $4
+          AND ur.created_at < $5
+          AND ($6::uuid IS NULL OR ur.team_id = $6)
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        filter.user_email,
+        filter.model_name,
+        group_by.as_str(),
+        filter.since,
+        filter.until,
+        filter.team_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(summaries)
+}
+
+pub async fn get_total_cost_usd_micros(
+    pool: &PgPool,
+    user_email: &str,
+    since: OffsetDateTime,
+) -> Result<i64> {
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(SUM(cost_usd_micros), 0)::bigint AS "total!"
+        FROM usage_records
+        WHERE user_id = (SELECT user_id FROM users WHERE user_email = $1)
+          AND created_at >= $2
+        "#,
+        user_email.to_lowercase(),
+        since,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(total)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UsageRow {
+    pub day: String,
+    pub user_email: String,
+    pub cost_center: String,
+    pub team: String,
+    pub api_key_prefix: String,
+    pub model_name: String,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cache_read_input_tokens: i64,
+    pub cache_write_input_tokens: i64,
+    pub cost_usd_micros: i64,
+}
+
+pub const USAGE_CSV_HEADER: &str = "day,user_email,cost_center,team,api_key,model,requests,input_tokens,output_tokens,cache_read_input_tokens,cache_write_input_tokens,cost_usd\n";
+
+impl UsageRow {
+    pub fn to_csv_line(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{:.6}\n",
+            csv_field(&self.day),
+            csv_field(&self.user_email),
+            csv_field(&self.cost_center),
+            csv_field(&self.team),
+            csv_field(&self.api_key_prefix),
+            csv_field(&self.model_name),
+            self.requests,
+            self.input_tokens,
+            self.output_tokens,
+            self.cache_read_input_tokens,
+            self.cache_write_input_tokens,
+            usd_micros_to_usd(self.cost_usd_micros)
+        )
+    }
+}
+
+/// Quotes a CSV field when it contains a delimiter, quote or line break.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Rolls up detailed rows into one summary per `group_by` value, ordered by
+/// group.
+pub fn summarize_rows(rows: &[UsageRow], group_by: GroupBy) -> Vec<UsageSummary> {
+    let mut summaries: BTreeMap<&str, UsageSummary> = BTreeMap::new();
+
+    for row in rows {
+        let group = match group_by {
+            GroupBy::ApiKey => row.api_key_prefix.as_str(),
+            GroupBy::CostCenter => row.cost_center.as_str(),
+            GroupBy::Day => row.day.as_str(),
+            GroupBy::Model => row.model_name.as_str(),
+            GroupBy::Team => row.team.as_str(),
+            GroupBy::User => row.user_email.as_str(),
+        };
+        let summary = summaries.entry(group).or_insert_with(|| UsageSummary {
+            group: group.to_string(),
+            requests: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+            cache_read_input_tokens: 0,
+            cache_write_input_tokens: 0,
+            cost_usd_micros: 0,
+        });
+        summary.requests += row.requests;
+        summary.input_tokens += row.input_tokens;
+        summary.output_tokens += row.output_tokens;
+        summary.cache_read_input_tokens += row.cache_read_input_tokens;
+        summary.cache_write_input_tokens += row.cache_write_input_tokens;
+        summary.cost_usd_micros += row.cost_usd_micros;
+    }
+
+    summaries.into_values().collect()
+}
+
+/// Returns the usage selected by `filter` broken down by day, user, API key
+/// and model.
+pub async fn get_usage_rows(pool: &PgPool, filter: &UsageFilter) -> Result<Vec<UsageRow>> {
+    Ok(stream_usage_rows(pool, filter).try_collect().await?)
+}
+
+/// Streaming variant of [`get_usage_rows`] for exports over large ranges.
+pub fn stream_usage_rows<'a>(
+    pool: &'a PgPool,
+    filter: &UsageFilter,
+) -> impl Stream<Item = Result<UsageRow, sqlx::Error>> + Send + use<'a> {
+    sqlx::query_as!(
+        UsageRow,
+        r#"
+        SELECT
+            to_char(ur.created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD') AS "day!",
+            u.user_email AS "user_email!",
+            COALESCE(u.cost_center, '-') AS "cost_center!",
+            COALESCE(t.team_name, '-') AS "team!",
+            COALESCE(left(ak.api_key, 8), '-') AS "api_key_prefix!",
+            ur.model_name AS "model_name!",
+            COUNT(*) AS "requests!",
+            COALESCE(SUM(ur.input_tokens), 0)::bigint AS "input_tokens!",
+            COALESCE(SUM(ur.output_tokens), 0)::bigint AS "output_tokens!",
+            COALESCE(SUM(ur.cache_read_input_tokens), 0)::bigint AS "cache_read_input_tokens!",
+            COALESCE(SUM(ur.cache_write_input_tokens), 0)::bigint AS "cache_write_input_tokens!",
+            COALESCE(SUM(ur.cost_usd_micros), 0)::bigint AS "cost_usd_micros!"
+        FROM usage_records ur
+        JOIN users u ON u.user_id = ur.user_id
+        LEFT JOIN api_keys ak ON ak.api_key_id = ur.api_key_id
+        LEFT JOIN teams t ON t.team_id = ur.team_id
+        WHERE ($1::text IS NULL OR u.user_email = $1)
+          AND ($2::text IS NULL OR ur.model_name = $2)
+          AND ur.created_at >= $3
+          AND ur.created_at < $4
+          AND ($5::uuid IS NULL OR ur.team_id = $5)
+        GROUP BY 1, 2, 3, 4, 5, 6
+        ORDER BY 1, 2, 3, 4, 5, 6
+        "#,
+        filter.user_email.clone(),
+        filter.model_name.clone(),
+        filter.since,
+        filter.until,
+        filter.team_id,
+    )
+    .fetch(pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sonnet() -> ModelPrice {
+        ModelPrice {
+            input_usd_per_mtok: 3.0,
+            output_usd_per_mtok: 15.0,
+            cache_read_usd_per_mtok: 0.3,
+            cache_write_usd_per_mtok: 3.75,
+            long_context_input_usd_per_mtok: Some(6.0),
+            long_context_output_usd_per_mtok: Some(22.5),
+            long_context_cache_read_usd_per_mtok: Some(0.6),
+            long_context_cache_write_usd_per_mtok: Some(7.5),
+        }
+    }
+
+    #[test]
+    fn cost_uses_standard_rates_below_threshold() {
+        let tokens = TokenCounts {
+            input_tokens: 1_000,
+            output_tokens: 500,
+            cache_read_input_tokens: 10_000,
+            cache_write_input_tokens: 2_000,
+        };
+        // 1000*3 + 500*15 + 10000*0.3 + 2000*3.75
+        assert_eq!(sonnet().cost_usd_micros(&tokens), 21_000);
+    }
+
+    #[test]
+    fn cost_uses_long_context_rates_above_threshold() {
+        let tokens = TokenCounts {
+            input_tokens: 1_000,
+            output_tokens: 1_000,
+            cache_read_input_tokens: 200_000,
+            cache_write_input_tokens: 0,
+        };
+        // 1000*6 + 1000*22.5 + 200000*0.6
+        assert_eq!(sonnet().cost_usd_micros(&tokens), 148_500);
+    }
+
+    #[test]
+    fn cost_at_threshold_is_not_long_context() {
+        let tokens = TokenCounts {
+            input_tokens: LONG_CONTEXT_THRESHOLD_TOKENS,
+            ..Default::default()
+        };
+        assert_eq!(sonnet().cost_usd_micros(&tokens), 600_000);
+    }
+
+    #[test]
+    fn cost_falls_back_to_standard_rates_without_premium() {
+        let price = ModelPrice {
+            input_usd_per_mtok: 1.0,
+            output_usd_per_mtok: 5.0,
+            ..Default::default()
+        };
+        let tokens = TokenCounts {
+            input_tokens: 300_000,
+            output_tokens: 100,
+            ..Default::default()
+        };
+        assert_eq!(price.cost_usd_micros(&tokens), 300_500);
+    }
+
+    #[test]
+    fn format_usd_rounds_to_cents() {
+        assert_eq!(format_usd(1_234_567), "$1.23");
+        assert_eq!(format_usd(0), "$0.00");
+    }
+
+    #[test]
+    fn parse_usd_micros_accepts_dollar_signs_and_separators() {
+        assert_eq!(parse_usd_micros("250"), Ok(250_000_000));
+        assert_eq!(parse_usd_micros(" $1,000.50 "), Ok(1_000_500_000));
+        assert!(parse_usd_micros("-5").is_err());
+        assert!(parse_usd_micros("ten").is_err());
+    }
+
+    #[test]
+    fn csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("alice@example.com"), "alice@example.com");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn usage_row_to_csv_line_formats_cost_in_dollars() {
+        let row = UsageRow {
+            day: "2026-01-02".to_string(),
+            user_email: "alice@example.com".to_string(),
+            cost_center: "cc-42".to_string(),
+            team: "platform".to_string(),
+            api_key_prefix: "ab12cd34".to_string(),
+            model_name: "us.anthropic.claude-sonnet-4-6".to_string(),
+            requests: 2,
+            input_tokens: 10,
+            output_tokens: 20,
+            cache_read_input_tokens: 0,
+            cache_write_input_tokens: 5,
+            cost_usd_micros: 1_500_000,
+        };
+        assert_eq!(
+            row.to_csv_line(),
+            "2026-01-02,alice@example.com,cc-42,platform,ab12cd34,us.anthropic.claude-sonnet-4-6,2,10,20,0,5,1.500000\n"
+        );
+    }
+
+    #[test]
+    fn summarize_rows_groups_and_sums() {
+        let row = |day: &str, model: &str, cost_usd_micros: i64| UsageRow {
+            day: day.to_string(),
+            user_email: "alice@example.com".to_string(),
+            cost_center: "cc-42".to_string(),
+            team: "-".to_string(),
+            api_key_prefix: "ab12cd34".to_string(),
+            model_name: model.to_string(),
+            requests: 1,
+            input_tokens: 100,
+            output_tokens: 10,
+            cache_read_input_tokens: 0,
+            cache_write_input_tokens: 0,
+            cost_usd_micros,
+        };
+        let rows = vec![
+            row("2026-01-01", "haiku", 5),
+            row("2026-01-01", "sonnet", 7),
+            row("2026-01-02", "haiku", 11),
+        ];
+
+        let by_model = summarize_rows(&rows, GroupBy::Model);
+        assert_eq!(by_model.len(), 2);
+        assert_eq!(by_model[0].group, "haiku");
+        assert_eq!(by_model[0].requests, 2);
+        assert_eq!(by_model[0].input_tokens, 200);
+        assert_eq!(by_model[0].cost_usd_micros, 16);
+
+        let by_user = summarize_rows(&rows, GroupBy::User);
+        assert_eq!(by_user.len(), 1);
+        assert_eq!(by_user[0].cost_usd_micros, 23);
+    }
+
+    #[test]
+    fn group_by_deserializes_snake_case() {
+        let group_by: GroupBy = serde_json::from_str(r#""api_key""#).unwrap();
+        assert_eq!(group_by, GroupBy::ApiKey);
+        assert_eq!(group_by.as_str(), "api_key");
+    }
+}
@@ -1,11 +1,298 @@
+use std::collections::BTreeMap;
+
 use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
 
-pub async fn create_user(pool: &PgPool, email: &str) -> anyhow::Result<()> {
-    sqlx::query!(
-        "INSERT INTO users (user_email) VALUES ($1)",
+/// Creates the user and returns their id.
+pub async fn create_user(pool: &PgPool, email: &str) -> anyhow::Result<Uuid> {
+    let user_id = sqlx::query_scalar!(
+        "INSERT INTO users (user_email) VALUES ($1) RETURNING user_id",
         email.to_lowercase()
     )
+    .fetch_one(pool)
+    .await?;
+    Ok(user_id)
+}
+
+/// Creates the user unless they already exist. Returns the id of a newly
+/// created user.
+pub async fn ensure_user(pool: &PgPool, email: &str) -> anyhow::Result<Option<Uuid>> {
+    let user_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (user_email) VALUES ($1)
+        ON CONFLICT (user_email) DO NOTHING
+        RETURNING user_id
+        "#,
+        email.to_lowercase()
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(user_id)
+}
+
+/// Replaces the user's identity provider attributes if they changed, so that
+/// unchanged sign-ins do not write.
+pub async fn update_user_attributes(
+    pool: &PgPool,
+    email: &str,
+    attributes: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET attributes = $2, updated_at = now()
+        WHERE user_email = $1 AND attributes IS DISTINCT FROM $2
+        "#,
+        email.to_lowercase(),
+        serde_json::to_value(attributes)?,
+    )
     .execute(pool)
     .await?;
     Ok(())
 }
+
+pub struct UserSummary {
+    pub api_keys_count_active: i64,
+    pub cost_center: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub external_id: Option<String>,
+    pub is_disabled: bool,
+    pub is_service_account: bool,
+    pub updated_at: OffsetDateTime,
+    pub user_email: String,
+    pub user_id: Uuid,
+}
+
+/// Lists human users whose email contains `search` (case-insensitive),
+/// ordered by email.
+pub async fn list_users(
+    pool: &PgPool,
+    search: Option<&str>,
+    offset: i64,
+    limit: i64,
+) -> anyhow::Result<Vec<UserSummary>> {
+    let users = sqlx::query_as!(
+        UserSummary,
+        r#"
+        SELECT
+            (
+                SELECT COUNT(*) FROM api_keys ak
+                WHERE ak.user_id = u.user_id AND ak.is_disabled = FALSE
+            ) AS "api_keys_count_active!",
+            u.cost_center,
+            u.created_at,
+            u.external_id,
+            u.is_disabled,
+            u.is_service_account,
+            u.updated_at,
+            u.user_email,
+            u.user_id
+        FROM users u
+        WHERE NOT u.is_service_account
+          AND ($1::text IS NULL OR u.user_email LIKE '%' || $1 || '%')
+        ORDER BY u.user_email
+        OFFSET $2
+        LIMIT $3
+        "#,
+        search.map(str::to_lowercase),
+        offset,
+        limit,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(users)
+}
+
+pub async fn get_user(pool: &PgPool, user_id: Uuid) -> anyhow::Result<Option<UserSummary>> {
+    let user = sqlx::query_as!(
+        UserSummary,
+        r#"
+        SELECT
+            (
+                SELECT COUNT(*) FROM api_keys ak
+                WHERE ak.user_id = u.user_id AND ak.is_disabled = FALSE
+            ) AS "api_keys_count_active!",
+            u.cost_center,
+            u.created_at,
+            u.external_id,
+            u.is_disabled,
+            u.is_service_account,
+            u.updated_at,
+            u.user_email,
+            u.user_id
+        FROM users u
+        WHERE u.user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user)
+}
+
+pub async fn count_users(pool: &PgPool) -> anyhow::Result<i64> {
+    let count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM users WHERE NOT is_service_account"#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+/// Looks up a human user by `email` or `external_id`.
+pub async fn find_user(
+    pool: &PgPool,
+    email: Option<&str>,
+    external_id: Option<&str>,
+) -> anyhow::Result<Option<UserSummary>> {
+    let user = sqlx::query_as!(
+        UserSummary,
+        r#"
+        SELECT
+            (
+                SELECT COUNT(*) FROM api_keys ak
+                WHERE ak.user_id = u.user_id AND ak.is_disabled = FALSE
+            ) AS "api_keys_count_active!",
+            u.cost_center,
+            u.created_at,
+            u.external_id,
+            u.is_disabled,
+            u.is_service_account,
+            u.updated_at,
+            u.user_email,
+            u.user_id
+        FROM users u
+        WHERE NOT u.is_service_account
+          AND (
+            ($1::text IS NOT NULL AND u.user_email = $1)
+            OR ($2::text IS NOT NULL AND u.external_id = $2)
+          )
+        LIMIT 1
+        "#,
+        email.map(str::to_lowercase),
+        external_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user)
+}
+
+/// Inserts a user with the given attributes and returns its id.
+pub async fn insert_user(
+    pool: &PgPool,
+    email: &str,
+    external_id: Option<&str>,
+    cost_center: Option<&str>,
+) -> anyhow::Result<Uuid> {
+    let user_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (user_email, external_id, cost_center)
+        VALUES ($1, $2, $3)
+        RETURNING user_id
+        "#,
+        email.to_lowercase(),
+        external_id,
+        cost_center,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(user_id)
+}
+
+/// Replaces the user's email, external id and cost center. Returns false if
+/// the user does not exist.
+pub async fn update_user(
+    pool: &PgPool,
+    user_id: Uuid,
+    email: &str,
+    external_id: Option<&str>,
+    cost_center: Option<&str>,
+) -> anyhow::Result<bool> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET user_email = $2, external_id = $3, cost_center = $4, updated_at = now()
+        WHERE user_id = $1
+        "#,
+        user_id,
+        email.to_lowercase(),
+        external_id,
+        cost_center,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(updated.rows_affected() > 0)
+}
+
+/// Returns true if the user exists and has been deactivated by an admin.
+pub async fn is_user_disabled(pool: &PgPool, email: &str) -> anyhow::Result<bool> {
+    let is_disabled = sqlx::query_scalar!(
+        "SELECT is_disabled FROM users WHERE user_email = $1",
+        email.to_lowercase()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(is_disabled.unwrap_or(false))
+}
+
+/// Marks the user as disabled and disables all of their personal API keys.
+/// Team keys the user created belong to the team and stay active. Returns
+/// the number of keys disabled, or `None` if the user does not exist.
+pub async fn deactivate_user(pool: &PgPool, user_id: Uuid) -> anyhow::Result<Option<u64>> {
+    let mut tx = pool.begin().await?;
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET is_disabled = TRUE, updated_at = now()
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    let disabled_api_keys = sqlx::query!(
+        r#"
+        UPDATE api_keys
+        SET is_disabled = TRUE, updated_at = now()
+        WHERE user_id = $1 AND team_id IS NULL AND is_disabled = FALSE
+        "#,
+        user_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some(disabled_api_keys.rows_affected()))
+}
+
+/// Clears the user's disabled flag. Keys disabled on deactivation stay
+/// disabled; the user has to provision a new one.
+pub async fn reactivate_user(pool: &PgPool, user_id: Uuid) -> anyhow::Result<bool> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET is_disabled = FALSE, updated_at = now()
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(updated.rows_affected() > 0)
+}
@@ -0,0 +1,589 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hashlink::LinkedHashMap;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Channel the database triggers notify when a cached lookup may be out of
+/// date. Payloads are `api_key:<sha256 of key>`, `api_key_id:<api_key_id>`,
+/// `user:<user_id>`, `team:<team_id>` or `all`.
+pub const VALIDATION_CACHE_CHANNEL: &str = "validation_cache";
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The result of validating an API key and model for an inference request.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ApiKeyAndModel {
+    pub api_key_exists: bool,
+    pub model_exists: bool,
+    pub inference_profile_arn: Option<String>,
+    pub api_key_id: Option<Uuid>,
+    pub model_id: Option<Uuid>,
+    /// The team billed for the request: the key's team, or for service
+    /// accounts the owning team.
+    pub team_id: Option<Uuid>,
+    pub is_service_account: bool,
+    pub user_id: Option<Uuid>,
+    pub user_email: Option<String>,
+    pub capture_enabled: bool,
+    /// `None` means unrestricted.
+    pub scopes: Option<Vec<String>>,
+}
+
+/// Keys are cached by hash so the map never holds them in plain text. The
+/// database triggers compute the same hash.
+pub fn hash_api_key(api_key: &str) -> String {
+    format!("{:x}", Sha256::digest(api_key.to_lowercase().as_bytes()))
+}
+
+/// Whose monthly budget a cached budget check is for.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BudgetOwner {
+    ServiceAccount(Uuid),
+    Team(Uuid),
+}
+
+struct Entry {
+    inserted_at: Instant,
+    value: ApiKeyAndModel,
+}
+
+/// A bounded TTL cache of successful API key and model validations, keyed by
+/// (hashed key, model). Token callers are cached the same way under a
+/// credential string of their own, such as `oidc:<email>`. When full, the
+/// least recently used entry is evicted.
+///
+/// Entries are fresh for `ttl`. Older entries are kept until `stale_ttl` so
+/// recently seen keys keep working while the database is unreachable.
+///
+/// Every invalidation bumps a generation. Callers read [`Self::generation`]
+/// before querying the database and pass it to the inserts, which are
+/// skipped if an invalidation arrived in between, so a result read before a
+/// change is never cached after it.
+///
+/// Whether each team and service account has spent its budget is cached for
+/// `ttl` too, so most requests skip summing the month's usage. Budget checks
+/// are never served stale.
+pub struct ValidationCache {
+    budgets: Mutex<LinkedHashMap<BudgetOwner, (Instant, bool)>>,
+    capacity: usize,
+    entries: Mutex<LinkedHashMap<(String, String), Entry>>,
+    generation: AtomicU64,
+    stale_ttl: Duration,
+    ttl: Duration,
+}
+
+impl ValidationCache {
+    /// A `capacity` of zero disables the cache.
+    pub fn new(capacity: usize, ttl: Duration, stale_ttl: Duration) -> Self {
+        Self {
+            budgets: Mutex::new(LinkedHashMap::new()),
+            capacity,
+            entries: Mutex::new(LinkedHashMap::new()),
+            generation: AtomicU64::new(0),
+            stale_ttl: stale_ttl.max(ttl),
+            ttl,
+        }
+    }
+
+    fn cache_key(api_key: &str, model_name: &str) -> (String, String) {
+        (hash_api_key(api_key), model_name.to_lowercase())
+    }
+
+    fn get_within(
+        &self,
+        api_key: &str,
+        model_name: &str,
+        max_age: Duration,
+    ) -> Option<ApiKeyAndModel> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut entries = self.entries.lock().ok()?;
+        entries
+            .to_back(&Self::cache_key(api_key, model_name))
+            .filter(|entry| entry.inserted_at.elapsed() < max_age)
+            .map(|entry| entry.value.clone())
+    }
+
+    /// Returns a fresh entry.
+    pub fn get(&self, api_key: &str, model_name: &str) -> Option<ApiKeyAndModel> {
+        self.get_within(api_key, model_name, self.ttl)
+    }
+
+    /// Returns an entry that may be past its TTL, for use when the database
+    /// cannot be reached.
+    pub fn get_stale(&self, api_key: &str, model_name: &str) -> Option<ApiKeyAndModel> {
+        self.get_within(api_key, model_name, self.stale_ttl)
+    }
+
+    /// The current invalidation generation, to read before the database
+    /// lookup whose result is inserted.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Bumps the generation. Called with the map being invalidated locked,
+    /// so an insert either sees the new generation or is invalidated.
+    fn bump_generation(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Caches `value` unless the cache was invalidated since `generation`
+    /// was read.
+    pub fn insert(&self, api_key: &str, model_name: &str, value: ApiKeyAndModel, generation: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if self.generation() != generation {
+            return;
+        }
+
+        let key = Self::cache_key(api_key, model_name);
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.pop_front();
+        }
+
+        entries.insert(
+            key,
+            Entry {
+                inserted_at: Instant::now(),
+                value,
+            },
+        );
+    }
+
+    /// Returns whether `owner` has spent its budget, if checked within the
+    /// TTL.
+    pub fn get_budget_exceeded(&self, owner: BudgetOwner) -> Option<bool> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut budgets = self.budgets.lock().ok()?;
+        budgets
+            .to_back(&owner)
+            .filter(|(inserted_at, _)| inserted_at.elapsed() < self.ttl)
+            .map(|(_, exceeded)| *exceeded)
+    }
+
+    /// Caches a budget check unless the cache was invalidated since
+    /// `generation` was read.
+    pub fn insert_budget_exceeded(&self, owner: BudgetOwner, exceeded: bool, generation: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let Ok(mut budgets) = self.budgets.lock() else {
+            return;
+        };
+        if self.generation() != generation {
+            return;
+        }
+
+        if budgets.len() >= self.capacity && !budgets.contains_key(&owner) {
+            budgets.pop_front();
+        }
+
+        budgets.insert(owner, (Instant::now(), exceeded));
+    }
+
+    pub fn remove(&self, api_key: &str, model_name: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            self.bump_generation();
+            entries.remove(&Self::cache_key(api_key, model_name));
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            self.bump_generation();
+            entries.clear();
+        }
+        self.clear_budgets();
+    }
+
+    fn clear_budgets(&self) {
+        if let Ok(mut budgets) = self.budgets.lock() {
+            self.bump_generation();
+            budgets.clear();
+        }
+    }
+
+    /// Makes every entry stale: it is revalidated on next use but can still
+    /// be served while the database is unreachable. Budget checks are
+    /// dropped.
+    pub fn expire_all(&self) {
+        self.clear_budgets();
+        let Some(expired_at) = Instant::now().checked_sub(self.ttl) else {
+            return;
+        };
+        if let Ok(mut entries) = self.entries.lock() {
+            self.bump_generation();
+            for entry in entries.values_mut() {
+                entry.inserted_at = entry.inserted_at.min(expired_at);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map_or(0, |entries| entries.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops the entries a [`VALIDATION_CACHE_CHANNEL`] notification refers
+    /// to. Unrecognized payloads clear the whole cache.
+    pub fn invalidate(&self, payload: &str) {
+        let (Ok(mut entries), Ok(mut budgets)) = (self.entries.lock(), self.budgets.lock()) else {
+            return;
+        };
+        self.bump_generation();
+
+        match payload.split_once(':') {
+            Some(("api_key", key_hash)) => {
+                entries.retain(|(entry_key_hash, _), _| entry_key_hash != key_hash);
+            }
+            Some(("api_key_id", api_key_id)) => match api_key_id.parse::<Uuid>() {
+                Ok(api_key_id) => {
+                    entries.retain(|_, entry| entry.value.api_key_id != Some(api_key_id));
+                }
+                Err(_) => {
+                    entries.clear();
+                    budgets.clear();
+                }
+            },
+            Some(("user", user_id)) => match user_id.parse::<Uuid>() {
+                Ok(user_id) => {
+                    entries.retain(|_, entry| entry.value.user_id != Some(user_id));
+                    budgets.remove(&BudgetOwner::ServiceAccount(user_id));
+                }
+                Err(_) => {
+                    entries.clear();
+                    budgets.clear();
+                }
+            },
+            Some(("team", team_id)) => match team_id.parse::<Uuid>() {
+                Ok(team_id) => {
+                    entries.retain(|_, entry| entry.value.team_id != Some(team_id));
+                    budgets.remove(&BudgetOwner::Team(team_id));
+                }
+                Err(_) => {
+                    entries.clear();
+                    budgets.clear();
+                }
+            },
+            _ => {
+                entries.clear();
+                budgets.clear();
+            }
+        }
+    }
+
+    /// Applies invalidations from [`VALIDATION_CACHE_CHANNEL`] until aborted.
+    /// Notifications sent while disconnected are lost, so every entry is
+    /// expired whenever the connection drops or is established. Expired
+    /// entries remain available to [`ValidationCache::get_stale`].
+    pub async fn continuously_listen(self: Arc<Self>, pool: Arc<PgPool>) {
+        loop {
+            match PgListener::connect_with(&pool).await {
+                Ok(mut listener) => match listener.listen(VALIDATION_CACHE_CHANNEL).await {
+                    Ok(()) => {
+                        info!("Listening for validation cache invalidations");
+                        self.expire_all();
+                        loop {
+                            match listener.try_recv().await {
+                                Ok(Some(notification)) => {
+                                    debug!(
+                                        payload = notification.payload(),
+                                        "Invalidating validation cache"
+                                    );
+                                    self.invalidate(notification.payload());
+                                }
+                                Ok(None) => {
+                                    warn!("Validation cache listener reconnecting");
+                                    self.expire_all();
+                                }
+                                Err(e) => {
+                                    error!("Validation cache listener failed: {:?}", e);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => error!(
+                        "Failed to listen for validation cache invalidations: {:?}",
+                        e
+                    ),
+                },
+                Err(e) => error!("Failed to connect validation cache listener: {:?}", e),
+            }
+
+            self.expire_all();
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const API_KEY: &str = "0a1b2c3d-4e5f-6789-abcd-ef0123456789";
+
+    fn value(user_id: Uuid, team_id: Option<Uuid>) -> ApiKeyAndModel {
+        ApiKeyAndModel {
+            api_key_exists: true,
+            model_exists: true,
+            team_id,
+            user_id: Some(user_id),
+            ..Default::default()
+        }
+    }
+
+    fn cache() -> ValidationCache {
+        ValidationCache::new(10, Duration::from_secs(60), Duration::from_secs(600))
+    }
+
+    #[test]
+    fn invalidate_by_key_id_drops_entries_of_that_key() {
+        let cache = cache();
+        let api_key_id = Uuid::new_v4();
+        cache.insert(
+            "session_token:1",
+            "model",
+            ApiKeyAndModel {
+                api_key_id: Some(api_key_id),
+                ..value(Uuid::new_v4(), None)
+            },
+            cache.generation(),
+        );
+        cache.insert(
+            "session_token:2",
+            "model",
+            value(Uuid::new_v4(), None),
+            cache.generation(),
+        );
+
+        cache.invalidate(&format!("api_key_id:{api_key_id}"));
+        assert_eq!(cache.get("session_token:1", "model"), None);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn entries_are_keyed_by_key_and_model_ignoring_case() {
+        let cache = cache();
+        let value = value(Uuid::new_v4(), None);
+        cache.insert(
+            API_KEY,
+            "Claude-Opus-4-6",
+            value.clone(),
+            cache.generation(),
+        );
+
+        assert_eq!(
+            cache.get(&API_KEY.to_uppercase(), "claude-opus-4-6"),
+            Some(value)
+        );
+        assert_eq!(cache.get(API_KEY, "claude-sonnet-4-6"), None);
+        assert_eq!(cache.get("other-key", "claude-opus-4-6"), None);
+    }
+
+    #[test]
+    fn expired_entries_are_only_served_stale() {
+        let cache = ValidationCache::new(10, Duration::ZERO, Duration::from_secs(600));
+        let value = value(Uuid::new_v4(), None);
+        cache.insert(
+            API_KEY,
+            "claude-opus-4-6",
+            value.clone(),
+            cache.generation(),
+        );
+
+        assert_eq!(cache.get(API_KEY, "claude-opus-4-6"), None);
+        assert_eq!(cache.get_stale(API_KEY, "claude-opus-4-6"), Some(value));
+    }
+
+    #[test]
+    fn expire_all_keeps_entries_for_stale_use() {
+        let cache = cache();
+        let value = value(Uuid::new_v4(), None);
+        cache.insert(
+            API_KEY,
+            "claude-opus-4-6",
+            value.clone(),
+            cache.generation(),
+        );
+
+        cache.expire_all();
+        assert_eq!(cache.get(API_KEY, "claude-opus-4-6"), None);
+        assert_eq!(cache.get_stale(API_KEY, "claude-opus-4-6"), Some(value));
+    }
+
+    #[test]
+    fn zero_capacity_disables_the_cache() {
+        let cache = ValidationCache::new(0, Duration::from_secs(60), Duration::from_secs(600));
+        cache.insert(
+            API_KEY,
+            "claude-opus-4-6",
+            value(Uuid::new_v4(), None),
+            cache.generation(),
+        );
+        assert!(cache.is_empty());
+        assert_eq!(cache.get_stale(API_KEY, "claude-opus-4-6"), None);
+    }
+
+    #[test]
+    fn capacity_evicts_the_least_recently_used_entry() {
+        let cache = ValidationCache::new(2, Duration::from_secs(60), Duration::from_secs(600));
+        cache.insert(
+            "key-1",
+            "model",
+            value(Uuid::new_v4(), None),
+            cache.generation(),
+        );
+        cache.insert(
+            "key-2",
+            "model",
+            value(Uuid::new_v4(), None),
+            cache.generation(),
+        );
+        assert!(cache.get("key-1", "model").is_some());
+        cache.insert(
+            "key-3",
+            "model",
+            value(Uuid::new_v4(), None),
+            cache.generation(),
+        );
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("key-2", "model"), None);
+        assert!(cache.get("key-1", "model").is_some());
+        assert!(cache.get("key-3", "model").is_some());
+    }
+
+    #[test]
+    fn results_read_before_an_invalidation_are_not_cached() {
+        let cache = cache();
+        let team_id = Uuid::new_v4();
+        let generation = cache.generation();
+
+        cache.invalidate(&format!("team:{team_id}"));
+        cache.insert(
+            "key-1",
+            "model",
+            value(Uuid::new_v4(), Some(team_id)),
+            generation,
+        );
+        cache.insert_budget_exceeded(BudgetOwner::Team(team_id), false, generation);
+        assert!(cache.is_empty());
+        assert_eq!(cache.get_budget_exceeded(BudgetOwner::Team(team_id)), None);
+
+        cache.insert(
+            "key-1",
+            "model",
+            value(Uuid::new_v4(), None),
+            cache.generation(),
+        );
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn invalidate_drops_matching_entries() {
+        let cache = cache();
+        let user_id = Uuid::new_v4();
+        let team_id = Uuid::new_v4();
+        cache.insert("key-1", "model", value(user_id, None), cache.generation());
+        cache.insert(
+            "key-2",
+            "model",
+            value(Uuid::new_v4(), Some(team_id)),
+            cache.generation(),
+        );
+        cache.insert(
+            "key-3",
+            "model",
+            value(Uuid::new_v4(), None),
+            cache.generation(),
+        );
+
+        cache.invalidate(&format!("api_key:{}", hash_api_key("key-3")));
+        assert_eq!(cache.get("key-3", "model"), None);
+        assert_eq!(cache.len(), 2);
+
+        cache.invalidate(&format!("user:{user_id}"));
+        assert_eq!(cache.get("key-1", "model"), None);
+        assert_eq!(cache.len(), 1);
+
+        cache.invalidate(&format!("team:{team_id}"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn budget_checks_expire_and_are_invalidated_with_their_owner() {
+        let cache = cache();
+        let team_id = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+        cache.insert_budget_exceeded(BudgetOwner::Team(team_id), true, cache.generation());
+        cache.insert_budget_exceeded(
+            BudgetOwner::ServiceAccount(user_id),
+            false,
+            cache.generation(),
+        );
+
+        assert_eq!(
+            cache.get_budget_exceeded(BudgetOwner::Team(team_id)),
+            Some(true)
+        );
+        assert_eq!(cache.get_budget_exceeded(BudgetOwner::Team(user_id)), None);
+
+        cache.invalidate(&format!("user:{user_id}"));
+        assert_eq!(
+            cache.get_budget_exceeded(BudgetOwner::ServiceAccount(user_id)),
+            None
+        );
+        cache.invalidate(&format!("team:{team_id}"));
+        assert_eq!(cache.get_budget_exceeded(BudgetOwner::Team(team_id)), None);
+
+        let cache = ValidationCache::new(10, Duration::ZERO, Duration::from_secs(600));
+        cache.insert_budget_exceeded(BudgetOwner::Team(team_id), true, cache.generation());
+        assert_eq!(cache.get_budget_exceeded(BudgetOwner::Team(team_id)), None);
+    }
+
+    #[test]
+    fn unrecognized_invalidations_clear_everything() {
+        let cache = cache();
+        cache.insert(
+            "key-1",
+            "model",
+            value(Uuid::new_v4(), None),
+            cache.generation(),
+        );
+        cache.insert(
+            "key-2",
+            "model",
+            value(Uuid::new_v4(), None),
+            cache.generation(),
+        );
+
+        cache.invalidate("user:not-a-uuid");
+        assert!(cache.is_empty());
+
+        cache.insert(
+            "key-1",
+            "model",
+            value(Uuid::new_v4(), None),
+            cache.generation(),
+        );
+        cache.invalidate("all");
+        assert!(cache.is_empty());
+    }
+}